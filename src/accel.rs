@@ -1,18 +1,41 @@
 use crate::structure::*;
-use cgmath::Point3;
+use cgmath::{EuclideanSpace, InnerSpace, Point3, Vector3};
 
+/// Flattened BVH node (depth first order)
+/// the first child of an interior node is always stored
+/// just after its parent.
 #[derive(Debug)]
 struct BVHNode {
     pub aabb: AABB,
-    pub first: usize,
+    // Leaf: index of the first element
+    // Interior: index of the second child
+    pub offset: usize,
+    // Number of elements (0 for interior nodes)
     pub count: usize,
-    pub left: Option<usize>,
-    pub right: Option<usize>,
+    // Split axis (used to traverse the node front-to-back)
+    pub axis: usize,
 }
 
 impl BVHNode {
     pub fn is_leaf(&self) -> bool {
-        self.left.is_none() && self.right.is_none()
+        self.count > 0
+    }
+}
+
+/// BVH construction parameters
+pub struct BVHBuildParameters {
+    /// Maximum number of elements inside a leaf
+    pub leaf_size: usize,
+    /// Number of bins used to evaluate the SAH
+    pub nb_bins: usize,
+}
+
+impl Default for BVHBuildParameters {
+    fn default() -> Self {
+        BVHBuildParameters {
+            leaf_size: 4,
+            nb_bins: 16,
+        }
     }
 }
 
@@ -30,87 +53,224 @@ pub trait BVHElement<D> {
     fn position(&self) -> Point3<f32>;
     // Used when collecting the different objects
     fn intersection(&self, r: &Ray) -> Option<D>;
+    // Used by the point queries (e.g., radius search)
+    fn contains(&self, p: &Point3<f32>) -> bool {
+        self.aabb().contains(p)
+    }
+}
+
+/// Intersection information that can be ordered along the ray
+/// (needed for the closest hit query)
+pub trait BVHHit {
+    fn t(&self) -> f32;
+}
+
+impl BVHHit for f32 {
+    fn t(&self) -> f32 {
+        *self
+    }
 }
 
-// Implementation from (C++): https://github.com/shiinamiyuki/minpt/blob/master/minpt.cpp
+/// Sphere of radius around a point with its data (e.g., a photon or a light vertex)
+/// only used with the point queries (see BHVAccel::gather_point)
+pub struct PointSphere<T> {
+    pub pos: Point3<f32>,
+    pub radius: f32,
+    pub data: T,
+}
+impl<T> BVHElement<f32> for PointSphere<T> {
+    fn aabb(&self) -> AABB {
+        let radius = Vector3::new(self.radius, self.radius, self.radius);
+        AABB::default()
+            .union_vec(&(self.pos.to_vec() - radius))
+            .union_vec(&(self.pos.to_vec() + radius))
+    }
+    fn position(&self) -> Point3<f32> {
+        self.pos
+    }
+    fn intersection(&self, _r: &Ray) -> Option<f32> {
+        None
+    }
+    fn contains(&self, p: &Point3<f32>) -> bool {
+        (self.pos - *p).magnitude2() <= self.radius * self.radius
+    }
+}
+
+// Element information cached during the construction
+struct BVHBuildElement {
+    id: usize,
+    aabb: AABB,
+    position: Point3<f32>,
+}
+
+#[derive(Default)]
+struct BVHBin {
+    count: usize,
+    aabb: AABB,
+}
+
+// Inspired by PBRT-v3 (binned SAH construction and linear BVH)
 impl<D, T: BVHElement<D>> BHVAccel<D, T> {
     // Internal build function
     // return the node ID (allocate node on the fly)
-    fn build(&mut self, begin: usize, end: usize, depth: u32) -> Option<usize> {
-        if end == begin {
-            return None;
+    fn build(
+        &mut self,
+        infos: &mut [BVHBuildElement],
+        offset: usize,
+        params: &BVHBuildParameters,
+    ) -> usize {
+        let aabb = infos
+            .iter()
+            .fold(AABB::default(), |aabb, e| aabb.union_aabb(&e.aabb));
+        let node_id = self.nodes.len();
+        self.nodes.push(BVHNode {
+            aabb,
+            offset,
+            count: infos.len(),
+            axis: 0,
+        });
+        if infos.len() == 1 {
+            return node_id;
         }
 
-        // TODO: Not very optimized ...
-        let mut aabb = AABB::default();
-        for i in begin..end {
-            aabb = aabb.union_aabb(&self.elements[i].aabb());
-        }
+        // Cut on the biggest axis of the element positions
+        let centroid_aabb = infos.iter().fold(AABB::default(), |aabb, e| {
+            aabb.union_vec(&e.position.to_vec())
+        });
+        let centroid_size = centroid_aabb.size();
+        let axis = if centroid_size.x > centroid_size.y {
+            if centroid_size.x > centroid_size.z {
+                0
+            } else {
+                2
+            }
+        } else if centroid_size.y > centroid_size.z {
+            1
+        } else {
+            2
+        };
 
-        // If the tree is too deep or not enough element
-        // force to make a leaf
-        // depth >= 20
-        if end - begin <= 4 {
-            // dbg!(aabb.size());
-            self.nodes.push(BVHNode {
-                aabb,
-                first: begin,
-                count: end - begin,
-                left: None,
-                right: None,
-            });
-            Some(self.nodes.len() - 1)
+        let split_id = if centroid_size[axis] <= 0.0 {
+            // All the positions are the same, SAH cannot separate the elements
+            if infos.len() <= params.leaf_size {
+                return node_id;
+            }
+            infos.len() / 2
         } else {
-            // For now cut on the biggest axis
-            let aabb_size = aabb.size();
-            let axis = if aabb_size.x > aabb_size.y {
-                if aabb_size.x > aabb_size.z {
-                    0
-                } else {
-                    2
+            // Bin the elements along the axis
+            let nb_bins = params.nb_bins;
+            let bin_id = |e: &BVHBuildElement| {
+                let v = (e.position[axis] - centroid_aabb.p_min[axis]) / centroid_size[axis];
+                ((v * nb_bins as f32) as usize).min(nb_bins - 1)
+            };
+            let mut bins = (0..nb_bins).map(|_| BVHBin::default()).collect::<Vec<_>>();
+            for e in infos.iter() {
+                let b = &mut bins[bin_id(e)];
+                b.count += 1;
+                b.aabb = b.aabb.union_aabb(&e.aabb);
+            }
+
+            // Sweep from the right to get the right side cost
+            let mut right_costs = vec![0.0; nb_bins];
+            let mut right_aabb = AABB::default();
+            let mut right_count = 0;
+            for i in (1..nb_bins).rev() {
+                right_aabb = right_aabb.union_aabb(&bins[i].aabb);
+                right_count += bins[i].count;
+                right_costs[i] = right_count as f32 * right_aabb.surface_area();
+            }
+            // Then sweep from the left to find the best split
+            // (split between bin i - 1 and i)
+            let mut left_aabb = AABB::default();
+            let mut left_count = 0;
+            let mut best = (std::f32::MAX, 0);
+            for i in 1..nb_bins {
+                left_aabb = left_aabb.union_aabb(&bins[i - 1].aabb);
+                left_count += bins[i - 1].count;
+                let cost = left_count as f32 * left_aabb.surface_area() + right_costs[i];
+                if cost < best.0 {
+                    best = (cost, i);
+                }
+            }
+
+            // Keep the leaf if it is cheaper than the best split
+            // (cost relative to the leaf intersection, the traversal costs 1/8)
+            // A node without area (e.g., line primitives) cannot be evaluated with SAH:
+            // it is a leaf if it is small enough, otherwise it is split by count
+            let surface_area = self.nodes[node_id].aabb.surface_area();
+            let degenerate = surface_area <= 0.0;
+            if infos.len() <= params.leaf_size
+                && (degenerate || 0.125 + best.0 / surface_area >= infos.len() as f32)
+            {
+                return node_id;
+            }
+
+            // Partition the elements
+            let mut split_id = 0;
+            if !degenerate {
+                let split_bin = best.1;
+                for i in 0..infos.len() {
+                    if bin_id(&infos[i]) < split_bin {
+                        infos.swap(i, split_id);
+                        split_id += 1;
+                    }
                 }
-            } else if aabb_size.y > aabb_size.z {
-                1
+            }
+            if split_id == 0 || split_id == infos.len() {
+                // SAH failed to split, use the median instead
+                infos.sort_unstable_by(|e1, e2| {
+                    e1.position[axis].partial_cmp(&e2.position[axis]).unwrap()
+                });
+                infos.len() / 2
             } else {
-                2
-            };
+                split_id
+            }
+        };
 
-            // Split based on largest axis (split inside the middle for now)
-            // or split between triangles
-            // TODO: Implements SAH
-            // let split = (aabb.p_max[axis] + aabb.p_min[axis]) / 2.0;
-            // let split_id = self.triangles[begin..end].iter_mut().partition_in_place(|t| t.middle()[axis] < split ) + begin;
-
-            self.elements[begin..end].sort_unstable_by(|t1, t2| {
-                t1.position()[axis]
-                    .partial_cmp(&t2.position()[axis])
-                    .unwrap()
-            });
-            let split_id = (begin + end) / 2;
-
-            // TODO: Make better
-            let left = self.build(begin, split_id, depth + 1);
-            let right = self.build(split_id, end, depth + 1);
-            self.nodes.push(BVHNode {
-                aabb,
-                first: 0, // TODO: Make the node invalid
-                count: 0,
-                left,
-                right,
-            });
-
-            Some(self.nodes.len() - 1)
-        }
+        let (left, right) = infos.split_at_mut(split_id);
+        self.build(left, offset, params);
+        let second_id = self.build(right, offset + split_id, params);
+        let node = &mut self.nodes[node_id];
+        node.offset = second_id;
+        node.count = 0;
+        node.axis = axis;
+        node_id
     }
 
     pub fn create(elements: Vec<T>) -> BHVAccel<D, T> {
+        BHVAccel::create_with_params(elements, &BVHBuildParameters::default())
+    }
+
+    pub fn create_with_params(elements: Vec<T>, params: &BVHBuildParameters) -> BHVAccel<D, T> {
+        assert!(params.leaf_size > 0);
+        assert!(params.nb_bins > 1);
+        let mut infos = elements
+            .iter()
+            .enumerate()
+            .map(|(id, e)| BVHBuildElement {
+                id,
+                aabb: e.aabb(),
+                position: e.position(),
+            })
+            .collect::<Vec<_>>();
+
         let mut accel = BHVAccel {
-            elements,
+            elements: vec![],
             nodes: Vec::new(),
             root: None,
             phantom: std::marker::PhantomData,
         };
-        accel.root = accel.build(0, accel.elements.len(), 0);
+        if !infos.is_empty() {
+            accel.root = Some(accel.build(&mut infos, 0, params));
+        }
+
+        // Reorder the elements to match the leaves
+        let mut elements = elements.into_iter().map(Some).collect::<Vec<_>>();
+        accel.elements = infos
+            .iter()
+            .map(|e| elements[e.id].take().unwrap())
+            .collect();
+
         match accel.root {
             None => warn!("BVH is empty!"),
             Some(ref v) => {
@@ -123,6 +283,17 @@ impl<D, T: BVHElement<D>> BHVAccel<D, T> {
         accel
     }
 
+    // Children of an interior node, the closest one (along the ray direction) first
+    fn children(&self, node_id: usize, r: &Ray) -> (usize, usize) {
+        let n = &self.nodes[node_id];
+        if r.d[n.axis] < 0.0 {
+            (n.offset, node_id + 1)
+        } else {
+            (node_id + 1, n.offset)
+        }
+    }
+
+    /// Gather all the elements intersected by the ray
     pub fn gather(&self, r: Ray) -> Vec<(D, usize)> {
         let mut res = vec![];
         if self.root.is_none() {
@@ -130,34 +301,119 @@ impl<D, T: BVHElement<D>> BHVAccel<D, T> {
         }
 
         // Indices of nodes
-        let mut stack: Vec<usize> = Vec::new();
-        stack.reserve(100); // In case
+        let mut stack: Vec<usize> = Vec::with_capacity(64);
         stack.push(self.root.unwrap());
 
         while let Some(curr_id) = stack.pop() {
             let n = &self.nodes[curr_id];
-            let t_aabb = n.aabb.intersect(&r);
-            match (n.is_leaf(), t_aabb) {
-                (_, None) => {
-                    // Nothing to do as we miss the node
-                }
-                (true, Some(_t)) => {
-                    for i in n.first..(n.first + n.count) {
-                        if let Some(d) = self.elements[i].intersection(&r) {
-                            res.push((d, i));
-                        }
+            if n.aabb.intersect(&r).is_none() {
+                // Nothing to do as we miss the node
+                continue;
+            }
+            if n.is_leaf() {
+                for i in n.offset..(n.offset + n.count) {
+                    if let Some(d) = self.elements[i].intersection(&r) {
+                        res.push((d, i));
                     }
                 }
-                (false, Some(_t)) => {
-                    if let Some(left_id) = n.left {
-                        stack.push(left_id);
+            } else {
+                stack.push(n.offset);
+                stack.push(curr_id + 1);
+            }
+        }
+        res
+    }
+
+    /// Gather all the elements containing the point
+    pub fn gather_point(&self, p: &Point3<f32>) -> Vec<usize> {
+        let mut res = vec![];
+        let root = match self.root {
+            None => return res,
+            Some(v) => v,
+        };
+
+        let mut stack: Vec<usize> = Vec::with_capacity(64);
+        stack.push(root);
+        while let Some(curr_id) = stack.pop() {
+            let n = &self.nodes[curr_id];
+            if !n.aabb.contains(p) {
+                continue;
+            }
+            if n.is_leaf() {
+                for i in n.offset..(n.offset + n.count) {
+                    if self.elements[i].contains(p) {
+                        res.push(i);
                     }
-                    if let Some(right_id) = n.right {
-                        stack.push(right_id);
+                }
+            } else {
+                stack.push(n.offset);
+                stack.push(curr_id + 1);
+            }
+        }
+        res
+    }
+
+    /// Closest element intersected by the ray
+    /// Nodes are visited front-to-back and pruned by the current hit distance
+    pub fn closest(&self, r: &Ray) -> Option<(D, usize)>
+    where
+        D: BVHHit,
+    {
+        let root = self.root?;
+        let mut r = *r;
+        let mut res = None;
+
+        let mut stack: Vec<usize> = Vec::with_capacity(64);
+        stack.push(root);
+        while let Some(curr_id) = stack.pop() {
+            let n = &self.nodes[curr_id];
+            if n.aabb.intersect(&r).is_none() {
+                continue;
+            }
+            if n.is_leaf() {
+                for i in n.offset..(n.offset + n.count) {
+                    if let Some(d) = self.elements[i].intersection(&r) {
+                        if d.t() <= r.tfar {
+                            r.tfar = d.t();
+                            res = Some((d, i));
+                        }
                     }
                 }
+            } else {
+                let (first, second) = self.children(curr_id, &r);
+                stack.push(second);
+                stack.push(first);
             }
         }
         res
     }
+
+    /// Check if the ray intersects any element (stop at the first hit)
+    pub fn any_hit(&self, r: &Ray) -> bool {
+        let root = match self.root {
+            None => return false,
+            Some(v) => v,
+        };
+
+        let mut stack: Vec<usize> = Vec::with_capacity(64);
+        stack.push(root);
+        while let Some(curr_id) = stack.pop() {
+            let n = &self.nodes[curr_id];
+            if n.aabb.intersect(r).is_none() {
+                continue;
+            }
+            if n.is_leaf() {
+                if (n.offset..(n.offset + n.count))
+                    .any(|i| self.elements[i].intersection(r).is_some())
+                {
+                    return true;
+                }
+            } else {
+                let (first, second) = self.children(curr_id, r);
+                stack.push(second);
+                stack.push(first);
+            }
+        }
+        false
+    }
 }
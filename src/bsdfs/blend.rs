@@ -11,15 +11,16 @@ impl BSDF for BSDFBlend {
         uv: &Option<Vector2<f32>>,
         d_in: &Vector3<f32>,
         sample: Point2<f32>,
+        transport: Transport,
     ) -> Option<SampledDirection> {
         assert!(!self.bsdf1.is_smooth() && !self.bsdf2.is_smooth());
 
         let sampled_dir = if sample.x < 0.5 {
             let scaled_sample = Point2::new(sample.x * 2.0, sample.y);
-            self.bsdf1.sample(uv, d_in, scaled_sample)
+            self.bsdf1.sample(uv, d_in, scaled_sample, transport)
         } else {
             let scaled_sample = Point2::new((sample.x - 0.5) * 2.0, sample.y);
-            self.bsdf2.sample(uv, d_in, scaled_sample)
+            self.bsdf2.sample(uv, d_in, scaled_sample, transport)
         };
 
         if let Some(mut sampled_dir) = sampled_dir {
@@ -27,8 +28,9 @@ impl BSDF for BSDFBlend {
             if sampled_dir.pdf.value() == 0.0 {
                 None
             } else {
-                sampled_dir.weight = self.eval(uv, d_in, &sampled_dir.d, Domain::SolidAngle)
-                    / sampled_dir.pdf.value();
+                sampled_dir.weight =
+                    self.eval(uv, d_in, &sampled_dir.d, Domain::SolidAngle, transport)
+                        / sampled_dir.pdf.value();
                 Some(sampled_dir)
             }
         } else {
@@ -58,8 +60,10 @@ impl BSDF for BSDFBlend {
         d_in: &Vector3<f32>,
         d_out: &Vector3<f32>,
         domain: Domain,
+        transport: Transport,
     ) -> Color {
-        self.bsdf1.eval(uv, d_in, d_out, domain) + self.bsdf2.eval(uv, d_in, d_out, domain)
+        self.bsdf1.eval(uv, d_in, d_out, domain, transport)
+            + self.bsdf2.eval(uv, d_in, d_out, domain, transport)
     }
 
     fn roughness(&self, uv: &Option<Vector2<f32>>) -> f32 {
@@ -68,6 +72,10 @@ impl BSDF for BSDFBlend {
         self.bsdf1.roughness(uv).min(self.bsdf2.roughness(uv))
     }
 
+    fn albedo(&self, uv: &Option<Vector2<f32>>) -> Color {
+        self.bsdf1.albedo(uv) + self.bsdf2.albedo(uv)
+    }
+
     fn is_smooth(&self) -> bool {
         if self.bsdf1.is_smooth() || self.bsdf2.is_smooth() {
             panic!("is smooth on blend material");
@@ -0,0 +1,133 @@
+use crate::bsdfs::*;
+
+/// Default interior IOR (BK7 glass)
+pub const DEFAULT_INT_IOR: f32 = 1.5046;
+/// Default exterior IOR (air), shared by all the scene loaders
+pub const DEFAULT_EXT_IOR: f32 = 1.000_277;
+
+/// Smooth dielectric interface (e.g. glass)
+pub struct BSDFDielectric {
+    pub specular_reflectance: BSDFColor,
+    pub specular_transmittance: BSDFColor,
+    pub int_ior: f32,
+    pub ext_ior: f32,
+}
+
+impl BSDFDielectric {
+    // Relative IOR
+    fn eta(&self) -> f32 {
+        self.int_ior / self.ext_ior
+    }
+    // Relative IOR between the incoming and the outgoing side
+    fn eta_ti(&self, d_in: &Vector3<f32>) -> f32 {
+        if d_in.z < 0.0 {
+            self.eta()
+        } else {
+            1.0 / self.eta()
+        }
+    }
+}
+
+// Only the radiance is scaled by the IOR ratio (not the importance)
+fn radiance_scale(eta_ti: f32, transport: Transport) -> f32 {
+    match transport {
+        Transport::Radiance => eta_ti * eta_ti,
+        Transport::Importance => 1.0,
+    }
+}
+
+impl BSDF for BSDFDielectric {
+    fn sample(
+        &self,
+        uv: &Option<Vector2<f32>>,
+        d_in: &Vector3<f32>,
+        sample: Point2<f32>,
+        transport: Transport,
+    ) -> Option<SampledDirection> {
+        if d_in.z == 0.0 {
+            return None;
+        }
+        let (f, cos_theta_t) = fresnel_dielectric(d_in.z, self.eta());
+        if sample.x <= f {
+            Some(SampledDirection {
+                weight: self.specular_reflectance.color(uv),
+                d: reflect(d_in),
+                pdf: PDF::Discrete(f),
+            })
+        } else {
+            let eta_ti = self.eta_ti(d_in);
+            // Radiance scaling when crossing the interface
+            // (cancel out for closed objects)
+            Some(SampledDirection {
+                weight: self.specular_transmittance.color(uv) * radiance_scale(eta_ti, transport),
+                d: refract(d_in, eta_ti, cos_theta_t),
+                pdf: PDF::Discrete(1.0 - f),
+            })
+        }
+    }
+
+    fn pdf(
+        &self,
+        _uv: &Option<Vector2<f32>>,
+        wi: &Vector3<f32>,
+        wo: &Vector3<f32>,
+        domain: Domain,
+    ) -> PDF {
+        assert!(domain == Domain::Discrete);
+        let (f, cos_theta_t) = fresnel_dielectric(wi.z, self.eta());
+        if wi.z * wo.z >= 0.0 {
+            if check_reflection_condition(wi, wo) {
+                PDF::Discrete(f)
+            } else {
+                PDF::Discrete(0.0)
+            }
+        } else if check_direlectric_condition(wi, wo, self.eta_ti(wi), cos_theta_t.abs()) {
+            PDF::Discrete(1.0 - f)
+        } else {
+            PDF::Discrete(0.0)
+        }
+    }
+
+    fn eval(
+        &self,
+        uv: &Option<Vector2<f32>>,
+        wi: &Vector3<f32>,
+        wo: &Vector3<f32>,
+        domain: Domain,
+        transport: Transport,
+    ) -> Color {
+        assert!(domain == Domain::Discrete);
+        let (f, cos_theta_t) = fresnel_dielectric(wi.z, self.eta());
+        if wi.z * wo.z >= 0.0 {
+            if check_reflection_condition(wi, wo) {
+                self.specular_reflectance.color(uv) * f
+            } else {
+                Color::zero()
+            }
+        } else {
+            let eta_ti = self.eta_ti(wi);
+            if check_direlectric_condition(wi, wo, eta_ti, cos_theta_t.abs()) {
+                self.specular_transmittance.color(uv)
+                    * ((1.0 - f) * radiance_scale(eta_ti, transport))
+            } else {
+                Color::zero()
+            }
+        }
+    }
+
+    fn roughness(&self, _uv: &Option<Vector2<f32>>) -> f32 {
+        0.0
+    }
+
+    fn albedo(&self, uv: &Option<Vector2<f32>>) -> Color {
+        self.specular_transmittance.color(uv)
+    }
+
+    fn is_smooth(&self) -> bool {
+        true
+    }
+    fn is_twosided(&self) -> bool {
+        // The side is used to know if we are inside or outside
+        false
+    }
+}
@@ -13,6 +13,7 @@ impl BSDF for BSDFDiffuse {
         uv: &Option<Vector2<f32>>,
         d_in: &Vector3<f32>,
         sample: Point2<f32>,
+        _transport: Transport,
     ) -> Option<SampledDirection> {
         if d_in.z <= 0.0 {
             None
@@ -51,6 +52,7 @@ impl BSDF for BSDFDiffuse {
         d_in: &Vector3<f32>,
         d_out: &Vector3<f32>,
         domain: Domain,
+        _transport: Transport,
     ) -> Color {
         assert!(domain == Domain::SolidAngle);
 
@@ -68,6 +70,10 @@ impl BSDF for BSDFDiffuse {
         std::f32::INFINITY
     }
 
+    fn albedo(&self, uv: &Option<Vector2<f32>>) -> Color {
+        self.diffuse.color(uv)
+    }
+
     fn is_smooth(&self) -> bool {
         false
     }
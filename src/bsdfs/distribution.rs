@@ -0,0 +1,367 @@
+use crate::math::{erf, erf_inv};
+use cgmath::{InnerSpace, Point2, Vector3};
+use std;
+use std::f32::consts::PI;
+
+// Trigonometric helpers (local space)
+fn cos_theta(w: &Vector3<f32>) -> f32 {
+    w.z
+}
+fn cos2_theta(w: &Vector3<f32>) -> f32 {
+    w.z * w.z
+}
+fn sin2_theta(w: &Vector3<f32>) -> f32 {
+    (1.0 - cos2_theta(w)).max(0.0)
+}
+fn sin_theta(w: &Vector3<f32>) -> f32 {
+    sin2_theta(w).sqrt()
+}
+fn tan_theta(w: &Vector3<f32>) -> f32 {
+    sin_theta(w) / cos_theta(w)
+}
+fn tan2_theta(w: &Vector3<f32>) -> f32 {
+    sin2_theta(w) / cos2_theta(w)
+}
+fn cos_phi(w: &Vector3<f32>) -> f32 {
+    let sin_theta = sin_theta(w);
+    if sin_theta == 0.0 {
+        1.0
+    } else {
+        (w.x / sin_theta).max(-1.0).min(1.0)
+    }
+}
+fn sin_phi(w: &Vector3<f32>) -> f32 {
+    let sin_theta = sin_theta(w);
+    if sin_theta == 0.0 {
+        0.0
+    } else {
+        (w.y / sin_theta).max(-1.0).min(1.0)
+    }
+}
+fn spherical_direction(sin_theta: f32, cos_theta: f32, phi: f32) -> Vector3<f32> {
+    Vector3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
+}
+
+// Azimuthal angle for sampling anisotropic distributions
+fn sample_phi(alpha_x: f32, alpha_y: f32, u: f32) -> f32 {
+    let mut phi = (alpha_y / alpha_x * (2.0 * PI * u + 0.5 * PI).tan()).atan();
+    if u > 0.5 {
+        phi += PI;
+    }
+    phi
+}
+
+/// Microfacet distribution (all the directions are in the local space)
+/// Code adapted from PBRT-v3
+pub trait MicrofacetDistribution: Send + Sync {
+    /// Distribution of the microfacet normals
+    fn d(&self, wh: &Vector3<f32>) -> f32;
+    /// Smith's auxiliary function
+    fn lambda(&self, w: &Vector3<f32>) -> f32;
+    /// Sample a microfacet normal (visible from wo if enabled)
+    fn sample_wh(&self, wo: &Vector3<f32>, u: Point2<f32>) -> Vector3<f32>;
+    /// Roughness (alpha_x, alpha_y)
+    fn alpha(&self) -> (f32, f32);
+    /// Use the distribution of visible normals
+    fn sample_visible_area(&self) -> bool;
+
+    /// Masking function
+    fn g1(&self, w: &Vector3<f32>) -> f32 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+    /// Masking-shadowing function
+    fn g(&self, wo: &Vector3<f32>, wi: &Vector3<f32>) -> f32 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+    /// PDF of sample_wh (with respect to wh)
+    fn pdf(&self, wo: &Vector3<f32>, wh: &Vector3<f32>) -> f32 {
+        if self.sample_visible_area() {
+            self.d(wh) * self.g1(wo) * wo.dot(*wh).abs() / cos_theta(wo).abs()
+        } else {
+            self.d(wh) * cos_theta(wh).abs()
+        }
+    }
+}
+
+/// Convert the artist friendly roughness to alpha
+pub fn roughness_to_alpha(roughness: f32) -> f32 {
+    let x = roughness.max(1e-3).ln();
+    1.62142 + 0.819955 * x + 0.1734 * x * x + 0.0171201 * x * x * x + 0.000640711 * x * x * x * x
+}
+
+/// Beckmann-Spizzichino distribution
+pub struct BeckmannDistribution {
+    pub alpha_x: f32,
+    pub alpha_y: f32,
+    pub sample_visible: bool,
+}
+
+impl BeckmannDistribution {
+    pub fn new(alpha_x: f32, alpha_y: f32, sample_visible: bool) -> Self {
+        BeckmannDistribution {
+            alpha_x: alpha_x.max(0.001),
+            alpha_y: alpha_y.max(0.001),
+            sample_visible,
+        }
+    }
+}
+
+// Sample the slopes for the stretched configuration (alpha = 1)
+fn beckmann_sample11(cos_theta_i: f32, u1: f32, u2: f32) -> (f32, f32) {
+    // Special case (normal incidence)
+    if cos_theta_i > 0.9999 {
+        let r = (-(1.0 - u1).ln()).sqrt();
+        let phi = 2.0 * PI * u2;
+        return (r * phi.cos(), r * phi.sin());
+    }
+
+    // The CDF is inverted with Newton-Bisection
+    let sin_theta_i = (1.0 - cos_theta_i * cos_theta_i).max(0.0).sqrt();
+    let tan_theta_i = sin_theta_i / cos_theta_i;
+    let cot_theta_i = 1.0 / tan_theta_i;
+
+    let mut a = -1.0;
+    let mut c = erf(cot_theta_i);
+    let sample_x = u1.max(1e-6);
+
+    // Start with a good initial guess
+    let theta_i = cos_theta_i.acos();
+    let fit = 1.0 + theta_i * (-0.876 + theta_i * (0.4265 - 0.0594 * theta_i));
+    let mut b = c - (1.0 + c) * (1.0 - sample_x).powf(fit);
+
+    let sqrt_pi_inv = 1.0 / PI.sqrt();
+    let normalization =
+        1.0 / (1.0 + c + sqrt_pi_inv * tan_theta_i * (-cot_theta_i * cot_theta_i).exp());
+
+    for _ in 0..10 {
+        // Bisection criterion
+        if !(b >= a && b <= c) {
+            b = 0.5 * (a + c);
+        }
+
+        let inv_erf = erf_inv(b);
+        let value = normalization
+            * (1.0 + b + sqrt_pi_inv * tan_theta_i * (-inv_erf * inv_erf).exp())
+            - sample_x;
+        let derivative = normalization * (1.0 - inv_erf * tan_theta_i);
+        if value.abs() < 1e-5 {
+            break;
+        }
+
+        // Update bisection intervals
+        if value > 0.0 {
+            c = b;
+        } else {
+            a = b;
+        }
+        b -= value / derivative;
+    }
+
+    (erf_inv(b), erf_inv(2.0 * u2.max(1e-6) - 1.0))
+}
+
+impl MicrofacetDistribution for BeckmannDistribution {
+    fn d(&self, wh: &Vector3<f32>) -> f32 {
+        let tan2_theta = tan2_theta(wh);
+        if tan2_theta.is_infinite() {
+            return 0.0;
+        }
+        let cos4_theta = cos2_theta(wh) * cos2_theta(wh);
+        let (cos_phi, sin_phi) = (cos_phi(wh), sin_phi(wh));
+        (-tan2_theta
+            * (cos_phi * cos_phi / (self.alpha_x * self.alpha_x)
+                + sin_phi * sin_phi / (self.alpha_y * self.alpha_y)))
+            .exp()
+            / (PI * self.alpha_x * self.alpha_y * cos4_theta)
+    }
+
+    fn lambda(&self, w: &Vector3<f32>) -> f32 {
+        let abs_tan_theta = tan_theta(w).abs();
+        if abs_tan_theta.is_infinite() {
+            return 0.0;
+        }
+        // Compute alpha for the direction w
+        let (cos_phi, sin_phi) = (cos_phi(w), sin_phi(w));
+        let alpha = (cos_phi * cos_phi * self.alpha_x * self.alpha_x
+            + sin_phi * sin_phi * self.alpha_y * self.alpha_y)
+            .sqrt();
+        let a = 1.0 / (alpha * abs_tan_theta);
+        if a >= 1.6 {
+            0.0
+        } else {
+            (1.0 - 1.259 * a + 0.396 * a * a) / (3.535 * a + 2.181 * a * a)
+        }
+    }
+
+    fn sample_wh(&self, wo: &Vector3<f32>, u: Point2<f32>) -> Vector3<f32> {
+        if !self.sample_visible {
+            // Sample the full distribution of normals
+            let log_sample = {
+                let v = (1.0 - u.x).ln();
+                if v.is_infinite() {
+                    0.0
+                } else {
+                    v
+                }
+            };
+            let (tan2_theta, phi) = if self.alpha_x == self.alpha_y {
+                (-self.alpha_x * self.alpha_x * log_sample, u.y * 2.0 * PI)
+            } else {
+                let phi = sample_phi(self.alpha_x, self.alpha_y, u.y);
+                let (sin_phi, cos_phi) = phi.sin_cos();
+                let tan2_theta = -log_sample
+                    / (cos_phi * cos_phi / (self.alpha_x * self.alpha_x)
+                        + sin_phi * sin_phi / (self.alpha_y * self.alpha_y));
+                (tan2_theta, phi)
+            };
+            let cos_theta = 1.0 / (1.0 + tan2_theta).sqrt();
+            let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+            let wh = spherical_direction(sin_theta, cos_theta, phi);
+            if wo.z * wh.z > 0.0 {
+                wh
+            } else {
+                -wh
+            }
+        } else {
+            // Sample the visible normals
+            let flip = wo.z < 0.0;
+            let wo = if flip { -*wo } else { *wo };
+            // 1. Stretch wo
+            let wo_stretched =
+                Vector3::new(self.alpha_x * wo.x, self.alpha_y * wo.y, wo.z).normalize();
+            // 2. Sample P22 (alpha = 1)
+            let (slope_x, slope_y) = beckmann_sample11(cos_theta(&wo_stretched), u.x, u.y);
+            // 3. Rotate
+            let (cos_phi, sin_phi) = (cos_phi(&wo_stretched), sin_phi(&wo_stretched));
+            let (slope_x, slope_y) = (
+                cos_phi * slope_x - sin_phi * slope_y,
+                sin_phi * slope_x + cos_phi * slope_y,
+            );
+            // 4. Unstretch and compute the normal
+            let wh =
+                Vector3::new(-self.alpha_x * slope_x, -self.alpha_y * slope_y, 1.0).normalize();
+            if flip {
+                -wh
+            } else {
+                wh
+            }
+        }
+    }
+
+    fn alpha(&self) -> (f32, f32) {
+        (self.alpha_x, self.alpha_y)
+    }
+    fn sample_visible_area(&self) -> bool {
+        self.sample_visible
+    }
+}
+
+/// Trowbridge-Reitz (GGX) distribution
+pub struct TrowbridgeReitzDistribution {
+    pub alpha_x: f32,
+    pub alpha_y: f32,
+    pub sample_visible: bool,
+}
+
+impl TrowbridgeReitzDistribution {
+    pub fn new(alpha_x: f32, alpha_y: f32, sample_visible: bool) -> Self {
+        TrowbridgeReitzDistribution {
+            alpha_x: alpha_x.max(0.001),
+            alpha_y: alpha_y.max(0.001),
+            sample_visible,
+        }
+    }
+}
+
+impl MicrofacetDistribution for TrowbridgeReitzDistribution {
+    fn d(&self, wh: &Vector3<f32>) -> f32 {
+        let tan2_theta = tan2_theta(wh);
+        if tan2_theta.is_infinite() {
+            return 0.0;
+        }
+        let cos4_theta = cos2_theta(wh) * cos2_theta(wh);
+        let (cos_phi, sin_phi) = (cos_phi(wh), sin_phi(wh));
+        let e = (cos_phi * cos_phi / (self.alpha_x * self.alpha_x)
+            + sin_phi * sin_phi / (self.alpha_y * self.alpha_y))
+            * tan2_theta;
+        1.0 / (PI * self.alpha_x * self.alpha_y * cos4_theta * (1.0 + e) * (1.0 + e))
+    }
+
+    fn lambda(&self, w: &Vector3<f32>) -> f32 {
+        let abs_tan_theta = tan_theta(w).abs();
+        if abs_tan_theta.is_infinite() {
+            return 0.0;
+        }
+        // Compute alpha for the direction w
+        let (cos_phi, sin_phi) = (cos_phi(w), sin_phi(w));
+        let alpha = (cos_phi * cos_phi * self.alpha_x * self.alpha_x
+            + sin_phi * sin_phi * self.alpha_y * self.alpha_y)
+            .sqrt();
+        let alpha2_tan2_theta = (alpha * abs_tan_theta) * (alpha * abs_tan_theta);
+        (-1.0 + (1.0 + alpha2_tan2_theta).sqrt()) / 2.0
+    }
+
+    fn sample_wh(&self, wo: &Vector3<f32>, u: Point2<f32>) -> Vector3<f32> {
+        if !self.sample_visible {
+            // Sample the full distribution of normals
+            let (tan2_theta, phi) = if self.alpha_x == self.alpha_y {
+                (
+                    self.alpha_x * self.alpha_x * u.x / (1.0 - u.x),
+                    2.0 * PI * u.y,
+                )
+            } else {
+                let phi = sample_phi(self.alpha_x, self.alpha_y, u.y);
+                let (sin_phi, cos_phi) = phi.sin_cos();
+                let alpha2 = 1.0
+                    / (cos_phi * cos_phi / (self.alpha_x * self.alpha_x)
+                        + sin_phi * sin_phi / (self.alpha_y * self.alpha_y));
+                (alpha2 * u.x / (1.0 - u.x), phi)
+            };
+            let cos_theta = 1.0 / (1.0 + tan2_theta).sqrt();
+            let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+            let wh = spherical_direction(sin_theta, cos_theta, phi);
+            if wo.z * wh.z > 0.0 {
+                wh
+            } else {
+                -wh
+            }
+        } else {
+            // Sample the visible normals
+            // See "Sampling the GGX Distribution of Visible Normals" (Heitz 2018)
+            let flip = wo.z < 0.0;
+            let wo = if flip { -*wo } else { *wo };
+            // Stretch wo
+            let v_h = Vector3::new(self.alpha_x * wo.x, self.alpha_y * wo.y, wo.z).normalize();
+            // Orthonormal basis
+            let len_sqr = v_h.x * v_h.x + v_h.y * v_h.y;
+            let t1 = if len_sqr > 0.0 {
+                Vector3::new(-v_h.y, v_h.x, 0.0) / len_sqr.sqrt()
+            } else {
+                Vector3::new(1.0, 0.0, 0.0)
+            };
+            let t2 = v_h.cross(t1);
+            // Sample the projected area
+            let r = u.x.sqrt();
+            let phi = 2.0 * PI * u.y;
+            let p1 = r * phi.cos();
+            let s = 0.5 * (1.0 + v_h.z);
+            let p2 = (1.0 - s) * (1.0 - p1 * p1).max(0.0).sqrt() + s * r * phi.sin();
+            // Reproject onto the hemisphere and unstretch
+            let n_h = t1 * p1 + t2 * p2 + v_h * (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt();
+            let wh = Vector3::new(self.alpha_x * n_h.x, self.alpha_y * n_h.y, n_h.z.max(1e-6))
+                .normalize();
+            if flip {
+                -wh
+            } else {
+                wh
+            }
+        }
+    }
+
+    fn alpha(&self) -> (f32, f32) {
+        (self.alpha_x, self.alpha_y)
+    }
+    fn sample_visible_area(&self) -> bool {
+        self.sample_visible
+    }
+}
@@ -0,0 +1,98 @@
+use crate::bsdfs::distribution::*;
+use crate::bsdfs::*;
+
+/// Rough conductor (microfacet reflection with complex IOR)
+pub struct BSDFMetal {
+    pub specular: BSDFColor,
+    /// Real part of the IOR
+    pub eta: BSDFColor,
+    /// Imaginary part of the IOR (absorption)
+    pub k: BSDFColor,
+    pub distribution: Box<dyn MicrofacetDistribution>,
+}
+
+impl BSDF for BSDFMetal {
+    fn sample(
+        &self,
+        uv: &Option<Vector2<f32>>,
+        d_in: &Vector3<f32>,
+        sample: Point2<f32>,
+        transport: Transport,
+    ) -> Option<SampledDirection> {
+        if d_in.z <= 0.0 {
+            return None;
+        }
+        let wh = self.distribution.sample_wh(d_in, sample);
+        if d_in.dot(wh) <= 0.0 {
+            return None;
+        }
+        let d_out = reflect_vector(*d_in, wh);
+        if d_out.z <= 0.0 {
+            return None;
+        }
+
+        let pdf = self.pdf(uv, d_in, &d_out, Domain::SolidAngle);
+        if pdf.is_zero() {
+            None
+        } else {
+            Some(SampledDirection {
+                weight: self.eval(uv, d_in, &d_out, Domain::SolidAngle, transport) / pdf.value(),
+                d: d_out,
+                pdf,
+            })
+        }
+    }
+
+    fn pdf(
+        &self,
+        _uv: &Option<Vector2<f32>>,
+        d_in: &Vector3<f32>,
+        d_out: &Vector3<f32>,
+        domain: Domain,
+    ) -> PDF {
+        assert!(domain == Domain::SolidAngle);
+
+        if d_in.z <= 0.0 || d_out.z <= 0.0 {
+            return PDF::SolidAngle(0.0);
+        }
+        let wh = (d_in + d_out).normalize();
+        PDF::SolidAngle(self.distribution.pdf(d_in, &wh) / (4.0 * d_in.dot(wh)))
+    }
+
+    fn eval(
+        &self,
+        uv: &Option<Vector2<f32>>,
+        d_in: &Vector3<f32>,
+        d_out: &Vector3<f32>,
+        domain: Domain,
+        _transport: Transport,
+    ) -> Color {
+        assert!(domain == Domain::SolidAngle);
+
+        if d_in.z <= 0.0 || d_out.z <= 0.0 {
+            return Color::zero();
+        }
+        let wh = (d_in + d_out).normalize();
+        let f = fresnel_conductor(d_out.dot(wh), self.eta.color(uv), self.k.color(uv));
+        // The cosine of the outgoing direction cancel out
+        self.specular.color(uv)
+            * f
+            * (self.distribution.d(&wh) * self.distribution.g(d_in, d_out) / (4.0 * d_in.z))
+    }
+
+    fn roughness(&self, _uv: &Option<Vector2<f32>>) -> f32 {
+        let (alpha_x, alpha_y) = self.distribution.alpha();
+        0.5 * (alpha_x + alpha_y)
+    }
+
+    fn albedo(&self, uv: &Option<Vector2<f32>>) -> Color {
+        self.specular.color(uv)
+    }
+
+    fn is_smooth(&self) -> bool {
+        false
+    }
+    fn is_twosided(&self) -> bool {
+        true
+    }
+}
@@ -1,6 +1,5 @@
 use crate::structure::*;
 use serde::{Deserialize, Deserializer};
-use serde_json;
 
 use cgmath::{InnerSpace, Point2, Vector2, Vector3};
 #[cfg(feature = "pbrt")]
@@ -30,10 +29,10 @@ pub struct Texture {
 }
 
 impl Texture {
-    pub fn load(path: &str) -> Texture {
-        Texture {
-            img: Bitmap::read(path),
-        }
+    pub fn load(path: &std::path::Path) -> std::io::Result<Texture> {
+        Ok(Texture {
+            img: Bitmap::try_read(path)?,
+        })
     }
     // Access to the texture
     pub fn pixel(&self, uv: Vector2<f32>) -> Color {
@@ -76,6 +75,66 @@ impl BSDFColor {
 fn reflect(d: &Vector3<f32>) -> Vector3<f32> {
     Vector3::new(-d.x, -d.y, d.z)
 }
+/// Refracted direction (local space)
+/// @eta_ti: relative IOR between the incoming and the outgoing side
+/// @cos_theta_t: signed cosine computed by fresnel_dielectric
+fn refract(d: &Vector3<f32>, eta_ti: f32, cos_theta_t: f32) -> Vector3<f32> {
+    Vector3::new(-eta_ti * d.x, -eta_ti * d.y, cos_theta_t)
+}
+
+/// Unpolarized Fresnel reflectance of a dielectric interface
+/// @cos_theta_i: cosine of the incident direction (negative if inside)
+/// @eta: relative IOR (interior / exterior)
+/// @return: (reflectance, signed cosine of the transmitted direction)
+pub fn fresnel_dielectric(cos_theta_i: f32, eta: f32) -> (f32, f32) {
+    if eta == 1.0 {
+        return (0.0, -cos_theta_i);
+    }
+
+    let scale = if cos_theta_i > 0.0 { 1.0 / eta } else { eta };
+    let cos_theta_t_sqr = 1.0 - (1.0 - cos_theta_i * cos_theta_i) * (scale * scale);
+    if cos_theta_t_sqr <= 0.0 {
+        // Total internal reflection
+        return (1.0, 0.0);
+    }
+
+    let cos_theta_i_abs = cos_theta_i.abs();
+    let cos_theta_t = cos_theta_t_sqr.sqrt();
+    let r_s = (cos_theta_i_abs - eta * cos_theta_t) / (cos_theta_i_abs + eta * cos_theta_t);
+    let r_p = (eta * cos_theta_i_abs - cos_theta_t) / (eta * cos_theta_i_abs + cos_theta_t);
+    let cos_theta_t = if cos_theta_i > 0.0 {
+        -cos_theta_t
+    } else {
+        cos_theta_t
+    };
+    (0.5 * (r_s * r_s + r_p * r_p), cos_theta_t)
+}
+
+/// Fresnel reflectance of a conductor (complex IOR: eta + i k)
+pub fn fresnel_conductor(cos_theta_i: f32, eta: Color, k: Color) -> Color {
+    let cos_theta_i = cos_theta_i.max(-1.0).min(1.0);
+    let cos2_theta_i = cos_theta_i * cos_theta_i;
+    let sin2_theta_i = 1.0 - cos2_theta_i;
+    let fresnel = |eta: f32, k: f32| {
+        let eta2 = eta * eta;
+        let k2 = k * k;
+        let t0 = eta2 - k2 - sin2_theta_i;
+        let a2_plus_b2 = (t0 * t0 + 4.0 * eta2 * k2).sqrt();
+        let t1 = a2_plus_b2 + cos2_theta_i;
+        let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+        let t2 = 2.0 * cos_theta_i * a;
+        let r_s = (t1 - t2) / (t1 + t2);
+        let t3 = cos2_theta_i * a2_plus_b2 + sin2_theta_i * sin2_theta_i;
+        let t4 = t2 * sin2_theta_i;
+        let r_p = r_s * (t3 - t4) / (t3 + t4);
+        0.5 * (r_p + r_s)
+    };
+    Color::new(
+        fresnel(eta.r, k.r),
+        fresnel(eta.g, k.g),
+        fresnel(eta.b, k.b),
+    )
+}
 
 /// Struct that represent a sampled direction
 #[derive(Clone)]
@@ -85,16 +144,28 @@ pub struct SampledDirection {
     pub pdf: PDF,
 }
 
+/// Quantity carried by the path
+/// (the BSDF is not symmetric when refracting)
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Transport {
+    /// Path traced from the sensor
+    Radiance,
+    /// Path traced from the emitters (light tracing, photons)
+    Importance,
+}
+
 pub trait BSDF: Send + Sync {
     /// sample an random direction based on the BSDF value
     /// @d_in: the incomming direction in the local space
     /// @sample: random number 2D
+    /// @transport: radiance or importance (adjoint BSDF)
     /// @return: the outgoing direction, the pdf and the bsdf value $fs(...) * | n . d_out |$
     fn sample(
         &self,
         uv: &Option<Vector2<f32>>,
         d_in: &Vector3<f32>,
         sample: Point2<f32>,
+        transport: Transport,
     ) -> Option<SampledDirection>;
     /// eval the bsdf pdf value in solid angle
     fn pdf(
@@ -111,38 +182,39 @@ pub trait BSDF: Send + Sync {
         d_in: &Vector3<f32>,
         d_out: &Vector3<f32>,
         domain: Domain,
+        transport: Transport,
     ) -> Color;
     /// return the roughness of the material
     fn roughness(&self, uv: &Option<Vector2<f32>>) -> f32;
+    /// return the reflectance of the material (used for the albedo AOV)
+    fn albedo(&self, uv: &Option<Vector2<f32>>) -> Color;
     /// check if it is smooth
     //TODO: Replace this using flags
     fn is_smooth(&self) -> bool;
     /// Used to automatically flip the normal vector
     fn is_twosided(&self) -> bool;
+    /// Surface only used as a participating media boundary
+    fn is_null(&self) -> bool {
+        false
+    }
 }
 
 pub mod blend;
+pub mod dielectric;
 pub mod diffuse;
+pub mod distribution;
+pub mod metal;
+pub mod null;
 pub mod phong;
 pub mod specular;
+pub mod substrate;
 
+use crate::bsdfs::dielectric::BSDFDielectric;
 use crate::bsdfs::diffuse::BSDFDiffuse;
-use crate::bsdfs::phong::BSDFPhong;
+use crate::bsdfs::distribution::*;
+use crate::bsdfs::metal::BSDFMetal;
 use crate::bsdfs::specular::BSDFSpecular;
-
-/// Dispatch coded BSDF
-pub fn parse_bsdf(
-    b: &serde_json::Value,
-) -> Result<Box<dyn BSDF + Send + Sync>, Box<dyn std::error::Error>> {
-    let new_bsdf_type: String = serde_json::from_value(b["type"].clone())?;
-    let new_bsdf: Box<dyn BSDF + Send + Sync> = match new_bsdf_type.as_ref() {
-        "phong" => Box::<BSDFPhong>::new(serde_json::from_value(b["data"].clone())?),
-        "diffuse" => Box::<BSDFDiffuse>::new(serde_json::from_value(b["data"].clone())?),
-        "specular" => Box::<BSDFSpecular>::new(serde_json::from_value(b["data"].clone())?),
-        _ => panic!("Unknown BSDF type {}", new_bsdf_type),
-    };
-    Ok(new_bsdf)
-}
+use crate::bsdfs::substrate::BSDFSubstrate;
 
 #[cfg(feature = "pbrt")]
 fn bsdf_texture_match(v: &pbrt_rs::Param, scene_info: &pbrt_rs::Scene) -> Option<BSDFColor> {
@@ -159,7 +231,13 @@ fn bsdf_texture_match(v: &pbrt_rs::Param, scene_info: &pbrt_rs::Scene) -> Option
         }
         pbrt_rs::Param::Name(ref name) => {
             if let Some(texture) = scene_info.textures.get(name) {
-                Some(BSDFColor::TextureColor(Texture::load(&texture.filename)))
+                match Texture::load(std::path::Path::new(&texture.filename)) {
+                    Ok(texture) => Some(BSDFColor::TextureColor(texture)),
+                    Err(e) => {
+                        warn!("Impossible to load the texture {}: {}", texture.filename, e);
+                        None
+                    }
+                }
             } else {
                 warn!("Impossible to found an texture with name: {}", name);
                 None
@@ -169,6 +247,14 @@ fn bsdf_texture_match(v: &pbrt_rs::Param, scene_info: &pbrt_rs::Scene) -> Option
     }
 }
 
+#[cfg(feature = "pbrt")]
+fn bsdf_float_match(v: &pbrt_rs::Param) -> Option<f32> {
+    match v {
+        pbrt_rs::Param::Float(ref v) if v.len() == 1 => Some(v[0]),
+        _ => None,
+    }
+}
+
 // Debug macro for color
 // macro_rules! default_color {
 //     ($texture: expr, $default:expr) => {{
@@ -192,8 +278,8 @@ pub fn bsdf_pbrt(bsdf: &pbrt_rs::BSDF, scene_info: &pbrt_rs::Scene) -> Box<dyn B
             }
         }
         pbrt_rs::BSDF::Metal(ref v) => {
-            let _eta = bsdf_texture_match(&v.eta, scene_info).unwrap();
-            let _k = bsdf_texture_match(&v.k, scene_info).unwrap();
+            let eta = bsdf_texture_match(&v.eta, scene_info).unwrap();
+            let k = bsdf_texture_match(&v.k, scene_info).unwrap();
             let (u_roughness, v_roughness) = if let (Some(ref u_rough), Some(ref v_rough)) =
                 (v.u_roughness.as_ref(), v.v_roughness.as_ref())
             {
@@ -208,48 +294,59 @@ pub fn bsdf_pbrt(bsdf: &pbrt_rs::BSDF, scene_info: &pbrt_rs::Scene) -> Box<dyn B
                 )
             };
             // FIXME: be able to load float textures?
+            // FIXME: remap only if "remaproughness" is true (default in PBRT)
             let (u_roughness, v_roughness) =
                 (u_roughness.color(&None).r, v_roughness.color(&None).r);
-            assert!(u_roughness != 0.0);
-            assert!(v_roughness != 0.0);
-            // FIXME: remap
-            // Some(Box::new(BSDFMetal {
-            //     r: BSDFColor::UniformColor(Color::value(1.0)),
-            //     distribution: TrowbridgeReitzDistribution::new(u_roughness, v_roughness, true),
-            //     k,
-            //     eta_i: BSDFColor::UniformColor(Color::one()),
-            //     eta_t: eta,
-            // }))
-            unimplemented!();
+            Some(Box::new(BSDFMetal {
+                specular: BSDFColor::UniformColor(Color::value(1.0)),
+                eta,
+                k,
+                distribution: Box::new(TrowbridgeReitzDistribution::new(
+                    roughness_to_alpha(u_roughness),
+                    roughness_to_alpha(v_roughness),
+                    true,
+                )),
+            }))
         }
         pbrt_rs::BSDF::Mirror(ref v) => {
             let specular = bsdf_texture_match(&v.kr, scene_info).unwrap();
             Some(Box::new(BSDFSpecular { specular }))
         }
+        pbrt_rs::BSDF::Glass(ref v) => {
+            let specular_reflectance = bsdf_texture_match(&v.kr, scene_info).unwrap();
+            let specular_transmittance = bsdf_texture_match(&v.kt, scene_info).unwrap();
+            let int_ior = bsdf_float_match(&v.eta).unwrap_or_else(|| {
+                warn!("Glass eta needs to be a float (got {:?}), use the default", v.eta);
+                dielectric::DEFAULT_INT_IOR
+            });
+            if v.u_roughness.is_some() || v.v_roughness.is_some() {
+                warn!("Rough glass is not supported, use smooth dielectric instead");
+            }
+            Some(Box::new(BSDFDielectric {
+                specular_reflectance,
+                specular_transmittance,
+                int_ior,
+                ext_ior: dielectric::DEFAULT_EXT_IOR,
+            }))
+        }
         pbrt_rs::BSDF::Substrate(ref v) => {
-            let _kd = bsdf_texture_match(&v.kd, scene_info).unwrap();
-            let _ks = bsdf_texture_match(&v.ks, scene_info).unwrap();
+            let diffuse = bsdf_texture_match(&v.kd, scene_info).unwrap();
+            let specular = bsdf_texture_match(&v.ks, scene_info).unwrap();
             let u_roughness = bsdf_texture_match(&v.u_roughness, scene_info).unwrap();
             let v_roughness = bsdf_texture_match(&v.v_roughness, scene_info).unwrap();
             // FIXME: be able to load float textures?
+            // FIXME: remap only if "remaproughness" is true (default in PBRT)
             let (u_roughness, v_roughness) =
                 (u_roughness.color(&None).r, v_roughness.color(&None).r);
-            assert!(u_roughness != 0.0);
-            assert!(v_roughness != 0.0);
-
-            // let metal = Box::new(BSDFMetal {
-            //     r: BSDFColor::UniformColor(Color::value(1.0)),
-            //     distribution: TrowbridgeReitzDistribution::new(u_roughness, v_roughness, true),
-            //     k: ks,
-            //     eta_i: BSDFColor::UniformColor(Color::one()),
-            //     eta_t: BSDFColor::UniformColor(Color::one()),
-            // });
-            // let diffuse = Box::new(BSDFDiffuse { diffuse: kd });
-            // Some(Box::new(BSDFBlend {
-            //     bsdf1: metal,
-            //     bsdf2: diffuse,
-            // }))
-            unimplemented!();
+            Some(Box::new(BSDFSubstrate {
+                diffuse,
+                specular,
+                distribution: Box::new(TrowbridgeReitzDistribution::new(
+                    roughness_to_alpha(u_roughness),
+                    roughness_to_alpha(v_roughness),
+                    true,
+                )),
+            }))
         }
         _ => None,
     };
@@ -0,0 +1,75 @@
+use crate::bsdfs::*;
+
+/// Invisible surface (index matched)
+/// used to delimit participating media
+pub struct BSDFNull {}
+
+// Check that d_out is the continuation of d_in
+fn check_null_condition(d_in: &Vector3<f32>, d_out: &Vector3<f32>) -> bool {
+    (d_in.dot(*d_out) + 1.0).abs() < 0.0001
+}
+
+impl BSDF for BSDFNull {
+    fn sample(
+        &self,
+        _uv: &Option<Vector2<f32>>,
+        d_in: &Vector3<f32>,
+        _sample: Point2<f32>,
+        _transport: Transport,
+    ) -> Option<SampledDirection> {
+        Some(SampledDirection {
+            weight: Color::one(),
+            d: -*d_in,
+            pdf: PDF::Discrete(1.0),
+        })
+    }
+
+    fn pdf(
+        &self,
+        _uv: &Option<Vector2<f32>>,
+        d_in: &Vector3<f32>,
+        d_out: &Vector3<f32>,
+        domain: Domain,
+    ) -> PDF {
+        assert!(domain == Domain::Discrete);
+        if check_null_condition(d_in, d_out) {
+            PDF::Discrete(1.0)
+        } else {
+            PDF::Discrete(0.0)
+        }
+    }
+
+    fn eval(
+        &self,
+        _uv: &Option<Vector2<f32>>,
+        d_in: &Vector3<f32>,
+        d_out: &Vector3<f32>,
+        domain: Domain,
+        _transport: Transport,
+    ) -> Color {
+        assert!(domain == Domain::Discrete);
+        if check_null_condition(d_in, d_out) {
+            Color::one()
+        } else {
+            Color::zero()
+        }
+    }
+
+    fn roughness(&self, _uv: &Option<Vector2<f32>>) -> f32 {
+        0.0
+    }
+
+    fn albedo(&self, _uv: &Option<Vector2<f32>>) -> Color {
+        Color::zero()
+    }
+
+    fn is_smooth(&self) -> bool {
+        true
+    }
+    fn is_twosided(&self) -> bool {
+        false
+    }
+    fn is_null(&self) -> bool {
+        true
+    }
+}
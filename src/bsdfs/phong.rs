@@ -15,6 +15,7 @@ impl BSDF for BSDFPhong {
         uv: &Option<Vector2<f32>>,
         d_in: &Vector3<f32>,
         sample: Point2<f32>,
+        transport: Transport,
     ) -> Option<SampledDirection> {
         let sin_alpha = (1.0 - sample.y.powf(2.0 / (self.exponent + 1.0))).sqrt();
         let cos_alpha = sample.y.powf(1.0 / (self.exponent + 1.0));
@@ -31,7 +32,8 @@ impl BSDF for BSDFPhong {
                 None
             } else {
                 Some(SampledDirection {
-                    weight: self.eval(uv, d_in, &d_out, Domain::SolidAngle) / pdf.value(),
+                    weight: self.eval(uv, d_in, &d_out, Domain::SolidAngle, transport)
+                        / pdf.value(),
                     d: d_out,
                     pdf,
                 })
@@ -72,6 +74,7 @@ impl BSDF for BSDFPhong {
         d_in: &Vector3<f32>,
         d_out: &Vector3<f32>,
         domain: Domain,
+        _transport: Transport,
     ) -> Color {
         assert!(domain == Domain::SolidAngle);
 
@@ -96,6 +99,10 @@ impl BSDF for BSDFPhong {
         (2.0 / (2.0 + self.exponent)).sqrt()
     }
 
+    fn albedo(&self, uv: &Option<Vector2<f32>>) -> Color {
+        self.specular.color(uv)
+    }
+
     fn is_smooth(&self) -> bool {
         false
     }
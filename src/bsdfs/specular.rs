@@ -11,6 +11,7 @@ impl BSDF for BSDFSpecular {
         uv: &Option<Vector2<f32>>,
         d_in: &Vector3<f32>,
         _: Point2<f32>,
+        _transport: Transport,
     ) -> Option<SampledDirection> {
         if d_in.z <= 0.0 {
             None
@@ -45,6 +46,7 @@ impl BSDF for BSDFSpecular {
         wi: &Vector3<f32>,
         wo: &Vector3<f32>,
         domain: Domain,
+        _transport: Transport,
     ) -> Color {
         assert!(domain == Domain::Discrete);
         if check_reflection_condition(wi, wo) {
@@ -59,6 +61,10 @@ impl BSDF for BSDFSpecular {
         0.0
     }
 
+    fn albedo(&self, uv: &Option<Vector2<f32>>) -> Color {
+        self.specular.color(uv)
+    }
+
     fn is_smooth(&self) -> bool {
         true
     }
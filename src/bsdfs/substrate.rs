@@ -0,0 +1,123 @@
+use crate::bsdfs::distribution::*;
+use crate::bsdfs::*;
+use crate::math::cosine_sample_hemisphere;
+use std;
+
+/// Rough plastic (Ashikhmin-Shirley model, substrate in PBRT)
+/// a diffuse base under a glossy coating
+pub struct BSDFSubstrate {
+    pub diffuse: BSDFColor,
+    pub specular: BSDFColor,
+    pub distribution: Box<dyn MicrofacetDistribution>,
+}
+
+fn pow5(v: f32) -> f32 {
+    (v * v) * (v * v) * v
+}
+
+impl BSDF for BSDFSubstrate {
+    fn sample(
+        &self,
+        uv: &Option<Vector2<f32>>,
+        d_in: &Vector3<f32>,
+        sample: Point2<f32>,
+        transport: Transport,
+    ) -> Option<SampledDirection> {
+        if d_in.z <= 0.0 {
+            return None;
+        }
+
+        // Select the diffuse or the glossy lobe
+        let d_out = if sample.x < 0.5 {
+            let sample = Point2::new(sample.x * 2.0, sample.y);
+            cosine_sample_hemisphere(sample)
+        } else {
+            let sample = Point2::new((sample.x - 0.5) * 2.0, sample.y);
+            let wh = self.distribution.sample_wh(d_in, sample);
+            reflect_vector(*d_in, wh)
+        };
+        if d_out.z <= 0.0 {
+            return None;
+        }
+
+        let pdf = self.pdf(uv, d_in, &d_out, Domain::SolidAngle);
+        if pdf.is_zero() {
+            None
+        } else {
+            Some(SampledDirection {
+                weight: self.eval(uv, d_in, &d_out, Domain::SolidAngle, transport) / pdf.value(),
+                d: d_out,
+                pdf,
+            })
+        }
+    }
+
+    fn pdf(
+        &self,
+        _uv: &Option<Vector2<f32>>,
+        d_in: &Vector3<f32>,
+        d_out: &Vector3<f32>,
+        domain: Domain,
+    ) -> PDF {
+        assert!(domain == Domain::SolidAngle);
+
+        if d_in.z <= 0.0 || d_out.z <= 0.0 {
+            return PDF::SolidAngle(0.0);
+        }
+        let wh = (d_in + d_out).normalize();
+        let pdf_glossy = self.distribution.pdf(d_in, &wh) / (4.0 * d_in.dot(wh));
+        let pdf_diffuse = d_out.z * std::f32::consts::FRAC_1_PI;
+        PDF::SolidAngle(0.5 * (pdf_glossy + pdf_diffuse))
+    }
+
+    fn eval(
+        &self,
+        uv: &Option<Vector2<f32>>,
+        d_in: &Vector3<f32>,
+        d_out: &Vector3<f32>,
+        domain: Domain,
+        _transport: Transport,
+    ) -> Color {
+        assert!(domain == Domain::SolidAngle);
+
+        if d_in.z <= 0.0 || d_out.z <= 0.0 {
+            return Color::zero();
+        }
+        let r_d = self.diffuse.color(uv);
+        let r_s = self.specular.color(uv);
+
+        let diffuse = r_d
+            * (Color::one() - r_s)
+            * ((28.0 / (23.0 * std::f32::consts::PI))
+                * (1.0 - pow5(1.0 - 0.5 * d_in.z))
+                * (1.0 - pow5(1.0 - 0.5 * d_out.z)));
+
+        let wh = d_in + d_out;
+        if wh.x == 0.0 && wh.y == 0.0 && wh.z == 0.0 {
+            return Color::zero();
+        }
+        let wh = wh.normalize();
+        // Schlick approximation of the Fresnel term
+        let schlick = r_s + (Color::one() - r_s) * pow5(1.0 - d_out.dot(wh));
+        let specular =
+            schlick * (self.distribution.d(&wh) / (4.0 * d_in.dot(wh).abs() * d_in.z.max(d_out.z)));
+
+        (diffuse + specular) * d_out.z
+    }
+
+    fn roughness(&self, _uv: &Option<Vector2<f32>>) -> f32 {
+        let (alpha_x, alpha_y) = self.distribution.alpha();
+        0.5 * (alpha_x + alpha_y)
+    }
+
+    fn albedo(&self, uv: &Option<Vector2<f32>>) -> Color {
+        self.diffuse.color(uv)
+    }
+
+    fn is_smooth(&self) -> bool {
+        false
+    }
+    fn is_twosided(&self) -> bool {
+        true
+    }
+}
@@ -1,33 +1,91 @@
+use crate::math::concentric_sample_disk;
+use crate::samplers::Sampler;
 use crate::structure::{Color, Ray};
 use cgmath::*;
 use std::f32;
 
-pub struct Camera {
-    pub img: Vector2<u32>,
-    pub fov: f32, //< y
+/// Projection used by the camera
+/// All the positions and directions are inside the camera local frame
+/// (+z: view direction, +y: up) and the film positions are normalized
+/// ([0, 1]^2, (0, 0) being the top left corner of the image)
+pub trait Sensor: Send + Sync {
+    /// The ray generation and the connections need a 2D sample (e.g., on the lens)
+    fn need_sample(&self) -> bool {
+        false
+    }
+    /// Ray (origin and direction) going through the film position uv
+    fn generate(&self, uv: Point2<f32>, sample: Point2<f32>) -> (Point3<f32>, Vector3<f32>);
+    /// Direction of the ray starting from the sensor position p (see generate)
+    /// and going through the film position uv
+    fn direction(&self, p: Point3<f32>, uv: Point2<f32>) -> Vector3<f32>;
+    /// Position on the sensor and film position seeing the point p (if any)
+    fn connect(&self, p: Point3<f32>, sample: Point2<f32>) -> Option<(Point3<f32>, Point2<f32>)>;
+    /// Solid angle density of generating the direction from the sensor position p_sensor toward p
+    /// (also the importance of the direction, the film having a unit area)
+    fn pdf_direction(&self, p_sensor: Point3<f32>, p: Point3<f32>) -> f32;
+    /// Distance along the direction d to the near clipping surface
+    /// (by default a plane at the depth near_clip)
+    fn clip_distance(&self, d: Vector3<f32>, near_clip: f32) -> f32 {
+        near_clip / d.z
+    }
+}
+
+/// Image axis along which the field of view of a perspective camera is given
+/// (same convention as Mitsuba "fovAxis", pbrt uses Smaller)
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum FovAxis {
+    X,
+    #[default]
+    Y,
+    Diagonal,
+    Smaller,
+    Larger,
+}
+
+impl FovAxis {
+    /// Horizontal field of view (in degrees) of an image with the aspect ratio (width / height)
+    pub fn fov_x(self, fov: f32, aspect_ratio: f32) -> f32 {
+        let axis = match self {
+            FovAxis::Smaller if aspect_ratio > 1.0 => FovAxis::Y,
+            FovAxis::Larger if aspect_ratio < 1.0 => FovAxis::Y,
+            FovAxis::Smaller | FovAxis::Larger => FovAxis::X,
+            axis => axis,
+        };
+        let tan_half = (fov.to_radians() * 0.5).tan();
+        let tan_half_x = match axis {
+            FovAxis::Y => tan_half * aspect_ratio,
+            FovAxis::Diagonal => tan_half / (1.0 + 1.0 / (aspect_ratio * aspect_ratio)).sqrt(),
+            _ => tan_half,
+        };
+        2.0 * tan_half_x.atan().to_degrees()
+    }
+}
+
+/// Perspective projection (pinhole or thin lens)
+pub struct PerspectiveSensor {
+    /// Horizontal field of view (in degrees)
+    pub fov: f32,
+    /// Radius of the thin lens (0: pinhole camera)
+    pub lens_radius: f32,
+    /// Distance of the plane in focus
+    pub focus_distance: f32,
     // Internally
     camera_to_sample: Matrix4<f32>,
     sample_to_camera: Matrix4<f32>,
-    to_world: Matrix4<f32>,
-    to_local: Matrix4<f32>,
     // image rect
     image_rect_min: Point2<f32>,
     image_rect_max: Point2<f32>,
 }
 
-impl Camera {
-    pub fn new(img: Vector2<u32>, fov: f32, mat: Matrix4<f32>) -> Camera {
-        let to_world = mat;
-        let to_local = to_world.inverse_transform().unwrap();
-
-        // Compute camera informations
-        // fov: y
-        // TODO: Check this fov problem
+impl PerspectiveSensor {
+    /// The field of view (in degrees) is given along fov_axis
+    pub fn new(img: Vector2<u32>, fov: f32, fov_axis: FovAxis) -> PerspectiveSensor {
         let aspect_ratio = img.x as f32 / img.y as f32;
-        let fov_rad = Rad(fov * aspect_ratio * f32::consts::PI / 180.0); //2.0 * f32::tan((fov / 2.0) * f32::consts::PI / 180.0));//(fov * f32::consts::PI / 180.0);
+        let fov = fov_axis.fov_x(fov, aspect_ratio);
         let camera_to_sample = Matrix4::from_nonuniform_scale(-0.5, -0.5 * aspect_ratio, 1.0)
             * Matrix4::from_translation(Vector3::new(-1.0, -1.0 / aspect_ratio, 0.0))
-            * perspective(fov_rad, 1.0, 1e-2, 1000.0)
+            * perspective(Deg(fov), 1.0, 1e-2, 1000.0)
             * Matrix4::from_nonuniform_scale(1.0, 1.0, -1.0); // undo gluPerspective (z neg)
         let sample_to_camera = camera_to_sample.inverse_transform().unwrap();
 
@@ -36,87 +94,450 @@ impl Camera {
         let p1 = sample_to_camera.transform_point(Point3::new(1.0, 1.0, 0.0));
         let image_rect_min = Point2::new(p0.x.min(p1.x), p0.y.min(p1.y)) / p0.z.min(p1.z);
         let image_rect_max = Point2::new(p0.x.max(p1.x), p0.y.max(p1.y)) / p0.z.max(p1.z);
-        Camera {
-            img,
+        PerspectiveSensor {
             fov,
+            lens_radius: 0.0,
+            focus_distance: 1.0,
             camera_to_sample,
             sample_to_camera,
-            to_world,
-            to_local,
             image_rect_min,
             image_rect_max,
         }
     }
 
-    pub fn size(&self) -> &Vector2<u32> {
-        &self.img
+    /// Thin lens camera with depth of field
+    pub fn thin_lens(mut self, lens_radius: f32, focus_distance: f32) -> Self {
+        assert!(lens_radius >= 0.0);
+        assert!(focus_distance > 0.0);
+        self.lens_radius = lens_radius;
+        self.focus_distance = focus_distance;
+        self
     }
 
-    pub fn scale_image(&mut self, s: f32) {
-        self.img = Vector2::new(
-            (s * self.img.x as f32) as u32,
-            (s * self.img.y as f32) as u32,
-        );
+    // Position on the lens
+    fn lens_position(&self, sample: Point2<f32>) -> Point3<f32> {
+        if self.lens_radius == 0.0 {
+            return Point3::new(0.0, 0.0, 0.0);
+        }
+        let p = concentric_sample_disk(sample) * self.lens_radius;
+        Point3::new(p.x, p.y, 0.0)
     }
 
-    /// Compute the ray direction going through the pixel passed
-    pub fn generate(&self, px: Point2<f32>) -> Ray {
-        let near_p = self.sample_to_camera.transform_point(Point3::new(
-            px.x / (self.img.x as f32),
-            px.y / (self.img.y as f32),
-            0.0,
-        ));
-        let d = near_p.to_vec().normalize();
-        // info!("d: {:?}",  self.to_world.transform_vector(d));
+    // The lens area is not included:
+    // it cancels out with the density of sampling the lens position
+    fn importance(&self, p_lens: Point3<f32>, d: Vector3<f32>) -> f32 {
+        let cos_theta = d.z;
+        if cos_theta <= 0.0 {
+            return 0.0;
+        }
+        let inv_cos_theta = 1.0 / cos_theta;
+        // Point on the image plane at unit distance (from the focus plane for a thin lens)
+        let p = if self.lens_radius == 0.0 {
+            Point2::new(d.x * inv_cos_theta, d.y * inv_cos_theta)
+        } else {
+            let p_focus = p_lens + d * (self.focus_distance * inv_cos_theta);
+            Point2::new(
+                p_focus.x / self.focus_distance,
+                p_focus.y / self.focus_distance,
+            )
+        };
+        if p.x < self.image_rect_min.x
+            || p.x > self.image_rect_max.x
+            || p.y < self.image_rect_min.y
+            || p.y > self.image_rect_max.y
+        {
+            return 0.0;
+        }
 
-        Ray::new(self.position(), self.to_world.transform_vector(d))
+        let size = (self.image_rect_max.x - self.image_rect_min.x)
+            * (self.image_rect_max.y - self.image_rect_min.y);
+        (1.0 / size) * inv_cos_theta * inv_cos_theta * inv_cos_theta
     }
+}
 
-    /// Method to splat a given sample on the camera
-    pub fn sample_direct(&self, p: &Point3<f32>) -> Option<(Color, Point2<f32>)> {
-        let ref_p = self.to_local.transform_point(*p);
-        if ref_p.z < 0.0 {
+impl Sensor for PerspectiveSensor {
+    fn need_sample(&self) -> bool {
+        self.lens_radius != 0.0
+    }
+
+    fn generate(&self, uv: Point2<f32>, sample: Point2<f32>) -> (Point3<f32>, Vector3<f32>) {
+        let p_lens = self.lens_position(sample);
+        (p_lens, self.direction(p_lens, uv))
+    }
+
+    fn direction(&self, p: Point3<f32>, uv: Point2<f32>) -> Vector3<f32> {
+        let near_p = self
+            .sample_to_camera
+            .transform_point(Point3::new(uv.x, uv.y, 0.0));
+        let d = near_p.to_vec().normalize();
+        if self.lens_radius == 0.0 {
+            return d;
+        }
+        // All the rays going through the pixel converge on the focus plane
+        let p_focus = Point3::from_vec(d * (self.focus_distance / d.z));
+        (p_focus - p).normalize()
+    }
+
+    fn connect(&self, p: Point3<f32>, sample: Point2<f32>) -> Option<(Point3<f32>, Point2<f32>)> {
+        let p_lens = self.lens_position(sample);
+        let d = p - p_lens;
+        if d.z < 0.0 {
             return None;
         }
 
-        let screen_pos = self.camera_to_sample.transform_point(ref_p);
+        // Point projected on the film (on the focus plane for a thin lens)
+        let screen_pos = if self.lens_radius == 0.0 {
+            self.camera_to_sample.transform_point(p)
+        } else {
+            let p_focus = p_lens + d * (self.focus_distance / d.z);
+            self.camera_to_sample.transform_point(p_focus)
+        };
         if screen_pos.x < 0.0 || screen_pos.x > 1.0 || screen_pos.y < 0.0 || screen_pos.y > 1.0 {
             return None;
         }
-        let screen_pos = Point2::new(
-            screen_pos.x * self.img.x as f32,
-            screen_pos.y * self.img.y as f32,
-        );
-        let mut local_d = ref_p.to_vec();
-        let inv_dist = 1.0 / local_d.magnitude();
-        local_d *= inv_dist;
+        Some((p_lens, Point2::new(screen_pos.x, screen_pos.y)))
+    }
 
-        let importance = self.importance(local_d);
-        if importance == 0.0 {
-            None
+    fn pdf_direction(&self, p_sensor: Point3<f32>, p: Point3<f32>) -> f32 {
+        let d = p - p_sensor;
+        if d.z <= 0.0 {
+            return 0.0;
+        }
+        self.importance(p_sensor, d / d.magnitude())
+    }
+}
+
+/// Orthographic projection (parallel rays along the view direction)
+pub struct OrthographicSensor {
+    /// Half size of the film (in the camera local frame)
+    pub half_size: Vector2<f32>,
+}
+
+impl OrthographicSensor {
+    /// The film covers [-scale, scale] along its smallest axis
+    pub fn new(img: Vector2<u32>, scale: f32) -> OrthographicSensor {
+        assert!(scale > 0.0);
+        let aspect_ratio = img.x as f32 / img.y as f32;
+        let half_size = if aspect_ratio >= 1.0 {
+            Vector2::new(scale * aspect_ratio, scale)
         } else {
-            Some((Color::value(importance) * inv_dist * inv_dist, screen_pos))
+            Vector2::new(scale, scale / aspect_ratio)
+        };
+        OrthographicSensor { half_size }
+    }
+
+    fn area(&self) -> f32 {
+        4.0 * self.half_size.x * self.half_size.y
+    }
+}
+
+impl Sensor for OrthographicSensor {
+    fn generate(&self, uv: Point2<f32>, _: Point2<f32>) -> (Point3<f32>, Vector3<f32>) {
+        let p = Point3::new(
+            (1.0 - 2.0 * uv.x) * self.half_size.x,
+            (1.0 - 2.0 * uv.y) * self.half_size.y,
+            0.0,
+        );
+        (p, Vector3::new(0.0, 0.0, 1.0))
+    }
+
+    fn direction(&self, _: Point3<f32>, _: Point2<f32>) -> Vector3<f32> {
+        Vector3::new(0.0, 0.0, 1.0)
+    }
+
+    fn connect(&self, p: Point3<f32>, _: Point2<f32>) -> Option<(Point3<f32>, Point2<f32>)> {
+        if p.z <= 0.0 {
+            return None;
+        }
+        let uv = Point2::new(
+            0.5 * (1.0 - p.x / self.half_size.x),
+            0.5 * (1.0 - p.y / self.half_size.y),
+        );
+        if uv.x < 0.0 || uv.x > 1.0 || uv.y < 0.0 || uv.y > 1.0 {
+            return None;
         }
+        Some((Point3::new(p.x, p.y, 0.0), uv))
     }
 
-    fn importance(&self, d: Vector3<f32>) -> f32 {
-        let cos_theta = d.z;
-        if cos_theta <= 0.0 {
+    // The direction is a delta distribution: the density is expressed
+    // such that the conversion to the area density at p gives cos / area
+    fn pdf_direction(&self, p_sensor: Point3<f32>, p: Point3<f32>) -> f32 {
+        let d = p - p_sensor;
+        if d.z <= 0.0 || p_sensor.x.abs() > self.half_size.x || p_sensor.y.abs() > self.half_size.y
+        {
             return 0.0;
         }
-        let inv_cos_theta = 1.0 / cos_theta;
-        let p = Point2::new(d.x * inv_cos_theta, d.y * inv_cos_theta);
-        if p.x < self.image_rect_min.x
-            || p.x > self.image_rect_max.x
-            || p.y < self.image_rect_min.y
-            || p.x > self.image_rect_max.y
-        {
+        d.magnitude2() / self.area()
+    }
+}
+
+/// Spherical panorama (lat-long)
+/// The center of the image is the view direction
+pub struct EquirectangularSensor;
+
+impl EquirectangularSensor {
+    fn direction_uv(uv: Point2<f32>) -> Vector3<f32> {
+        let theta = uv.y * f32::consts::PI;
+        let phi = (uv.x - 0.5) * 2.0 * f32::consts::PI;
+        Vector3::new(
+            -theta.sin() * phi.sin(),
+            theta.cos(),
+            theta.sin() * phi.cos(),
+        )
+    }
+}
+
+impl Sensor for EquirectangularSensor {
+    fn generate(&self, uv: Point2<f32>, _: Point2<f32>) -> (Point3<f32>, Vector3<f32>) {
+        (
+            Point3::new(0.0, 0.0, 0.0),
+            EquirectangularSensor::direction_uv(uv),
+        )
+    }
+
+    fn direction(&self, _: Point3<f32>, uv: Point2<f32>) -> Vector3<f32> {
+        EquirectangularSensor::direction_uv(uv)
+    }
+
+    fn connect(&self, p: Point3<f32>, _: Point2<f32>) -> Option<(Point3<f32>, Point2<f32>)> {
+        let dist = p.to_vec().magnitude();
+        if dist == 0.0 {
+            return None;
+        }
+        let d = p.to_vec() / dist;
+        let theta = d.y.clamp(-1.0, 1.0).acos();
+        let phi = (-d.x).atan2(d.z);
+        Some((
+            Point3::new(0.0, 0.0, 0.0),
+            Point2::new(
+                phi * f32::consts::FRAC_1_PI * 0.5 + 0.5,
+                theta * f32::consts::FRAC_1_PI,
+            ),
+        ))
+    }
+
+    fn pdf_direction(&self, p_sensor: Point3<f32>, p: Point3<f32>) -> f32 {
+        let d = (p - p_sensor).normalize();
+        let sin_theta = (1.0 - d.y * d.y).max(0.0).sqrt();
+        if sin_theta == 0.0 {
             return 0.0;
         }
+        1.0 / (2.0 * f32::consts::PI * f32::consts::PI * sin_theta)
+    }
 
-        let size = (self.image_rect_max.x - self.image_rect_min.x)
-            * (self.image_rect_max.y - self.image_rect_min.y);
-        (1.0 / size as f32) * inv_cos_theta * inv_cos_theta * inv_cos_theta
+    fn clip_distance(&self, _: Vector3<f32>, near_clip: f32) -> f32 {
+        near_clip
+    }
+}
+
+/// Camera to world matrix looking from origin toward target
+/// (same convention as Mitsuba "lookat": the local +x axis is the left of the image)
+/// None if the view direction and the up vector are collinear
+pub fn look_at(origin: Point3<f32>, target: Point3<f32>, up: Vector3<f32>) -> Option<Matrix4<f32>> {
+    let dir = target - origin;
+    if dir.magnitude2() == 0.0 {
+        return None;
+    }
+    let dir = dir.normalize();
+    let left = up.cross(dir);
+    if left.magnitude2() < 1e-12 {
+        return None;
+    }
+    let left = left.normalize();
+    let new_up = dir.cross(left);
+    Some(Matrix4::from_cols(
+        left.extend(0.0),
+        new_up.extend(0.0),
+        dir.extend(0.0),
+        origin.to_homogeneous(),
+    ))
+}
+
+pub struct Camera {
+    /// Size of the rendered image (the crop window of the film)
+    pub img: Vector2<u32>,
+    pub sensor: Box<dyn Sensor>,
+    // Internally
+    film: Vector2<u32>,
+    crop_window: (Point2<f32>, Point2<f32>),
+    crop_offset: Vector2<u32>,
+    near_clip: f32,
+    to_world: Matrix4<f32>,
+    to_local: Matrix4<f32>,
+}
+
+impl Camera {
+    /// Perspective camera (the field of view is vertical)
+    pub fn new(img: Vector2<u32>, fov: f32, mat: Matrix4<f32>) -> Camera {
+        Camera::with_sensor(
+            img,
+            Box::new(PerspectiveSensor::new(img, fov, FovAxis::Y)),
+            mat,
+        )
+    }
+
+    pub fn with_sensor(img: Vector2<u32>, sensor: Box<dyn Sensor>, mat: Matrix4<f32>) -> Camera {
+        let to_world = mat;
+        let to_local = to_world.inverse_transform().unwrap();
+        Camera {
+            img,
+            sensor,
+            film: img,
+            crop_window: (Point2::new(0.0, 0.0), Point2::new(1.0, 1.0)),
+            crop_offset: Vector2::new(0, 0),
+            near_clip: 0.0,
+            to_world,
+            to_local,
+        }
+    }
+
+    /// Render only a part of the film: [min, max] in normalized film coordinates
+    /// (same convention as pbrt "cropwindow", the image size is the one of the crop window)
+    pub fn crop_window(mut self, min: Point2<f32>, max: Point2<f32>) -> Camera {
+        assert!(min.x >= 0.0 && min.y >= 0.0 && max.x <= 1.0 && max.y <= 1.0);
+        assert!(min.x < max.x && min.y < max.y);
+        self.crop_window = (min, max);
+        self.update_crop();
+        self
+    }
+
+    /// The objects closer than the near clipping plane are not visible
+    pub fn near_clip(mut self, near_clip: f32) -> Camera {
+        assert!(near_clip >= 0.0);
+        self.near_clip = near_clip;
+        self
+    }
+
+    // Pixels of the film inside the crop window
+    fn update_crop(&mut self) {
+        let (min, max) = self.crop_window;
+        let pixel = |v: f32, size: u32| (v * size as f32).ceil() as u32;
+        self.crop_offset = Vector2::new(pixel(min.x, self.film.x), pixel(min.y, self.film.y));
+        self.img = Vector2::new(
+            pixel(max.x, self.film.x) - self.crop_offset.x,
+            pixel(max.y, self.film.y) - self.crop_offset.y,
+        );
+        assert!(
+            self.img.x > 0 && self.img.y > 0,
+            "the crop window does not contain any pixel"
+        );
+    }
+
+    pub fn size(&self) -> &Vector2<u32> {
+        &self.img
+    }
+    /// Size of the full image (without the crop window)
+    pub fn film_size(&self) -> &Vector2<u32> {
+        &self.film
+    }
+
+    pub fn scale_image(&mut self, s: f32) {
+        self.film = Vector2::new(
+            (s * self.film.x as f32) as u32,
+            (s * self.film.y as f32) as u32,
+        );
+        self.update_crop();
+    }
+
+    // Ray with its origin on the near clipping surface
+    fn clip(&self, mut ray: Ray, d: Vector3<f32>) -> Ray {
+        if self.near_clip > 0.0 {
+            ray.tnear = self.sensor.clip_distance(d, self.near_clip);
+        }
+        ray
+    }
+
+    // The importance is normalized over the crop window (the rendered image)
+    fn crop_scale(&self) -> f32 {
+        (self.film.x * self.film.y) as f32 / (self.img.x * self.img.y) as f32
+    }
+    /// Sample used by the sensor to generate the rays (e.g., on the lens)
+    /// The sampler is not used if the sensor does not need it
+    pub fn sample_sensor(&self, sampler: &mut dyn Sampler) -> Point2<f32> {
+        if self.sensor.need_sample() {
+            sampler.next2d()
+        } else {
+            Point2::new(0.5, 0.5)
+        }
+    }
+
+    // Normalized film position
+    fn film_uv(&self, px: Point2<f32>) -> Point2<f32> {
+        Point2::new(
+            (px.x + self.crop_offset.x as f32) / (self.film.x as f32),
+            (px.y + self.crop_offset.y as f32) / (self.film.y as f32),
+        )
+    }
+
+    /// Compute the ray going through the pixel passed
+    pub fn generate(&self, px: Point2<f32>, sampler: &mut dyn Sampler) -> Ray {
+        let sample = self.sample_sensor(sampler);
+        self.generate_sample(px, sample)
+    }
+
+    /// Compute the ray going through the pixel passed with the sensor sample (see sample_sensor)
+    pub fn generate_sample(&self, px: Point2<f32>, sample: Point2<f32>) -> Ray {
+        let (p, d) = self.sensor.generate(self.film_uv(px), sample);
+        let ray = Ray::new(
+            self.to_world.transform_point(p),
+            self.to_world.transform_vector(d),
+        );
+        self.clip(ray, d)
+    }
+
+    /// Compute the ray starting from the sensor position pos and going through the pixel passed
+    /// (pos need to be generated for this pixel, see generate)
+    pub fn generate_from(&self, pos: &Point3<f32>, px: Point2<f32>) -> Ray {
+        let d = self
+            .sensor
+            .direction(self.to_local.transform_point(*pos), self.film_uv(px));
+        self.clip(Ray::new(*pos, self.to_world.transform_vector(d)), d)
+    }
+
+    /// Method to splat a given sample on the camera
+    /// Return the importance, the position on the film and the position on the sensor
+    pub fn sample_direct(
+        &self,
+        p: &Point3<f32>,
+        sampler: &mut dyn Sampler,
+    ) -> Option<(Color, Point2<f32>, Point3<f32>)> {
+        let sample = self.sample_sensor(sampler);
+        let ref_p = self.to_local.transform_point(*p);
+        let (p_sensor, uv) = self.sensor.connect(ref_p, sample)?;
+        let uv = Point2::new(
+            uv.x * self.film.x as f32 - self.crop_offset.x as f32,
+            uv.y * self.film.y as f32 - self.crop_offset.y as f32,
+        );
+        if uv.x < 0.0 || uv.y < 0.0 || uv.x > self.img.x as f32 || uv.y > self.img.y as f32 {
+            return None;
+        }
+        let dist = (ref_p - p_sensor).magnitude();
+        if self.near_clip > 0.0
+            && dist
+                < self
+                    .sensor
+                    .clip_distance((ref_p - p_sensor) / dist, self.near_clip)
+        {
+            return None;
+        }
+        let importance = self.sensor.pdf_direction(p_sensor, ref_p) * self.crop_scale();
+        if importance == 0.0 {
+            return None;
+        }
+        Some((
+            Color::value(importance / (dist * dist)),
+            uv,
+            self.to_world.transform_point(p_sensor),
+        ))
+    }
+
+    /// Solid angle density of generating the direction from the sensor position pos toward p
+    /// (the direction is assumed to be inside the crop window)
+    pub fn pdf_direction(&self, pos: &Point3<f32>, p: &Point3<f32>) -> f32 {
+        self.sensor.pdf_direction(
+            self.to_local.transform_point(*pos),
+            self.to_local.transform_point(*p),
+        ) * self.crop_scale()
     }
 
     pub fn position(&self) -> Point3<f32> {
@@ -124,9 +545,19 @@ impl Camera {
     }
 
     pub fn print_info(&self) {
-        let pix = Point2::new(self.img.x as f32 * 0.5 + 0.5, self.img.y as f32 * 0.5 + 0.5);
-        let view_dir = self.generate(pix).d;
+        let (_, view_dir) = self
+            .sensor
+            .generate(Point2::new(0.5, 0.5), Point2::new(0.5, 0.5));
         info!(" - Position: {:?}", self.position());
-        info!(" - View direction: {:?}", view_dir);
+        info!(
+            " - View direction: {:?}",
+            self.to_world.transform_vector(view_dir)
+        );
+        if self.img != self.film {
+            info!(
+                " - Crop window: {:?} ({:?} pixels of the {:?} film)",
+                self.crop_window, self.img, self.film
+            );
+        }
     }
 }
@@ -1,5 +1,7 @@
 use crate::geometry::Mesh;
-use crate::math::{sample_uniform_sphere, Distribution1D};
+use crate::math::{
+    cosine_sample_hemisphere, sample_uniform_sphere, Distribution1D, Distribution1DConstruct, Frame,
+};
 use crate::structure::*;
 use cgmath::*;
 
@@ -41,35 +43,573 @@ pub trait Emitter: Send + Sync {
     fn sample_direct(&self, p: &Point3<f32>, r: f32, uv: Point2<f32>) -> LightSampling;
     fn flux(&self) -> Color;
     fn emitted_luminance(&self, d: Vector3<f32>) -> Color;
+    /// Contribution received at the distance dist along d (leaving the emitter)
+    /// the delta position emitters are attenuated by the squared distance
+    fn emitted_contribution(&self, d: Vector3<f32>, _dist: f32) -> Color {
+        self.emitted_luminance(d)
+    }
+    /// Sample an outgoing direction from a sampled position (used by light tracing)
+    /// return the direction, its pdf and its weight (relative to the emitter flux)
+    /// by default, the emission is diffuse around the normal n
+    fn sample_direction(
+        &self,
+        _p: &Point3<f32>,
+        n: &Vector3<f32>,
+        uv: Point2<f32>,
+    ) -> Option<(Vector3<f32>, PDF, Color)> {
+        let d_out = cosine_sample_hemisphere(uv);
+        if d_out.z == 0.0 {
+            return None; // Failed to sample the outgoing direction
+        }
+        Some((
+            Frame::new(*n).to_world(d_out),
+            PDF::SolidAngle(d_out.z * std::f32::consts::FRAC_1_PI),
+            Color::one(), // Perfectly importance sampled
+        ))
+    }
+    /// Emission profile along the direction d leaving p (relative to the emitter flux)
+    /// sample_direction returns this profile divided by the direction pdf
+    fn eval_direction(&self, _p: &Point3<f32>, n: &Vector3<f32>, d: &Vector3<f32>) -> Color {
+        Color::value(n.dot(*d).max(0.0) * std::f32::consts::FRAC_1_PI)
+    }
+    /// Density of sample_position at p (Discrete for the delta position emitters)
+    fn pdf_position(&self, p: &Point3<f32>) -> PDF;
+    /// Density of sample_direction for the direction d leaving p
+    fn pdf_direction(&self, _p: &Point3<f32>, n: &Vector3<f32>, d: &Vector3<f32>) -> PDF {
+        PDF::SolidAngle(n.dot(*d).max(0.0) * std::f32::consts::FRAC_1_PI)
+    }
+    /// Emitters at infinity (environment, directional light)
+    fn is_infinite(&self) -> bool {
+        false
+    }
+    /// Update the emitter with the scene bounds
+    fn preprocess(&mut self, _aabb: &AABB) {}
+}
+
+/// Lat-long environment map (Mitsuba convention, y is up)
+/// with its piecewise constant importance sampling
+pub struct EnvironmentMap {
+    pub bitmap: Bitmap,
+    // Row selection and the column selection inside each row
+    marginal: Distribution1D,
+    conditionals: Vec<Distribution1D>,
+    average: Color,
+}
+
+impl EnvironmentMap {
+    /// Fails if the map does not emit any light
+    pub fn new(bitmap: Bitmap) -> Result<EnvironmentMap, String> {
+        let (width, height) = (bitmap.size.x, bitmap.size.y);
+        let mut marginal = Distribution1DConstruct::new(height as usize);
+        let mut conditionals = Vec::with_capacity(height as usize);
+        for y in 0..height {
+            // Account for the lat-long mapping distortion
+            let sin_theta = (std::f32::consts::PI * (y as f32 + 0.5) / height as f32).sin();
+            let mut row = Distribution1DConstruct::new(width as usize);
+            for x in 0..width {
+                row.add(bitmap.pixel(Point2::new(x, y)).luminance().max(0.0) * sin_theta);
+            }
+            let row_sum: f32 = row.elements.iter().sum();
+            marginal.add(row_sum);
+            if row_sum == 0.0 {
+                // Never selected, avoid an invalid CDF
+                row.elements.iter_mut().for_each(|v| *v = 1.0);
+            }
+            conditionals.push(row.normalize());
+        }
+        if marginal.elements.iter().sum::<f32>() == 0.0 {
+            return Err("the environment map is black".to_string());
+        }
+        let average = EnvironmentMap::compute_average(&bitmap);
+        Ok(EnvironmentMap {
+            bitmap,
+            marginal: marginal.normalize(),
+            conditionals,
+            average,
+        })
+    }
+
+    fn uv(d: &Vector3<f32>) -> Point2<f32> {
+        let mut u = d.x.atan2(-d.z) / (2.0 * std::f32::consts::PI);
+        if u < 0.0 {
+            u += 1.0;
+        }
+        let v = d.y.clamp(-1.0, 1.0).acos() / std::f32::consts::PI;
+        Point2::new(u, v)
+    }
+
+    fn texel(&self, uv: Point2<f32>) -> Point2<u32> {
+        Point2::new(
+            ((uv.x * self.bitmap.size.x as f32) as u32).min(self.bitmap.size.x - 1),
+            ((uv.y * self.bitmap.size.y as f32) as u32).min(self.bitmap.size.y - 1),
+        )
+    }
+
+    // Convert the pdf over the image to the solid angle measure
+    fn pdf_texel(&self, p: Point2<u32>, sin_theta: f32) -> f32 {
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        let pdf =
+            self.marginal.pdf(p.y as usize) * self.conditionals[p.y as usize].pdf(p.x as usize);
+        let nb_texels = (self.bitmap.size.x * self.bitmap.size.y) as f32;
+        pdf * nb_texels / (2.0 * std::f32::consts::PI * std::f32::consts::PI * sin_theta)
+    }
+
+    /// Luminance along the direction d (local coordinates)
+    pub fn eval(&self, d: &Vector3<f32>) -> Color {
+        self.bitmap.pixel(self.texel(EnvironmentMap::uv(d)))
+    }
+
+    /// Sample a local direction, return the direction and its solid angle pdf
+    pub fn sample(&self, u: Point2<f32>) -> (Vector3<f32>, f32) {
+        let (y, v_offset) = self.marginal.sample_continuous(u.y);
+        let (x, u_offset) = self.conditionals[y].sample_continuous(u.x);
+        let phi = 2.0 * std::f32::consts::PI * (x as f32 + u_offset) / self.bitmap.size.x as f32;
+        let theta = std::f32::consts::PI * (y as f32 + v_offset) / self.bitmap.size.y as f32;
+        let sin_theta = theta.sin();
+        let d = Vector3::new(sin_theta * phi.sin(), theta.cos(), -sin_theta * phi.cos());
+        (
+            d,
+            self.pdf_texel(Point2::new(x as u32, y as u32), sin_theta),
+        )
+    }
+
+    /// Solid angle pdf of the local direction d
+    pub fn pdf(&self, d: &Vector3<f32>) -> f32 {
+        let sin_theta = (1.0 - d.y * d.y).max(0.0).sqrt();
+        self.pdf_texel(self.texel(EnvironmentMap::uv(d)), sin_theta)
+    }
+
+    fn compute_average(bitmap: &Bitmap) -> Color {
+        let (width, height) = (bitmap.size.x, bitmap.size.y);
+        let mut avg = Color::zero();
+        for y in 0..height {
+            let sin_theta = (std::f32::consts::PI * (y as f32 + 0.5) / height as f32).sin();
+            for x in 0..width {
+                avg += bitmap.pixel(Point2::new(x, y)) * sin_theta;
+            }
+        }
+        // Solid angle of a texel (without sin_theta) over the sphere solid angle
+        avg * (std::f32::consts::PI / (2.0 * (width * height) as f32))
+    }
+
+    /// Average luminance over the sphere of directions
+    pub fn average(&self) -> Color {
+        self.average
+    }
 }
 
 pub struct EnvironmentLight {
+    /// Constant luminance (or the scale of the environment map)
     pub luminance: Color,
+    pub map: Option<EnvironmentMap>,
+    /// Rotation from the environment map space to the world
+    pub to_world: Matrix3<f32>,
     pub world_radius: f32,
     pub world_position: Point3<f32>,
 }
+// Divide a contribution by the emitter flux (channels without flux do not contribute)
+fn relative_to_flux(c: Color, flux: Color) -> Color {
+    let div = |v: f32, f: f32| if f == 0.0 { 0.0 } else { v / f };
+    Color::new(div(c.r, flux.r), div(c.g, flux.g), div(c.b, flux.b))
+}
+
+// Sphere bounding the scene
+fn bounding_sphere(aabb: &AABB) -> Option<(Point3<f32>, f32)> {
+    if aabb.surface_area() == 0.0 {
+        warn!("Empty scene bounds, the emitter bounding sphere is not updated");
+        None
+    } else {
+        Some((
+            Point3::from_vec(aabb.center()),
+            aabb.size().magnitude() * 0.5 * 1.01,
+        ))
+    }
+}
+
+impl EnvironmentLight {
+    /// Luminance seen along the world direction d
+    pub fn eval(&self, d: Vector3<f32>) -> Color {
+        match self.map {
+            None => self.luminance,
+            Some(ref map) => self.luminance * map.eval(&(self.to_world.transpose() * d)),
+        }
+    }
+
+    // Sample a direction toward the environment (world space) and its solid angle pdf
+    fn sample_incoming(&self, uv: Point2<f32>) -> (Vector3<f32>, f32) {
+        match self.map {
+            None => (
+                sample_uniform_sphere(uv),
+                1.0 / (std::f32::consts::PI * 4.0),
+            ),
+            Some(ref map) => {
+                let (d, pdf) = map.sample(uv);
+                (self.to_world * d, pdf)
+            }
+        }
+    }
+
+    fn pdf_incoming(&self, d: Vector3<f32>) -> f32 {
+        match self.map {
+            None => 1.0 / (std::f32::consts::PI * 4.0),
+            Some(ref map) => map.pdf(&(self.to_world.transpose() * d)),
+        }
+    }
+
+    // Area of the sphere bounding the scene
+    fn world_area(&self) -> f32 {
+        4.0 * std::f32::consts::PI * self.world_radius * self.world_radius
+    }
+}
 impl Emitter for EnvironmentLight {
+    // The light paths start uniformly on the bounding sphere (facing inward)
+    // and their direction is importance sampled with the environment
     fn sample_position(&self, _s: f32, uv: Point2<f32>) -> SampledPosition {
-        // TODO: Check this function
         let d = sample_uniform_sphere(uv);
-        let pdf = 1.0 / (self.world_radius * self.world_radius * std::f32::consts::PI * 4.0);
         SampledPosition {
             p: self.world_position + d * self.world_radius,
             n: -d,
-            pdf: PDF::Area(pdf),
+            pdf: PDF::Area(1.0 / self.world_area()),
+        }
+    }
+    fn direct_pdf(&self, light_sampling: &LightSamplingPDF) -> PDF {
+        PDF::SolidAngle(self.pdf_incoming(light_sampling.dir))
+    }
+    fn sample_direct(&self, p: &Point3<f32>, _r: f32, uv: Point2<f32>) -> LightSampling {
+        let (d, pdf) = self.sample_incoming(uv);
+        let weight = if pdf == 0.0 {
+            Color::zero()
+        } else {
+            self.eval(d) / pdf
+        };
+        LightSampling {
+            emitter: self,
+            pdf: PDF::SolidAngle(pdf),
+            // Outside the environment sphere as p is inside the scene
+            p: p + d * (2.0 * self.world_radius),
+            n: -d,
+            d,
+            weight,
+        }
+    }
+    fn flux(&self) -> Color {
+        let luminance = match self.map {
+            None => self.luminance,
+            Some(ref map) => self.luminance * map.average(),
+        };
+        // Power entering the bounding sphere
+        std::f32::consts::PI * self.world_area() * luminance
+    }
+    fn emitted_luminance(&self, d: Vector3<f32>) -> Color {
+        // d is the direction leaving the emitter
+        self.eval(-d)
+    }
+    fn sample_direction(
+        &self,
+        p: &Point3<f32>,
+        n: &Vector3<f32>,
+        uv: Point2<f32>,
+    ) -> Option<(Vector3<f32>, PDF, Color)> {
+        let (d, pdf) = self.sample_incoming(uv);
+        if pdf == 0.0 || n.dot(-d) <= 0.0 {
+            return None; // Leaves the scene bounds
+        }
+        Some((
+            -d,
+            PDF::SolidAngle(pdf),
+            self.eval_direction(p, n, &-d) / pdf,
+        ))
+    }
+    fn eval_direction(&self, _p: &Point3<f32>, n: &Vector3<f32>, d: &Vector3<f32>) -> Color {
+        let cos_theta = n.dot(*d);
+        if cos_theta <= 0.0 {
+            return Color::zero();
+        }
+        relative_to_flux(
+            self.eval(-*d) * (cos_theta * self.world_area()),
+            self.flux(),
+        )
+    }
+    fn pdf_position(&self, _p: &Point3<f32>) -> PDF {
+        PDF::Area(1.0 / self.world_area())
+    }
+    fn pdf_direction(&self, _p: &Point3<f32>, n: &Vector3<f32>, d: &Vector3<f32>) -> PDF {
+        if n.dot(*d) <= 0.0 {
+            PDF::SolidAngle(0.0)
+        } else {
+            PDF::SolidAngle(self.pdf_incoming(-*d))
+        }
+    }
+    fn is_infinite(&self) -> bool {
+        true
+    }
+    fn preprocess(&mut self, aabb: &AABB) {
+        if let Some((world_position, world_radius)) = bounding_sphere(aabb) {
+            self.world_position = world_position;
+            self.world_radius = world_radius;
+        }
+    }
+}
+
+/// Isotropic point light
+pub struct PointLight {
+    pub position: Point3<f32>,
+    pub intensity: Color,
+}
+
+// Light sampling record for the delta position emitters
+fn sample_delta_position<'a>(
+    emitter: &'a dyn Emitter,
+    p: &Point3<f32>,
+    position: &Point3<f32>,
+) -> LightSampling<'a> {
+    let mut d = position - p;
+    let dist = d.magnitude();
+    d /= dist;
+    LightSampling {
+        emitter,
+        pdf: PDF::Discrete(1.0),
+        p: *position,
+        n: -d, // No normal, face the receiver
+        d,
+        weight: emitter.emitted_contribution(-d, dist),
+    }
+}
+
+impl Emitter for PointLight {
+    fn sample_position(&self, _s: f32, _uv: Point2<f32>) -> SampledPosition {
+        SampledPosition {
+            p: self.position,
+            n: Vector3::unit_z(), // No normal
+            pdf: PDF::Discrete(1.0),
+        }
+    }
+    fn direct_pdf(&self, _light_sampling: &LightSamplingPDF) -> PDF {
+        PDF::Discrete(1.0)
+    }
+    fn sample_direct(&self, p: &Point3<f32>, _r: f32, _uv: Point2<f32>) -> LightSampling {
+        sample_delta_position(self, p, &self.position)
+    }
+    fn flux(&self) -> Color {
+        4.0 * std::f32::consts::PI * self.intensity
+    }
+    fn emitted_luminance(&self, _d: Vector3<f32>) -> Color {
+        self.intensity
+    }
+    fn emitted_contribution(&self, d: Vector3<f32>, dist: f32) -> Color {
+        self.emitted_luminance(d) / (dist * dist)
+    }
+    fn sample_direction(
+        &self,
+        _p: &Point3<f32>,
+        _n: &Vector3<f32>,
+        uv: Point2<f32>,
+    ) -> Option<(Vector3<f32>, PDF, Color)> {
+        Some((
+            sample_uniform_sphere(uv),
+            PDF::SolidAngle(1.0 / (std::f32::consts::PI * 4.0)),
+            Color::one(),
+        ))
+    }
+    fn eval_direction(&self, _p: &Point3<f32>, _n: &Vector3<f32>, _d: &Vector3<f32>) -> Color {
+        Color::value(1.0 / (std::f32::consts::PI * 4.0))
+    }
+    fn pdf_position(&self, _p: &Point3<f32>) -> PDF {
+        PDF::Discrete(1.0)
+    }
+    fn pdf_direction(&self, _p: &Point3<f32>, _n: &Vector3<f32>, _d: &Vector3<f32>) -> PDF {
+        PDF::SolidAngle(1.0 / (std::f32::consts::PI * 4.0))
+    }
+}
+
+/// Spot light with a smooth falloff (PBRT)
+pub struct SpotLight {
+    pub position: Point3<f32>,
+    pub direction: Vector3<f32>,
+    pub intensity: Color,
+    // Cosines of the cone angle and the angle where the falloff starts
+    pub cos_total_width: f32,
+    pub cos_falloff_start: f32,
+}
+
+impl SpotLight {
+    fn falloff(&self, d: &Vector3<f32>) -> f32 {
+        let cos_theta = d.dot(self.direction);
+        if cos_theta < self.cos_total_width {
+            0.0
+        } else if cos_theta >= self.cos_falloff_start {
+            1.0
+        } else {
+            let delta = (cos_theta - self.cos_total_width)
+                / (self.cos_falloff_start - self.cos_total_width);
+            delta.powi(4)
+        }
+    }
+}
+
+impl Emitter for SpotLight {
+    fn sample_position(&self, _s: f32, _uv: Point2<f32>) -> SampledPosition {
+        SampledPosition {
+            p: self.position,
+            n: self.direction,
+            pdf: PDF::Discrete(1.0),
+        }
+    }
+    fn direct_pdf(&self, _light_sampling: &LightSamplingPDF) -> PDF {
+        PDF::Discrete(1.0)
+    }
+    fn sample_direct(&self, p: &Point3<f32>, _r: f32, _uv: Point2<f32>) -> LightSampling {
+        sample_delta_position(self, p, &self.position)
+    }
+    fn flux(&self) -> Color {
+        self.intensity
+            * (2.0
+                * std::f32::consts::PI
+                * (1.0 - 0.5 * (self.cos_falloff_start + self.cos_total_width)))
+    }
+    fn emitted_luminance(&self, d: Vector3<f32>) -> Color {
+        self.intensity * self.falloff(&d)
+    }
+    fn emitted_contribution(&self, d: Vector3<f32>, dist: f32) -> Color {
+        self.emitted_luminance(d) / (dist * dist)
+    }
+    fn sample_direction(
+        &self,
+        p: &Point3<f32>,
+        n: &Vector3<f32>,
+        uv: Point2<f32>,
+    ) -> Option<(Vector3<f32>, PDF, Color)> {
+        // Uniform sampling inside the cone
+        let cos_theta = 1.0 - uv.x * (1.0 - self.cos_total_width);
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * std::f32::consts::PI * uv.y;
+        let d = Frame::new(self.direction).to_world(Vector3::new(
+            sin_theta * phi.cos(),
+            sin_theta * phi.sin(),
+            cos_theta,
+        ));
+        let pdf = 1.0 / (2.0 * std::f32::consts::PI * (1.0 - self.cos_total_width));
+        Some((d, PDF::SolidAngle(pdf), self.eval_direction(p, n, &d) / pdf))
+    }
+    fn eval_direction(&self, _p: &Point3<f32>, _n: &Vector3<f32>, d: &Vector3<f32>) -> Color {
+        relative_to_flux(self.emitted_luminance(*d), self.flux())
+    }
+    fn pdf_position(&self, _p: &Point3<f32>) -> PDF {
+        PDF::Discrete(1.0)
+    }
+    fn pdf_direction(&self, _p: &Point3<f32>, _n: &Vector3<f32>, d: &Vector3<f32>) -> PDF {
+        if d.dot(self.direction) < self.cos_total_width {
+            PDF::SolidAngle(0.0)
+        } else {
+            PDF::SolidAngle(1.0 / (2.0 * std::f32::consts::PI * (1.0 - self.cos_total_width)))
+        }
+    }
+}
+
+/// Distant light (e.g. sun) where all the rays travel along direction
+pub struct DirectionalLight {
+    pub direction: Vector3<f32>,
+    pub irradiance: Color,
+    pub world_radius: f32,
+    pub world_position: Point3<f32>,
+}
+
+impl Emitter for DirectionalLight {
+    fn sample_position(&self, _s: f32, uv: Point2<f32>) -> SampledPosition {
+        // Uniform sampling of the disk covering the scene
+        let r = uv.x.sqrt() * self.world_radius;
+        let phi = 2.0 * std::f32::consts::PI * uv.y;
+        let p = Frame::new(self.direction).to_world(Vector3::new(
+            r * phi.cos(),
+            r * phi.sin(),
+            -self.world_radius,
+        ));
+        SampledPosition {
+            p: self.world_position + p,
+            n: self.direction,
+            pdf: PDF::Area(1.0 / (std::f32::consts::PI * self.world_radius * self.world_radius)),
         }
     }
     fn direct_pdf(&self, _light_sampling: &LightSamplingPDF) -> PDF {
-        unimplemented!();
+        PDF::Discrete(1.0)
     }
-    fn sample_direct(&self, _p: &Point3<f32>, _r: f32, _uv: Point2<f32>) -> LightSampling {
-        unimplemented!();
+    fn sample_direct(&self, p: &Point3<f32>, _r: f32, _uv: Point2<f32>) -> LightSampling {
+        LightSampling {
+            emitter: self,
+            pdf: PDF::Discrete(1.0),
+            // Outside the scene bounds
+            p: p - self.direction * (2.0 * self.world_radius),
+            n: self.direction,
+            d: -self.direction,
+            weight: self.irradiance,
+        }
     }
     fn flux(&self) -> Color {
-        std::f32::consts::PI * self.world_radius.powi(2) * self.luminance
+        std::f32::consts::PI * self.world_radius.powi(2) * self.irradiance
     }
     fn emitted_luminance(&self, _d: Vector3<f32>) -> Color {
-        self.luminance
+        self.irradiance
+    }
+    fn sample_direction(
+        &self,
+        _p: &Point3<f32>,
+        _n: &Vector3<f32>,
+        _uv: Point2<f32>,
+    ) -> Option<(Vector3<f32>, PDF, Color)> {
+        Some((self.direction, PDF::Discrete(1.0), Color::one()))
+    }
+    fn pdf_position(&self, _p: &Point3<f32>) -> PDF {
+        PDF::Area(1.0 / (std::f32::consts::PI * self.world_radius * self.world_radius))
+    }
+    fn pdf_direction(&self, _p: &Point3<f32>, _n: &Vector3<f32>, _d: &Vector3<f32>) -> PDF {
+        PDF::Discrete(1.0)
+    }
+    fn is_infinite(&self) -> bool {
+        true
+    }
+    fn preprocess(&mut self, aabb: &AABB) {
+        if let Some((world_position, world_radius)) = bounding_sphere(aabb) {
+            self.world_position = world_position;
+            self.world_radius = world_radius;
+        }
+    }
+}
+
+// Cosines of the spot cone (angles in degrees)
+fn spot_cone(cone_angle: f32, cone_delta_angle: f32) -> (f32, f32) {
+    (
+        cone_angle.to_radians().cos(),
+        (cone_angle - cone_delta_angle).to_radians().cos(),
+    )
+}
+
+/// Create a spot light with the PBRT parameters (angles in degrees)
+pub fn spot_light(
+    position: Point3<f32>,
+    direction: Vector3<f32>,
+    intensity: Color,
+    cone_angle: f32,
+    cone_delta_angle: f32,
+) -> SpotLight {
+    let (cos_total_width, cos_falloff_start) = spot_cone(cone_angle, cone_delta_angle);
+    SpotLight {
+        position,
+        direction: direction.normalize(),
+        intensity,
+        cos_total_width,
+        cos_falloff_start,
+    }
+}
+
+/// Create a directional light (the bounds are set by preprocess)
+pub fn directional_light(direction: Vector3<f32>, irradiance: Color) -> DirectionalLight {
+    DirectionalLight {
+        direction: direction.normalize(),
+        irradiance,
+        world_radius: 1.0,
+        world_position: Point3::origin(),
     }
 }
 
@@ -133,6 +673,10 @@ impl Emitter for Mesh {
     fn sample_position(&self, s: f32, uv: Point2<f32>) -> SampledPosition {
         self.sample(s, uv)
     }
+
+    fn pdf_position(&self, _p: &Point3<f32>) -> PDF {
+        PDF::Area(self.pdf())
+    }
 }
 
 pub struct EmitterSampler<'scene> {
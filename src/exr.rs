@@ -0,0 +1,313 @@
+//! Minimal OpenEXR support for the layered images
+//! (single part, scanlines without compression)
+//! The "primal" layer uses the R, G, B channels so the viewers display it by default,
+//! the other layers use the "<layer>.R", "<layer>.G", "<layer>.B" channels
+use crate::structure::{Bitmap, Color};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use cgmath::{Point2, Vector2};
+use std::io::{Cursor, Error, ErrorKind, Read, Result, Write};
+
+const MAGIC: u32 = 20_000_630;
+// Version flags not supported by the reader
+const FLAG_TILED: u32 = 0x200;
+const FLAG_NON_IMAGE: u32 = 0x800;
+const FLAG_MULTI_PART: u32 = 0x1000;
+const PIXEL_TYPE_UINT: i32 = 0;
+const PIXEL_TYPE_HALF: i32 = 1;
+const PIXEL_TYPE_FLOAT: i32 = 2;
+const NO_COMPRESSION: u8 = 0;
+
+/// Layer stored inside the R, G, B channels
+pub const DEFAULT_LAYER: &str = "primal";
+
+/// Layered image with its string attributes (metadata)
+pub struct LayeredImage {
+    pub size: Vector2<u32>,
+    pub layers: Vec<(String, Bitmap)>,
+    pub attributes: Vec<(String, String)>,
+}
+
+fn invalid(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, msg.to_string())
+}
+
+fn channel_names(layer: &str) -> Vec<String> {
+    ["R", "G", "B"]
+        .iter()
+        .map(|c| {
+            if layer == DEFAULT_LAYER {
+                c.to_string()
+            } else {
+                format!("{}.{}", layer, c)
+            }
+        })
+        .collect()
+}
+
+// Layer and component (0: R, 1: G, 2: B) of a channel
+fn channel_layer(name: &str) -> Option<(String, usize)> {
+    let (layer, c) = match name.rfind('.') {
+        Some(i) => (&name[..i], &name[i + 1..]),
+        None => (DEFAULT_LAYER, name),
+    };
+    let c = match c {
+        "R" => 0,
+        "G" => 1,
+        "B" => 2,
+        _ => return None,
+    };
+    Some((layer.to_string(), c))
+}
+
+fn write_attribute<W: Write>(f: &mut W, name: &str, type_name: &str, value: &[u8]) -> Result<()> {
+    f.write_all(name.as_bytes())?;
+    f.write_u8(0)?;
+    f.write_all(type_name.as_bytes())?;
+    f.write_u8(0)?;
+    f.write_i32::<LittleEndian>(value.len() as i32)?;
+    f.write_all(value)
+}
+
+fn box2i(size: Vector2<u32>) -> Vec<u8> {
+    [0, 0, size.x as i32 - 1, size.y as i32 - 1]
+        .iter()
+        .flat_map(|v| v.to_le_bytes().to_vec())
+        .collect()
+}
+
+/// Write the layers (with the same size) and the string attributes
+pub fn write<W: Write>(
+    f: &mut W,
+    layers: &[(&str, &Bitmap)],
+    attributes: &[(String, String)],
+) -> Result<()> {
+    assert!(!layers.is_empty());
+    let size = layers[0].1.size;
+
+    // The channels are sorted by name inside the file
+    let mut channels = vec![];
+    for (layer, bitmap) in layers {
+        assert_eq!(bitmap.size, size);
+        for (c, name) in channel_names(layer).into_iter().enumerate() {
+            channels.push((name, *bitmap, c));
+        }
+    }
+    channels.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut header = vec![];
+    header.write_u32::<LittleEndian>(MAGIC)?;
+    // Version 2, single part scanline file (long names if needed)
+    let long_names = channels
+        .iter()
+        .map(|c| c.0.as_str())
+        .chain(attributes.iter().map(|a| a.0.as_str()))
+        .any(|n| n.len() > 31);
+    header.write_u32::<LittleEndian>(if long_names { 2 | 0x400 } else { 2 })?;
+    let mut chlist = vec![];
+    for (name, _, _) in &channels {
+        chlist.write_all(name.as_bytes())?;
+        chlist.write_u8(0)?;
+        chlist.write_i32::<LittleEndian>(PIXEL_TYPE_FLOAT)?;
+        chlist.write_all(&[0, 0, 0, 0])?; // pLinear and reserved
+        chlist.write_i32::<LittleEndian>(1)?; // xSampling
+        chlist.write_i32::<LittleEndian>(1)?; // ySampling
+    }
+    chlist.write_u8(0)?;
+    write_attribute(&mut header, "channels", "chlist", &chlist)?;
+    write_attribute(&mut header, "compression", "compression", &[NO_COMPRESSION])?;
+    write_attribute(&mut header, "dataWindow", "box2i", &box2i(size))?;
+    write_attribute(&mut header, "displayWindow", "box2i", &box2i(size))?;
+    write_attribute(&mut header, "lineOrder", "lineOrder", &[0])?;
+    write_attribute(
+        &mut header,
+        "pixelAspectRatio",
+        "float",
+        &1.0f32.to_le_bytes(),
+    )?;
+    write_attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8])?;
+    write_attribute(
+        &mut header,
+        "screenWindowWidth",
+        "float",
+        &1.0f32.to_le_bytes(),
+    )?;
+    for (name, value) in attributes {
+        write_attribute(&mut header, name, "string", value.as_bytes())?;
+    }
+    header.write_u8(0)?;
+    f.write_all(&header)?;
+
+    // Offset table (one scanline per block)
+    let line_size = (size.x as usize) * channels.len() * 4;
+    let data_start = header.len() + 8 * size.y as usize;
+    for y in 0..size.y as usize {
+        f.write_u64::<LittleEndian>((data_start + y * (8 + line_size)) as u64)?;
+    }
+
+    // Scanlines
+    let mut line = Vec::with_capacity(line_size);
+    for y in 0..size.y {
+        line.clear();
+        for (_, bitmap, c) in &channels {
+            for x in 0..size.x {
+                let p = bitmap.pixel(Point2::new(x, y));
+                let v = match c {
+                    0 => p.r,
+                    1 => p.g,
+                    _ => p.b,
+                };
+                line.write_f32::<LittleEndian>(v)?;
+            }
+        }
+        f.write_i32::<LittleEndian>(y as i32)?;
+        f.write_i32::<LittleEndian>(line_size as i32)?;
+        f.write_all(&line)?;
+    }
+    Ok(())
+}
+
+fn read_string<R: Read>(f: &mut R) -> Result<String> {
+    let mut s = vec![];
+    loop {
+        match f.read_u8()? {
+            0 => break,
+            c => s.push(c),
+        }
+    }
+    String::from_utf8(s).map_err(|_| invalid("wrong EXR string"))
+}
+
+fn half_to_f32(h: u16) -> f32 {
+    let sign = if h & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = i32::from((h >> 10) & 0x1f);
+    let mantissa = f32::from(h & 0x3ff);
+    match exponent {
+        0 => sign * mantissa * 2f32.powi(-24),
+        31 if mantissa == 0.0 => sign * f32::INFINITY,
+        31 => f32::NAN,
+        _ => sign * (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+    }
+}
+
+/// Read an uncompressed scanline file
+/// (only the R, G, B channels of each layer are kept)
+pub fn read(data: &[u8]) -> Result<LayeredImage> {
+    let mut f = Cursor::new(data);
+    if f.read_u32::<LittleEndian>()? != MAGIC {
+        return Err(invalid("not an EXR file"));
+    }
+    let version = f.read_u32::<LittleEndian>()?;
+    if version & (FLAG_TILED | FLAG_NON_IMAGE | FLAG_MULTI_PART) != 0 {
+        return Err(invalid("only the scanline EXR files are supported"));
+    }
+
+    // Header
+    let mut channels = vec![];
+    let mut window = None;
+    let mut attributes = vec![];
+    loop {
+        let name = read_string(&mut f)?;
+        if name.is_empty() {
+            break;
+        }
+        let type_name = read_string(&mut f)?;
+        let value_size = f.read_i32::<LittleEndian>()?;
+        if value_size < 0 || value_size as u64 > data.len() as u64 - f.position() {
+            return Err(invalid("wrong EXR attribute size"));
+        }
+        let mut value = vec![0; value_size as usize];
+        f.read_exact(&mut value)?;
+        let mut v = Cursor::new(&value[..]);
+        match (name.as_str(), type_name.as_str()) {
+            ("channels", _) => loop {
+                let channel = read_string(&mut v)?;
+                if channel.is_empty() {
+                    break;
+                }
+                let pixel_type = v.read_i32::<LittleEndian>()?;
+                v.read_u32::<LittleEndian>()?; // pLinear and reserved
+                if v.read_i32::<LittleEndian>()? != 1 || v.read_i32::<LittleEndian>()? != 1 {
+                    return Err(invalid("subsampled EXR channels are not supported"));
+                }
+                channels.push((channel, pixel_type));
+            },
+            ("compression", _) if value.first() != Some(&NO_COMPRESSION) => {
+                return Err(invalid("compressed EXR files are not supported"));
+            }
+            ("dataWindow", _) => {
+                let mut w = [0; 4];
+                v.read_i32_into::<LittleEndian>(&mut w)?;
+                window = Some(w);
+            }
+            (_, "string") => {
+                attributes.push((
+                    name,
+                    String::from_utf8(value).map_err(|_| invalid("wrong EXR string"))?,
+                ));
+            }
+            _ => {}
+        }
+    }
+    let window = window.ok_or_else(|| invalid("missing EXR data window"))?;
+    let extent = |min: i32, max: i32| match i64::from(max) - i64::from(min) + 1 {
+        e if e > 0 && e <= i64::from(u32::MAX) => Ok(e as u32),
+        _ => Err(invalid("wrong EXR data window")),
+    };
+    let size = Vector2::new(extent(window[0], window[2])?, extent(window[1], window[3])?);
+    if u64::from(size.x) * u64::from(size.y) > data.len() as u64 {
+        return Err(invalid("EXR data window larger than the file"));
+    }
+
+    // Layers in the order of their channels
+    let mut layers: Vec<(String, Bitmap)> = vec![];
+    let mut mapping = vec![];
+    for (name, _) in &channels {
+        mapping.push(channel_layer(name).map(|(layer, c)| {
+            let id = match layers.iter().position(|(l, _)| *l == layer) {
+                Some(id) => id,
+                None => {
+                    layers.push((layer, Bitmap::new(size)));
+                    layers.len() - 1
+                }
+            };
+            (id, c)
+        }));
+    }
+
+    // Scanlines (the blocks are found with the offset table)
+    let mut offsets = vec![0; size.y as usize];
+    f.read_u64_into::<LittleEndian>(&mut offsets)?;
+    for offset in offsets {
+        f.set_position(offset);
+        let y = i64::from(f.read_i32::<LittleEndian>()?) - i64::from(window[1]);
+        f.read_i32::<LittleEndian>()?;
+        if !(0..i64::from(size.y)).contains(&y) {
+            return Err(invalid("wrong EXR scanline"));
+        }
+        let y = y as u32;
+        for ((_, pixel_type), m) in channels.iter().zip(&mapping) {
+            for x in 0..size.x {
+                let v = match *pixel_type {
+                    PIXEL_TYPE_UINT => f.read_u32::<LittleEndian>()? as f32,
+                    PIXEL_TYPE_HALF => half_to_f32(f.read_u16::<LittleEndian>()?),
+                    PIXEL_TYPE_FLOAT => f.read_f32::<LittleEndian>()?,
+                    _ => return Err(invalid("unknown EXR pixel type")),
+                };
+                if let Some((id, c)) = m {
+                    let v = match c {
+                        0 => Color::new(v, 0.0, 0.0),
+                        1 => Color::new(0.0, v, 0.0),
+                        _ => Color::new(0.0, 0.0, v),
+                    };
+                    layers[*id].1.accumulate(Point2::new(x, y), v);
+                }
+            }
+        }
+    }
+
+    Ok(LayeredImage {
+        size,
+        layers,
+        attributes,
+    })
+}
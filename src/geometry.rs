@@ -1,5 +1,6 @@
 use crate::bsdfs;
 use crate::math::{uniform_sample_triangle, Distribution1D, Distribution1DConstruct};
+use crate::scene_loader::SceneLoadError;
 use crate::structure::*;
 use cgmath::*;
 use std;
@@ -9,9 +10,12 @@ use tobj;
 /// Read obj file format and build a list of meshes
 /// for now, only add diffuse color
 /// custom texture coordinates or normals are not supported yet
-pub fn load_obj(file_name: &std::path::Path) -> Result<Vec<Mesh>, tobj::LoadError> {
+pub fn load_obj(file_name: &std::path::Path) -> Result<Vec<Mesh>, SceneLoadError> {
     println!("Try to load {:?}", file_name);
-    let (models, materials) = tobj::load_obj(file_name)?;
+    let (models, materials) = tobj::load_obj(file_name).map_err(|error| SceneLoadError::Obj {
+        file: file_name.to_string_lossy().to_string(),
+        error,
+    })?;
     let wk = file_name.parent().unwrap();
     info!("Working directory for loading the scene: {:?}", wk);
 
@@ -65,10 +69,14 @@ pub fn load_obj(file_name: &std::path::Path) -> Result<Vec<Mesh>, tobj::LoadErro
                 let mat = &materials[id];
                 if !mat.diffuse_texture.is_empty() {
                     let path_texture = wk.join(&mat.diffuse_texture);
+                    let texture = bsdfs::Texture::load(&path_texture).map_err(|error| {
+                        SceneLoadError::Io {
+                            file: path_texture.to_string_lossy().to_string(),
+                            error,
+                        }
+                    })?;
                     Box::new(bsdfs::diffuse::BSDFDiffuse {
-                        diffuse: bsdfs::BSDFColor::TextureColor(bsdfs::Texture::load(
-                            path_texture.to_str().unwrap(),
-                        )),
+                        diffuse: bsdfs::BSDFColor::TextureColor(texture),
                     })
                 } else {
                     let diffuse_color = Color::new(mat.diffuse[0], mat.diffuse[1], mat.diffuse[2]);
@@ -100,6 +108,10 @@ pub struct Mesh {
     pub bsdf: Box<dyn bsdfs::BSDF>,
     pub emission: Color,
     pub cdf: Distribution1D,
+    // Participating media inside and outside the mesh
+    // (index inside Scene::media, None is the scene medium)
+    pub interior: Option<usize>,
+    pub exterior: Option<usize>,
 }
 
 impl Mesh {
@@ -110,17 +122,7 @@ impl Mesh {
         normals: Option<Vec<Vector3<f32>>>,
         uv: Option<Vec<Vector2<f32>>>,
     ) -> Mesh {
-        // Construct the mesh CDF
-        let mut dist_const = Distribution1DConstruct::new(indices.len());
-        for id in &indices {
-            let v0 = vertices[id.x];
-            let v1 = vertices[id.y];
-            let v2 = vertices[id.z];
-
-            let area = (v1 - v0).cross(v2 - v0).magnitude() * 0.5;
-            dist_const.add(area);
-        }
-
+        let cdf = area_cdf(&vertices, &indices);
         Mesh {
             name,
             vertices,
@@ -131,7 +133,9 @@ impl Mesh {
                 diffuse: bsdfs::BSDFColor::UniformColor(Color::zero()),
             }),
             emission: Color::zero(),
-            cdf: dist_const.normalize(),
+            cdf,
+            interior: None,
+            exterior: None,
         }
     }
 
@@ -171,4 +175,36 @@ impl Mesh {
     pub fn is_light(&self) -> bool {
         !self.emission.is_zero()
     }
+
+    pub fn have_media(&self) -> bool {
+        self.interior.is_some() || self.exterior.is_some()
+    }
+
+    /// Move the mesh to the world (the matrix needs to be invertible)
+    pub fn transform(&mut self, m: &Matrix4<f32>) {
+        for v in &mut self.vertices {
+            *v = m.transform_point(Point3::from_vec(*v)).to_vec();
+        }
+        if let Some(ref mut normals) = self.normals {
+            let m_normal = m.invert().unwrap().transpose();
+            for n in normals {
+                *n = m_normal.transform_vector(*n).normalize();
+            }
+        }
+        self.cdf = area_cdf(&self.vertices, &self.indices);
+    }
+}
+
+// Triangle selection proportional to their area
+fn area_cdf(vertices: &[Vector3<f32>], indices: &[Vector3<usize>]) -> Distribution1D {
+    let mut dist_const = Distribution1DConstruct::new(indices.len());
+    for id in indices {
+        let v0 = vertices[id.x];
+        let v1 = vertices[id.y];
+        let v2 = vertices[id.z];
+
+        let area = (v1 - v0).cross(v2 - v0).magnitude() * 0.5;
+        dist_const.add(area);
+    }
+    dist_const.normalize()
 }
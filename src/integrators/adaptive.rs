@@ -0,0 +1,237 @@
+use crate::integrators::film::Film;
+use crate::integrators::*;
+use cgmath::{Point2, Vector2};
+use rayon::prelude::*;
+
+/// Adaptive sampling used by compute_mc
+/// A pilot pass estimates the variance of each pixel, then the remaining samples
+/// (Scene::nb_samples per pixel in average) are distributed in rounds
+/// following the relative error of the pixels
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AdaptiveSampling {
+    /// Number of samples per pixel of the pilot pass (and of each round in average)
+    pub pilot_samples: usize,
+    /// Number of samples before a pixel can be considered converged
+    /// (at least the pilot samples)
+    pub min_samples: usize,
+    /// Fraction of each round distributed uniformly over the pixels not converged
+    /// (the rest follows their relative error)
+    pub uniform_fraction: f32,
+    /// Relative error under which a pixel is considered converged
+    pub target_error: f32,
+    /// Time out in seconds
+    pub time_out: Option<usize>,
+}
+
+impl AdaptiveSampling {
+    pub fn new(target_error: f32, time_out: Option<usize>) -> AdaptiveSampling {
+        AdaptiveSampling {
+            pilot_samples: 4,
+            min_samples: 8,
+            uniform_fraction: 0.25,
+            target_error,
+            time_out,
+        }
+    }
+}
+
+// Rendering state of an image block (kept between the rounds)
+struct AdaptiveBlock {
+    pos: Point2<u32>,
+    size: Vector2<u32>,
+    film: Film,
+    sampler: Box<dyn Sampler>,
+    estimators: Vec<VarianceEstimator>,
+}
+
+// Relative standard error of the pixel estimate
+fn relative_error(e: &VarianceEstimator) -> f32 {
+    if e.sample_count < 2 {
+        return f32::INFINITY;
+    }
+    (e.variance() / e.sample_count as f32).sqrt() / e.mean.abs().max(1e-3)
+}
+
+pub fn compute_adaptive<T: IntegratorMC + Integrator>(
+    int: &T,
+    accel: &dyn Acceleration,
+    scene: &Scene,
+    adaptive: &AdaptiveSampling,
+) -> BufferCollection {
+    assert!(
+        scene.nb_samples >= 2,
+        "adaptive sampling needs at least 2 samples per pixel"
+    );
+    let start = Instant::now();
+    let buffernames = aov_buffernames(scene);
+    let pilot = adaptive.pilot_samples.max(2).min(scene.nb_samples);
+    // A pixel cannot be converged before this number of samples
+    // (e.g., all the pilot samples can hit the emitter near its silhouette)
+    let min_samples = adaptive.min_samples.max(pilot).min(scene.nb_samples);
+    let uniform = f64::from(adaptive.uniform_fraction.max(0.0).min(1.0));
+
+    // The blocks keep their samplers: the sample indices continue at each round
+    let seed = scene.pass_seed();
+    let mut blocks = generate_img_blocks(scene, &buffernames)
+        .iter()
+        .map(|im_block| AdaptiveBlock {
+            pos: im_block.pos,
+            size: im_block.size,
+            film: Film::new(scene.filter, im_block.pos, im_block.size, &buffernames),
+            sampler: scene
+                .sampler
+                .create(pixel_seed(seed, im_block.pos), scene.nb_samples),
+            estimators: vec![
+                VarianceEstimator::default();
+                (im_block.size.x * im_block.size.y) as usize
+            ],
+        })
+        .collect::<Vec<_>>();
+    let nb_pixels = scene.image_region().nb_pixels();
+    let mut budget = scene.nb_samples * nb_pixels;
+
+    let pool = generate_pool(scene);
+    for round in 0.. {
+        // Number of samples of each pixel for this round
+        let nb_round = (pilot * nb_pixels).min(budget);
+        let samples = if round == 0 {
+            blocks
+                .iter()
+                .map(|b| vec![pilot; b.estimators.len()])
+                .collect::<Vec<_>>()
+        } else {
+            // Error of the pixels which are not converged
+            let errors = blocks
+                .iter()
+                .map(|b| {
+                    b.estimators
+                        .iter()
+                        .map(|e| {
+                            let error = relative_error(e);
+                            if error > adaptive.target_error
+                                || (e.sample_count as usize) < min_samples
+                            {
+                                Some(f64::from(error))
+                            } else {
+                                None
+                            }
+                        })
+                        .collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>();
+            let nb_active = errors.iter().flatten().flatten().count();
+            if nb_active == 0 {
+                info!("Adaptive sampling: all pixels converged");
+                break;
+            }
+            let total = errors.iter().flatten().flatten().sum::<f64>();
+            // A part of the samples is distributed uniformly
+            // so the pixels where the pilot pass underestimated the variance still progress
+            // (the fractional parts are carried to the next pixels)
+            let mut carry = 0.0;
+            errors
+                .iter()
+                .map(|errors| {
+                    errors
+                        .iter()
+                        .map(|e| match e {
+                            None => 0,
+                            Some(e) => {
+                                let ratio = if total > 0.0 { e / total } else { 0.0 };
+                                carry += nb_round as f64
+                                    * (uniform / nb_active as f64 + (1.0 - uniform) * ratio);
+                                let n = carry.floor();
+                                carry -= n;
+                                n as usize
+                            }
+                        })
+                        .collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>()
+        };
+        let nb_round = samples.iter().flatten().sum::<usize>();
+        if nb_round == 0 {
+            break;
+        }
+        budget -= nb_round;
+
+        pool.install(|| {
+            blocks
+                .par_iter_mut()
+                .zip(samples.par_iter())
+                .for_each(|(block, samples)| {
+                    let AdaptiveBlock {
+                        pos,
+                        size,
+                        ref mut film,
+                        ref mut sampler,
+                        ref mut estimators,
+                    } = *block;
+                    let light_sampling = scene.emitters_sampler();
+                    for iy in 0..size.y {
+                        for ix in 0..size.x {
+                            let id = (iy * size.x + ix) as usize;
+                            let pix = Point2::new(ix + pos.x, iy + pos.y);
+                            for _ in 0..samples[id] {
+                                sampler.start_sample(pix, estimators[id].sample_count as usize);
+                                let offset = sampler.next2d();
+                                let p =
+                                    Point2::new(pix.x as f32 + offset.x, pix.y as f32 + offset.y);
+                                let sensor = scene.camera.sample_sensor(sampler.as_mut());
+                                let c = int.compute_pixel(
+                                    p,
+                                    sensor,
+                                    accel,
+                                    scene,
+                                    sampler.as_mut(),
+                                    &light_sampling,
+                                );
+                                estimators[id].add(c.luminance());
+                                let mut contribs = vec![(buffernames[0].clone(), c)];
+                                contribs.extend(int.compute_aovs(p, sensor, accel, scene));
+                                film.add_sample(p, &contribs);
+                            }
+                        }
+                    }
+                });
+        });
+
+        let elapsed = start.elapsed();
+        info!(
+            "Adaptive sampling round {}: {} samples ({} remaining) in {:?}",
+            round, nb_round, budget, elapsed
+        );
+        let finished = adaptive
+            .time_out
+            .is_some_and(|t| elapsed.as_secs() >= t as u64);
+        if budget == 0 || finished {
+            break;
+        }
+    }
+
+    // Fill the image and the number of samples of each pixel
+    let mut image = Film::new(
+        scene.filter,
+        Point2::new(0, 0),
+        *scene.camera.size(),
+        &buffernames,
+    );
+    for block in &blocks {
+        image.merge(&block.film);
+    }
+    let mut image = image.develop();
+    image.register("samples".to_string());
+    for block in &blocks {
+        for iy in 0..block.size.y {
+            for ix in 0..block.size.x {
+                let e = &block.estimators[(iy * block.size.x + ix) as usize];
+                image.accumulate(
+                    Point2::new(ix + block.pos.x, iy + block.pos.y),
+                    Color::value(e.sample_count as f32),
+                    "samples",
+                );
+            }
+        }
+    }
+    image
+}
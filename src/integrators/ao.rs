@@ -14,14 +14,14 @@ impl Integrator for IntegratorAO {
 impl IntegratorMC for IntegratorAO {
     fn compute_pixel(
         &self,
-        (ix, iy): (u32, u32),
+        pix: Point2<f32>,
+        sensor: Point2<f32>,
         accel: &dyn Acceleration,
         scene: &Scene,
         sampler: &mut dyn Sampler,
         _: &EmitterSampler,
     ) -> Color {
-        let pix = Point2::new(ix as f32 + sampler.next(), iy as f32 + sampler.next());
-        let ray = scene.camera.generate(pix);
+        let ray = scene.camera.generate_sample(pix, sensor);
 
         // Do the intersection for the first path
         let its = match accel.trace(&ray) {
@@ -0,0 +1,103 @@
+use crate::integrators::*;
+
+/// Auxiliary output (AOV) of the first surface visible from the sensor
+/// Each AOV is written inside its own buffer (see AOV::name)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AOV {
+    /// Reflectance of the BSDF (see BSDF::albedo)
+    Albedo,
+    /// Shading normal (world space)
+    Normal,
+    /// Distance from the sensor
+    Depth,
+    /// World position
+    Position,
+    /// Texture coordinates (u, v, 0)
+    UV,
+    /// Index of the mesh inside Scene::meshes (-1 if nothing is visible)
+    MeshID,
+}
+
+impl AOV {
+    pub fn from_name(name: &str) -> Option<AOV> {
+        match name {
+            "albedo" => Some(AOV::Albedo),
+            "normal" => Some(AOV::Normal),
+            "depth" => Some(AOV::Depth),
+            "position" => Some(AOV::Position),
+            "uv" => Some(AOV::UV),
+            "mesh_id" => Some(AOV::MeshID),
+            _ => None,
+        }
+    }
+
+    /// The pixel value is the average of its samples
+    /// (otherwise the value of the sample nearest to the pixel center)
+    pub fn averaged(&self) -> bool {
+        *self != AOV::MeshID
+    }
+
+    /// Name of the buffer
+    pub fn name(&self) -> &'static str {
+        match self {
+            AOV::Albedo => "albedo",
+            AOV::Normal => "normal",
+            AOV::Depth => "depth",
+            AOV::Position => "position",
+            AOV::UV => "uv",
+            AOV::MeshID => "mesh_id",
+        }
+    }
+}
+
+/// Values of the AOVs along the primary ray of the sample
+/// The null surfaces (media boundaries) are skipped
+pub fn first_hit_aovs(
+    aovs: &[AOV],
+    ray: &Ray,
+    accel: &dyn Acceleration,
+    scene: &Scene,
+) -> Vec<(String, Color)> {
+    if aovs.is_empty() {
+        return vec![];
+    }
+
+    let mut ray = *ray;
+    let mut dist = 0.0;
+    let its = loop {
+        match accel.trace(&ray) {
+            None => break None,
+            Some(its) => {
+                dist += its.dist;
+                if its.mesh.bsdf.is_null() {
+                    ray = Ray::new(its.p, ray.d);
+                } else {
+                    break Some(its);
+                }
+            }
+        }
+    };
+
+    aovs.iter()
+        .map(|aov| {
+            let c = match its {
+                None => match aov {
+                    AOV::MeshID => Color::value(-1.0),
+                    _ => Color::zero(),
+                },
+                Some(ref its) => match aov {
+                    AOV::Albedo => its.mesh.bsdf.albedo(&its.uv),
+                    AOV::Normal => Color::new(its.n_s.x, its.n_s.y, its.n_s.z),
+                    AOV::Depth => Color::value(dist),
+                    AOV::Position => Color::new(its.p.x, its.p.y, its.p.z),
+                    AOV::UV => match its.uv {
+                        Some(uv) => Color::new(uv.x, uv.y, 0.0),
+                        None => Color::zero(),
+                    },
+                    AOV::MeshID => Color::value(its.mesh_id as f32),
+                },
+            };
+            (aov.name().to_string(), c)
+        })
+        .collect()
+}
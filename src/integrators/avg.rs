@@ -1,10 +1,184 @@
+use crate::integrators::denoise::{Denoiser, HALF_BUFFERS};
 use crate::integrators::*;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use std;
-use std::io::Write;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::time::Duration;
 
 pub struct IntegratorAverage {
     pub time_out: Option<usize>, //< Time out in seconds
     pub integrator: IntegratorType,
+    /// Minimum time (in seconds) between two checkpoints (None: no checkpoint)
+    pub checkpoint: Option<usize>,
+    /// Checkpoint to continue the accumulation from (see Checkpoint::check)
+    pub resume: Option<Checkpoint>,
+    /// Denoise the final average (the odd and even passes give the half buffers)
+    /// the images saved at each iteration are not denoised
+    pub denoiser: Option<Denoiser>,
+    // Number of passes averaged (including the resumed ones)
+    iteration: usize,
+}
+
+impl IntegratorAverage {
+    pub fn new(
+        time_out: Option<usize>,
+        integrator: IntegratorType,
+        checkpoint: Option<usize>,
+        resume: Option<Checkpoint>,
+    ) -> IntegratorAverage {
+        IntegratorAverage {
+            time_out,
+            integrator,
+            checkpoint,
+            resume,
+            denoiser: None,
+            iteration: 0,
+        }
+    }
+
+    /// Number of passes averaged by the last compute() call
+    /// (including the passes restored from the checkpoint)
+    pub fn iteration(&self) -> usize {
+        self.iteration
+    }
+}
+
+/// State of the progressive rendering saved on the disk
+/// the buffers contain the current average (before gradient reconstruction)
+pub struct Checkpoint {
+    pub iteration: usize,
+    pub elapsed: Duration,
+    pub bitmap: BufferCollection,
+}
+
+const CHECKPOINT_MAGIC: &[u8; 4] = b"RLCP";
+const CHECKPOINT_VERSION: u32 = 1;
+
+impl Checkpoint {
+    /// Write the checkpoint (a temporary file is renamed
+    /// so an interruption never corrupts the previous checkpoint)
+    pub fn save(&self, filename: &str) -> std::io::Result<()> {
+        let tmp_filename = format!("{}.tmp", filename);
+        {
+            let mut f = BufWriter::new(std::fs::File::create(&tmp_filename)?);
+            f.write_all(CHECKPOINT_MAGIC)?;
+            f.write_u32::<LittleEndian>(CHECKPOINT_VERSION)?;
+            f.write_u64::<LittleEndian>(self.iteration as u64)?;
+            f.write_u64::<LittleEndian>(self.elapsed.as_secs())?;
+            f.write_u32::<LittleEndian>(self.elapsed.subsec_nanos())?;
+            f.write_u32::<LittleEndian>(self.bitmap.pos.x)?;
+            f.write_u32::<LittleEndian>(self.bitmap.pos.y)?;
+            f.write_u32::<LittleEndian>(self.bitmap.size.x)?;
+            f.write_u32::<LittleEndian>(self.bitmap.size.y)?;
+            f.write_u32::<LittleEndian>(self.bitmap.values.len() as u32)?;
+            // Sort the buffers to have a deterministic file
+            let mut names = self.bitmap.values.keys().collect::<Vec<_>>();
+            names.sort();
+            for name in names {
+                f.write_u32::<LittleEndian>(name.len() as u32)?;
+                f.write_all(name.as_bytes())?;
+                for c in &self.bitmap.values[name].colors {
+                    f.write_f32::<LittleEndian>(c.r)?;
+                    f.write_f32::<LittleEndian>(c.g)?;
+                    f.write_f32::<LittleEndian>(c.b)?;
+                }
+            }
+            f.flush()?;
+        }
+        std::fs::rename(&tmp_filename, filename)
+    }
+
+    pub fn load(filename: &str) -> std::io::Result<Checkpoint> {
+        let invalid = |msg: &str| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("{}: {}", filename, msg),
+            )
+        };
+        let mut f = BufReader::new(std::fs::File::open(filename)?);
+        let mut magic = [0; 4];
+        f.read_exact(&mut magic)?;
+        if &magic != CHECKPOINT_MAGIC {
+            return Err(invalid("not a checkpoint file"));
+        }
+        if f.read_u32::<LittleEndian>()? != CHECKPOINT_VERSION {
+            return Err(invalid("unsupported checkpoint version"));
+        }
+        let iteration = f.read_u64::<LittleEndian>()? as usize;
+        let elapsed = {
+            let secs = f.read_u64::<LittleEndian>()?;
+            let nanos = f.read_u32::<LittleEndian>()?;
+            Duration::new(secs, nanos)
+        };
+        let pos = Point2::new(f.read_u32::<LittleEndian>()?, f.read_u32::<LittleEndian>()?);
+        let size = Vector2::new(f.read_u32::<LittleEndian>()?, f.read_u32::<LittleEndian>()?);
+        let mut bitmap = BufferCollection::new(pos, size, &[]);
+        let nb_buffers = f.read_u32::<LittleEndian>()?;
+        for _ in 0..nb_buffers {
+            let name = {
+                let mut name = vec![0; f.read_u32::<LittleEndian>()? as usize];
+                f.read_exact(&mut name)?;
+                String::from_utf8(name).map_err(|_| invalid("wrong buffer name"))?
+            };
+            let mut buffer = Bitmap::new(size);
+            for c in &mut buffer.colors {
+                c.r = f.read_f32::<LittleEndian>()?;
+                c.g = f.read_f32::<LittleEndian>()?;
+                c.b = f.read_f32::<LittleEndian>()?;
+            }
+            bitmap.values.insert(name, buffer);
+        }
+        Ok(Checkpoint {
+            iteration,
+            elapsed,
+            bitmap,
+        })
+    }
+
+    /// Check that the integrator can continue the accumulation from the checkpoint
+    /// (not progressive, same image size and all its buffers are saved)
+    /// the half buffers are needed to continue a denoised accumulation
+    pub fn check(
+        &self,
+        scene: &Scene,
+        integrator: &IntegratorType,
+        denoise: bool,
+    ) -> Result<(), String> {
+        let mut buffernames = match integrator {
+            IntegratorType::Primal(v) if v.progressive() => {
+                // The integrator state is not inside the checkpoint
+                return Err("impossible to resume a progressive integrator".to_string());
+            }
+            IntegratorType::Primal(_) => {
+                let mut buffernames = aov_buffernames(scene);
+                if scene.adaptive.is_some() {
+                    buffernames.push("samples".to_string());
+                }
+                buffernames
+            }
+            IntegratorType::Gradient(v) => gradient::gradient_buffernames(v.reconstruct()).1,
+        };
+        if denoise {
+            buffernames.extend(HALF_BUFFERS.iter().map(|name| name.to_string()));
+        }
+        if self.bitmap.size != *scene.camera.size() {
+            return Err(format!(
+                "the checkpoint size {:?} does not match the image size {:?}",
+                self.bitmap.size,
+                scene.camera.size()
+            ));
+        }
+        match buffernames
+            .into_iter()
+            .find(|name| !self.bitmap.values.contains_key(name))
+        {
+            Some(name) => Err(format!(
+                "the checkpoint does not contain the {} buffer",
+                name
+            )),
+            None => Ok(()),
+        }
+    }
 }
 
 impl Integrator for IntegratorAverage {
@@ -17,26 +191,94 @@ impl Integrator for IntegratorAverage {
         let mut base_output_img_path = scene.output_img_path.clone();
         base_output_img_path.truncate(scene.output_img_path.len() - output_ext.len() - 1);
         info!("Base output name: {:?}", base_output_img_path);
-
-        // Open an CSV file for register the time
-        let mut csv = std::fs::File::create(base_output_img_path.clone() + "_time.csv").unwrap();
+        let checkpoint_path = base_output_img_path.clone() + "_checkpoint.bin";
 
         // Other values
         let mut bitmap: Option<BufferCollection> = None;
+        let progressive = self.integrator.progressive();
         let mut iteration = 1;
-        let start = Instant::now();
+        let mut previous_elapsed = Duration::new(0, 0);
 
+        // Restore the previous accumulation (see Checkpoint::check)
+        let resumed = self.resume.is_some();
+        if let Some(checkpoint) = self.resume.take() {
+            info!(
+                "Resume from the checkpoint ({} iterations, {:?} secs)",
+                checkpoint.iteration,
+                checkpoint.elapsed.as_secs()
+            );
+            iteration = checkpoint.iteration + 1;
+            // Do not reuse the random numbers of the previous passes
+            scene.skip_passes(checkpoint.iteration as u64);
+            previous_elapsed = checkpoint.elapsed;
+            let mut checkpoint_bitmap = checkpoint.bitmap;
+            if self.denoiser.is_none() {
+                for name in &HALF_BUFFERS {
+                    checkpoint_bitmap.values.remove(*name);
+                }
+            }
+            bitmap = Some(checkpoint_bitmap);
+        }
+
+        // Open an CSV file for register the time
+        // (continue the previous one in case of resume)
+        let mut csv = std::fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(resumed)
+            .truncate(!resumed)
+            .open(base_output_img_path.clone() + "_time.csv")
+            .unwrap();
+
+        let start = Instant::now();
+        let mut last_checkpoint = previous_elapsed;
         loop {
-            let new_bitmap = match self.integrator {
+            let mut new_bitmap = match self.integrator {
                 IntegratorType::Primal(ref mut v) => v.compute(accel, scene),
                 IntegratorType::Gradient(ref mut v) => v.compute_gradients(accel, scene),
             };
-            if iteration == 1 {
+            // The half buffers of the denoiser are averaged separately
+            let halves = match (&self.denoiser, bitmap.as_mut()) {
+                (None, _) => vec![],
+                (Some(_), None) => vec![Bitmap::new(new_bitmap.size); 2],
+                (Some(_), Some(bitmap)) => HALF_BUFFERS
+                    .iter()
+                    .map(|name| bitmap.values.remove(*name).unwrap())
+                    .collect(),
+            };
+            let primal = if halves.is_empty() {
+                None
+            } else {
+                Some(new_bitmap.values["primal"].clone())
+            };
+            if iteration == 1 || progressive {
                 bitmap = Some(new_bitmap);
             } else {
-                bitmap.as_mut().unwrap().scale(iteration as f32);
+                // Only the buffers of the checkpoint are averaged
+                // (e.g. the BDPT strategies are not saved inside)
+                {
+                    let bitmap = bitmap.as_ref().unwrap();
+                    new_bitmap
+                        .values
+                        .retain(|k, _| bitmap.values.contains_key(k));
+                }
+                // The running average is over the previous (iteration - 1) passes
+                bitmap.as_mut().unwrap().scale((iteration - 1) as f32);
                 bitmap.as_mut().unwrap().accumulate_bitmap(&new_bitmap);
-                bitmap.as_mut().unwrap().scale(1.0 / (iteration + 1) as f32);
+                bitmap.as_mut().unwrap().scale(1.0 / iteration as f32);
+            }
+            if let Some(primal) = primal {
+                // The odd passes go to the first half buffer, the even ones to the second
+                let bitmap = bitmap.as_mut().unwrap();
+                for (i, (name, mut half)) in HALF_BUFFERS.iter().zip(halves).enumerate() {
+                    if i == (iteration - 1) % 2 {
+                        let n = ((iteration + 1) / 2) as f32;
+                        half.scale(n - 1.0);
+                        half.accumulate_bitmap(&primal, Point2::new(0, 0));
+                        half.scale(1.0 / n);
+                    }
+                    bitmap.values.insert(name.to_string(), half);
+                }
             }
 
             // Save the bitmap for the current iteration
@@ -57,7 +299,8 @@ impl Integrator for IntegratorAverage {
             };
 
             // Check the time elapsed when we started the rendering...
-            let elapsed = start.elapsed();
+            // (including the time of the resumed rendering)
+            let elapsed = previous_elapsed + start.elapsed();
             match self.time_out {
                 None => info!("Total time (no timeout): {:?} secs", elapsed.as_secs()),
                 Some(t) => info!("Total time: {:?} / {:?} secs", elapsed.as_secs(), t),
@@ -65,10 +308,33 @@ impl Integrator for IntegratorAverage {
             // Write the rendering time
             writeln!(csv, "{}.{},", elapsed.as_secs(), elapsed.subsec_millis()).unwrap();
 
-            if self
+            let finished = self
                 .time_out
-                .map_or(false, |t| elapsed.as_secs() >= t as u64)
-            {
+                .map_or(false, |t| elapsed.as_secs() >= t as u64);
+
+            // Save the accumulation state
+            if let Some(t) = self.checkpoint {
+                if finished || (elapsed - last_checkpoint).as_secs() >= t as u64 {
+                    let checkpoint = Checkpoint {
+                        iteration,
+                        elapsed,
+                        bitmap: bitmap.take().unwrap(),
+                    };
+                    if let Err(e) = checkpoint.save(&checkpoint_path) {
+                        warn!(
+                            "Impossible to save the checkpoint {}: {}",
+                            checkpoint_path, e
+                        );
+                    } else {
+                        info!("Checkpoint saved: {}", checkpoint_path);
+                    }
+                    bitmap = Some(checkpoint.bitmap);
+                    last_checkpoint = elapsed;
+                }
+            }
+
+            if finished {
+                self.iteration = iteration;
                 break;
             }
             // Update the number of iterations
@@ -76,16 +342,37 @@ impl Integrator for IntegratorAverage {
         }
 
         if let Some(bitmap) = bitmap {
-            match &self.integrator {
+            let mut img = match &self.integrator {
                 IntegratorType::Primal(_) => bitmap,
                 IntegratorType::Gradient(v) => {
                     info!("Do the final reconstruction");
                     v.reconstruct().reconstruct(scene, &bitmap)
                 }
+            };
+            if let Some(denoiser) = &self.denoiser {
+                if self.iteration < 2 {
+                    warn!("Only one pass is averaged, the image is not denoised");
+                } else {
+                    let denoised = denoiser
+                        .denoise_buffers(&img)
+                        .expect("the half buffers are averaged");
+                    img.values.insert("primal".to_string(), denoised);
+                }
             }
+            img
         } else {
             let buffernames = vec![String::from("primal")];
             BufferCollection::new(Point2::new(0, 0), *scene.camera.size(), &buffernames)
         }
     }
+
+    fn samples_per_pixel(&self, scene: &Scene, img: &BufferCollection) -> f32 {
+        let spp = self.integrator.samples_per_pixel(scene, img);
+        if self.integrator.progressive() {
+            // The last image already includes all the passes
+            spp
+        } else {
+            spp * self.iteration as f32
+        }
+    }
 }
@@ -0,0 +1,298 @@
+use crate::integrators::*;
+use cgmath::{Point2, Vector2};
+use rayon::prelude::*;
+
+/// Feature buffers used by the denoiser (when they are available)
+pub const FEATURE_BUFFERS: [&str; 3] = ["albedo", "normal", "depth"];
+/// Half buffers (independent estimates) used to estimate the variance
+pub const HALF_BUFFERS: [&str; 2] = ["primal_0", "primal_1"];
+
+/// Non-local means denoiser guided by the feature buffers
+/// The color weights use the variance estimated from two half buffers
+/// (Rousselle et al. 2012, "Adaptive rendering with non-local means filtering")
+/// the feature weights are normalized by the feature gradients
+/// (Rousselle et al. 2013, "Robust denoising using feature and color information")
+#[derive(Clone, Copy, Debug)]
+pub struct Denoiser {
+    /// Radius of the search window (in pixels)
+    pub window_radius: u32,
+    /// Radius of the patches compared for the color weights
+    pub patch_radius: u32,
+    /// Sensitivity to the color differences (higher: smoother)
+    pub k_color: f32,
+    /// Sensitivity to the feature differences (higher: smoother)
+    pub k_features: f32,
+}
+
+impl Default for Denoiser {
+    fn default() -> Self {
+        Denoiser {
+            window_radius: 10,
+            patch_radius: 3,
+            k_color: 0.45,
+            k_features: 0.6,
+        }
+    }
+}
+
+// Image stored as RGB values
+type Image = Vec<[f32; 3]>;
+
+fn to_image(b: &Bitmap) -> Image {
+    b.colors.iter().map(|c| [c.r, c.g, c.b]).collect()
+}
+
+// Box filter (separable, clamped at the borders)
+fn box_filter(v: &[f32], size: Vector2<u32>, radius: i32) -> Vec<f32> {
+    let (w, h) = (size.x as i32, size.y as i32);
+    let pass = |v: &[f32], horizontal: bool| -> Vec<f32> {
+        let mut out = vec![0.0; v.len()];
+        out.par_chunks_mut(w as usize)
+            .enumerate()
+            .for_each(|(y, row)| {
+                let y = y as i32;
+                for (x, o) in row.iter_mut().enumerate() {
+                    let x = x as i32;
+                    let mut sum = 0.0;
+                    let mut count = 0;
+                    for i in -radius..=radius {
+                        let (sx, sy) = if horizontal { (x + i, y) } else { (x, y + i) };
+                        if sx >= 0 && sy >= 0 && sx < w && sy < h {
+                            sum += v[(sy * w + sx) as usize];
+                            count += 1;
+                        }
+                    }
+                    *o = sum / count as f32;
+                }
+            });
+        out
+    };
+    pass(&pass(v, true), false)
+}
+
+impl Denoiser {
+    /// Denoise the average of two independent estimates (same number of samples)
+    /// the feature buffers (e.g., albedo, normal, depth) can be empty
+    pub fn denoise(&self, half: (&Bitmap, &Bitmap), features: &[&Bitmap]) -> Bitmap {
+        let size = half.0.size;
+        assert_eq!(half.1.size, size);
+        let (w, h) = (size.x as i32, size.y as i32);
+        let nb_pixels = (size.x * size.y) as usize;
+
+        // Mean and variance of the mean (smoothed) from the half buffers
+        let (a, b) = (to_image(half.0), to_image(half.1));
+        let mean = a
+            .iter()
+            .zip(&b)
+            .map(|(a, b)| {
+                [
+                    0.5 * (a[0] + b[0]),
+                    0.5 * (a[1] + b[1]),
+                    0.5 * (a[2] + b[2]),
+                ]
+            })
+            .collect::<Image>();
+        let variance = {
+            let mut variance = vec![[0.0; 3]; nb_pixels];
+            for c in 0..3 {
+                let v = a
+                    .iter()
+                    .zip(&b)
+                    .map(|(a, b)| 0.25 * (a[c] - b[c]) * (a[c] - b[c]))
+                    .collect::<Vec<_>>();
+                for (i, v) in box_filter(&v, size, 1).into_iter().enumerate() {
+                    variance[i][c] = v;
+                }
+            }
+            variance
+        };
+
+        // Features and their squared gradient norm
+        let features = features
+            .iter()
+            .map(|f| {
+                assert_eq!(f.size, size);
+                let f = to_image(f);
+                let gradient = (0..nb_pixels)
+                    .map(|i| {
+                        let (x, y) = (i as i32 % w, i as i32 / w);
+                        let at = |x: i32, y: i32| {
+                            f[(y.max(0).min(h - 1) * w + x.max(0).min(w - 1)) as usize]
+                        };
+                        let (x0, x1, y0, y1) =
+                            (at(x - 1, y), at(x + 1, y), at(x, y - 1), at(x, y + 1));
+                        (0..3)
+                            .map(|c| {
+                                let (dx, dy) = (0.5 * (x1[c] - x0[c]), 0.5 * (y1[c] - y0[c]));
+                                dx * dx + dy * dy
+                            })
+                            .sum::<f32>()
+                    })
+                    .collect::<Vec<_>>();
+                (f, gradient)
+            })
+            .collect::<Vec<_>>();
+
+        // Accumulate the weighted neighbors for each offset of the search window
+        const EPSILON: f32 = 1e-10;
+        const TAU: f32 = 1e-3;
+        let k2_color = self.k_color * self.k_color;
+        let k2_features = self.k_features * self.k_features;
+        let r = self.window_radius as i32;
+        let mut sum = vec![[0.0; 3]; nb_pixels];
+        let mut weights = vec![0.0; nb_pixels];
+        for oy in -r..=r {
+            for ox in -r..=r {
+                let neighbor = |i: usize| -> Option<usize> {
+                    let (x, y) = (i as i32 % w + ox, i as i32 / w + oy);
+                    if x >= 0 && y >= 0 && x < w && y < h {
+                        Some((y * w + x) as usize)
+                    } else {
+                        None
+                    }
+                };
+
+                // Color distance of the pixels, averaged over the patches
+                let distance = (0..nb_pixels)
+                    .into_par_iter()
+                    .map(|p| match neighbor(p) {
+                        None => 0.0,
+                        Some(q) => {
+                            (0..3)
+                                .map(|c| {
+                                    let (vp, vq) = (variance[p][c], variance[q][c]);
+                                    let d = mean[p][c] - mean[q][c];
+                                    (d * d - (vp + vp.min(vq))) / (EPSILON + k2_color * (vp + vq))
+                                })
+                                .sum::<f32>()
+                                / 3.0
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                let distance = box_filter(&distance, size, self.patch_radius as i32);
+
+                sum.par_iter_mut()
+                    .zip(weights.par_iter_mut())
+                    .enumerate()
+                    .for_each(|(p, (sum, weight))| {
+                        let q = match neighbor(p) {
+                            None => return,
+                            Some(q) => q,
+                        };
+                        let mut w = (-distance[p].max(0.0)).exp();
+                        for (f, gradient) in &features {
+                            let d = (0..3).map(|c| (f[p][c] - f[q][c]).powi(2)).sum::<f32>();
+                            let d = d / (k2_features * gradient[p].max(TAU));
+                            w = w.min((-d).exp());
+                        }
+                        for c in 0..3 {
+                            sum[c] += w * mean[q][c];
+                        }
+                        *weight += w;
+                    });
+            }
+        }
+
+        let mut image = Bitmap::new(size);
+        for (i, (s, w)) in sum.iter().zip(&weights).enumerate() {
+            let p = Point2::new(i as u32 % size.x, i as u32 / size.x);
+            image.accumulate(p, Color::new(s[0], s[1], s[2]) / *w);
+        }
+        image
+    }
+
+    /// Denoise the "primal" buffer of an image with the half buffers (HALF_BUFFERS)
+    /// and the feature buffers (FEATURE_BUFFERS) that are available
+    pub fn denoise_buffers(&self, img: &BufferCollection) -> Result<Bitmap, String> {
+        for name in &HALF_BUFFERS {
+            if !img.values.contains_key(*name) {
+                return Err(format!("the denoiser needs the half buffer {}", name));
+            }
+        }
+        // The depth is normalized to be comparable to the other features
+        let features = FEATURE_BUFFERS
+            .iter()
+            .filter_map(|name| img.values.get(*name).map(|b| (*name, b)))
+            .map(|(name, feature)| {
+                let mut b = Bitmap::new(feature.size);
+                b.accumulate_bitmap(feature, Point2::new(0, 0));
+                if name == "depth" {
+                    let max = b.colors.iter().map(|c| c.r).fold(0.0, f32::max);
+                    b.scale(1.0 / max.max(1e-6));
+                }
+                b
+            })
+            .collect::<Vec<_>>();
+        info!(
+            "Denoising with the features: {:?}",
+            FEATURE_BUFFERS
+                .iter()
+                .filter(|name| img.values.contains_key(**name))
+                .collect::<Vec<_>>()
+        );
+        Ok(self.denoise(
+            (&img.values[HALF_BUFFERS[0]], &img.values[HALF_BUFFERS[1]]),
+            &features.iter().collect::<Vec<_>>(),
+        ))
+    }
+}
+
+/// Denoise the image of any integrator
+/// The integrator is run twice to get the half buffers,
+/// the "primal" buffer is replaced by the denoised image
+pub struct IntegratorDenoise {
+    pub integrator: IntegratorType,
+    pub denoiser: Denoiser,
+}
+
+impl Integrator for IntegratorDenoise {
+    fn compute(&mut self, accel: &dyn Acceleration, scene: &Scene) -> BufferCollection {
+        if self.integrator.progressive() {
+            warn!("The half buffers of a progressive integrator are correlated");
+        }
+        let mut halves = vec![];
+        for i in 0..2 {
+            info!("Denoising: half buffer {} / 2", i + 1);
+            halves.push(match self.integrator {
+                IntegratorType::Primal(ref mut v) => v.compute(accel, scene),
+                IntegratorType::Gradient(ref mut v) => {
+                    IntegratorGradient::compute(v.as_mut(), accel, scene)
+                }
+            });
+        }
+
+        // The other buffers (e.g., the features) are averaged
+        let size = *scene.camera.size();
+        let mut img = BufferCollection::new(Point2::new(0, 0), size, &[]);
+        for half in &halves {
+            for name in half.values.keys() {
+                if !img.values.contains_key(name) {
+                    img.register(name.clone());
+                }
+                img.accumulate_bitmap_buffer(half, name, name);
+            }
+        }
+        img.scale(0.5);
+        for (i, half) in halves.iter().enumerate() {
+            img.register(HALF_BUFFERS[i].to_string());
+            img.accumulate_bitmap_buffer(half, "primal", HALF_BUFFERS[i]);
+        }
+
+        let denoised = self
+            .denoiser
+            .denoise_buffers(&img)
+            .expect("the half buffers are rendered");
+        img.values.insert("primal".to_string(), denoised);
+        img
+    }
+
+    fn samples_per_pixel(&self, scene: &Scene, img: &BufferCollection) -> f32 {
+        let spp = self.integrator.samples_per_pixel(scene, img);
+        if self.integrator.progressive() {
+            // The second half buffer already includes the first one
+            spp
+        } else {
+            2.0 * spp
+        }
+    }
+}
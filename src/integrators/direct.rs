@@ -1,3 +1,4 @@
+use crate::bsdfs::Transport;
 use crate::emitter::*;
 use crate::integrators::*;
 
@@ -14,14 +15,14 @@ impl Integrator for IntegratorDirect {
 impl IntegratorMC for IntegratorDirect {
     fn compute_pixel(
         &self,
-        (ix, iy): (u32, u32),
+        pix: Point2<f32>,
+        sensor: Point2<f32>,
         accel: &dyn Acceleration,
         scene: &Scene,
         sampler: &mut dyn Sampler,
         emitters: &EmitterSampler,
     ) -> Color {
-        let pix = Point2::new(ix as f32 + sampler.next(), iy as f32 + sampler.next());
-        let ray = scene.camera.generate(pix);
+        let ray = scene.camera.generate_sample(pix, sensor);
         let mut l_i = Color::zero();
 
         // Do the intersection for the first path
@@ -58,10 +59,6 @@ impl IntegratorMC for IntegratorDirect {
         for _ in 0..self.nb_light_samples {
             let light_record =
                 emitters.sample_light(&its.p, sampler.next(), sampler.next(), sampler.next2d());
-            let light_pdf = match light_record.pdf {
-                PDF::SolidAngle(v) => v,
-                _ => panic!("Wrong light PDF"),
-            };
 
             let d_out_local = its.frame.to_local(light_record.d);
             if light_record.is_valid()
@@ -70,22 +67,30 @@ impl IntegratorMC for IntegratorDirect {
             {
                 // Compute the contribution of direct lighting
                 // FIXME: A bit waste full, need to detect before sampling the light...
-                if let PDF::SolidAngle(pdf_bsdf) =
+                let pdf_bsdf =
                     its.mesh
                         .bsdf
-                        .pdf(&its.uv, &its.wi, &d_out_local, Domain::SolidAngle)
-                {
-                    // Compute MIS weights
-                    let weight_light =
-                        mis_weight(light_pdf * weight_nb_light, pdf_bsdf * weight_nb_bsdf);
-                    l_i += &(weight_light
-                        * its
-                            .mesh
-                            .bsdf
-                            .eval(&its.uv, &its.wi, &d_out_local, Domain::SolidAngle)
-                        * weight_nb_light
-                        * light_record.weight);
-                }
+                        .pdf(&its.uv, &its.wi, &d_out_local, Domain::SolidAngle);
+                // Compute MIS weights
+                let weight_light = match (light_record.pdf, pdf_bsdf) {
+                    (PDF::SolidAngle(light_pdf), PDF::SolidAngle(pdf_bsdf)) => {
+                        mis_weight(light_pdf * weight_nb_light, pdf_bsdf * weight_nb_bsdf)
+                    }
+                    // Delta lights (point, spot, directional) cannot be hit by BSDF sampling
+                    (PDF::Discrete(_), PDF::SolidAngle(_)) => 1.0,
+                    (PDF::SolidAngle(_), _) | (PDF::Discrete(_), _) => continue,
+                    _ => panic!("Wrong light PDF"),
+                };
+                l_i += &(weight_light
+                    * its.mesh.bsdf.eval(
+                        &its.uv,
+                        &its.wi,
+                        &d_out_local,
+                        Domain::SolidAngle,
+                        Transport::Radiance,
+                    )
+                    * weight_nb_light
+                    * light_record.weight);
             }
         }
 
@@ -94,18 +99,41 @@ impl IntegratorMC for IntegratorDirect {
         /////////////////////////////////
         // Compute an new direction (diffuse)
         for _ in 0..self.nb_bsdf_samples {
-            if let Some(sampled_bsdf) = its.mesh.bsdf.sample(&its.uv, &its.wi, sampler.next2d()) {
+            if let Some(sampled_bsdf) =
+                its.mesh
+                    .bsdf
+                    .sample(&its.uv, &its.wi, sampler.next2d(), Transport::Radiance)
+            {
                 // Generate the new ray and do the intersection
                 let d_out_world = its.frame.to_world(sampled_bsdf.d);
                 let ray = Ray::new(its.p, d_out_world);
                 let next_its = match accel.trace(&ray) {
                     Some(x) => x,
                     None => {
-                        l_i += sampled_bsdf.weight
+                        // The environment can also be sampled by the light sampling
+                        let weight_bsdf = match (&scene.emitter_environment, &sampled_bsdf.pdf) {
+                            (Some(env), PDF::SolidAngle(bsdf_pdf)) => {
+                                let light_pdf = emitters
+                                    .direct_pdf(
+                                        env,
+                                        &LightSamplingPDF {
+                                            o: its.p,
+                                            p: its.p + d_out_world * (2.0 * env.world_radius),
+                                            n: -d_out_world,
+                                            dir: d_out_world,
+                                        },
+                                    )
+                                    .value();
+                                mis_weight(bsdf_pdf * weight_nb_bsdf, light_pdf * weight_nb_light)
+                            }
+                            _ => 1.0,
+                        };
+                        l_i += weight_bsdf
+                            * sampled_bsdf.weight
                             * scene.enviroment_luminance(ray.d)
                             * weight_nb_bsdf;
                         continue;
-                    } // FIXME: Need to implement MIS for BSDF
+                    }
                 };
 
                 // Check that we have intersected a light or not
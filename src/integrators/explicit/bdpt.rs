@@ -0,0 +1,882 @@
+use crate::bsdfs::Transport;
+use crate::integrators::aovs::first_hit_aovs;
+use crate::integrators::explicit::light::TechniqueLightTracing;
+use crate::integrators::explicit::path::TechniquePathTracing;
+use crate::integrators::film::Film;
+use crate::integrators::*;
+use crate::paths::path::*;
+use crate::paths::vertex::*;
+use cgmath::{InnerSpace, Point2, Point3, Vector3};
+
+/// Bidirectional path tracing (Veach 1997)
+/// all the (s, t) strategies are combined with the balance heuristic
+pub struct IntegratorBDPT {
+    pub max_depth: Option<u32>,
+    /// Add the weighted contribution of each strategy
+    /// inside the "s{}_t{}" buffers
+    pub strategies: bool,
+}
+
+/// Subpath vertex with the densities needed for the MIS
+/// the pdfs are expressed in area measure (except for the volume vertices)
+#[derive(Clone)]
+pub(crate) struct BDPTVertex<'scene, 'emitter> {
+    pub(crate) vertex: Vertex<'scene, 'emitter>,
+    // Incoming edge inside the generated path
+    edge_in: Option<EdgeID>,
+    // Product of the sampling weights from the subpath origin
+    pub(crate) throughput: Color,
+    // Density of generating this vertex from its subpath
+    pub(crate) pdf_fwd: f32,
+    // Density of generating this vertex from the other subpath
+    pdf_rev: f32,
+    // Vertex sampled with a Dirac distribution
+    delta: bool,
+}
+
+impl<'scene, 'emitter> BDPTVertex<'scene, 'emitter> {
+    fn new(vertex: Vertex<'scene, 'emitter>, throughput: Color, pdf_fwd: f32) -> Self {
+        BDPTVertex {
+            vertex,
+            edge_in: None,
+            throughput,
+            pdf_fwd,
+            pdf_rev: 0.0,
+            delta: false,
+        }
+    }
+
+    pub(crate) fn position(&self) -> Point3<f32> {
+        self.vertex.position()
+    }
+
+    /// Only the non-smooth surfaces can be used for merging
+    pub(crate) fn mergeable(&self) -> bool {
+        match self.vertex {
+            Vertex::Surface(ref v) => !v.its.mesh.bsdf.is_smooth(),
+            _ => false,
+        }
+    }
+
+    /// Only the non-smooth surfaces and the volume vertices can be connected
+    fn connectible(&self) -> bool {
+        match self.vertex {
+            Vertex::Surface(ref v) => !v.its.mesh.bsdf.is_smooth(),
+            Vertex::Volume(_) => true,
+            Vertex::Light(_) | Vertex::Sensor(_) => true,
+        }
+    }
+
+    /// Emitter attached to this vertex (if any)
+    fn emitter(&self) -> Option<&dyn Emitter> {
+        match self.vertex {
+            Vertex::Surface(ref v) if v.its.mesh.is_light() => Some(v.its.mesh),
+            Vertex::Light(ref v) => Some(v.emitter),
+            _ => None,
+        }
+    }
+
+    /// Normal used for the density conversion (None if not on a surface)
+    fn normal(&self) -> Option<Vector3<f32>> {
+        match self.vertex {
+            Vertex::Surface(ref v) => Some(v.its.n_g),
+            Vertex::Light(ref v) => match v.emitter.pdf_position(&v.pos) {
+                PDF::Area(_) => Some(v.n.normalize()),
+                _ => None,
+            },
+            Vertex::Sensor(_) | Vertex::Volume(_) => None,
+        }
+    }
+
+    /// Normal used for the emission (None if not on an emitter)
+    fn emitter_normal(&self) -> Vector3<f32> {
+        match self.vertex {
+            Vertex::Surface(ref v) => v.its.n_s,
+            Vertex::Light(ref v) => v.n.normalize(),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Convert a solid angle density at this vertex to an area density at next
+    fn convert_density(&self, pdf: f32, next: &BDPTVertex) -> f32 {
+        let mut d = next.position() - self.position();
+        let dist2 = d.magnitude2();
+        if dist2 == 0.0 {
+            return 0.0;
+        }
+        d /= dist2.sqrt();
+        match next.normal() {
+            Some(n) => pdf * n.dot(d).abs() / dist2,
+            None => pdf / dist2,
+        }
+    }
+
+    /// Solid angle density of sampling the direction toward next
+    /// knowing that the path arrives from prev
+    fn pdf_direction(&self, scene: &Scene, prev: Option<&BDPTVertex>, next: &BDPTVertex) -> f32 {
+        let d_out = (next.position() - self.position()).normalize();
+        match self.vertex {
+            Vertex::Sensor(_) => scene
+                .camera
+                .pdf_direction(&self.position(), &next.position()),
+            Vertex::Surface(ref v) => {
+                let d_in = (prev.unwrap().position() - self.position()).normalize();
+                match v.its.mesh.bsdf.pdf(
+                    &v.its.uv,
+                    &v.its.to_local(&d_in),
+                    &v.its.to_local(&d_out),
+                    Domain::SolidAngle,
+                ) {
+                    PDF::SolidAngle(pdf) => pdf,
+                    _ => 0.0,
+                }
+            }
+            Vertex::Volume(ref v) => {
+                let d_in = (prev.unwrap().position() - self.position()).normalize();
+                v.phase_function.pdf(&d_in, &d_out)
+            }
+            Vertex::Light(ref v) => match v.emitter.pdf_direction(&v.pos, &v.n, &d_out) {
+                PDF::SolidAngle(pdf) => pdf,
+                _ => 0.0,
+            },
+        }
+    }
+
+    /// Area density of sampling next from this vertex
+    fn pdf(&self, scene: &Scene, prev: Option<&BDPTVertex>, next: &BDPTVertex) -> f32 {
+        if let Vertex::Light(_) = self.vertex {
+            return self.pdf_light(next);
+        }
+        self.convert_density(self.pdf_direction(scene, prev, next), next)
+    }
+
+    /// Area density of sampling this vertex as the light subpath origin
+    fn pdf_light_origin(&self, emitters: &EmitterSampler) -> f32 {
+        let emitter = self.emitter().unwrap();
+        emitters.pdf(emitter) * emitter.pdf_position(&self.position()).value()
+    }
+
+    /// Area density of sampling next from this vertex (on an emitter)
+    fn pdf_light(&self, next: &BDPTVertex) -> f32 {
+        let emitter = self.emitter().unwrap();
+        let d = (next.position() - self.position()).normalize();
+        match emitter.pdf_direction(&self.position(), &self.emitter_normal(), &d) {
+            PDF::SolidAngle(pdf) => self.convert_density(pdf, next),
+            _ => 0.0,
+        }
+    }
+
+    fn is_delta_light(&self) -> bool {
+        match self.emitter() {
+            Some(emitter) => matches!(emitter.pdf_position(&self.position()), PDF::Discrete(_)),
+            None => false,
+        }
+    }
+
+    /// Scattering toward d (including the cosine), the path arriving from
+    /// the direction stored inside the vertex
+    /// The adjoint BSDF is used for the light subpath (shading normals)
+    pub(crate) fn eval(&self, d: &Vector3<f32>, from_sensor: bool) -> Color {
+        match self.vertex {
+            Vertex::Surface(ref v) => {
+                let d_local = v.its.to_local(d);
+                let transport = if from_sensor {
+                    Transport::Radiance
+                } else {
+                    Transport::Importance
+                };
+                let value = v.its.mesh.bsdf.eval(
+                    &v.its.uv,
+                    &v.its.wi,
+                    &d_local,
+                    Domain::SolidAngle,
+                    transport,
+                );
+                if from_sensor {
+                    value
+                } else {
+                    let wi_global = v.its.to_world(&v.its.wi);
+                    let correction =
+                        (v.its.wi.z * d.dot(v.its.n_g)) / (d_local.z * wi_global.dot(v.its.n_g));
+                    if correction.is_finite() {
+                        value * correction
+                    } else {
+                        Color::zero()
+                    }
+                }
+            }
+            Vertex::Volume(ref v) => v.phase_function.eval(&v.d_in, d),
+            _ => unreachable!(),
+        }
+    }
+}
+
+// Treat the zero densities (delta) as one inside the MIS ratios
+fn remap0(v: f32) -> f32 {
+    if v != 0.0 {
+        v
+    } else {
+        1.0
+    }
+}
+
+impl IntegratorBDPT {
+    /// Flatten the (linear) path graph into a subpath
+    fn subpath<'scene, 'emitter>(
+        &self,
+        path: &Path<'scene, 'emitter>,
+        root: VertexID,
+        scene: &'scene Scene,
+        emitters: &EmitterSampler,
+        mut origin: BDPTVertex<'scene, 'emitter>,
+        from_sensor: bool,
+    ) -> Vec<BDPTVertex<'scene, 'emitter>> {
+        origin.vertex = path.vertex(root).clone();
+        let mut vertices = vec![origin];
+        let mut curr_id = root;
+        while let Some(&(edge_id, next_id)) = path.next_vertices(curr_id).first() {
+            let edge = path.edge(edge_id);
+            let curr = &vertices[vertices.len() - 1];
+            let mut next = BDPTVertex::new(
+                path.vertex(next_id).clone(),
+                curr.throughput * edge.weight * edge.rr_weight,
+                0.0,
+            );
+            next.edge_in = Some(edge_id);
+            if !from_sensor {
+                // Environment reached by the light subpath
+                if let Vertex::Light(_) = next.vertex {
+                    break;
+                }
+                // Shading normals correction
+                if let Vertex::Surface(ref v) = curr.vertex {
+                    let wi_global = v.its.to_world(&v.its.wi);
+                    let correction = (v.its.wi.z * edge.d.dot(v.its.n_g))
+                        / (v.its.to_local(&edge.d).z * wi_global.dot(v.its.n_g));
+                    next.throughput *= correction;
+                }
+            }
+
+            // Forward density
+            let delta = matches!(edge.pdf_direction, PDF::Discrete(_));
+            next.pdf_fwd = match (&curr.vertex, &edge.pdf_direction) {
+                (_, PDF::Discrete(_)) => 0.0,
+                (Vertex::Sensor(_), _) => curr.pdf(scene, None, &next),
+                (Vertex::Light(_), _) => curr.pdf_light(&next),
+                (_, PDF::SolidAngle(pdf)) => curr.convert_density(*pdf, &next),
+                (_, PDF::Area(pdf)) => *pdf,
+            };
+
+            // Reverse density of the previous vertex
+            let n = vertices.len();
+            if n >= 2 {
+                let pdf_rev = if delta {
+                    0.0
+                } else {
+                    vertices[n - 1].pdf(scene, Some(&next), &vertices[n - 2])
+                };
+                vertices[n - 2].pdf_rev = pdf_rev;
+            }
+            vertices[n - 1].delta = delta;
+
+            vertices.push(next);
+            curr_id = next_id;
+        }
+        // The light subpath origin cannot be reached by the sensor subpath
+        if !from_sensor {
+            vertices[0].pdf_fwd = vertices[0].pdf_light_origin(emitters);
+        }
+        vertices
+    }
+
+    /// Densities of the full path vertices (z_0 on the emitter): the density of
+    /// being sampled from the light, from the sensor, the delta flag and if mergeable
+    /// The strategies override some entries instead of copying the subpaths
+    fn path_densities(light: &[BDPTVertex], camera: &[BDPTVertex]) -> Vec<(f32, f32, bool, bool)> {
+        light
+            .iter()
+            .map(|v| (v.pdf_fwd, v.pdf_rev, v.delta, v.mergeable()))
+            .chain(
+                camera
+                    .iter()
+                    .rev()
+                    .map(|v| (v.pdf_rev, v.pdf_fwd, v.delta, v.mergeable())),
+            )
+            .collect()
+    }
+
+    /// Balance heuristic over all the strategies generating the same path
+    /// z contains the densities of the path (see path_densities) with s light vertices
+    /// with the updated reverse densities around the current strategy
+    /// merging is the merging density factor (0: no merging strategies)
+    /// and merged is set if the path was obtained by merging at the last camera vertex
+    fn mis_weight(
+        z: &[(f32, f32, bool, bool)],
+        s: usize,
+        delta_light: bool,
+        merging: f32,
+        merged: bool,
+    ) -> f32 {
+        if z.len() == 2 {
+            return 1.0;
+        }
+        let k = z.len() - 1;
+
+        // Densities of the connection strategies relative to the current one
+        // (the sensor is never reached by the light subpaths)
+        let mut ri = vec![0.0; k + 1];
+        ri[s] = 1.0;
+        for i in s..k {
+            ri[i + 1] = ri[i] * remap0(z[i].0) / remap0(z[i].1);
+        }
+        for i in (0..s).rev() {
+            ri[i] = ri[i + 1] * remap0(z[i].1) / remap0(z[i].0);
+        }
+
+        let mut sum_ri = 0.0;
+        for (i, r) in ri.iter().enumerate() {
+            let delta_prev = if i > 0 { z[i - 1].2 } else { delta_light };
+            if !z[i].2 && !delta_prev {
+                sum_ri += r;
+            }
+        }
+        // Merging strategies at the interior vertices
+        for i in 1..k {
+            if z[i].3 && !z[i].2 {
+                sum_ri += ri[i] * remap0(z[i].0) * merging;
+            }
+        }
+        let current = if merged {
+            remap0(z[s].0) * merging
+        } else {
+            1.0
+        };
+        current / sum_ri
+    }
+
+    /// Update the reverse densities around the connection and compute the MIS weight
+    fn connection_weight(
+        scene: &Scene,
+        emitters: &EmitterSampler,
+        light: &[BDPTVertex],
+        camera: &[BDPTVertex],
+        merging: f32,
+    ) -> f32 {
+        let (s, t) = (light.len(), camera.len());
+        let mut z = IntegratorBDPT::path_densities(light, camera);
+        // z[s] is the last camera vertex and z[s - 1] the last light vertex
+        if t > 0 {
+            z[s].2 = false;
+            z[s].0 = if s > 0 {
+                let prev = if s > 1 { Some(&light[s - 2]) } else { None };
+                light[s - 1].pdf(scene, prev, &camera[t - 1])
+            } else {
+                camera[t - 1].pdf_light_origin(emitters)
+            };
+        }
+        if t > 1 {
+            z[s + 1].0 = if s > 0 {
+                camera[t - 1].pdf(scene, Some(&light[s - 1]), &camera[t - 2])
+            } else {
+                camera[t - 1].pdf_light(&camera[t - 2])
+            };
+        }
+        if s > 0 {
+            z[s - 1].2 = false;
+            let prev = if t > 1 { Some(&camera[t - 2]) } else { None };
+            z[s - 1].1 = camera[t - 1].pdf(scene, prev, &light[s - 1]);
+        }
+        if s > 1 {
+            z[s - 2].1 = light[s - 1].pdf(scene, Some(&camera[t - 1]), &light[s - 2]);
+        }
+        let delta_light = if s > 0 {
+            light[0].is_delta_light()
+        } else {
+            camera[t - 1].is_delta_light()
+        };
+        IntegratorBDPT::mis_weight(&z, s, delta_light, merging, false)
+    }
+
+    /// Update the reverse densities around the merged vertex (last camera vertex)
+    /// and compute the MIS weight
+    /// light contains the light subpath without the merged vertex
+    /// and pdf_merged the density of the merged vertex from the light subpath
+    pub(crate) fn merging_weight(
+        scene: &Scene,
+        light: &[BDPTVertex],
+        camera: &[BDPTVertex],
+        pdf_merged: f32,
+        merging: f32,
+    ) -> f32 {
+        let (s, t) = (light.len(), camera.len());
+        let mut z = IntegratorBDPT::path_densities(light, camera);
+        z[s].2 = false;
+        z[s].0 = pdf_merged;
+        z[s + 1].0 = camera[t - 1].pdf(scene, Some(&light[s - 1]), &camera[t - 2]);
+        z[s - 1].1 = camera[t - 1].pdf(scene, Some(&camera[t - 2]), &light[s - 1]);
+        // The light vertex before the merged one can be specular (caustics)
+        if s > 1 && !light[s - 1].delta {
+            z[s - 2].1 = light[s - 1].pdf(scene, Some(&camera[t - 1]), &light[s - 2]);
+        }
+        IntegratorBDPT::mis_weight(&z, s, light[0].is_delta_light(), merging, true)
+    }
+
+    /// MIS for the paths ending on the emitters at infinity
+    /// that can only be sampled by the sensor (s = 0) or by the light sampling (s = 1)
+    fn infinite_weight(pdf_sensor: f32, pdf_light: f32, s: usize) -> f32 {
+        let pdf = if s == 0 { pdf_sensor } else { pdf_light };
+        if pdf == 0.0 {
+            // Delta distribution: the other strategy is impossible
+            1.0
+        } else {
+            pdf / (pdf_sensor + pdf_light)
+        }
+    }
+
+    /// Sensor subpath (the path graph is kept to evaluate the emission)
+    /// the sensor sample gives the primary ray (see Camera::generate_sample)
+    pub(crate) fn sensor_subpath<'scene: 'emitter, 'emitter>(
+        &self,
+        pix: Point2<f32>,
+        sensor: Point2<f32>,
+        accel: &'scene dyn Acceleration,
+        scene: &'scene Scene,
+        sampler: &mut dyn Sampler,
+        emitters: &'emitter EmitterSampler,
+    ) -> (Path<'scene, 'emitter>, Vec<BDPTVertex<'scene, 'emitter>>) {
+        let mut technique = TechniquePathTracing {
+            max_depth: self.max_depth,
+            samplings: vec![Box::new(DirectionalSamplingStrategy { from_sensor: true })],
+            img_pos: pix,
+            sensor,
+            single_scattering: false,
+        };
+        let mut path_sensor = Path::default();
+        let root = generate(
+            &mut path_sensor,
+            accel,
+            scene,
+            emitters,
+            sampler,
+            &mut technique,
+        );
+        let camera = self.subpath(
+            &path_sensor,
+            root[0].0,
+            scene,
+            emitters,
+            BDPTVertex::new(path_sensor.vertex(root[0].0).clone(), Color::one(), 1.0),
+            true,
+        );
+        (path_sensor, camera)
+    }
+
+    /// Light subpath
+    /// only the emitters with a finite extent generate light subpaths
+    pub(crate) fn light_subpath<'scene: 'emitter, 'emitter>(
+        &self,
+        accel: &'scene dyn Acceleration,
+        scene: &'scene Scene,
+        sampler: &mut dyn Sampler,
+        emitters: &'emitter EmitterSampler,
+    ) -> Vec<BDPTVertex<'scene, 'emitter>> {
+        let mut technique = TechniqueLightTracing {
+            max_depth: self.max_depth.map(|m| m.saturating_sub(1)),
+            samplings: vec![Box::new(DirectionalSamplingStrategy { from_sensor: false })],
+            flux: None,
+            render_surface: true,
+            render_volume: true,
+        };
+        let mut path_light = Path::default();
+        let root = generate(
+            &mut path_light,
+            accel,
+            scene,
+            emitters,
+            sampler,
+            &mut technique,
+        );
+        match path_light.vertex(root[0].0) {
+            Vertex::Light(ref v) if !v.emitter.is_infinite() => {
+                let origin =
+                    BDPTVertex::new(path_light.vertex(root[0].0).clone(), Color::one(), 0.0);
+                let mut light =
+                    self.subpath(&path_light, root[0].0, scene, emitters, origin, false);
+                // The first vertex only evaluates the emission profile
+                light[0].throughput = Color::value(1.0 / light[0].pdf_fwd);
+                // The flux accounts for the emission and the sampling
+                let flux = technique.flux.unwrap();
+                for v in light.iter_mut().skip(1) {
+                    v.throughput *= flux;
+                }
+                light
+            }
+            _ => vec![],
+        }
+    }
+
+    /// Evaluate all the connections between the two subpaths
+    /// merging is the density factor of the merging strategies used by the MIS (0: BDPT)
+    pub(crate) fn connect_subpaths<'scene, 'emitter>(
+        &self,
+        accel: &dyn Acceleration,
+        scene: &Scene,
+        sampler: &mut dyn Sampler,
+        emitters: &EmitterSampler,
+        (path_sensor, camera): (&Path<'scene, 'emitter>, &[BDPTVertex<'scene, 'emitter>]),
+        light: &[BDPTVertex<'scene, 'emitter>],
+        merging: f32,
+        contribs: &mut Vec<(String, Color)>,
+        splats: &mut Film,
+    ) {
+        let max_length = self.max_depth.map_or(usize::MAX, |m| m as usize);
+        let mut add = |s: usize, t: usize, c: Color, splat: Option<Point2<f32>>| {
+            if c.is_zero() || !c.r.is_finite() || !c.g.is_finite() || !c.b.is_finite() {
+                return;
+            }
+            let mut names = vec!["primal".to_string()];
+            if self.strategies {
+                names.push(format!("s{}_t{}", s, t));
+            }
+            for name in names {
+                match splat {
+                    None => contribs.push((name, c)),
+                    Some(uv) => splats.splat(uv, c, &name),
+                }
+            }
+        };
+
+        for t in 1..=camera.len() {
+            for s in 0..=light.len() {
+                if s + t < 2 || s + t > max_length {
+                    continue;
+                }
+                if t == 1 && s <= 1 {
+                    // Pinhole camera and emitters directly visible (s = 0 only)
+                    continue;
+                }
+
+                if s == 0 {
+                    // The sensor subpath reaches an emitter
+                    let pt = &camera[t - 1];
+                    let emitter = match pt.emitter() {
+                        Some(e) => e,
+                        None => continue,
+                    };
+                    let le = match pt.edge_in {
+                        Some(edge_id) => pt.vertex.contribution(path_sensor.edge(edge_id)),
+                        None => continue,
+                    };
+                    let c = pt.throughput * le;
+                    if c.is_zero() {
+                        continue;
+                    }
+                    let weight = if emitter.is_infinite() {
+                        let edge = path_sensor.edge(pt.edge_in.unwrap());
+                        let pdf_sensor = match edge.pdf_direction {
+                            PDF::SolidAngle(pdf) if t > 2 => pdf,
+                            _ => 0.0,
+                        };
+                        let pdf_light = if t > 2 && camera[t - 2].connectible() {
+                            emitters
+                                .direct_pdf(
+                                    emitter,
+                                    &LightSamplingPDF {
+                                        o: camera[t - 2].position(),
+                                        p: pt.position(),
+                                        n: pt.emitter_normal(),
+                                        dir: edge.d,
+                                    },
+                                )
+                                .value()
+                        } else {
+                            0.0
+                        };
+                        if t == 2 {
+                            1.0
+                        } else {
+                            IntegratorBDPT::infinite_weight(pdf_sensor, pdf_light, 0)
+                        }
+                    } else {
+                        IntegratorBDPT::connection_weight(
+                            scene,
+                            emitters,
+                            &[],
+                            &camera[..t],
+                            merging,
+                        )
+                    };
+                    add(s, t, c * weight, None);
+                } else if t == 1 {
+                    // Splat the light subpath vertex on the sensor
+                    let qs = &light[s - 1];
+                    if !qs.connectible() {
+                        continue;
+                    }
+                    let (importance, uv, pos_sensor) =
+                        match scene.camera.sample_direct(&qs.position(), sampler) {
+                            Some(v) => v,
+                            None => continue,
+                        };
+                    // s >= 2: the emitters are not splatted directly (see above)
+                    let d = (pos_sensor - qs.position()).normalize();
+                    let c = qs.throughput * qs.eval(&d, false) * importance;
+                    if c.is_zero() {
+                        continue;
+                    }
+                    let transmittance = scene.transmittance(
+                        accel,
+                        &qs.position(),
+                        &pos_sensor,
+                        qs.vertex.medium(scene, accel, &d),
+                        sampler,
+                    );
+                    if transmittance.is_zero() {
+                        continue;
+                    }
+                    let sensor = BDPTVertex::new(
+                        Vertex::Sensor(SensorVertex {
+                            uv,
+                            pos: pos_sensor,
+                            edge_in: None,
+                            edge_out: None,
+                        }),
+                        Color::one(),
+                        1.0,
+                    );
+                    let weight = IntegratorBDPT::connection_weight(
+                        scene,
+                        emitters,
+                        &light[..s],
+                        &[sensor],
+                        merging,
+                    );
+                    add(s, t, c * transmittance * weight, Some(uv));
+                } else if s == 1 {
+                    // Light sampling from the sensor subpath
+                    let pt = &camera[t - 1];
+                    if !pt.connectible() {
+                        continue;
+                    }
+                    if let Vertex::Light(_) = pt.vertex {
+                        continue;
+                    }
+                    let light_record = emitters.sample_light(
+                        &pt.position(),
+                        sampler.next(),
+                        sampler.next(),
+                        sampler.next2d(),
+                    );
+                    if !light_record.is_valid() {
+                        continue;
+                    }
+                    let c = pt.throughput * pt.eval(&light_record.d, true) * light_record.weight;
+                    if c.is_zero() {
+                        continue;
+                    }
+                    let transmittance = scene.transmittance(
+                        accel,
+                        &pt.position(),
+                        &light_record.p,
+                        pt.vertex.medium(scene, accel, &light_record.d),
+                        sampler,
+                    );
+                    if transmittance.is_zero() {
+                        continue;
+                    }
+                    let weight = if light_record.emitter.is_infinite() {
+                        let pdf_light = match light_record.pdf {
+                            PDF::SolidAngle(pdf) => pdf,
+                            _ => 0.0,
+                        };
+                        let sampled = BDPTVertex::new(
+                            Vertex::Light(EmitterVertex {
+                                pos: light_record.p,
+                                n: light_record.n,
+                                emitter: light_record.emitter,
+                                edge_in: None,
+                                edge_out: None,
+                            }),
+                            Color::one(),
+                            0.0,
+                        );
+                        let pdf_sensor = pt.pdf_direction(scene, Some(&camera[t - 2]), &sampled);
+                        IntegratorBDPT::infinite_weight(pdf_sensor, pdf_light, 1)
+                    } else {
+                        let mut sampled = BDPTVertex::new(
+                            Vertex::Light(EmitterVertex {
+                                pos: light_record.p,
+                                n: light_record.n,
+                                emitter: light_record.emitter,
+                                edge_in: None,
+                                edge_out: None,
+                            }),
+                            Color::one(),
+                            0.0,
+                        );
+                        sampled.pdf_fwd = sampled.pdf_light_origin(emitters);
+                        IntegratorBDPT::connection_weight(
+                            scene,
+                            emitters,
+                            &[sampled],
+                            &camera[..t],
+                            merging,
+                        )
+                    };
+                    add(s, t, c * transmittance * weight, None);
+                } else {
+                    // Connect the two subpaths
+                    let (qs, pt) = (&light[s - 1], &camera[t - 1]);
+                    if !qs.connectible() || !pt.connectible() {
+                        continue;
+                    }
+                    if let Vertex::Light(_) = pt.vertex {
+                        continue;
+                    }
+                    let mut d = qs.position() - pt.position();
+                    let dist2 = d.magnitude2();
+                    d /= dist2.sqrt();
+                    let c = qs.throughput * qs.eval(&-d, false) * pt.eval(&d, true) * pt.throughput
+                        / dist2;
+                    if c.is_zero() {
+                        continue;
+                    }
+                    let transmittance = scene.transmittance(
+                        accel,
+                        &pt.position(),
+                        &qs.position(),
+                        pt.vertex.medium(scene, accel, &d),
+                        sampler,
+                    );
+                    if transmittance.is_zero() {
+                        continue;
+                    }
+                    let weight = IntegratorBDPT::connection_weight(
+                        scene,
+                        emitters,
+                        &light[..s],
+                        &camera[..t],
+                        merging,
+                    );
+                    add(s, t, c * transmittance * weight, None);
+                }
+            }
+        }
+    }
+
+    fn compute_sample(
+        &self,
+        pix: Point2<f32>,
+        sensor: Point2<f32>,
+        accel: &dyn Acceleration,
+        scene: &Scene,
+        sampler: &mut dyn Sampler,
+        emitters: &EmitterSampler,
+        contribs: &mut Vec<(String, Color)>,
+        splats: &mut Film,
+    ) {
+        let camera = self.sensor_subpath(pix, sensor, accel, scene, sampler, emitters);
+        let light = self.light_subpath(accel, scene, sampler, emitters);
+        self.connect_subpaths(
+            accel,
+            scene,
+            sampler,
+            emitters,
+            (&camera.0, &camera.1),
+            &light,
+            0.0,
+            contribs,
+            splats,
+        );
+    }
+}
+
+impl Integrator for IntegratorBDPT {
+    fn compute(&mut self, accel: &dyn Acceleration, scene: &Scene) -> BufferCollection {
+        assert_ne!(scene.nb_samples, 0);
+        let buffernames = aov_buffernames(scene);
+
+        // Create rendering blocks
+        // (distributed over the jobs, each one with a film covering the image
+        // to receive the light tracing strategies (t = 1) of its blocks)
+        let image_blocks = generate_img_blocks(scene, &buffernames);
+        let nb_jobs = SPLAT_JOBS.min(image_blocks.len());
+        let mut jobs = (0..nb_jobs)
+            .map(|job| {
+                image_blocks
+                    .iter()
+                    .skip(job)
+                    .step_by(nb_jobs)
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        let seed = scene.pass_seed();
+        let progress_bar = Mutex::new(ProgressBar::new(image_blocks.len() as u64));
+        let pool = generate_pool(scene);
+        let mut image = pool.install(|| {
+            splat_jobs(&mut jobs, &|blocks| {
+                // The splats outside the crop are discarded
+                let mut image = Film::new(
+                    scene.filter,
+                    Point2::new(0, 0),
+                    *scene.camera.size(),
+                    &buffernames,
+                )
+                .crop(scene.crop);
+                let emitters = scene.emitters_sampler();
+                let mut contribs = vec![];
+                for im_block in blocks.iter() {
+                    let mut film =
+                        Film::new(scene.filter, im_block.pos, im_block.size, &buffernames);
+                    let mut sampler = scene
+                        .sampler
+                        .create(pixel_seed(seed, im_block.pos), scene.nb_samples);
+                    for iy in 0..im_block.size.y {
+                        for ix in 0..im_block.size.x {
+                            let pix = Point2::new(ix + im_block.pos.x, iy + im_block.pos.y);
+                            for i in 0..scene.nb_samples {
+                                sampler.start_sample(pix, i);
+                                let offset = sampler.next2d();
+                                let p =
+                                    Point2::new(pix.x as f32 + offset.x, pix.y as f32 + offset.y);
+                                let sensor = scene.camera.sample_sensor(sampler.as_mut());
+                                contribs.clear();
+                                self.compute_sample(
+                                    p,
+                                    sensor,
+                                    accel,
+                                    scene,
+                                    sampler.as_mut(),
+                                    &emitters,
+                                    &mut contribs,
+                                    &mut image,
+                                );
+                                contribs.extend(first_hit_aovs(
+                                    &scene.aovs,
+                                    &scene.camera.generate_sample(p, sensor),
+                                    accel,
+                                    scene,
+                                ));
+                                film.add_sample(p, &contribs);
+                            }
+                        }
+                    }
+                    image.merge(&film);
+                    {
+                        progress_bar.lock().unwrap().inc();
+                    }
+                }
+                image
+            })
+        });
+
+        // Each pixel sample traced one light subpath
+        // (only the pixels of the crop)
+        image.scale_splats(
+            scene.camera.size().x as f32 * scene.camera.size().y as f32
+                / (scene.image_region().nb_pixels() * scene.nb_samples) as f32,
+        );
+        image.develop()
+    }
+}
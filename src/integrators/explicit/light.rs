@@ -1,3 +1,5 @@
+use crate::bsdfs::Transport;
+use crate::integrators::film::Film;
 use crate::integrators::*;
 use crate::paths::path::*;
 use crate::paths::vertex::*;
@@ -60,35 +62,31 @@ impl TechniqueLightTracing {
         path: &Path<'scene, '_>,
         accel: &dyn Acceleration,
         scene: &'scene Scene,
+        sampler: &mut dyn Sampler,
         vertex_id: VertexID,
-        bitmap: &mut BufferCollection,
+        film: &mut Film,
         flux: Color,
     ) {
         match path.vertex(vertex_id) {
             Vertex::Volume(ref v) => {
                 if self.render_volume {
-                    let pos_sensor = scene.camera.position();
-                    let d = (pos_sensor - v.pos).normalize();
-                    if accel.visible(&v.pos, &pos_sensor) {
-                        // Splat the contribution
-                        if let Some((importance, uv)) = scene.camera.sample_direct(&v.pos) {
-                            let m = scene.volume.as_ref().unwrap();
-
+                    // Splat the contribution
+                    if let Some((importance, uv, pos_sensor)) =
+                        scene.camera.sample_direct(&v.pos, sampler)
+                    {
+                        let d = (pos_sensor - v.pos).normalize();
+                        // Transmittance through the media (zero if occluded)
+                        let transmittance =
+                            scene.transmittance(accel, &v.pos, &pos_sensor, v.medium, sampler);
+                        if !transmittance.is_zero() {
                             // Compute BSDF for the splatting
                             let bsdf_value = v.phase_function.eval(&v.d_in, &d);
 
-                            // If medium, need to take into account the transmittance
-                            let transmittance = {
-                                let mut ray = Ray::new(v.pos, d);
-                                ray.tfar = (v.pos - pos_sensor).magnitude();
-                                m.transmittance(ray)
-                            };
-
                             // Accumulate the results
-                            bitmap.accumulate_safe(
-                                Point2::new(uv.x as i32, uv.y as i32),
+                            film.splat(
+                                uv,
                                 flux * importance * bsdf_value * transmittance,
-                                &"primal".to_owned(),
+                                "primal",
                             );
                         }
                     }
@@ -102,21 +100,30 @@ impl TechniqueLightTracing {
                             path,
                             accel,
                             scene,
+                            sampler,
                             vertex_next,
-                            bitmap,
+                            film,
                             flux * edge.weight * edge.rr_weight,
                         );
                     }
                 }
             }
             Vertex::Surface(ref v) => {
-                if self.render_surface {
-                    // Chech the visibility from the point to the sensor
-                    let pos_sensor = scene.camera.position();
-                    let d = (pos_sensor - v.its.p).normalize();
-                    if !v.its.mesh.bsdf.is_smooth() && accel.visible(&v.its.p, &pos_sensor) {
-                        // Splat the contribution
-                        if let Some((importance, uv)) = scene.camera.sample_direct(&v.its.p) {
+                if self.render_surface && !v.its.mesh.bsdf.is_smooth() {
+                    if let Some((importance, uv, pos_sensor)) =
+                        scene.camera.sample_direct(&v.its.p, sampler)
+                    {
+                        // Chech the visibility from the point to the sensor
+                        let d = (pos_sensor - v.its.p).normalize();
+                        let transmittance = scene.transmittance(
+                            accel,
+                            &v.its.p,
+                            &pos_sensor,
+                            v.its.medium(&d),
+                            sampler,
+                        );
+                        if !transmittance.is_zero() {
+                            // Splat the contribution
                             // Compute BSDF for the splatting
                             let wo_local = v.its.frame.to_local(d);
                             let wi_global = v.its.frame.to_world(v.its.wi);
@@ -125,24 +132,16 @@ impl TechniqueLightTracing {
                                 &v.its.wi,
                                 &wo_local,
                                 Domain::SolidAngle,
+                                Transport::Importance,
                             );
                             let correction = (v.its.wi.z * d.dot(v.its.n_g))
                                 / (wo_local.z * wi_global.dot(v.its.n_g));
 
-                            // If medium, need to take into account the transmittance
-                            let transmittance = if let Some(ref m) = scene.volume {
-                                let mut ray = Ray::new(v.its.p, d);
-                                ray.tfar = (v.its.p - pos_sensor).magnitude();
-                                m.transmittance(ray)
-                            } else {
-                                Color::one()
-                            };
-
                             // Accumulate the results
-                            bitmap.accumulate_safe(
-                                Point2::new(uv.x as i32, uv.y as i32),
+                            film.splat(
+                                uv,
                                 flux * importance * bsdf_value * correction * transmittance,
-                                &"primal".to_owned(),
+                                "primal",
                             );
                         }
                     }
@@ -155,8 +154,9 @@ impl TechniqueLightTracing {
                             path,
                             accel,
                             scene,
+                            sampler,
                             vertex_next,
-                            bitmap,
+                            film,
                             flux * edge.weight * edge.rr_weight,
                         );
                     }
@@ -164,27 +164,32 @@ impl TechniqueLightTracing {
             }
             Vertex::Light(ref v) => {
                 let flux = *self.flux.as_ref().unwrap();
-                if self.render_surface {
-                    let pos_sensor = scene.camera.position();
-                    let d = (pos_sensor - v.pos).normalize();
-                    if accel.visible(&v.pos, &pos_sensor) {
-                        if let Some((importance, uv)) = scene.camera.sample_direct(&v.pos) {
-                            let transmittance = if let Some(ref m) = scene.volume {
-                                let mut ray = Ray::new(v.pos, d);
-                                ray.tfar = (v.pos - pos_sensor).magnitude();
-                                m.transmittance(ray)
-                            } else {
-                                Color::one()
-                            };
-
-                            bitmap.accumulate_safe(
-                                Point2::new(uv.x as i32, uv.y as i32),
+                // The delta and infinite emitters are not directly visible
+                let visible = match v.emitter.pdf_position(&v.pos) {
+                    PDF::Area(_) => !v.emitter.is_infinite(),
+                    _ => false,
+                };
+                if self.render_surface && visible {
+                    if let Some((importance, uv, pos_sensor)) =
+                        scene.camera.sample_direct(&v.pos, sampler)
+                    {
+                        let d = (pos_sensor - v.pos).normalize();
+                        let transmittance = scene.transmittance(
+                            accel,
+                            &v.pos,
+                            &pos_sensor,
+                            scene.medium_at(accel, &v.pos, &d),
+                            sampler,
+                        );
+                        if !transmittance.is_zero() {
+                            film.splat(
+                                uv,
                                 transmittance
                                     * flux
                                     * importance
                                     * d.dot(v.n)
                                     * std::f32::consts::FRAC_1_PI,
-                                &"primal".to_owned(),
+                                "primal",
                             );
                         }
                     }
@@ -196,8 +201,9 @@ impl TechniqueLightTracing {
                             path,
                             accel,
                             scene,
+                            sampler,
                             next_vertex,
-                            bitmap,
+                            film,
                             edge.weight * flux * edge.rr_weight,
                         );
                     }
@@ -211,34 +217,38 @@ impl TechniqueLightTracing {
 impl Integrator for IntegratorLightTracing {
     fn compute(&mut self, accel: &dyn Acceleration, scene: &Scene) -> BufferCollection {
         // Number of samples that the system will trace
-        // The strategy for multithread is to have 4 job per threads
+        // The number of jobs does not depend on the number of threads
+        // so the image only depends on the scene seed
         // All job will have the same number of samples to deal with
-        let nb_threads = rayon::current_num_threads();
-        let nb_jobs = nb_threads * 4;
+        let nb_jobs = SPLAT_JOBS;
+        let seed = scene.pass_seed();
         let mut samplers = Vec::new();
-        for _ in 0..nb_jobs {
-            samplers.push(samplers::independent::IndependentSampler::default());
+        for job in 0..nb_jobs {
+            samplers.push(samplers::independent::IndependentSampler::from_seed(
+                hash_combine(seed, job as u64),
+            ));
         }
 
         // Ajust the number of light path that we need to generate
         let nb_samples = (scene.nb_samples
             * ((scene.camera.size().x * scene.camera.size().y) as usize))
-            / nb_jobs as usize;
+            / nb_jobs;
 
         // Global information
         let progress_bar = Mutex::new(ProgressBar::new(samplers.len() as u64));
         let buffer_names = vec![String::from("primal")];
-        let img = Mutex::new(BufferCollection::new(
-            Point2::new(0, 0),
-            *scene.camera.size(),
-            &buffer_names,
-        ));
 
         let pool = generate_pool(scene);
-        pool.install(|| {
-            samplers.par_iter_mut().for_each(|s| {
-                let mut my_img =
-                    BufferCollection::new(Point2::new(0, 0), *scene.camera.size(), &buffer_names);
+        let mut img = pool.install(|| {
+            splat_jobs(&mut samplers, &|s| {
+                // The contributions outside the crop are discarded
+                let mut my_img = Film::new(
+                    scene.filter,
+                    Point2::new(0, 0),
+                    *scene.camera.size(),
+                    &buffer_names,
+                )
+                .crop(scene.crop);
                 let emitters = scene.emitters_sampler();
                 (0..nb_samples).for_each(|_| {
                     // The sampling strategies
@@ -255,22 +265,26 @@ impl Integrator for IntegratorLightTracing {
                     let mut path = Path::default();
                     let root = generate(&mut path, accel, scene, &emitters, s, &mut technique);
                     // Evaluate the path generated using camera splatting operation
-                    technique.evaluate(&path, accel, scene, root[0].0, &mut my_img, Color::one());
+                    technique.evaluate(
+                        &path,
+                        accel,
+                        scene,
+                        s,
+                        root[0].0,
+                        &mut my_img,
+                        Color::one(),
+                    );
                 });
 
-                // Scale and add the results
-                my_img.scale(1.0 / (nb_samples as f32));
-                {
-                    img.lock().unwrap().accumulate_bitmap(&my_img);
-                    progress_bar.lock().unwrap().inc();
-                }
-            });
+                // Scale the results (merged by splat_jobs)
+                my_img.scale_splats(1.0 / (nb_samples as f32));
+                progress_bar.lock().unwrap().inc();
+                my_img
+            })
         });
 
-        // All job are independent, so we just merge them...
-        let mut img: BufferCollection = img.into_inner().unwrap();
-        img.scale(1.0 / nb_jobs as f32);
-        img.scale((scene.camera.img.x * scene.camera.img.y) as f32);
-        img
+        img.scale_splats(1.0 / nb_jobs as f32);
+        img.scale_splats((scene.camera.img.x * scene.camera.img.y) as f32);
+        img.develop()
     }
 }
@@ -5,3 +5,6 @@ pub mod uncorrelated_plane_single;
 pub mod vol_primitives;
 pub mod vpl;
 pub mod path_kulla;
+pub mod bdpt;
+pub mod sppm;
+pub mod vcm;
@@ -20,7 +20,10 @@ pub struct IntegratorPathTracing {
 pub struct TechniquePathTracing {
     pub max_depth: Option<u32>,
     pub samplings: Vec<Box<dyn SamplingStrategy>>,
-    pub img_pos: Point2<u32>,
+    /// Position of the sample on the film (in pixels)
+    pub img_pos: Point2<f32>,
+    /// Sample on the sensor (see Camera::sample_sensor)
+    pub sensor: Point2<f32>,
     pub single_scattering: bool,
 }
 impl Technique for TechniquePathTracing {
@@ -29,16 +32,13 @@ impl Technique for TechniquePathTracing {
         path: &mut Path<'scene, 'emitter>,
         _accel: &dyn Acceleration,
         scene: &'scene Scene,
-        sampler: &mut dyn Sampler,
+        _sampler: &mut dyn Sampler,
         _emitters: &'emitter EmitterSampler,
     ) -> Vec<(VertexID, Color)> {
         // Only generate a path from the sensor
         let root = Vertex::Sensor(SensorVertex {
-            uv: Point2::new(
-                self.img_pos.x as f32 + sampler.next(),
-                self.img_pos.y as f32 + sampler.next(),
-            ),
-            pos: scene.camera.position(),
+            uv: self.img_pos,
+            pos: scene.camera.generate_sample(self.img_pos, self.sensor).o,
             edge_in: None,
             edge_out: None,
         });
@@ -210,7 +210,8 @@ impl Integrator for IntegratorPathTracing {
 impl IntegratorMC for IntegratorPathTracing {
     fn compute_pixel(
         &self,
-        (ix, iy): (u32, u32),
+        pix: Point2<f32>,
+        sensor: Point2<f32>,
         accel: &dyn Acceleration,
         scene: &Scene,
         sampler: &mut dyn Sampler,
@@ -233,7 +234,8 @@ impl IntegratorMC for IntegratorPathTracing {
         let mut technique = TechniquePathTracing {
             max_depth: self.max_depth,
             samplings,
-            img_pos: Point2::new(ix, iy),
+            img_pos: pix,
+            sensor,
             single_scattering: self.single_scattering,
         };
         // Call the generator on this technique
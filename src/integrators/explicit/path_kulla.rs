@@ -135,17 +135,14 @@ impl Integrator for IntegratorPathKulla {
 impl IntegratorMC for IntegratorPathKulla {
     fn compute_pixel(
         &self,
-        (ix, iy): (u32, u32),
+        pix: Point2<f32>,
+        sensor: Point2<f32>,
         accel: &dyn Acceleration,
         scene: &Scene,
         sampler: &mut dyn Sampler,
         emitters: &EmitterSampler,
     ) -> Color {
-        let pix = Point2::new(
-            ix as f32 + sampler.next(),
-            iy as f32 + sampler.next(),
-        );
-        let ray = scene.camera.generate(pix);
+        let ray = scene.camera.generate_sample(pix, sensor);
 
         // Get the max distance (to a surface)
         // TODO: Note that we need to revisit this approach
@@ -162,7 +159,7 @@ impl IntegratorMC for IntegratorPathKulla {
             m.transmittance(ray)
         };
         let m = scene.volume.as_ref().unwrap();
-        let phase = PhaseFunction::Isotropic();
+        let phase = &m.phase;
             
 
         // Sampling the distance with Kulla et al.'s scheme
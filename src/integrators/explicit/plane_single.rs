@@ -341,8 +341,8 @@ impl Integrator for IntegratorSinglePlane {
 
         // Create the planes
         let m = scene.volume.as_ref().unwrap();
-        //let mut sampler = samplers::independent::IndependentSampler::default();
-        let mut sampler = samplers::independent::IndependentSampler::from_seed(0);
+        let seed = scene.pass_seed();
+        let mut sampler = samplers::independent::IndependentSampler::from_seed(seed);
 
         let mut planes = vec![];
         let mut number_plane_gen = 0;
@@ -390,10 +390,11 @@ impl Integrator for IntegratorSinglePlane {
         info!("Gathering Single planes...");
         let progress_bar = Mutex::new(ProgressBar::new(image_blocks.len() as u64));
         let pool = generate_pool(scene);
-        let phase_function = PhaseFunction::Isotropic();
+        let phase_function = &scene.volume.as_ref().unwrap().phase;
         pool.install(|| {
             image_blocks.par_iter_mut().for_each(|im_block| {
-                let mut sampler_ray =  samplers::independent::IndependentSampler::from_seed((im_block.pos.x + im_block.pos.y) as u64);
+                let mut sampler_ray =
+                    samplers::independent::IndependentSampler::from_seed(pixel_seed(seed, im_block.pos));
                 for ix in 0..im_block.size.x {
                     for iy in 0..im_block.size.y {
                         for _ in 0..scene.nb_samples {
@@ -402,7 +403,7 @@ impl Integrator for IntegratorSinglePlane {
                                 ix_c as f32 + sampler_ray.next(),
                                 iy_c as f32 + sampler_ray.next(),
                             );
-                            let mut ray = scene.camera.generate(pix);
+                            let mut ray = scene.camera.generate(pix, &mut sampler_ray);
 
                             // Get the max distance
                             let max_dist = match accel.trace(&ray) {
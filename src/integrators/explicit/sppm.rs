@@ -0,0 +1,374 @@
+use crate::accel::*;
+use crate::bsdfs::Transport;
+use crate::integrators::explicit::light::TechniqueLightTracing;
+use crate::integrators::*;
+use crate::paths::path::*;
+use crate::paths::vertex::*;
+use crate::structure::AABB;
+use cgmath::{InnerSpace, Point2};
+use rayon::prelude::*;
+
+/// Stochastic progressive photon mapping (Hachisuka and Jensen 2009)
+/// Only the surfaces are handled (the participating media are ignored)
+/// Each call to compute() does one iteration per sample (-n)
+/// and continues from the previous calls.
+pub struct IntegratorSPPM {
+    /// Maximum number of bounces of the camera and photon paths
+    pub max_depth: Option<u32>,
+    /// Number of photons traced at each iteration
+    pub nb_photons: usize,
+    /// Initial gathering radius (None: computed from the scene size)
+    pub init_radius: Option<f32>,
+    /// Fraction of the photons kept at each iteration
+    pub alpha: f32,
+    // Progressive state (one per pixel)
+    pixels: Vec<SPPMPixel>,
+    iteration: usize,
+}
+
+#[derive(Clone)]
+struct SPPMPixel {
+    radius: f32,
+    // Accumulated number of photons
+    n: f32,
+    // Accumulated (weighted) flux
+    tau: Color,
+    // Sum of the contribution not handled by the photons
+    // (emitters seen through specular surfaces and direct lighting)
+    ld: Color,
+}
+
+// Visible point from the sensor where the photons are gathered
+struct VisiblePoint<'a> {
+    its: Intersection<'a>,
+    throughput: Color,
+}
+
+// Element of the acceleration structure over the visible points (pixel index)
+type VisiblePointSphere = PointSphere<usize>;
+
+/// Initial gathering radius relative to the scene size
+pub(crate) fn default_radius(scene: &Scene) -> f32 {
+    let aabb = scene.meshes.iter().fold(AABB::default(), |aabb, m| {
+        m.vertices.iter().fold(aabb, |aabb, v| aabb.union_vec(v))
+    });
+    aabb.size().magnitude() * 0.005
+}
+
+// Number of photons traced by each task
+const PHOTONS_PER_TASK: usize = 4096;
+
+impl IntegratorSPPM {
+    pub fn new(
+        max_depth: Option<u32>,
+        nb_photons: usize,
+        init_radius: Option<f32>,
+        alpha: f32,
+    ) -> IntegratorSPPM {
+        IntegratorSPPM {
+            max_depth,
+            nb_photons,
+            init_radius,
+            alpha,
+            pixels: vec![],
+            iteration: 0,
+        }
+    }
+
+    /// Trace the camera path until the first non smooth surface
+    /// Return the visible point (if any) and the contribution not handled by the photons
+    fn visible_point<'a>(
+        &self,
+        (ix, iy): (u32, u32),
+        accel: &'a dyn Acceleration,
+        scene: &Scene,
+        sampler: &mut dyn Sampler,
+        emitters: &EmitterSampler,
+    ) -> (Option<VisiblePoint<'a>>, Color) {
+        let pix = Point2::new(ix as f32 + sampler.next(), iy as f32 + sampler.next());
+        let mut ray = scene.camera.generate(pix, sampler);
+        let mut throughput = Color::one();
+        let mut l_i = Color::zero();
+        let mut depth = 0;
+        while self.max_depth.map_or(true, |max| depth < max) {
+            depth += 1;
+            let its = match accel.trace(&ray) {
+                Some(its) => its,
+                None => {
+                    l_i += throughput * scene.enviroment_luminance(ray.d);
+                    break;
+                }
+            };
+
+            // Only specular paths reach this point
+            if its.cos_theta() > 0.0 {
+                l_i += throughput * its.mesh.emission;
+            }
+
+            if !its.mesh.bsdf.is_smooth() {
+                // Direct lighting (the photons only handle the indirect lighting)
+                let light_record =
+                    emitters.sample_light(&its.p, sampler.next(), sampler.next(), sampler.next2d());
+                if light_record.is_valid() && accel.visible(&its.p, &light_record.p) {
+                    let d_out_local = its.frame.to_local(light_record.d);
+                    l_i += throughput
+                        * its.mesh.bsdf.eval(
+                            &its.uv,
+                            &its.wi,
+                            &d_out_local,
+                            Domain::SolidAngle,
+                            Transport::Radiance,
+                        )
+                        * light_record.weight;
+                }
+                return (Some(VisiblePoint { its, throughput }), l_i);
+            }
+
+            // Continue the path through the specular surface
+            match its
+                .mesh
+                .bsdf
+                .sample(&its.uv, &its.wi, sampler.next2d(), Transport::Radiance)
+            {
+                Some(sampled_bsdf) => {
+                    throughput *= sampled_bsdf.weight;
+                    if throughput.is_zero() {
+                        break;
+                    }
+                    ray = Ray::new(its.p, its.frame.to_world(sampled_bsdf.d));
+                }
+                None => break,
+            }
+        }
+        (None, l_i)
+    }
+
+    /// Trace the photons and gather them at the visible points
+    /// return the number of photons and the flux received by each pixel
+    fn photon_pass(
+        &self,
+        accel: &dyn Acceleration,
+        scene: &Scene,
+        visible_points: &[Option<VisiblePoint>],
+        bvh: &BHVAccel<f32, VisiblePointSphere>,
+        seed: u64,
+    ) -> (Vec<u32>, Vec<Color>) {
+        let nb_tasks = self.nb_photons.div_ceil(PHOTONS_PER_TASK);
+        let progress_bar = Mutex::new(ProgressBar::new(nb_tasks as u64));
+        let pool = generate_pool(scene);
+        let gathered = pool.install(|| {
+            (0..nb_tasks)
+                .into_par_iter()
+                .map(|task| {
+                    let mut sampler =
+                        independent::IndependentSampler::from_seed(hash_combine(seed, task as u64));
+                    let emitters = scene.emitters_sampler();
+                    let mut gathered = vec![];
+                    let nb_photons =
+                        PHOTONS_PER_TASK.min(self.nb_photons - task * PHOTONS_PER_TASK);
+                    for _ in 0..nb_photons {
+                        let mut technique = TechniqueLightTracing {
+                            max_depth: self.max_depth,
+                            samplings: vec![Box::new(DirectionalSamplingStrategy {
+                                from_sensor: false,
+                            })],
+                            flux: None,
+                            render_surface: true,
+                            render_volume: false,
+                        };
+                        let mut path = Path::default();
+                        let root = generate(
+                            &mut path,
+                            accel,
+                            scene,
+                            &emitters,
+                            &mut sampler,
+                            &mut technique,
+                        );
+                        let flux = technique.flux.unwrap();
+                        let mut curr = root[0].0;
+                        let mut throughput = flux;
+                        let mut depth = 0;
+                        while let Some(&(edge_id, next)) = path.next_vertices(curr).first() {
+                            let edge = path.edge(edge_id);
+                            throughput *= edge.weight * edge.rr_weight;
+                            depth += 1;
+                            curr = next;
+                            // The first intersection is the direct lighting
+                            if depth < 2 {
+                                continue;
+                            }
+                            let v = match path.vertex(curr) {
+                                Vertex::Surface(ref v) => v,
+                                _ => continue,
+                            };
+                            if v.its.mesh.bsdf.is_smooth() {
+                                continue;
+                            }
+                            let d_photon = -edge.d;
+                            for id in bvh.gather_point(&v.its.p) {
+                                let pixel_id = bvh.elements[id].data;
+                                let vp = visible_points[pixel_id].as_ref().unwrap();
+                                let d_local = vp.its.frame.to_local(d_photon);
+                                if d_local.z <= 0.0 {
+                                    continue;
+                                }
+                                // The BSDF evaluation includes the cosine
+                                let f = vp.its.mesh.bsdf.eval(
+                                    &vp.its.uv,
+                                    &vp.its.wi,
+                                    &d_local,
+                                    Domain::SolidAngle,
+                                    Transport::Radiance,
+                                ) / d_local.z;
+                                gathered.push((pixel_id, throughput * f));
+                            }
+                        }
+                    }
+                    {
+                        progress_bar.lock().unwrap().inc();
+                    }
+                    gathered
+                })
+                .collect::<Vec<_>>()
+        });
+
+        let mut nb_gathered = vec![0; visible_points.len()];
+        let mut phi = vec![Color::zero(); visible_points.len()];
+        for (pixel_id, c) in gathered.into_iter().flatten() {
+            nb_gathered[pixel_id] += 1;
+            phi[pixel_id] += c;
+        }
+        (nb_gathered, phi)
+    }
+
+    fn iteration(&mut self, accel: &dyn Acceleration, scene: &Scene, seed: u64) {
+        let size = *scene.camera.size();
+        let region = scene.image_region();
+
+        // Camera pass
+        let mut pixels = std::mem::take(&mut self.pixels);
+        let pool = generate_pool(scene);
+        let visible_points = pool.install(|| {
+            pixels
+                .par_chunks_mut(size.x as usize)
+                .enumerate()
+                .map(|(iy, row)| {
+                    let mut sampler = independent::IndependentSampler::from_seed(pixel_seed(
+                        seed,
+                        Point2::new(0, iy as u32),
+                    ));
+                    let emitters = scene.emitters_sampler();
+                    row.iter_mut()
+                        .enumerate()
+                        .map(|(ix, pixel)| {
+                            // No visible point outside the crop
+                            if !region.contains(Point2::new(ix as f32 + 0.5, iy as f32 + 0.5)) {
+                                return None;
+                            }
+                            let (vp, l_i) = self.visible_point(
+                                (ix as u32, iy as u32),
+                                accel,
+                                scene,
+                                &mut sampler,
+                                &emitters,
+                            );
+                            pixel.ld += l_i;
+                            vp
+                        })
+                        .collect::<Vec<_>>()
+                })
+                .flatten()
+                .collect::<Vec<_>>()
+        });
+        self.pixels = pixels;
+
+        // Photon pass
+        let bvh = BHVAccel::create(
+            visible_points
+                .iter()
+                .enumerate()
+                .filter_map(|(pixel_id, vp)| {
+                    vp.as_ref().map(|vp| VisiblePointSphere {
+                        pos: vp.its.p,
+                        radius: self.pixels[pixel_id].radius,
+                        data: pixel_id,
+                    })
+                })
+                .collect(),
+        );
+        let (nb_gathered, phi) =
+            self.photon_pass(accel, scene, &visible_points, &bvh, hash_combine(seed, 1));
+
+        // Progressive radius reduction
+        for (pixel_id, pixel) in self.pixels.iter_mut().enumerate() {
+            let m = nb_gathered[pixel_id] as f32;
+            if m == 0.0 {
+                continue;
+            }
+            let n_new = pixel.n + self.alpha * m;
+            let radius_new = pixel.radius * (n_new / (pixel.n + m)).sqrt();
+            let throughput = visible_points[pixel_id].as_ref().unwrap().throughput;
+            pixel.tau = (pixel.tau + throughput * phi[pixel_id])
+                * (radius_new * radius_new / (pixel.radius * pixel.radius));
+            pixel.n = n_new;
+            pixel.radius = radius_new;
+        }
+        self.iteration += 1;
+    }
+}
+
+impl Integrator for IntegratorSPPM {
+    fn compute(&mut self, accel: &dyn Acceleration, scene: &Scene) -> BufferCollection {
+        let size = *scene.camera.size();
+        if scene.sampler != SamplerType::Independent {
+            warn!("SPPM only uses the independent sampler (--sampler is ignored)");
+        }
+        if self.pixels.is_empty() {
+            let radius = match self.init_radius {
+                Some(r) => r,
+                None => default_radius(scene),
+            };
+            info!("Initial radius: {}", radius);
+            self.pixels = vec![
+                SPPMPixel {
+                    radius,
+                    n: 0.0,
+                    tau: Color::zero(),
+                    ld: Color::zero(),
+                };
+                (size.x * size.y) as usize
+            ];
+        }
+
+        let seed = scene.pass_seed();
+        for i in 0..scene.nb_samples {
+            self.iteration(accel, scene, hash_combine(seed, i as u64));
+            info!("SPPM iteration: {}", self.iteration);
+        }
+
+        // Fill the image
+        let buffernames = vec![String::from("primal")];
+        let mut image = BufferCollection::new(Point2::new(0, 0), size, &buffernames);
+        let nb_photons = (self.iteration * self.nb_photons) as f32;
+        for (pixel_id, pixel) in self.pixels.iter().enumerate() {
+            let c = pixel.ld / self.iteration as f32
+                + pixel.tau / (nb_photons * std::f32::consts::PI * pixel.radius * pixel.radius);
+            image.accumulate(
+                Point2::new(pixel_id as u32 % size.x, pixel_id as u32 / size.x),
+                c,
+                "primal",
+            );
+        }
+        image
+    }
+
+    fn progressive(&self) -> bool {
+        true
+    }
+
+    fn samples_per_pixel(&self, _scene: &Scene, _img: &BufferCollection) -> f32 {
+        // One sample per pixel for each iteration (since the first compute() call)
+        self.iteration as f32
+    }
+}
@@ -72,10 +72,12 @@ impl Integrator for IntegratorSinglePlaneUncorrelated {
         info!("Gathering Single planes...");
         let progress_bar = Mutex::new(ProgressBar::new(image_blocks.len() as u64));
         let pool = generate_pool(scene);
-        let phase_function = PhaseFunction::Isotropic();
+        let phase_function = &m.phase;
+        let seed = scene.pass_seed();
         pool.install(|| {
             image_blocks.par_iter_mut().for_each(|im_block| {
-                let mut sampler_ray = independent::IndependentSampler::from_seed((im_block.pos.x + im_block.pos.y) as u64);
+                let mut sampler_ray =
+                    independent::IndependentSampler::from_seed(pixel_seed(seed, im_block.pos));
                 for ix in 0..im_block.size.x {
                     for iy in 0..im_block.size.y {
                         for _ in 0..scene.nb_samples {
@@ -84,7 +86,7 @@ impl Integrator for IntegratorSinglePlaneUncorrelated {
                                 ix_c as f32 + sampler_ray.next(),
                                 iy_c as f32 + sampler_ray.next(),
                             );
-                            let mut ray = scene.camera.generate(pix);
+                            let mut ray = scene.camera.generate(pix, &mut sampler_ray);
 
                             // Get the max distance
                             let max_dist = match accel.trace(&ray) {
@@ -0,0 +1,254 @@
+use crate::accel::*;
+use crate::integrators::aovs::first_hit_aovs;
+use crate::integrators::explicit::bdpt::{BDPTVertex, IntegratorBDPT};
+use crate::integrators::explicit::sppm::default_radius;
+use crate::integrators::film::Film;
+use crate::integrators::*;
+use crate::paths::vertex::*;
+use cgmath::Point2;
+use rayon::prelude::*;
+
+/// Vertex connection and merging (Georgiev et al. 2012, Hachisuka et al. 2012)
+/// the bidirectional connections and the merging of the light vertices
+/// are combined with the balance heuristic
+/// Each call to compute() does one iteration per sample (-n)
+/// and the merging radius continues to shrink from the previous calls.
+pub struct IntegratorVCM {
+    pub max_depth: Option<u32>,
+    /// Initial merging radius (None: computed from the scene size)
+    pub init_radius: Option<f32>,
+    /// Radius reduction factor
+    pub alpha: f32,
+    iteration: usize,
+}
+
+// Light subpath vertex cached for the merging (path and vertex indices)
+type LightVertexSphere = PointSphere<(usize, usize)>;
+
+impl IntegratorVCM {
+    pub fn new(max_depth: Option<u32>, init_radius: Option<f32>, alpha: f32) -> IntegratorVCM {
+        IntegratorVCM {
+            max_depth,
+            init_radius,
+            alpha,
+            iteration: 0,
+        }
+    }
+
+    /// Merge the sensor subpath vertices with the cached light vertices
+    fn merge_subpaths<'scene, 'emitter>(
+        &self,
+        scene: &Scene,
+        camera: &[BDPTVertex<'scene, 'emitter>],
+        light_paths: &[Vec<BDPTVertex<'scene, 'emitter>>],
+        bvh: &BHVAccel<f32, LightVertexSphere>,
+        merging: f32,
+    ) -> Color {
+        let max_length = self.max_depth.map_or(usize::MAX, |m| m as usize);
+        let mut l_i = Color::zero();
+        for t in 2..=camera.len() {
+            let pt = &camera[t - 1];
+            if !pt.mergeable() {
+                continue;
+            }
+            for id in bvh.gather_point(&pt.position()) {
+                let (path_id, s) = bvh.elements[id].data;
+                if s + t > max_length {
+                    continue;
+                }
+                let light = &light_paths[path_id];
+                let qs = &light[s];
+                let d_light = match qs.vertex {
+                    Vertex::Surface(ref v) => v.its.to_world(&v.its.wi),
+                    _ => continue,
+                };
+                // The BSDF evaluation includes the cosine
+                let cos = match pt.vertex {
+                    Vertex::Surface(ref v) => v.its.to_local(&d_light).z.abs(),
+                    _ => continue,
+                };
+                if cos == 0.0 {
+                    continue;
+                }
+                let c = pt.throughput * pt.eval(&d_light, true) * qs.throughput / (cos * merging);
+                if c.is_zero() {
+                    continue;
+                }
+                let weight = IntegratorBDPT::merging_weight(
+                    scene,
+                    &light[..s],
+                    &camera[..t],
+                    qs.pdf_fwd,
+                    merging,
+                );
+                let c = c * weight;
+                if c.r.is_finite() && c.g.is_finite() && c.b.is_finite() {
+                    l_i += c;
+                }
+            }
+        }
+        l_i
+    }
+}
+
+impl Integrator for IntegratorVCM {
+    fn compute(&mut self, accel: &dyn Acceleration, scene: &Scene) -> BufferCollection {
+        assert_ne!(scene.nb_samples, 0);
+        let size = *scene.camera.size();
+        let buffernames = aov_buffernames(scene);
+        if scene.sampler != SamplerType::Independent {
+            warn!("VCM only uses the independent sampler (--sampler is ignored)");
+        }
+        let bdpt = IntegratorBDPT {
+            max_depth: self.max_depth,
+            strategies: false,
+        };
+        let init_radius = match self.init_radius {
+            Some(r) => r,
+            None => default_radius(scene),
+        };
+        // One light subpath per pixel at each iteration
+        let nb_light_paths = (size.x * size.y) as usize;
+
+        let image_blocks_pos = generate_img_blocks(scene, &buffernames)
+            .iter()
+            .map(|im_block| (im_block.pos, im_block.size))
+            .collect::<Vec<_>>();
+        let mut films = image_blocks_pos
+            .iter()
+            .map(|(pos, size)| Film::new(scene.filter, *pos, *size, &buffernames))
+            .collect::<Vec<_>>();
+        // The light tracing strategies (t = 1) contribute to any pixel
+        // (the splats outside the crop are discarded)
+        let mut image =
+            Film::new(scene.filter, Point2::new(0, 0), size, &buffernames).crop(scene.crop);
+        // The blocks are distributed over the jobs
+        // each one splats into its own film covering the image
+        let job_size = (films.len() + SPLAT_JOBS - 1) / SPLAT_JOBS;
+        let mut jobs = films
+            .chunks_mut(job_size)
+            .zip(image_blocks_pos.chunks(job_size))
+            .collect::<Vec<_>>();
+        let seed = scene.pass_seed();
+        let emitters = scene.emitters_sampler();
+        let progress_bar = Mutex::new(ProgressBar::new(scene.nb_samples as u64));
+        let pool = generate_pool(scene);
+        for i in 0..scene.nb_samples {
+            let seed = hash_combine(seed, i as u64);
+            self.iteration += 1;
+            let radius = init_radius * (self.iteration as f32).powf((self.alpha - 1.0) * 0.5);
+            let merging = nb_light_paths as f32 * std::f32::consts::PI * radius * radius;
+
+            // Light pass
+            let light_paths = pool.install(|| {
+                (0..size.y)
+                    .into_par_iter()
+                    .map(|iy| {
+                        let mut sampler = independent::IndependentSampler::from_seed(pixel_seed(
+                            hash_combine(seed, 1),
+                            Point2::new(0, iy),
+                        ));
+                        (0..size.x)
+                            .map(|_| bdpt.light_subpath(accel, scene, &mut sampler, &emitters))
+                            .collect::<Vec<_>>()
+                    })
+                    .flatten()
+                    .collect::<Vec<_>>()
+            });
+            let bvh = BHVAccel::create(
+                light_paths
+                    .iter()
+                    .enumerate()
+                    .flat_map(|(path_id, light)| {
+                        light
+                            .iter()
+                            .enumerate()
+                            .skip(1)
+                            .filter(|(_, v)| v.mergeable())
+                            .map(move |(vertex_id, v)| LightVertexSphere {
+                                pos: v.position(),
+                                radius,
+                                data: (path_id, vertex_id),
+                            })
+                    })
+                    .collect(),
+            );
+
+            // Sensor pass
+            let splats = pool.install(|| {
+                splat_jobs(&mut jobs, &|(films, blocks_pos)| {
+                    let mut splats =
+                        Film::new(scene.filter, Point2::new(0, 0), size, &[]).crop(scene.crop);
+                    for (film, (pos, block_size)) in films.iter_mut().zip(blocks_pos.iter()) {
+                        let mut sampler =
+                            independent::IndependentSampler::from_seed(pixel_seed(seed, *pos));
+                        let mut contribs = vec![];
+                        for iy in 0..block_size.y {
+                            for ix in 0..block_size.x {
+                                let (px, py) = (ix + pos.x, iy + pos.y);
+                                let offset = sampler.next2d();
+                                let p = Point2::new(px as f32 + offset.x, py as f32 + offset.y);
+                                let sensor = scene.camera.sample_sensor(&mut sampler);
+                                let (path_sensor, camera) = bdpt.sensor_subpath(
+                                    p,
+                                    sensor,
+                                    accel,
+                                    scene,
+                                    &mut sampler,
+                                    &emitters,
+                                );
+                                contribs.clear();
+                                bdpt.connect_subpaths(
+                                    accel,
+                                    scene,
+                                    &mut sampler,
+                                    &emitters,
+                                    (&path_sensor, &camera),
+                                    &light_paths[(py * size.x + px) as usize],
+                                    merging,
+                                    &mut contribs,
+                                    &mut splats,
+                                );
+                                let mut c = self.merge_subpaths(
+                                    scene,
+                                    &camera,
+                                    &light_paths,
+                                    &bvh,
+                                    merging,
+                                );
+                                for (_, v) in &contribs {
+                                    c += *v;
+                                }
+                                let mut contribs = vec![(buffernames[0].clone(), c)];
+                                contribs.extend(first_hit_aovs(
+                                    &scene.aovs,
+                                    &scene.camera.generate_sample(p, sensor),
+                                    accel,
+                                    scene,
+                                ));
+                                film.add_sample(p, &contribs);
+                            }
+                        }
+                    }
+                    splats
+                })
+            });
+            image.merge(&splats);
+            {
+                progress_bar.lock().unwrap().inc();
+            }
+        }
+        info!("VCM iteration: {}", self.iteration);
+
+        // Fill the image
+        // (merged in the block order to be independent of the scheduling)
+        // Only the pixels of the crop connect to their light subpath
+        image.scale_splats(
+            nb_light_paths as f32 / (scene.image_region().nb_pixels() * scene.nb_samples) as f32,
+        );
+        for film in &films {
+            image.merge(film);
+        }
+        image.develop()
+    }
+}
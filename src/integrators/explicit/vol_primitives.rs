@@ -111,7 +111,7 @@ impl Photon {
         };
 
         // Evaluate the phase function
-        // FIXME: Check the direction of d_in
+        // (d_in points toward the previous vertex of the light path)
         let phase_func = self.phase_function.eval(&(-ray.d), &self.d_in);
 
         // Kernel (2D in case of BRE)
@@ -431,7 +431,7 @@ impl TechniqueVolPrimitives {
                                 d1: next_edge.d,
                                 length0,
                                 length1,
-                                phase_function: PhaseFunction::Isotropic(),
+                                phase_function: scene.volume.as_ref().unwrap().phase.clone(),
                                 radiance: flux,
                             });
                         }
@@ -479,7 +479,7 @@ impl TechniqueVolPrimitives {
                             o: v.its.p,
                             d: edge.d,
                             length: edge.dist.unwrap(),
-                            phase_function: PhaseFunction::Isotropic(),
+                            phase_function: scene.volume.as_ref().unwrap().phase.clone(),
                             radiance: flux,
                             radius,
                             from_surface: true,
@@ -505,7 +505,7 @@ impl TechniqueVolPrimitives {
                                 o: v.pos,
                                 d: edge.d,
                                 length: edge.dist.unwrap(),
-                                phase_function: PhaseFunction::Isotropic(),
+                                phase_function: scene.volume.as_ref().unwrap().phase.clone(),
                                 radiance: flux,
                                 radius,
                                 from_surface: false,
@@ -523,7 +523,7 @@ impl TechniqueVolPrimitives {
                             o: v.pos,
                             d: edge.d,
                             length: edge.dist.unwrap(),
-                            phase_function: PhaseFunction::Isotropic(),
+                            phase_function: scene.volume.as_ref().unwrap().phase.clone(),
                             radiance: flux,
                             radius,
                             from_surface: true,
@@ -604,7 +604,8 @@ impl Integrator for IntegratorVolPrimitives {
 
         info!("Generating the light paths...");
         let buffernames = vec![String::from("primal")];
-        let mut sampler = samplers::independent::IndependentSampler::default();
+        let seed = scene.pass_seed();
+        let mut sampler = samplers::independent::IndependentSampler::from_seed(seed);
         let mut nb_path_shot = 0;
 
         // Primitives vectors
@@ -754,7 +755,8 @@ impl Integrator for IntegratorVolPrimitives {
         let pool = generate_pool(scene);
         pool.install(|| {
             image_blocks.par_iter_mut().for_each(|im_block| {
-                let mut sampler = independent::IndependentSampler::default();
+                let mut sampler =
+                    independent::IndependentSampler::from_seed(pixel_seed(seed, im_block.pos));
                 for ix in 0..im_block.size.x {
                     for iy in 0..im_block.size.y {
                         for _ in 0..scene.nb_samples {
@@ -763,7 +765,7 @@ impl Integrator for IntegratorVolPrimitives {
                                 ix_c as f32 + sampler.next(),
                                 iy_c as f32 + sampler.next(),
                             );
-                            let mut ray = scene.camera.generate(pix);
+                            let mut ray = scene.camera.generate(pix, &mut sampler);
 
                             // Get the max distance
                             let max_dist = match accel.trace(&ray) {
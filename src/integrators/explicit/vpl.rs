@@ -1,3 +1,4 @@
+use crate::bsdfs::Transport;
 use crate::integrators::*;
 use crate::paths::path::*;
 use crate::paths::vertex::*;
@@ -30,16 +31,39 @@ struct VPLVolume {
     phase_function: PhaseFunction,
     radiance: Color,
 }
-struct VPLEmitter {
+struct VPLEmitter<'a> {
     pos: Point3<f32>,
     n: Vector3<f32>,
+    emitter: &'a dyn Emitter,
     emitted_radiance: Color,
 }
 
 enum VPL<'a> {
     Surface(VPLSurface<'a>),
     Volume(VPLVolume),
-    Emitter(VPLEmitter),
+    Emitter(VPLEmitter<'a>),
+}
+
+impl<'a> VPL<'a> {
+    // Radiance emitted by the VPL toward -d
+    // (d is the direction from the gathering point toward the VPL)
+    fn emitted_radiance(&self, d: &Vector3<f32>) -> Color {
+        match *self {
+            VPL::Emitter(ref vpl) => {
+                vpl.emitted_radiance * vpl.emitter.eval_direction(&vpl.pos, &vpl.n, &-*d)
+            }
+            VPL::Volume(ref vpl) => vpl.phase_function.eval(&vpl.d_in, &-*d) * vpl.radiance,
+            VPL::Surface(ref vpl) => {
+                vpl.its.mesh.bsdf.eval(
+                    &vpl.its.uv,
+                    &vpl.its.wi,
+                    &vpl.its.to_local(&-*d),
+                    Domain::SolidAngle,
+                    Transport::Importance,
+                ) * vpl.radiance
+            }
+        }
+    }
 }
 
 pub struct TechniqueVPL {
@@ -83,13 +107,13 @@ impl Technique for TechniqueVPL {
 }
 
 impl TechniqueVPL {
-    fn convert_vpl<'scene>(
+    fn convert_vpl<'scene: 'emitter, 'emitter>(
         &self,
-        path: &Path<'scene, '_>,
+        path: &Path<'scene, 'emitter>,
         scene: &'scene Scene,
         vertex_id: VertexID,
         options: IntegratorVPLOption,
-        vpls: &mut Vec<VPL<'scene>>,
+        vpls: &mut Vec<VPL<'emitter>>,
         flux: Color,
     ) {
         match path.vertex(vertex_id) {
@@ -147,6 +171,7 @@ impl TechniqueVPL {
                     vpls.push(VPL::Emitter(VPLEmitter {
                         pos: v.pos,
                         n: v.n,
+                        emitter: v.emitter,
                         emitted_radiance: flux,
                     }));
                 }
@@ -174,7 +199,8 @@ impl Integrator for IntegratorVPL {
     fn compute(&mut self, accel: &dyn Acceleration, scene: &Scene) -> BufferCollection {
         info!("Generating the VPL...");
         let buffernames = vec![String::from("primal")];
-        let mut sampler = samplers::independent::IndependentSampler::default();
+        let seed = scene.pass_seed();
+        let mut sampler = samplers::independent::IndependentSampler::from_seed(seed);
         let mut nb_path_shot = 0;
         let mut vpls = vec![];
         let emitters = scene.emitters_sampler();
@@ -210,7 +236,8 @@ impl Integrator for IntegratorVPL {
         let pool = generate_pool(scene);
         pool.install(|| {
             image_blocks.par_iter_mut().for_each(|im_block| {
-                let mut sampler = independent::IndependentSampler::default();
+                let mut sampler =
+                    independent::IndependentSampler::from_seed(pixel_seed(seed, im_block.pos));
                 for ix in 0..im_block.size.x {
                     for iy in 0..im_block.size.y {
                         for _ in 0..scene.nb_samples {
@@ -244,28 +271,12 @@ impl Integrator for IntegratorVPL {
 }
 
 impl IntegratorVPL {
-    fn transmittance(
-        &self,
-        medium: Option<&HomogenousVolume>,
-        p1: Point3<f32>,
-        p2: Point3<f32>,
-    ) -> Color {
-        if let Some(m) = medium {
-            let mut d = p2 - p1;
-            let dist = d.magnitude();
-            d /= dist;
-            let mut r = Ray::new(p1, d);
-            r.tfar = dist;
-            m.transmittance(r)
-        } else {
-            Color::one()
-        }
-    }
-
+    // Radiance reflected at the surface (VPLs connected through the media)
     fn gathering_surface<'a>(
         &self,
-        medium: Option<&HomogenousVolume>,
+        scene: &Scene,
         accel: &dyn Acceleration,
+        sampler: &mut dyn Sampler,
         vpls: &[VPL<'a>],
         norm_vpl: f32,
         its: &Intersection,
@@ -276,135 +287,71 @@ impl IntegratorVPL {
         if its.cos_theta() > 0.0 {
             l_i += &(its.mesh.emission);
         }
+        if its.mesh.bsdf.is_smooth() {
+            return l_i;
+        }
 
         for vpl in vpls {
-            match *vpl {
-                VPL::Emitter(ref vpl) => {
-                    if accel.visible(&vpl.pos, &its.p) {
-                        let mut d = vpl.pos - its.p;
-                        let dist = d.magnitude();
-                        d /= dist;
-
-                        let emitted_radiance = vpl.emitted_radiance
-                            * vpl.n.dot(-d).max(0.0)
-                            * std::f32::consts::FRAC_1_PI;
-                        if !its.mesh.bsdf.is_smooth() {
-                            let bsdf_val = its.mesh.bsdf.eval(
-                                &its.uv,
-                                &its.wi,
-                                &its.to_local(&d),
-                                Domain::SolidAngle,
-                            );
-                            let trans = self.transmittance(medium, its.p, vpl.pos);
-                            l_i += trans * norm_vpl * emitted_radiance * bsdf_val / (dist * dist);
-                        }
-                    }
-                }
-                VPL::Volume(ref vpl) => {
-                    let mut d = vpl.pos - its.p;
-                    let dist = d.magnitude();
-                    d /= dist;
-
-                    if !its.mesh.bsdf.is_smooth() {
-                        let emitted_radiance = vpl.phase_function.eval(&vpl.d_in, &d);
-                        let bsdf_val = its.mesh.bsdf.eval(
-                            &its.uv,
-                            &its.wi,
-                            &its.to_local(&d),
-                            Domain::SolidAngle,
-                        );
-                        let trans = self.transmittance(medium, its.p, vpl.pos);
-                        l_i += trans * norm_vpl * emitted_radiance * bsdf_val * vpl.radiance
-                            / (dist * dist);
-                    }
-                }
-                VPL::Surface(ref vpl) => {
-                    if accel.visible(&vpl.its.p, &its.p) {
-                        let mut d = vpl.its.p - its.p;
-                        let dist = d.magnitude();
-                        d /= dist;
+            let pos = match *vpl {
+                VPL::Emitter(ref vpl) => vpl.pos,
+                VPL::Volume(ref vpl) => vpl.pos,
+                VPL::Surface(ref vpl) => vpl.its.p,
+            };
+            let mut d = pos - its.p;
+            let dist = d.magnitude();
+            d /= dist;
 
-                        if !its.mesh.bsdf.is_smooth() {
-                            let emitted_radiance = vpl.its.mesh.bsdf.eval(
-                                &vpl.its.uv,
-                                &vpl.its.wi,
-                                &vpl.its.to_local(&-d),
-                                Domain::SolidAngle,
-                            );
-                            let bsdf_val = its.mesh.bsdf.eval(
-                                &its.uv,
-                                &its.wi,
-                                &its.to_local(&d),
-                                Domain::SolidAngle,
-                            );
-                            let trans = self.transmittance(medium, its.p, vpl.its.p);
-                            l_i += trans * norm_vpl * emitted_radiance * bsdf_val * vpl.radiance
-                                / (dist * dist);
-                        }
-                    }
-                }
+            let bsdf_val = its.mesh.bsdf.eval(
+                &its.uv,
+                &its.wi,
+                &its.to_local(&d),
+                Domain::SolidAngle,
+                Transport::Radiance,
+            );
+            if bsdf_val.is_zero() {
+                continue;
+            }
+            let emitted_radiance = vpl.emitted_radiance(&d);
+            if emitted_radiance.is_zero() {
+                continue;
             }
+            let trans = scene.transmittance(accel, &its.p, &pos, its.medium(&d), sampler);
+            l_i += trans * norm_vpl * emitted_radiance * bsdf_val / (dist * dist);
         }
         l_i
     }
 
+    // Radiance scattered inside the medium at pos (VPLs connected through the media)
     fn gathering_volume<'a>(
         &self,
-        medium: Option<&HomogenousVolume>,
+        scene: &Scene,
         accel: &dyn Acceleration,
+        sampler: &mut dyn Sampler,
         vpls: &[VPL<'a>],
         norm_vpl: f32,
         d_cam: Vector3<f32>,
         pos: Point3<f32>,
+        medium: Option<usize>,
         phase: &PhaseFunction,
     ) -> Color {
         let mut l_i = Color::zero();
         for vpl in vpls {
-            match *vpl {
-                VPL::Emitter(ref vpl) => {
-                    if accel.visible(&vpl.pos, &pos) {
-                        let mut d = vpl.pos - pos;
-                        let dist = d.magnitude();
-                        d /= dist;
-
-                        let emitted_radiance = vpl.emitted_radiance
-                            * vpl.n.dot(-d).max(0.0)
-                            * std::f32::consts::FRAC_1_PI;
-                        let phase_val = phase.eval(&d_cam, &d);
-                        let trans = self.transmittance(medium, pos, vpl.pos);
-                        l_i += trans * norm_vpl * emitted_radiance * phase_val / (dist * dist);
-                    }
-                }
-                VPL::Volume(ref vpl) => {
-                    let mut d = vpl.pos - pos;
-                    let dist = d.magnitude();
-                    d /= dist;
-
-                    let emitted_radiance = vpl.phase_function.eval(&vpl.d_in, &d);
-                    let phase_val = phase.eval(&d_cam, &d);
-                    let trans = self.transmittance(medium, pos, vpl.pos);
-                    l_i += trans * norm_vpl * emitted_radiance * phase_val * vpl.radiance
-                        / (dist * dist);
-                }
-                VPL::Surface(ref vpl) => {
-                    if accel.visible(&vpl.its.p, &pos) {
-                        let mut d = vpl.its.p - pos;
-                        let dist = d.magnitude();
-                        d /= dist;
+            let vpl_pos = match *vpl {
+                VPL::Emitter(ref vpl) => vpl.pos,
+                VPL::Volume(ref vpl) => vpl.pos,
+                VPL::Surface(ref vpl) => vpl.its.p,
+            };
+            let mut d = vpl_pos - pos;
+            let dist = d.magnitude();
+            d /= dist;
 
-                        let emitted_radiance = vpl.its.mesh.bsdf.eval(
-                            &vpl.its.uv,
-                            &vpl.its.wi,
-                            &vpl.its.to_local(&-d),
-                            Domain::SolidAngle,
-                        );
-                        let phase_val = phase.eval(&d_cam, &d);
-                        let trans = self.transmittance(medium, pos, vpl.its.p);
-                        l_i += trans * norm_vpl * emitted_radiance * phase_val * vpl.radiance
-                            / (dist * dist);
-                    }
-                }
+            let emitted_radiance = vpl.emitted_radiance(&d);
+            if emitted_radiance.is_zero() {
+                continue;
             }
+            let phase_val = phase.eval(&d_cam, &d);
+            let trans = scene.transmittance(accel, &pos, &vpl_pos, medium, sampler);
+            l_i += trans * norm_vpl * emitted_radiance * phase_val / (dist * dist);
         }
         l_i
     }
@@ -419,64 +366,55 @@ impl IntegratorVPL {
         norm_vpl: f32,
     ) -> Color {
         let pix = Point2::new(ix as f32 + sampler.next(), iy as f32 + sampler.next());
-        let ray = scene.camera.generate(pix);
-        let mut l_i = Color::zero();
+        let mut ray = scene.camera.generate(pix, sampler);
 
-        // Check if we have a intersection with the primary ray
-        let its = match accel.trace(&ray) {
-            Some(x) => x,
-            None => {
-                if let Some(m) = &scene.volume {
-                    // Sample the participating media
-                    let mrec = m.sample(&ray, sampler.next2d());
-                    assert!(!mrec.exited);
+        // Follow the primary ray across the media boundaries (null BSDF)
+        // until a scattering event inside a medium or a surface
+        let mut medium = scene.medium_at(accel, &ray.o, &ray.d);
+        let mut weight = Color::one();
+        loop {
+            let its = accel.trace(&ray);
+            if let Some(m) = scene.medium(medium) {
+                // Sample the participating media
+                let mut ray_med = ray;
+                if let Some(ref its) = its {
+                    ray_med.tfar = its.dist;
+                }
+                let mrec = m.sample_distance(&ray_med, sampler);
+                weight *= mrec.w;
+                if !mrec.exited {
+                    if self.option_lt == IntegratorVPLOption::Surface {
+                        return Color::zero();
+                    }
                     let pos = Point3::from_vec(ray.o.to_vec() + ray.d * mrec.t);
-                    let phase_function = PhaseFunction::Isotropic(); // FIXME:
-                    l_i *= self.gathering_volume(
-                        scene.volume.as_ref(),
-                        accel,
-                        vpls,
-                        norm_vpl,
-                        -ray.d,
-                        pos,
-                        &phase_function,
-                    ) * mrec.w;
-                    return l_i;
-                } else {
-                    return l_i;
+                    return weight
+                        * self.gathering_volume(
+                            scene,
+                            accel,
+                            sampler,
+                            vpls,
+                            norm_vpl,
+                            -ray.d,
+                            pos,
+                            medium,
+                            m.phase(),
+                        );
                 }
             }
-        };
 
-        if let Some(m) = &scene.volume {
-            let mut ray_med = ray;
-            ray_med.tfar = its.dist;
-            let mrec = m.sample(&ray_med, sampler.next2d());
-            if !mrec.exited {
-                let pos = Point3::from_vec(ray.o.to_vec() + ray.d * mrec.t);
-                let phase_function = PhaseFunction::Isotropic(); // FIXME:
-                l_i += self.gathering_volume(
-                    scene.volume.as_ref(),
-                    accel,
-                    vpls,
-                    norm_vpl,
-                    -ray.d,
-                    pos,
-                    &phase_function,
-                ) * mrec.w;
-                l_i
-            } else {
-                if self.option_lt != IntegratorVPLOption::Volume {
-                    l_i += self.gathering_surface(scene.volume.as_ref(), accel, vpls, norm_vpl, &its)
-                        * mrec.w;
-                }
-                l_i
+            let its = match its {
+                Some(its) => its,
+                None => return Color::zero(),
+            };
+            if its.mesh.bsdf.is_null() {
+                medium = its.medium(&ray.d);
+                ray = Ray::new(its.p, ray.d);
+                continue;
             }
-        } else {
-            if self.option_lt != IntegratorVPLOption::Surface {
-                l_i += self.gathering_surface(scene.volume.as_ref(), accel, vpls, norm_vpl, &its);
+            if self.option_lt == IntegratorVPLOption::Volume {
+                return Color::zero();
             }
-            l_i
+            return weight * self.gathering_surface(scene, accel, sampler, vpls, norm_vpl, &its);
         }
     }
 }
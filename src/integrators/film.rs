@@ -0,0 +1,391 @@
+use crate::integrators::aovs::AOV;
+use crate::integrators::*;
+use crate::scene::ImageRegion;
+use crate::structure::Color;
+use cgmath::{Point2, Vector2};
+
+/// Pixel reconstruction filter (separable)
+/// the offsets are in pixels from the pixel center
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum Filter {
+    Box {
+        radius: f32,
+    },
+    Tent {
+        radius: f32,
+    },
+    Gaussian {
+        radius: f32,
+        sigma: f32,
+    },
+    /// Mitchell-Netravali with the B and C parameters
+    Mitchell {
+        radius: f32,
+        b: f32,
+        c: f32,
+    },
+    /// Windowed sinc (tau: number of lobes of the window)
+    Lanczos {
+        radius: f32,
+        tau: f32,
+    },
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Filter::Box { radius: 0.5 }
+    }
+}
+
+impl Filter {
+    /// Filter with the default parameters (box, tent, gaussian, mitchell or lanczos)
+    /// the radius can be changed (None: default radius)
+    pub fn from_name(name: &str, radius: Option<f32>) -> Option<Filter> {
+        let filter = match name {
+            "box" => Filter::Box { radius: 0.5 },
+            "tent" => Filter::Tent { radius: 1.0 },
+            "gaussian" => Filter::Gaussian {
+                radius: 1.5,
+                sigma: 0.5,
+            },
+            "mitchell" => Filter::Mitchell {
+                radius: 2.0,
+                b: 1.0 / 3.0,
+                c: 1.0 / 3.0,
+            },
+            "lanczos" => Filter::Lanczos {
+                radius: 3.0,
+                tau: 3.0,
+            },
+            _ => return None,
+        };
+        Some(match radius {
+            Some(r) => filter.with_radius(r),
+            None => filter,
+        })
+    }
+
+    fn with_radius(self, r: f32) -> Filter {
+        match self {
+            Filter::Box { .. } => Filter::Box { radius: r },
+            Filter::Tent { .. } => Filter::Tent { radius: r },
+            Filter::Gaussian { sigma, .. } => Filter::Gaussian { radius: r, sigma },
+            Filter::Mitchell { b, c, .. } => Filter::Mitchell { radius: r, b, c },
+            Filter::Lanczos { tau, .. } => Filter::Lanczos { radius: r, tau },
+        }
+    }
+
+    pub fn radius(&self) -> f32 {
+        match *self {
+            Filter::Box { radius }
+            | Filter::Tent { radius }
+            | Filter::Gaussian { radius, .. }
+            | Filter::Mitchell { radius, .. }
+            | Filter::Lanczos { radius, .. } => radius,
+        }
+    }
+
+    fn eval1d(&self, x: f32) -> f32 {
+        match *self {
+            // Half-open interval: a sample only belongs to one pixel
+            Filter::Box { radius } => {
+                if x >= -radius && x < radius {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            Filter::Tent { radius } => (radius - x.abs()).max(0.0),
+            Filter::Gaussian { radius, sigma } => {
+                let gaussian = |x: f32| (-x * x / (2.0 * sigma * sigma)).exp();
+                (gaussian(x) - gaussian(radius)).max(0.0)
+            }
+            Filter::Mitchell { radius, b, c } => {
+                let x = (2.0 * x / radius).abs();
+                if x > 2.0 {
+                    0.0
+                } else if x > 1.0 {
+                    ((-b - 6.0 * c) * x * x * x
+                        + (6.0 * b + 30.0 * c) * x * x
+                        + (-12.0 * b - 48.0 * c) * x
+                        + (8.0 * b + 24.0 * c))
+                        / 6.0
+                } else {
+                    ((12.0 - 9.0 * b - 6.0 * c) * x * x * x
+                        + (-18.0 + 12.0 * b + 6.0 * c) * x * x
+                        + (6.0 - 2.0 * b))
+                        / 6.0
+                }
+            }
+            Filter::Lanczos { radius, tau } => {
+                let sinc = |x: f32| {
+                    let x = x.abs() * std::f32::consts::PI;
+                    if x < 1e-5 {
+                        1.0
+                    } else {
+                        x.sin() / x
+                    }
+                };
+                if x.abs() > radius {
+                    0.0
+                } else {
+                    sinc(x) * sinc(x / tau)
+                }
+            }
+        }
+    }
+
+    pub fn eval(&self, x: f32, y: f32) -> f32 {
+        self.eval1d(x) * self.eval1d(y)
+    }
+
+    /// Integral of the filter over its footprint (used to normalize the splats)
+    pub fn integral(&self) -> f32 {
+        const NB_STEPS: usize = 1024;
+        let radius = self.radius();
+        let dx = 2.0 * radius / NB_STEPS as f32;
+        let integral_1d = (0..NB_STEPS)
+            .map(|i| self.eval1d(-radius + (i as f32 + 0.5) * dx) * dx)
+            .sum::<f32>();
+        integral_1d * integral_1d
+    }
+}
+
+/// Film accumulating the filtered samples of a part of the image
+/// The film has a margin around its pixels to receive the
+/// footprint of the samples crossing its borders (merged with Film::merge)
+pub struct Film {
+    pub filter: Filter,
+    // Position of the first pixel (margin included)
+    origin: Point2<i32>,
+    // Position and size of the pixels without the margin
+    pos: Point2<u32>,
+    size: Vector2<u32>,
+    // Weighted sum of the camera samples
+    values: BufferCollection,
+    // Sum of the filter weights of the camera samples
+    weights: Vec<f32>,
+    // AOVs of the camera samples, not filtered (see AOV::averaged):
+    // sum of the samples inside each pixel or the sample nearest to its center
+    aovs: BufferCollection,
+    aov_counts: Vec<f32>,
+    aov_nearest: Vec<f32>,
+    // Contributions of the light paths (not normalized by the weights)
+    splats: BufferCollection,
+    splat_scale: f32,
+    // Region where the splats are kept (see Scene::crop)
+    crop: Option<ImageRegion>,
+}
+
+impl Film {
+    pub fn new(filter: Filter, pos: Point2<u32>, size: Vector2<u32>, names: &[String]) -> Film {
+        let margin = (filter.radius() - 0.5).ceil().max(0.0) as u32;
+        let size_margin = size + Vector2::new(2 * margin, 2 * margin);
+        let nb_pixels = (size_margin.x * size_margin.y) as usize;
+        let (aov_names, names): (Vec<String>, Vec<String>) = names
+            .iter()
+            .cloned()
+            .partition(|name| AOV::from_name(name).is_some());
+        Film {
+            filter,
+            origin: Point2::new(pos.x as i32 - margin as i32, pos.y as i32 - margin as i32),
+            pos,
+            size,
+            values: BufferCollection::new(Point2::new(0, 0), size_margin, &names),
+            weights: vec![0.0; nb_pixels],
+            aovs: BufferCollection::new(Point2::new(0, 0), size_margin, &aov_names),
+            aov_counts: vec![0.0; nb_pixels],
+            aov_nearest: vec![std::f32::INFINITY; nb_pixels],
+            splats: BufferCollection::new(Point2::new(0, 0), size_margin, &names),
+            splat_scale: 1.0 / filter.integral(),
+            crop: None,
+        }
+    }
+
+    /// Discard the splats outside the crop (see Scene::crop)
+    pub fn crop(mut self, crop: Option<ImageRegion>) -> Film {
+        self.crop = crop;
+        self
+    }
+
+    // Film pixels inside the filter footprint of p with their weights
+    fn footprint(&self, p: Point2<f32>) -> Vec<(Point2<u32>, f32)> {
+        let radius = self.filter.radius();
+        let size = self.values.size;
+        let mut pixels = vec![];
+        let y_min = ((p.y - 0.5 - radius).ceil() as i32).max(self.origin.y);
+        let y_max = ((p.y - 0.5 + radius).floor() as i32).min(self.origin.y + size.y as i32 - 1);
+        let x_min = ((p.x - 0.5 - radius).ceil() as i32).max(self.origin.x);
+        let x_max = ((p.x - 0.5 + radius).floor() as i32).min(self.origin.x + size.x as i32 - 1);
+        for y in y_min..=y_max {
+            for x in x_min..=x_max {
+                let w = self
+                    .filter
+                    .eval(p.x - (x as f32 + 0.5), p.y - (y as f32 + 0.5));
+                if w != 0.0 {
+                    pixels.push((
+                        Point2::new((x - self.origin.x) as u32, (y - self.origin.y) as u32),
+                        w,
+                    ));
+                }
+            }
+        }
+        pixels
+    }
+
+    /// Add a camera sample at the film position p (in pixels)
+    /// with its contribution to each buffer
+    /// The weight is accumulated even if the contributions are zero
+    /// The AOVs (see AOV) only go to the pixel containing p
+    pub fn add_sample(&mut self, p: Point2<f32>, contribs: &[(String, Color)]) {
+        let (aovs, contribs): (Vec<_>, Vec<_>) = contribs
+            .iter()
+            .partition(|(name, _)| AOV::from_name(name).is_some());
+        for (pixel, w) in self.footprint(p) {
+            self.weights[(pixel.y * self.values.size.x + pixel.x) as usize] += w;
+            for (name, c) in &contribs {
+                if !self.values.values.contains_key(name) {
+                    self.values.register(name.clone());
+                }
+                self.values.accumulate(pixel, *c * w, name);
+            }
+        }
+
+        let size = self.aovs.size;
+        let (x, y) = (
+            p.x.floor() as i32 - self.origin.x,
+            p.y.floor() as i32 - self.origin.y,
+        );
+        if x < 0 || y < 0 || x >= size.x as i32 || y >= size.y as i32 {
+            return;
+        }
+        let pixel = Point2::new(x as u32, y as u32);
+        let index = (pixel.y * size.x + pixel.x) as usize;
+        let dist = (p.x.fract() - 0.5).powi(2) + (p.y.fract() - 0.5).powi(2);
+        let nearest = dist < self.aov_nearest[index];
+        self.aov_counts[index] += 1.0;
+        if nearest {
+            self.aov_nearest[index] = dist;
+        }
+        for (name, c) in aovs {
+            if !self.aovs.values.contains_key(name) {
+                self.aovs.register(name.clone());
+            }
+            if AOV::from_name(name).unwrap().averaged() {
+                self.aovs.accumulate(pixel, *c, name);
+            } else if nearest {
+                self.aovs.values.get_mut(name).unwrap().colors[index] = *c;
+            }
+        }
+    }
+
+    /// Add the contribution of a light path at the film position p (in pixels)
+    pub fn splat(&mut self, p: Point2<f32>, c: Color, name: &str) {
+        if let Some(crop) = self.crop {
+            if !crop.contains(p) {
+                return;
+            }
+        }
+        if !self.splats.values.contains_key(name) {
+            self.splats.register(name.to_string());
+        }
+        for (pixel, w) in self.footprint(p) {
+            self.splats
+                .accumulate(pixel, c * (w * self.splat_scale), name);
+        }
+    }
+
+    /// Add the pixels (and the margin) of a film covered by this film
+    pub fn merge(&mut self, o: &Film) {
+        let size = self.values.size;
+        for y in 0..o.values.size.y {
+            for x in 0..o.values.size.x {
+                let (gx, gy) = (o.origin.x + x as i32, o.origin.y + y as i32);
+                let (lx, ly) = (gx - self.origin.x, gy - self.origin.y);
+                if lx < 0 || ly < 0 || lx >= size.x as i32 || ly >= size.y as i32 {
+                    continue;
+                }
+                let (src, dst) = (Point2::new(x, y), Point2::new(lx as u32, ly as u32));
+                let (src_id, dst_id) = (
+                    (src.y * o.values.size.x + src.x) as usize,
+                    (dst.y * size.x + dst.x) as usize,
+                );
+                self.weights[dst_id] += o.weights[src_id];
+                for (name, bitmap) in &o.values.values {
+                    if !self.values.values.contains_key(name) {
+                        self.values.register(name.clone());
+                    }
+                    self.values.accumulate(dst, bitmap.pixel(src), name);
+                }
+                self.aov_counts[dst_id] += o.aov_counts[src_id];
+                let nearest = o.aov_nearest[src_id] < self.aov_nearest[dst_id];
+                if nearest {
+                    self.aov_nearest[dst_id] = o.aov_nearest[src_id];
+                }
+                for (name, bitmap) in &o.aovs.values {
+                    if !self.aovs.values.contains_key(name) {
+                        self.aovs.register(name.clone());
+                    }
+                    if AOV::from_name(name).unwrap().averaged() {
+                        self.aovs.accumulate(dst, bitmap.pixel(src), name);
+                    } else if nearest {
+                        self.aovs.values.get_mut(name).unwrap().colors[dst_id] = bitmap.pixel(src);
+                    }
+                }
+                for (name, bitmap) in &o.splats.values {
+                    if !self.splats.values.contains_key(name) {
+                        self.splats.register(name.clone());
+                    }
+                    self.splats.accumulate(dst, bitmap.pixel(src), name);
+                }
+            }
+        }
+    }
+
+    /// Scale the splatted contributions (e.g., by the number of light paths)
+    pub fn scale_splats(&mut self, f: f32) {
+        self.splats.scale(f);
+    }
+
+    /// Normalize the camera samples and add the splats
+    /// (the margin is removed)
+    pub fn develop(&self) -> BufferCollection {
+        let margin = Vector2::new(
+            (self.pos.x as i32 - self.origin.x) as u32,
+            (self.pos.y as i32 - self.origin.y) as u32,
+        );
+        let mut names = self.values.values.keys().cloned().collect::<Vec<_>>();
+        for name in self.splats.values.keys() {
+            if !self.values.values.contains_key(name) {
+                names.push(name.clone());
+            }
+        }
+        names.extend(self.aovs.values.keys().cloned());
+        let mut image = BufferCollection::new(self.pos, self.size, &names);
+        for y in 0..self.size.y {
+            for x in 0..self.size.x {
+                let src = Point2::new(x + margin.x, y + margin.y);
+                let w = self.weights[(src.y * self.values.size.x + src.x) as usize];
+                for (name, bitmap) in &self.values.values {
+                    if w != 0.0 {
+                        image.accumulate(Point2::new(x, y), bitmap.pixel(src) * (1.0 / w), name);
+                    }
+                }
+                for (name, bitmap) in &self.splats.values {
+                    image.accumulate(Point2::new(x, y), bitmap.pixel(src), name);
+                }
+                let count = self.aov_counts[(src.y * self.values.size.x + src.x) as usize];
+                for (name, bitmap) in &self.aovs.values {
+                    let c = bitmap.pixel(src);
+                    if !AOV::from_name(name).unwrap().averaged() {
+                        image.accumulate(Point2::new(x, y), c, name);
+                    } else if count != 0.0 {
+                        image.accumulate(Point2::new(x, y), c * (1.0 / count), name);
+                    }
+                }
+            }
+        }
+        image
+    }
+}
@@ -28,12 +28,13 @@ impl Technique for TechniqueGradientPathTracing {
         _emitters: &'emitter EmitterSampler,
     ) -> Vec<(VertexID, Color)> {
         // Only generate a path from the sensor
+        let uv = Point2::new(
+            self.img_pos.x as f32 + sampler.next(),
+            self.img_pos.y as f32 + sampler.next(),
+        );
         let root = Vertex::Sensor(SensorVertex {
-            uv: Point2::new(
-                self.img_pos.x as f32 + sampler.next(),
-                self.img_pos.y as f32 + sampler.next(),
-            ),
-            pos: scene.camera.position(),
+            uv,
+            pos: scene.camera.generate(uv, sampler).o,
             edge_in: None,
             edge_out: None,
         });
@@ -122,11 +123,13 @@ impl IntegratorGradient for IntegratorGradientPathTracing {
         let (nb_buffers, buffernames, mut image_blocks, ids) =
             generate_img_blocks_gradient(scene, self.recons.as_ref());
 
+        let seed = scene.pass_seed();
         let progress_bar = Mutex::new(ProgressBar::new(image_blocks.len() as u64));
         let pool = generate_pool(scene);
         pool.install(|| {
             image_blocks.par_iter_mut().for_each(|(info, im_block)| {
-                let mut sampler = independent::IndependentSampler::default();
+                let mut sampler =
+                    independent::IndependentSampler::from_seed(pixel_seed(seed, im_block.pos));
                 let mut shiftmapping = RandomReplay::default();
                 let emitters = scene.emitters_sampler();
                 for ix in info.x_pos_off..im_block.size.x - info.x_size_off {
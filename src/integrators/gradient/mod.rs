@@ -2,7 +2,7 @@ use crate::integrators::*;
 use crate::scene::Scene;
 use crate::structure::Color;
 use crate::tools::StepRangeInt;
-use cgmath::{Point2, Vector2};
+use cgmath::Point2;
 use std::cmp;
 
 #[derive(Clone, Debug, Copy)]
@@ -55,18 +55,10 @@ pub struct BufferIDGradient {
     pub gradient_x: usize,
     pub gradient_y: usize,
 }
-pub fn generate_img_blocks_gradient(
-    scene: &Scene,
-    recons: &(dyn PoissonReconstruction + Sync),
-) -> (
-    usize,
-    Vec<String>,
-    Vec<(BlockInfoGradient, BufferCollection)>,
-    BufferIDGradient,
-) {
-    // The buffers names are always:
-    // ["very_direct", ("primal", "gradient_x", "gradient_y")+]
-    let (nb_buffers, buffernames) = if let Some(number_buffers) = recons.need_variance_estimates() {
+/// Number of estimates and the names of the buffers:
+/// ["very_direct", ("primal", "gradient_x", "gradient_y")+]
+pub fn gradient_buffernames(recons: &(dyn PoissonReconstruction + Sync)) -> (usize, Vec<String>) {
+    if let Some(number_buffers) = recons.need_variance_estimates() {
         let mut buffernames = Vec::new();
         buffernames.reserve((3 * number_buffers) + 1);
         buffernames.push(String::from("very_direct"));
@@ -86,36 +78,50 @@ pub fn generate_img_blocks_gradient(
                 String::from("gradient_y"),
             ],
         )
-    };
+    }
+}
+
+pub fn generate_img_blocks_gradient(
+    scene: &Scene,
+    recons: &(dyn PoissonReconstruction + Sync),
+) -> (
+    usize,
+    Vec<String>,
+    Vec<(BlockInfoGradient, BufferCollection)>,
+    BufferIDGradient,
+) {
+    let (nb_buffers, buffernames) = gradient_buffernames(recons);
 
+    // The blocks cover the rendered region (see Scene::image_region)
+    // with a margin of one pixel (inside the image) for the offset paths
+    let region = scene.image_region();
+    let (min, max) = (region.pos, region.max());
+    let size = *scene.camera.size();
     let mut image_blocks = Vec::new();
-    for ix in StepRangeInt::new(0, scene.camera.size().x as usize, 16) {
-        for iy in StepRangeInt::new(0, scene.camera.size().y as usize, 16) {
-            let pos_off = Point2 {
-                x: cmp::max(0, ix as i32 - 1) as u32,
-                y: cmp::max(0, iy as i32 - 1) as u32,
+    for ix in StepRangeInt::new((min.x - min.x % 16) as usize, max.x as usize, 16) {
+        for iy in StepRangeInt::new((min.y - min.y % 16) as usize, max.y as usize, 16) {
+            let start = Point2 {
+                x: cmp::max(ix as u32, min.x),
+                y: cmp::max(iy as u32, min.y),
+            };
+            let end = Point2 {
+                x: cmp::min(ix as u32 + 16, max.x),
+                y: cmp::min(iy as u32 + 16, max.y),
             };
-            let desired_size = Vector2 {
-                x: 16 + if ix == 0 { 1 } else { 2 },
-                y: 16 + if iy == 0 { 1 } else { 2 },
+            let pos_off = Point2 {
+                x: start.x.saturating_sub(1),
+                y: start.y.saturating_sub(1),
             };
-            let max_size = Vector2 {
-                x: (scene.camera.size().x - pos_off.x) as u32,
-                y: (scene.camera.size().y - pos_off.y) as u32,
+            let end_off = Point2 {
+                x: cmp::min(end.x + 1, size.x),
+                y: cmp::min(end.y + 1, size.y),
             };
-            let block = BufferCollection::new(
-                pos_off,
-                Vector2 {
-                    x: cmp::min(desired_size.x, max_size.x),
-                    y: cmp::min(desired_size.y, max_size.y),
-                },
-                &buffernames,
-            );
+            let block = BufferCollection::new(pos_off, end_off - pos_off, &buffernames);
             let info = BlockInfoGradient {
-                x_pos_off: if ix == 0 { 0 } else { 1 },
-                y_pos_off: if iy == 0 { 0 } else { 1 },
-                x_size_off: if desired_size.x <= max_size.x { 1 } else { 0 },
-                y_size_off: if desired_size.y <= max_size.y { 1 } else { 0 },
+                x_pos_off: start.x - pos_off.x,
+                y_pos_off: start.y - pos_off.y,
+                x_size_off: end_off.x - end.x,
+                y_size_off: end_off.y - end.y,
             };
             image_blocks.push((info, block));
         }
@@ -1,4 +1,5 @@
 use crate::bsdfs::reflect_vector;
+use crate::bsdfs::Transport;
 use crate::emitter::*;
 use crate::integrators::gradient::*;
 use crate::integrators::*;
@@ -67,6 +68,7 @@ impl<'a> RayState<'a> {
     pub fn new(
         (x, y): (f32, f32),
         off: Point2<i32>,
+        sample: Point2<f32>,
         accel: &'a dyn Acceleration,
         scene: &'a Scene,
     ) -> RayState<'a> {
@@ -79,7 +81,7 @@ impl<'a> RayState<'a> {
             return RayState::Dead;
         }
 
-        let ray = scene.camera.generate(pix);
+        let ray = scene.camera.generate_sample(pix, sample);
         let its = match accel.trace(&ray) {
             Some(x) => x,
             None => return RayState::Dead,
@@ -104,12 +106,14 @@ impl IntegratorGradient for IntegratorGradientPath {
         let (nb_buffers, buffernames, mut image_blocks, ids) =
             generate_img_blocks_gradient(scene, self.recons.as_ref());
 
+        let seed = scene.pass_seed();
         let progress_bar = Mutex::new(ProgressBar::new(image_blocks.len() as u64));
         let pool = generate_pool(scene);
         pool.install(|| {
             image_blocks.par_iter_mut().for_each(|(info, im_block)| {
                 let emitters = scene.emitters_sampler();
-                let mut sampler = independent::IndependentSampler::default();
+                let mut sampler =
+                    independent::IndependentSampler::from_seed(pixel_seed(seed, im_block.pos));
                 for ix in info.x_pos_off..im_block.size.x - info.x_size_off {
                     for iy in info.y_pos_off..im_block.size.y - info.y_size_off {
                         for n in 0..scene.nb_samples {
@@ -221,14 +225,16 @@ impl IntegratorGradientPath {
     ) -> ColorGradient {
         let mut l_i = ColorGradient::default();
         let pix = (ix as f32 + sampler.next(), iy as f32 + sampler.next());
-        let mut main = match RayState::new(pix, Point2::new(0, 0), accel, scene) {
+        // The offset paths use the same sensor sample (e.g., position on the lens)
+        let sample = scene.camera.sample_sensor(sampler);
+        let mut main = match RayState::new(pix, Point2::new(0, 0), sample, accel, scene) {
             RayState::NotConnected(x) => x,
             _ => return l_i,
         };
         let mut offsets: Vec<RayState> = {
             GRADIENT_ORDER
                 .iter()
-                .map(|e| RayState::new(pix, *e, accel, scene))
+                .map(|e| RayState::new(pix, *e, sample, accel, scene))
                 .collect()
         };
 
@@ -273,6 +279,7 @@ impl IntegratorGradientPath {
                     &main.its.wi,
                     &main_d_out_local,
                     Domain::SolidAngle,
+                    Transport::Radiance,
                 ); // f(...) * cos(...)
                 let main_bsdf_pdf = if main_light_visible {
                     f64::from(
@@ -344,6 +351,7 @@ impl IntegratorGradientPath {
                                         &shift_d_in_local,
                                         &main_d_out_local,
                                         Domain::SolidAngle,
+                                        Transport::Radiance,
                                     );
                                     // Compute and return
                                     let shift_weight_dem = (s.pdf / main.pdf).powi(MIS_POWER)
@@ -383,6 +391,7 @@ impl IntegratorGradientPath {
                                         &s.its.wi,
                                         &shift_d_out_local,
                                         Domain::SolidAngle, // Already check that we are on a non smooth surface
+                                        Transport::Radiance,
                                     );
                                     let shift_bsdf_pdf = if shift_light_visible {
                                         f64::from(
@@ -447,16 +456,15 @@ impl IntegratorGradientPath {
             // BSDF sampling
             /////////////////////////////////
             // Compute an new direction (diffuse)
-            let main_sampled_bsdf =
-                match main
-                    .its
-                    .mesh
-                    .bsdf
-                    .sample(&main.its.uv, &main.its.wi, sampler.next2d())
-                {
-                    Some(x) => x,
-                    None => return l_i,
-                };
+            let main_sampled_bsdf = match main.its.mesh.bsdf.sample(
+                &main.its.uv,
+                &main.its.wi,
+                sampler.next2d(),
+                Transport::Radiance,
+            ) {
+                Some(x) => x,
+                None => return l_i,
+            };
 
             // Generate the new ray and do the intersection
             let main_d_out_global = main.its.frame.to_world(main_sampled_bsdf.d);
@@ -563,6 +571,7 @@ impl IntegratorGradientPath {
                                         &shift_d_in_local,
                                         &main_sampled_bsdf.d,
                                         Domain::SolidAngle,
+                                        Transport::Radiance,
                                     );
                                     // Update main path
                                     let shift_pdf_pred = s.pdf;
@@ -612,6 +621,7 @@ impl IntegratorGradientPath {
                                         &s.its.wi,
                                         &shift_d_out_local,
                                         Domain::SolidAngle, // Already checked that we are not on a smooth surface
+                                        Transport::Radiance,
                                     );
                                     let shift_bsdf_pdf = f64::from(
                                         s.its
@@ -761,6 +771,7 @@ impl IntegratorGradientPath {
                                         &s.its.wi,
                                         &wo,
                                         Domain::Discrete,
+                                        Transport::Radiance,
                                     );
                                     s.pdf *= f64::from(
                                         s.its
@@ -161,6 +161,9 @@ impl PoissonReconstruction for WeightedPoissonReconstruction {
 
         // Generate the buffer names
         let mut image_blocks = generate_img_blocks(scene, &buffernames);
+        // The pixels outside the crop are not rendered (see Scene::crop)
+        let region = scene.image_region();
+        let (min, max) = (region.pos, region.max());
         let pool = generate_pool(scene);
         pool.install(|| {
             for iter in 0..self.iterations {
@@ -182,7 +185,7 @@ impl PoissonReconstruction for WeightedPoissonReconstruction {
                             let mut c = current.get(pos, &recons_name) * curr_weight;
                             let mut w = curr_weight;
 
-                            if x > 0 {
+                            if x > min.x {
                                 let pos_off = Point2::new(x - 1, y);
                                 let curr_weight = inv_or_1(
                                     var_pos
@@ -195,7 +198,7 @@ impl PoissonReconstruction for WeightedPoissonReconstruction {
                                     * curr_weight;
                                 w += curr_weight;
                             }
-                            if x < img_size.x - 1 {
+                            if x + 1 < max.x {
                                 let pos_off = Point2::new(x + 1, y);
                                 let curr_weight = inv_or_1(
                                     var_pos
@@ -208,7 +211,7 @@ impl PoissonReconstruction for WeightedPoissonReconstruction {
                                     * curr_weight;
                                 w += curr_weight;
                             }
-                            if y > 0 {
+                            if y > min.y {
                                 let pos_off = Point2::new(x, y - 1);
                                 let curr_weight = inv_or_1(
                                     var_pos
@@ -221,7 +224,7 @@ impl PoissonReconstruction for WeightedPoissonReconstruction {
                                     * curr_weight;
                                 w += curr_weight;
                             }
-                            if y < img_size.y - 1 {
+                            if y + 1 < max.y {
                                 let pos_off = Point2::new(x, y + 1);
                                 let curr_weight = inv_or_1(
                                     var_pos
@@ -271,6 +274,9 @@ impl PoissonReconstruction for UniformPoissonReconstruction {
         let buffernames = vec!["recons".to_string()];
         let mut current = BufferCollection::new(Point2::new(0, 0), img_size, &buffernames);
         let mut image_blocks = generate_img_blocks(scene, &buffernames);
+        // The pixels outside the crop are not rendered (see Scene::crop)
+        let region = scene.image_region();
+        let (min, max) = (region.pos, region.max());
 
         // Define names of buffers so we do not need to reallocate them
         let primal_name = "primal";
@@ -299,25 +305,25 @@ impl PoissonReconstruction for UniformPoissonReconstruction {
                             let pos = Point2::new(x, y);
                             let mut c = current.get(pos, &recons_name);
                             let mut w = 1.0;
-                            if x > 0 {
+                            if x > min.x {
                                 let pos_off = Point2::new(x - 1, y);
                                 c += current.get(pos_off, &recons_name)
                                     + est.get(pos_off, &gradient_x_name);
                                 w += 1.0;
                             }
-                            if x < img_size.x - 1 {
+                            if x + 1 < max.x {
                                 let pos_off = Point2::new(x + 1, y);
                                 c += current.get(pos_off, &recons_name)
                                     - est.get(pos, &gradient_x_name);
                                 w += 1.0;
                             }
-                            if y > 0 {
+                            if y > min.y {
                                 let pos_off = Point2::new(x, y - 1);
                                 c += current.get(pos_off, &recons_name)
                                     + est.get(pos_off, &gradient_y_name);
                                 w += 1.0;
                             }
-                            if y < img_size.y - 1 {
+                            if y + 1 < max.y {
                                 let pos_off = Point2::new(x, y + 1);
                                 c += current.get(pos_off, &recons_name)
                                     - est.get(pos, &gradient_y_name);
@@ -14,7 +14,7 @@ impl Default for DiffuseReconnection {
     }
 }
 impl ShiftMapping for DiffuseReconnection {
-    fn base<'scene, 'emitter>(
+    fn base<'scene: 'emitter, 'emitter>(
         &mut self,
         path: &mut Path<'scene, 'emitter>,
         technique: &mut TechniqueGradientPathTracing,
@@ -29,7 +29,7 @@ impl ShiftMapping for DiffuseReconnection {
         self.base_contrib = technique.evaluate(path, scene, emitters, root[0].0);
         (self.base_contrib, root[0].0)
     }
-    fn shift<'scene, 'emitter>(
+    fn shift<'scene: 'emitter, 'emitter>(
         &mut self,
         _path: &mut Path<'scene, 'emitter>,
         _technique: &mut TechniqueGradientPathTracing,
@@ -29,7 +29,7 @@ impl ShiftValue {
     }
 }
 pub trait ShiftMapping {
-    fn base<'scene, 'emitter>(
+    fn base<'scene: 'emitter, 'emitter>(
         &mut self,
         path: &mut Path<'scene, 'emitter>,
         technique: &mut TechniqueGradientPathTracing,
@@ -39,7 +39,7 @@ pub trait ShiftMapping {
         emitters: &'emitter EmitterSampler,
         sampler: &mut dyn Sampler,
     ) -> (Color, VertexID);
-    fn shift<'scene, 'emitter>(
+    fn shift<'scene: 'emitter, 'emitter>(
         &mut self,
         path: &mut Path<'scene, 'emitter>,
         technique: &mut TechniqueGradientPathTracing,
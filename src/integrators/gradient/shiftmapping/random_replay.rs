@@ -51,7 +51,7 @@ impl Default for RandomReplay {
     }
 }
 impl ShiftMapping for RandomReplay {
-    fn base<'scene, 'emitter>(
+    fn base<'scene: 'emitter, 'emitter>(
         &mut self,
         path: &mut Path<'scene, 'emitter>,
         technique: &mut TechniqueGradientPathTracing,
@@ -82,7 +82,7 @@ impl ShiftMapping for RandomReplay {
         self.base_value = technique.evaluate(path, scene, emitters, root);
         (self.base_value, root)
     }
-    fn shift<'scene, 'emitter>(
+    fn shift<'scene: 'emitter, 'emitter>(
         &mut self,
         path: &mut Path<'scene, 'emitter>,
         technique: &mut TechniqueGradientPathTracing,
@@ -0,0 +1,209 @@
+use crate::exr;
+use crate::integrators::*;
+use crate::scene::ImageRegion;
+use cgmath::Point2;
+
+/// Image rendered separately (e.g., on another machine with --crop)
+pub struct PartialImage {
+    pub buffers: BufferCollection,
+    /// Pixels rendered (see Scene::crop)
+    pub region: ImageRegion,
+    /// Number of samples per pixel (if known)
+    pub spp: Option<f32>,
+}
+
+impl PartialImage {
+    /// Read all the layers and the "crop" and "spp" attributes of a layered EXR file
+    /// the other images only have a "primal" buffer covering the full image
+    pub fn read(filename: &str) -> Result<PartialImage, String> {
+        let layered = if filename.ends_with(".exr") {
+            std::fs::read(filename)
+                .ok()
+                .and_then(|data| exr::read(&data).ok())
+        } else {
+            None
+        };
+        let (bitmaps, attributes) = match layered {
+            Some(image) => (image.layers, image.attributes),
+            None => (
+                vec![(exr::DEFAULT_LAYER.to_string(), Bitmap::read(filename))],
+                vec![],
+            ),
+        };
+
+        let size = bitmaps[0].1.size;
+        let mut buffers = BufferCollection::new(Point2::new(0, 0), size, &[]);
+        buffers.values.extend(bitmaps);
+        let attribute = |name: &str| {
+            attributes
+                .iter()
+                .find(|(n, _)| n == name)
+                .map(|(_, v)| v.as_str())
+        };
+        let region = match attribute("crop") {
+            Some(crop) => crop
+                .parse::<ImageRegion>()
+                .map_err(|e| format!("{}: {}", filename, e))?,
+            None => ImageRegion {
+                pos: Point2::new(0, 0),
+                size,
+            },
+        };
+        let max = region.max();
+        if max.x > size.x || max.y > size.y {
+            return Err(format!(
+                "{}: the crop {} is outside the image ({}x{})",
+                filename, region, size.x, size.y
+            ));
+        }
+        let spp = match attribute("spp") {
+            Some(spp) => Some(
+                spp.parse::<f32>()
+                    .map_err(|e| format!("{}: wrong spp {}: {}", filename, spp, e))?,
+            ),
+            None => None,
+        };
+        info!(
+            "{}: {:?} (pixels {}, spp {:?})",
+            filename,
+            buffers.values.keys().collect::<Vec<_>>(),
+            region,
+            spp
+        );
+        Ok(PartialImage {
+            buffers,
+            region,
+            spp,
+        })
+    }
+}
+
+/// Merge images of the same size and with the same buffers
+/// Each pixel is the average of the images covering it, weighted by their
+/// number of samples per pixel if weighted (otherwise the weights are 1)
+/// With sum, the images are added (e.g., PFM tiles which do not know their crop)
+pub fn merge(
+    images: &[PartialImage],
+    weighted: bool,
+    sum: bool,
+) -> Result<BufferCollection, String> {
+    if images.is_empty() {
+        return Err("no image to merge".to_string());
+    }
+    let size = images[0].buffers.size;
+    let mut names = images[0].buffers.values.keys().cloned().collect::<Vec<_>>();
+    names.sort();
+    for (i, image) in images.iter().enumerate() {
+        if image.buffers.size != size {
+            return Err(format!(
+                "the image {} has a different size ({:?} instead of {:?})",
+                i + 1,
+                image.buffers.size,
+                size
+            ));
+        }
+        let mut other = image.buffers.values.keys().cloned().collect::<Vec<_>>();
+        other.sort();
+        if other != names {
+            return Err(format!(
+                "the image {} has different buffers ({:?} instead of {:?})",
+                i + 1,
+                other,
+                names
+            ));
+        }
+        if weighted && image.spp.is_none() {
+            return Err(format!(
+                "the image {} has no spp, needed by the sample weighted merge (layered EXR)",
+                i + 1
+            ));
+        }
+    }
+
+    let mut merged = BufferCollection::new(Point2::new(0, 0), size, &names);
+    let mut weights = vec![0.0; (size.x * size.y) as usize];
+    for image in images {
+        let w = match image.spp {
+            Some(spp) if weighted => spp,
+            _ => 1.0,
+        };
+        let (min, max) = (image.region.pos, image.region.max());
+        for y in min.y..max.y {
+            for x in min.x..max.x {
+                let p = Point2::new(x, y);
+                weights[(y * size.x + x) as usize] += w;
+                for name in &names {
+                    merged.accumulate(p, image.buffers.get(p, name) * w, name);
+                }
+            }
+        }
+    }
+    if sum {
+        return Ok(merged);
+    }
+
+    let mut missing = 0;
+    for (i, w) in weights.iter().enumerate() {
+        if *w == 0.0 {
+            missing += 1;
+            continue;
+        }
+        for bitmap in merged.values.values_mut() {
+            bitmap.colors[i] *= 1.0 / w;
+        }
+    }
+    if missing != 0 {
+        warn!("{} pixels are not covered by the images (black)", missing);
+    }
+    Ok(merged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Image of 4x1 pixels where the pixels of the region have the value v
+    fn partial(x0: u32, x1: u32, v: f32, spp: f32) -> PartialImage {
+        let size = Vector2::new(4, 1);
+        let mut buffers = BufferCollection::new(Point2::new(0, 0), size, &["primal".to_string()]);
+        for x in x0..x1 {
+            buffers.accumulate(Point2::new(x, 0), Color::value(v), "primal");
+        }
+        PartialImage {
+            buffers,
+            region: ImageRegion {
+                pos: Point2::new(x0, 0),
+                size: Vector2::new(x1 - x0, 1),
+            },
+            spp: Some(spp),
+        }
+    }
+
+    #[test]
+    fn weighted_overlap() {
+        let images = [partial(0, 3, 1.0, 1.0), partial(1, 4, 2.0, 3.0)];
+        let merged = merge(&images, true, false).unwrap();
+        // The overlapping pixels are weighted by the spp of each image
+        let expected = [1.0, 1.75, 1.75, 2.0];
+        for (x, e) in expected.iter().enumerate() {
+            let v = merged.get(Point2::new(x as u32, 0), "primal");
+            assert!((v.r - e).abs() < 1e-5, "pixel {}: {} != {}", x, v.r, e);
+        }
+
+        // Without the weights, it is the average of the images
+        let merged = merge(&images, false, false).unwrap();
+        let v = merged.get(Point2::new(1, 0), "primal");
+        assert!((v.r - 1.5).abs() < 1e-5);
+    }
+
+    #[test]
+    fn wrong_images() {
+        let mut no_spp = partial(0, 4, 1.0, 1.0);
+        no_spp.spp = None;
+        assert!(merge(&[partial(0, 2, 1.0, 1.0), no_spp], true, false).is_err());
+
+        let mut other_buffers = partial(0, 4, 1.0, 1.0);
+        other_buffers.buffers.register("albedo".to_string());
+        assert!(merge(&[partial(0, 2, 1.0, 1.0), other_buffers], false, false).is_err());
+    }
+}
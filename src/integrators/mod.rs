@@ -1,4 +1,7 @@
 use crate::emitter::*;
+use crate::integrators::adaptive::compute_adaptive;
+use crate::integrators::aovs::first_hit_aovs;
+use crate::integrators::film::Film;
 use crate::samplers::*;
 use crate::scene::*;
 use crate::structure::*;
@@ -8,7 +11,7 @@ use crate::Scale;
 use cgmath::{Point2, Vector2};
 use pbr::ProgressBar;
 use rayon;
-use rayon::iter::{IntoParallelRefMutIterator, ParallelIterator};
+use rayon::iter::{IntoParallelRefIterator, IntoParallelRefMutIterator, ParallelIterator};
 use std;
 use std::cmp;
 use std::collections::HashMap;
@@ -67,6 +70,21 @@ impl BufferCollection {
         }
     }
 
+    /// Save all the buffers inside one EXR file with the string attributes
+    /// (see exr::write)
+    pub fn save_layers(&self, filename: &str, attributes: &[(String, String)]) {
+        let mut names = self.values.keys().collect::<Vec<_>>();
+        names.sort();
+        let layers = names
+            .into_iter()
+            .map(|name| (name.as_str(), &self.values[name]))
+            .collect::<Vec<_>>();
+        let file = std::fs::File::create(std::path::Path::new(filename)).unwrap();
+        let mut file = std::io::BufWriter::new(file);
+        crate::exr::write(&mut file, &layers, attributes)
+            .expect("failed to write the EXR layers");
+    }
+
     /// Register a name for a particular buffer
     pub fn register(&mut self, name: String) {
         self.values.insert(name, Bitmap::new(self.size));
@@ -130,6 +148,22 @@ impl BufferCollection {
         self.values.get_mut(name).unwrap().accumulate(p, f);
     }
 
+    /// Set the pixels outside the region (in image coordinates) to black
+    pub fn mask(&mut self, region: &ImageRegion) {
+        let (pos, size) = (self.pos, self.size);
+        for bitmap in self.values.values_mut() {
+            for (i, c) in bitmap.colors.iter_mut().enumerate() {
+                let p = Point2::new(
+                    (pos.x + i as u32 % size.x) as f32 + 0.5,
+                    (pos.y + i as u32 / size.x) as f32 + 0.5,
+                );
+                if !region.contains(p) {
+                    *c = Color::zero();
+                }
+            }
+        }
+    }
+
     pub fn accumulate_safe(&mut self, p: Point2<i32>, f: Color, name: &str) {
         if p.x >= 0 && p.y >= 0 && p.x < (self.size.x as i32) && p.y < (self.size.y as i32) {
             self.accumulate(
@@ -190,7 +224,34 @@ pub trait Integrator {
         let buffernames = vec!["primal".to_string()];
         BufferCollection::new(Point2::new(0, 0), *scene.camera.size(), &buffernames)
     }
+    /// The integrator refines its own estimate at each compute() call
+    /// (the images are not averaged by IntegratorAverage)
+    fn progressive(&self) -> bool {
+        false
+    }
+    /// Average number of samples per pixel of the image img returned by compute()
+    fn samples_per_pixel(&self, scene: &Scene, img: &BufferCollection) -> f32 {
+        rendered_samples_per_pixel(scene, img)
+    }
+}
+
+/// Average number of samples per pixel over the rendered region (see Scene::image_region)
+/// given by the "samples" buffer of the adaptive sampling, otherwise Scene::nb_samples
+pub fn rendered_samples_per_pixel(scene: &Scene, img: &BufferCollection) -> f32 {
+    let samples = match img.values.get("samples") {
+        None => return scene.nb_samples as f32,
+        Some(samples) => samples,
+    };
+    let region = scene.image_region();
+    let mut sum = 0.0;
+    for y in region.pos.y..region.max().y {
+        for x in region.pos.x..region.max().x {
+            sum += samples.pixel(Point2::new(x, y)).r;
+        }
+    }
+    sum / region.nb_pixels() as f32
 }
+
 pub trait IntegratorGradient: Integrator {
     fn compute_gradients(&mut self, accel: &dyn Acceleration, scene: &Scene) -> BufferCollection;
     fn reconstruct(&self) -> &(dyn PoissonReconstruction + Sync);
@@ -222,8 +283,35 @@ pub enum IntegratorType {
     Gradient(Box<dyn IntegratorGradient>),
 }
 impl IntegratorType {
+    /// Average number of samples per pixel of the image img returned by compute()
+    pub fn samples_per_pixel(&self, scene: &Scene, img: &BufferCollection) -> f32 {
+        match self {
+            IntegratorType::Primal(v) => v.samples_per_pixel(scene, img),
+            IntegratorType::Gradient(v) => v.samples_per_pixel(scene, img),
+        }
+    }
+
+    /// The integrator refines its own estimate at each compute() call
+    pub fn progressive(&self) -> bool {
+        match self {
+            IntegratorType::Primal(v) => v.progressive(),
+            IntegratorType::Gradient(_) => false,
+        }
+    }
+
     pub fn compute(&mut self, scene: &Scene) -> BufferCollection {
         info!("Build acceleration data structure...");
+        match scene.acceleration {
+            AccelerationType::Embree => self.compute_embree(scene),
+            AccelerationType::BVH => {
+                let accel = BVHAcceleration::new(scene);
+                self.compute_accel(&accel, scene)
+            }
+        }
+    }
+
+    #[cfg(feature = "embree")]
+    fn compute_embree(&mut self, scene: &Scene) -> BufferCollection {
         let embree_device = embree_rs::Device::new();
         let mut embree_scene = embree_rs::Scene::new(&embree_device);
         // Add all meshes
@@ -258,16 +346,29 @@ impl IntegratorType {
             embree_scene.attach_geometry(tri_geom);
         }
         let accel = EmbreeAcceleration::new(scene, &embree_scene);
+        self.compute_accel(&accel, scene)
+    }
+
+    #[cfg(not(feature = "embree"))]
+    fn compute_embree(&mut self, _scene: &Scene) -> BufferCollection {
+        panic!("Rustlight wasn't built with Embree support");
+    }
 
+    fn compute_accel(&mut self, accel: &dyn Acceleration, scene: &Scene) -> BufferCollection {
         info!("Run Integrator...");
         let start = Instant::now();
 
-        let img = match self {
-            IntegratorType::Primal(ref mut v) => v.compute(&accel, scene),
+        let mut img = match self {
+            IntegratorType::Primal(ref mut v) => v.compute(accel, scene),
             IntegratorType::Gradient(ref mut v) => {
-                IntegratorGradient::compute(v.as_mut(), &accel, scene)
+                IntegratorGradient::compute(v.as_mut(), accel, scene)
             }
         };
+        // The pixels outside the crop are black
+        // (e.g., filter margins or gradient reconstruction)
+        if let Some(ref crop) = scene.crop {
+            img.mask(crop);
+        }
 
         let elapsed = start.elapsed();
         info!("Elapsed Integrator: {} ms", elapsed.as_millis());
@@ -278,28 +379,48 @@ impl IntegratorType {
 
 /////////////// Implementation gradients
 pub trait IntegratorMC: Sync + Send {
+    /// Estimate the radiance for the sample at the film position pix (in pixels)
+    /// the sensor sample gives the primary ray (see Camera::generate_sample)
     fn compute_pixel(
         &self,
-        pix: (u32, u32),
+        pix: Point2<f32>,
+        sensor: Point2<f32>,
         accel: &dyn Acceleration,
         scene: &Scene,
         sampler: &mut dyn Sampler,
         emitters: &EmitterSampler,
     ) -> Color;
+    /// Auxiliary outputs of the sample (Scene::aovs)
+    /// by default, the values of the first surface visible along the primary ray
+    fn compute_aovs(
+        &self,
+        pix: Point2<f32>,
+        sensor: Point2<f32>,
+        accel: &dyn Acceleration,
+        scene: &Scene,
+    ) -> Vec<(String, Color)> {
+        let ray = scene.camera.generate_sample(pix, sensor);
+        first_hit_aovs(&scene.aovs, &ray, accel, scene)
+    }
 }
 
+/// Blocks of 16x16 pixels covering the rendered region (see Scene::image_region)
+/// the blocks stay aligned on the image grid when the image is cropped
 pub fn generate_img_blocks(scene: &Scene, buffernames: &[String]) -> Vec<BufferCollection> {
+    let region = scene.image_region();
+    let (min, max) = (region.pos, region.max());
     let mut image_blocks: Vec<BufferCollection> = Vec::new();
-    for ix in StepRangeInt::new(0, scene.camera.size().x as usize, 16) {
-        for iy in StepRangeInt::new(0, scene.camera.size().y as usize, 16) {
+    for ix in StepRangeInt::new((min.x - min.x % 16) as usize, max.x as usize, 16) {
+        for iy in StepRangeInt::new((min.y - min.y % 16) as usize, max.y as usize, 16) {
+            let pos = Point2 {
+                x: cmp::max(ix as u32, min.x),
+                y: cmp::max(iy as u32, min.y),
+            };
             let block = BufferCollection::new(
-                Point2 {
-                    x: ix as u32,
-                    y: iy as u32,
-                },
+                pos,
                 Vector2 {
-                    x: cmp::min(16, scene.camera.size().x - ix as u32),
-                    y: cmp::min(16, scene.camera.size().y - iy as u32),
+                    x: cmp::min(ix as u32 + 16, max.x) - pos.x,
+                    y: cmp::min(iy as u32 + 16, max.y) - pos.y,
                 },
                 buffernames,
             );
@@ -314,49 +435,120 @@ pub fn compute_mc<T: IntegratorMC + Integrator>(
     accel: &dyn Acceleration,
     scene: &Scene,
 ) -> BufferCollection {
+    if let Some(ref adaptive) = scene.adaptive {
+        return compute_adaptive(int, accel, scene, adaptive);
+    }
+
     // Here we can to the classical parallelisation
     assert_ne!(scene.nb_samples, 0);
-    let buffernames = vec!["primal".to_string()];
+    let buffernames = aov_buffernames(scene);
 
     // Create rendering blocks
-    let mut image_blocks = generate_img_blocks(scene, &buffernames);
+    let image_blocks = generate_img_blocks(scene, &buffernames);
 
     // Render the image blocks
+    // the scrambling of the samplers changes at each call
+    let seed = scene.pass_seed();
     let progress_bar = Mutex::new(ProgressBar::new(image_blocks.len() as u64));
     let pool = generate_pool(scene);
-    pool.install(|| {
-        image_blocks.par_iter_mut().for_each(|im_block| {
-            // image_blocks.iter_mut().for_each(|im_block| {
-            let mut sampler = independent::IndependentSampler::default();
-            let light_sampling = scene.emitters_sampler();
-            for iy in 0..im_block.size.y {
-                for ix in 0..im_block.size.x {
-                    for _ in 0..scene.nb_samples {
-                        let c = int.compute_pixel(
-                            (ix + im_block.pos.x, iy + im_block.pos.y),
-                            accel,
-                            scene,
-                            &mut sampler,
-                            &light_sampling,
-                        );
-                        im_block.accumulate(Point2 { x: ix, y: iy }, c, &"primal".to_string());
+    let films = pool.install(|| {
+        image_blocks
+            .par_iter()
+            .map(|im_block| {
+                let mut film = Film::new(scene.filter, im_block.pos, im_block.size, &buffernames);
+                let mut sampler = scene
+                    .sampler
+                    .create(pixel_seed(seed, im_block.pos), scene.nb_samples);
+                let light_sampling = scene.emitters_sampler();
+                for iy in 0..im_block.size.y {
+                    for ix in 0..im_block.size.x {
+                        let pix = Point2::new(ix + im_block.pos.x, iy + im_block.pos.y);
+                        for i in 0..scene.nb_samples {
+                            sampler.start_sample(pix, i);
+                            let offset = sampler.next2d();
+                            let p = Point2::new(pix.x as f32 + offset.x, pix.y as f32 + offset.y);
+                            let sensor = scene.camera.sample_sensor(sampler.as_mut());
+                            let c = int.compute_pixel(
+                                p,
+                                sensor,
+                                accel,
+                                scene,
+                                sampler.as_mut(),
+                                &light_sampling,
+                            );
+                            let mut contribs = vec![(buffernames[0].clone(), c)];
+                            contribs.extend(int.compute_aovs(p, sensor, accel, scene));
+                            film.add_sample(p, &contribs);
+                        }
                     }
                 }
-            }
-            im_block.scale(1.0 / (scene.nb_samples as f32));
 
-            {
-                progress_bar.lock().unwrap().inc();
-            }
-        });
+                {
+                    progress_bar.lock().unwrap().inc();
+                }
+                film
+            })
+            .collect::<Vec<_>>()
     });
 
     // Fill the image
-    let mut image = BufferCollection::new(Point2::new(0, 0), *scene.camera.size(), &buffernames);
-    for im_block in &image_blocks {
-        image.accumulate_bitmap(im_block);
+    // (the block films are merged in order: their margins overlap)
+    let mut image = Film::new(
+        scene.filter,
+        Point2::new(0, 0),
+        *scene.camera.size(),
+        &buffernames,
+    );
+    for film in &films {
+        image.merge(film);
     }
-    image
+    image.develop()
+}
+
+/// Number of jobs of the integrators splatting over the whole image
+/// (it does not depend on the number of threads)
+pub const SPLAT_JOBS: usize = 64;
+
+/// Run the jobs in parallel and combine their results with a fixed binary tree:
+/// the result does not depend on the scheduling and only a few results
+/// are alive at the same time
+pub fn reduce_jobs<S, T, F, M>(jobs: &mut [S], job: &F, merge: &M) -> T
+where
+    S: Send,
+    T: Send,
+    F: Fn(&mut S) -> T + Sync,
+    M: Fn(&mut T, &T) + Sync,
+{
+    assert!(!jobs.is_empty());
+    if jobs.len() == 1 {
+        return job(&mut jobs[0]);
+    }
+    let (left, right) = jobs.split_at_mut(jobs.len() / 2);
+    let (mut res, res_right) = rayon::join(
+        || reduce_jobs(left, job, merge),
+        || reduce_jobs(right, job, merge),
+    );
+    merge(&mut res, &res_right);
+    res
+}
+
+/// Run the jobs in parallel, each one returns a film covering the image
+/// The films are summed with reduce_jobs
+pub fn splat_jobs<S, F>(jobs: &mut [S], job: &F) -> Film
+where
+    S: Send,
+    F: Fn(&mut S) -> Film + Sync,
+{
+    reduce_jobs(jobs, job, &|film: &mut Film, other: &Film| {
+        film.merge(other)
+    })
+}
+
+/// Names of the buffers: "primal" followed by the AOVs of the scene
+pub fn aov_buffernames(scene: &Scene) -> Vec<String> {
+    let mut buffernames = vec!["primal".to_string()];
+    buffernames.extend(scene.aovs.iter().map(|aov| aov.name().to_string()));
+    buffernames
 }
 
 pub fn generate_pool(scene: &Scene) -> rayon::ThreadPool {
@@ -385,9 +577,14 @@ pub fn mis_weight(pdf_a: f32, pdf_b: f32) -> f32 {
     }
 }
 
+pub mod adaptive;
 pub mod ao;
+pub mod aovs;
 pub mod avg;
+pub mod denoise;
 pub mod direct;
 pub mod explicit;
+pub mod film;
 pub mod gradient;
+pub mod merge;
 pub mod pssmlt;
@@ -31,31 +31,37 @@ pub struct IntegratorPSSMLT {
 impl Integrator for IntegratorPSSMLT {
     fn compute(&mut self, accel: &dyn Acceleration, scene: &Scene) -> BufferCollection {
         ///////////// Define the closure
+        // The states cover the rendered region (see Scene::image_region)
+        let region = scene.image_region();
         let sample = |s: &mut dyn Sampler, emitters: &EmitterSampler| {
-            let x = (s.next() * scene.camera.size().x as f32) as u32;
-            let y = (s.next() * scene.camera.size().y as f32) as u32;
+            let x = region.pos.x as f32 + s.next() * region.size.x as f32;
+            let y = region.pos.y as f32 + s.next() * region.size.y as f32;
             let c = {
+                let sensor = scene.camera.sample_sensor(s);
                 self.integrator
-                    .compute_pixel((x, y), accel, scene, s, emitters)
+                    .compute_pixel(Point2::new(x, y), sensor, accel, scene, s, emitters)
             };
-            MCMCState::new(c, Point2::new(x, y))
+            MCMCState::new(c, Point2::new(x as u32, y as u32))
         };
 
         ///////////// Compute the normalization factor
         info!("Computing normalization factor...");
-        let b = self.compute_normalization(accel, scene, 10000);
+        let seed = scene.pass_seed();
+        let b = self.compute_normalization(accel, scene, 10000, seed);
         info!("Normalisation factor: {:?}", b);
 
         ///////////// Compute the state initialization
-        let nb_samples_total =
-            scene.nb_samples * (scene.camera.size().x * scene.camera.size().y) as usize;
+        let nb_samples_total = scene.nb_samples * region.nb_pixels();
         let nb_samples_per_chains = 100_000;
-        let nb_chains = nb_samples_total / nb_samples_per_chains;
+        // (at least one chain for small images or crops)
+        let nb_chains = (nb_samples_total / nb_samples_per_chains).max(1);
         info!("Number of states: {:?}", nb_chains);
         // - Initialize the samplers
         let mut samplers = Vec::new();
-        for _ in 0..nb_chains {
-            samplers.push(samplers::mcmc::IndependentSamplerReplay::default());
+        for chain in 0..nb_chains {
+            samplers.push(samplers::mcmc::IndependentSamplerReplay::from_seed(
+                hash_combine(seed, chain as u64 + 1),
+            ));
         }
 
         ///////////// Compute the rendering (with the number of samples)
@@ -63,69 +69,73 @@ impl Integrator for IntegratorPSSMLT {
         let start = Instant::now();
         let progress_bar = Mutex::new(ProgressBar::new(samplers.len() as u64));
         let buffer_names = vec!["primal".to_string()];
-        let img = Mutex::new(BufferCollection::new(
-            Point2::new(0, 0),
-            *scene.camera.size(),
-            &buffer_names,
-        ));
+        // The chains are merged with a fixed tree (independent of the scheduling)
         let pool = generate_pool(scene);
-        pool.install(|| {
-            samplers.par_iter_mut().for_each(|s| {
-                let emitters = scene.emitters_sampler();
-                // Initialize the sampler
-                s.large_step = true;
-                let mut current_state = sample(s as &mut dyn Sampler, &emitters);
-                while current_state.tf == 0.0 {
-                    s.reject();
-                    current_state = sample(s as &mut dyn Sampler, &emitters);
-                }
-                s.accept();
-
-                let mut my_img: BufferCollection =
-                    BufferCollection::new(Point2::new(0, 0), *scene.camera.size(), &buffer_names);
-                (0..nb_samples_per_chains).for_each(|_| {
-                    // Choose randomly between large and small perturbation
-                    s.large_step = s.rand() < self.large_prob;
-                    let mut proposed_state = sample(s, &emitters);
-                    let accept_prob = (proposed_state.tf / current_state.tf).min(1.0);
-                    // Do waste reclycling
-                    current_state.weight += 1.0 - accept_prob;
-                    proposed_state.weight += accept_prob;
-                    if accept_prob > s.rand() {
-                        my_img.accumulate(
-                            current_state.pix,
-                            current_state.color(),
-                            &buffer_names[0],
-                        );
-                        s.accept();
-                        current_state = proposed_state;
-                    } else {
-                        my_img.accumulate(
-                            proposed_state.pix,
-                            proposed_state.color(),
-                            &buffer_names[0],
-                        );
+        let mut img = pool.install(|| {
+            reduce_jobs(
+                &mut samplers,
+                &|s| {
+                    let emitters = scene.emitters_sampler();
+                    // Initialize the sampler
+                    s.large_step = true;
+                    let mut current_state = sample(s as &mut dyn Sampler, &emitters);
+                    while current_state.tf == 0.0 {
                         s.reject();
+                        current_state = sample(s as &mut dyn Sampler, &emitters);
                     }
-                });
-                // Flush the last state
-                my_img.accumulate(current_state.pix, current_state.color(), &buffer_names[0]);
+                    s.accept();
 
-                my_img.scale(1.0 / (nb_samples_per_chains as f32));
-                {
-                    img.lock().unwrap().accumulate_bitmap(&my_img);
+                    let mut my_img: BufferCollection = BufferCollection::new(
+                        Point2::new(0, 0),
+                        *scene.camera.size(),
+                        &buffer_names,
+                    );
+                    (0..nb_samples_per_chains).for_each(|_| {
+                        // Choose randomly between large and small perturbation
+                        s.large_step = s.rand() < self.large_prob;
+                        let mut proposed_state = sample(s, &emitters);
+                        let accept_prob = (proposed_state.tf / current_state.tf).min(1.0);
+                        // Do waste reclycling
+                        current_state.weight += 1.0 - accept_prob;
+                        proposed_state.weight += accept_prob;
+                        if accept_prob > s.rand() {
+                            my_img.accumulate(
+                                current_state.pix,
+                                current_state.color(),
+                                &buffer_names[0],
+                            );
+                            s.accept();
+                            current_state = proposed_state;
+                        } else {
+                            my_img.accumulate(
+                                proposed_state.pix,
+                                proposed_state.color(),
+                                &buffer_names[0],
+                            );
+                            s.reject();
+                        }
+                    });
+                    // Flush the last state
+                    my_img.accumulate(current_state.pix, current_state.color(), &buffer_names[0]);
+
+                    my_img.scale(1.0 / (nb_samples_per_chains as f32));
                     progress_bar.lock().unwrap().inc();
-                }
-            });
+                    my_img
+                },
+                &|img: &mut BufferCollection, other: &BufferCollection| {
+                    img.accumulate_bitmap(other)
+                },
+            )
         });
-
-        let mut img: BufferCollection = img.into_inner().unwrap();
         let elapsed = start.elapsed();
         info!("Elapsed: {:?}", elapsed,);
 
         // ==== Compute and scale to the normalization factor
+        // (the average over the rendered region)
         let img_avg = img.average_pixel(&buffer_names[0]);
-        let img_avg_lum = (img_avg.r + img_avg.g + img_avg.b) / 3.0;
+        let img_avg_lum = (img_avg.r + img_avg.g + img_avg.b) / 3.0
+            * (scene.camera.size().x * scene.camera.size().y) as f32
+            / region.nb_pixels() as f32;
         img.scale(b / img_avg_lum);
 
         img
@@ -137,18 +147,26 @@ impl IntegratorPSSMLT {
         accel: &dyn Acceleration,
         scene: &Scene,
         nb_samples: usize,
+        seed: u64,
     ) -> f32 {
         assert_ne!(nb_samples, 0);
 
-        let mut sampler = samplers::independent::IndependentSampler::default();
+        let mut sampler = samplers::independent::IndependentSampler::from_seed(seed);
+        let region = scene.image_region();
         (0..nb_samples)
             .map(|_i| {
                 let emitters = scene.emitters_sampler();
-                let x = (sampler.next() * scene.camera.size().x as f32) as u32;
-                let y = (sampler.next() * scene.camera.size().y as f32) as u32;
-                let c =
-                    self.integrator
-                        .compute_pixel((x, y), accel, scene, &mut sampler, &emitters);
+                let x = region.pos.x as f32 + sampler.next() * region.size.x as f32;
+                let y = region.pos.y as f32 + sampler.next() * region.size.y as f32;
+                let sensor = scene.camera.sample_sensor(&mut sampler);
+                let c = self.integrator.compute_pixel(
+                    Point2::new(x, y),
+                    sensor,
+                    accel,
+                    scene,
+                    &mut sampler,
+                    &emitters,
+                );
                 (c.r + c.g + c.b) / 3.0
             })
             .sum::<f32>()
@@ -12,6 +12,7 @@ extern crate byteorder;
 // For the vector op
 extern crate cgmath;
 // For fast intersection
+#[cfg(feature = "embree")]
 extern crate embree_rs;
 // For the image (LDR) export and loading
 #[cfg(feature = "image")]
@@ -52,12 +53,14 @@ pub mod accel;
 pub mod bsdfs;
 pub mod camera;
 pub mod emitter;
+pub mod exr;
 pub mod geometry;
 pub mod integrators;
 pub mod math;
 pub mod paths;
 pub mod samplers;
 pub mod scene;
+pub mod scene_description;
 pub mod scene_loader;
 pub mod structure;
 pub mod tools;
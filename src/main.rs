@@ -16,6 +16,13 @@ extern crate rustlight;
 
 use clap::{App, Arg, SubCommand};
 use rustlight::integrators::IntegratorType;
+
+// Embree is only selectable when compiled in
+#[cfg(feature = "embree")]
+const ACCELERATIONS: &[&str] = &["embree", "bvh"];
+#[cfg(not(feature = "embree"))]
+const ACCELERATIONS: &[&str] = &["bvh"];
+
 fn match_infinity<T: std::str::FromStr>(input: &str) -> Option<T> {
     match input {
         "inf" => None,
@@ -54,14 +61,27 @@ fn main() {
             .about("A Rusty Light Transport simulation program")
             .arg(
                 Arg::with_name("scene")
-                    .required(true)
                     .takes_value(true)
                     .index(1)
-                    .help("JSON/PBRT file path (scene description)"),
+                    .help("JSON/PBRT file path (scene description, needed to render)"),
             )
             .arg(Arg::with_name("average").short("a").takes_value(true).help(
                 "average several pass of the integrator with a time limit ('inf' is possible)",
             ))
+            .arg(
+                Arg::with_name("checkpoint")
+                    .long("checkpoint")
+                    .takes_value(true)
+                    .requires("average")
+                    .help("save the averaged buffers every N secs (<output>_checkpoint.bin)"),
+            )
+            .arg(
+                Arg::with_name("resume")
+                    .long("resume")
+                    .takes_value(true)
+                    .requires("average")
+                    .help("continue the averaging from a checkpoint file"),
+            )
             .arg(
                 Arg::with_name("nbthreads")
                     .takes_value(true)
@@ -88,9 +108,93 @@ fn main() {
                     .short("m")
                     .takes_value(true)
                     .default_value("0.0")
-                    .help("add medium with defined density"),
+                    .help("add medium with defined density and phase function (density[,isotropic|hg:g])"),
             )
             .arg(Arg::with_name("debug").short("d").help("debug output"))
+            .arg(
+                Arg::with_name("acceleration")
+                    .long("accel")
+                    .takes_value(true)
+                    .possible_values(ACCELERATIONS)
+                    .default_value(if cfg!(feature = "embree") {
+                        "embree"
+                    } else {
+                        "bvh"
+                    })
+                    .help("acceleration structure used for ray intersection"),
+            )
+            .arg(
+                Arg::with_name("sampler")
+                    .long("sampler")
+                    .takes_value(true)
+                    .possible_values(&["independent", "stratified", "halton", "sobol"])
+                    .default_value("independent")
+                    .help("sampler used by the Monte Carlo integrators"),
+            )
+            .arg(
+                Arg::with_name("filter")
+                    .long("filter")
+                    .takes_value(true)
+                    .default_value("box")
+                    .help("pixel reconstruction filter (box|tent|gaussian|mitchell|lanczos)[:radius]"),
+            )
+            .arg(
+                Arg::with_name("aovs")
+                    .long("aov")
+                    .takes_value(true)
+                    .help("auxiliary outputs saved next to the image (albedo,normal,depth,position,uv,mesh_id)"),
+            )
+            .arg(
+                Arg::with_name("adaptive")
+                    .long("adaptive")
+                    .takes_value(true)
+                    .help("adaptive sampling with a target relative error (-n: average number of samples)"),
+            )
+            .arg(
+                Arg::with_name("adaptive_time")
+                    .long("adaptive-time")
+                    .takes_value(true)
+                    .requires("adaptive")
+                    .help("time out of the adaptive sampling (in seconds)"),
+            )
+            .arg(
+                Arg::with_name("adaptive_uniform")
+                    .long("adaptive-uniform")
+                    .takes_value(true)
+                    .requires("adaptive")
+                    .value_name("fraction")
+                    .help("fraction of the adaptive samples distributed uniformly (default: 0.25)"),
+            )
+            .arg(
+                Arg::with_name("layers")
+                    .long("layers")
+                    .help("save all the buffers inside one EXR file (with the rendering information)"),
+            )
+            .arg(
+                Arg::with_name("denoise")
+                    .long("denoise")
+                    .help("denoise the image (two half buffers, with the albedo, normal and depth features)"),
+            )
+            .arg(
+                Arg::with_name("seed")
+                    .long("seed")
+                    .takes_value(true)
+                    .help("seed of the random numbers (same image for any number of threads)"),
+            )
+            .arg(
+                Arg::with_name("crop")
+                    .long("crop")
+                    .takes_value(true)
+                    .number_of_values(4)
+                    .value_names(&["x0", "y0", "x1", "y1"])
+                    .help("render only the pixels [x0, x1[ x [y0, y1[ (the image keeps its size, see merge)"),
+            )
+            .arg(
+                Arg::with_name("save_scene")
+                    .long("save-scene")
+                    .takes_value(true)
+                    .help("save the scene (JSON) with the rendering options, before rendering if an integrator is given"),
+            )
             .arg(
                 Arg::with_name("nbsamples")
                     .short("n")
@@ -172,6 +276,61 @@ fn main() {
                             .default_value("all"),
                     ),
             )
+            .subcommand(
+                SubCommand::with_name("bdpt")
+                    .about("bidirectional path tracing combining all the strategies with MIS")
+                    .arg(&max_arg)
+                    .arg(
+                        Arg::with_name("strategies")
+                            .help("output the weighted contribution of each strategy (s, t)")
+                            .short("s"),
+                    ),
+            )
+            .subcommand(
+                SubCommand::with_name("sppm")
+                    .about("stochastic progressive photon mapping (surfaces only)")
+                    .arg(&max_arg)
+                    .arg(
+                        Arg::with_name("photons")
+                            .takes_value(true)
+                            .help("number of photons traced at each iteration")
+                            .short("p")
+                            .default_value("100000"),
+                    )
+                    .arg(
+                        Arg::with_name("radius")
+                            .takes_value(true)
+                            .help("initial gathering radius (auto: from the scene size)")
+                            .short("r")
+                            .default_value("auto"),
+                    )
+                    .arg(
+                        Arg::with_name("alpha")
+                            .takes_value(true)
+                            .help("fraction of the photons kept at each iteration")
+                            .short("a")
+                            .default_value("0.7"),
+                    ),
+            )
+            .subcommand(
+                SubCommand::with_name("vcm")
+                    .about("vertex connection and merging (surfaces only for the merging)")
+                    .arg(&max_arg)
+                    .arg(
+                        Arg::with_name("radius")
+                            .takes_value(true)
+                            .help("initial merging radius (auto: from the scene size)")
+                            .short("r")
+                            .default_value("auto"),
+                    )
+                    .arg(
+                        Arg::with_name("alpha")
+                            .takes_value(true)
+                            .help("radius reduction factor at each iteration")
+                            .short("a")
+                            .default_value("0.75"),
+                    ),
+            )
             .subcommand(
                 SubCommand::with_name("vpl")
                     .about("brute force virtual point light integrator")
@@ -277,6 +436,52 @@ fn main() {
                             .short("n"),
                     ),
             )
+            .subcommand(
+                SubCommand::with_name("merge")
+                    .about("merge partial images (e.g., rendered on several machines with --crop)")
+                    .arg(
+                        Arg::with_name("inputs")
+                            .required(true)
+                            .takes_value(true)
+                            .multiple(true)
+                            .help("images to merge (the layered EXR files know their crop and spp)"),
+                    )
+                    .arg(
+                        Arg::with_name("weighted")
+                            .long("weighted")
+                            .help("weight the images by their number of samples per pixel"),
+                    )
+                    .arg(
+                        Arg::with_name("sum")
+                            .long("sum")
+                            .conflicts_with("weighted")
+                            .help("add the images (tiles without crop information, e.g. PFM)"),
+                    ),
+            )
+            .subcommand(
+                SubCommand::with_name("denoise")
+                    .about("denoise a layered EXR image (rendered with --denoise --layers)")
+                    .arg(
+                        Arg::with_name("input")
+                            .required(true)
+                            .takes_value(true)
+                            .help("layered EXR file with the primal_0 and primal_1 buffers"),
+                    )
+                    .arg(
+                        Arg::with_name("window")
+                            .takes_value(true)
+                            .short("w")
+                            .default_value("10")
+                            .help("radius of the search window"),
+                    )
+                    .arg(
+                        Arg::with_name("patch")
+                            .takes_value(true)
+                            .short("p")
+                            .default_value("3")
+                            .help("radius of the patches"),
+                    ),
+            )
             .subcommand(
                 SubCommand::with_name("direct")
                     .about("direct lighting")
@@ -311,17 +516,89 @@ fn main() {
     }
     /////////////// Check output extension
     let imgout_path_str = matches.value_of("output").unwrap_or("test.pfm");
+    if matches.is_present("layers") && !imgout_path_str.ends_with(".exr") {
+        eprintln!("--layers needs an EXR output file (-o)");
+        std::process::exit(1);
+    }
 
-    //////////////// Load the rendering configuration
-    let nb_samples = value_t_or_exit!(matches.value_of("nbsamples"), usize);
+    //////////////// Denoise an image (no rendering)
+    if let ("denoise", Some(m)) = matches.subcommand() {
+        let filename = m.value_of("input").unwrap();
+        let image = match rustlight::structure::Bitmap::read_layers(filename) {
+            Ok(image) => image,
+            Err(e) => {
+                eprintln!("Impossible to read {}: {}", filename, e);
+                std::process::exit(1);
+            }
+        };
+        let mut img = rustlight::integrators::BufferCollection::new(
+            cgmath::Point2::new(0, 0),
+            image.size,
+            &[],
+        );
+        img.values.extend(image.layers);
+        let denoiser = rustlight::integrators::denoise::Denoiser {
+            window_radius: value_t_or_exit!(m.value_of("window"), u32),
+            patch_radius: value_t_or_exit!(m.value_of("patch"), u32),
+            ..Default::default()
+        };
+        match denoiser.denoise_buffers(&img) {
+            Ok(denoised) => denoised.save(imgout_path_str),
+            Err(e) => {
+                eprintln!("Impossible to denoise {}: {}", filename, e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    //////////////// Merge partial images (no rendering)
+    if let ("merge", Some(m)) = matches.subcommand() {
+        let images = m
+            .values_of("inputs")
+            .unwrap()
+            .map(rustlight::integrators::merge::PartialImage::read)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap_or_else(|e| {
+                eprintln!("Impossible to merge the images: {}", e);
+                std::process::exit(1);
+            });
+        let img = rustlight::integrators::merge::merge(
+            &images,
+            m.is_present("weighted"),
+            m.is_present("sum"),
+        )
+        .unwrap_or_else(|e| {
+            eprintln!("Impossible to merge the images: {}", e);
+            std::process::exit(1);
+        });
+        if imgout_path_str.ends_with(".exr") {
+            img.save_layers(imgout_path_str, &[]);
+        } else {
+            img.save("primal", imgout_path_str);
+        }
+        return;
+    }
 
     //////////////// Load the scene
-    let scene = matches
-        .value_of("scene")
-        .expect("no scene parameter provided");
-    let scene = rustlight::scene_loader::SceneLoaderManager::default()
+    // Only the denoise and merge subcommands do not need a scene
+    let scene = match matches.value_of("scene") {
+        Some(scene) => scene,
+        None => clap::Error::with_description(
+            "The scene argument is required to render",
+            clap::ErrorKind::MissingRequiredArgument,
+        )
+        .exit(),
+    };
+    let scene = match rustlight::scene_loader::SceneLoaderManager::default()
         .load(scene.to_string())
-        .expect("error on loading the scene");
+    {
+        Ok(scene) => scene,
+        Err(e) => {
+            eprintln!("Error on loading the scene: {}", e);
+            std::process::exit(1);
+        }
+    };
     let scene = match matches.value_of("nbthreads").unwrap() {
         "auto" => scene,
         x => {
@@ -342,27 +619,141 @@ fn main() {
             }
         }
     };
-    let mut scene = scene.nb_samples(nb_samples).output_img(imgout_path_str);
+
+    //////////////// Load the rendering configuration
+    // (the command line overrides the scene "render" settings)
+    let scene_samples = scene
+        .description
+        .as_ref()
+        .and_then(|d| d.render.as_ref())
+        .and_then(|r| r.samples);
+    let nb_samples = match (matches.value_of("nbsamples"), scene_samples) {
+        (Some(_), _) => value_t_or_exit!(matches.value_of("nbsamples"), usize),
+        (None, Some(n)) => n,
+        (None, None) if matches.subcommand_name().is_none() => scene.nb_samples,
+        (None, None) => {
+            eprintln!("The number of samples is not provided (-n or the scene render settings)");
+            std::process::exit(1);
+        }
+    };
+    let acceleration = match matches.value_of("acceleration").unwrap() {
+        "embree" => rustlight::scene::AccelerationType::Embree,
+        "bvh" => rustlight::scene::AccelerationType::BVH,
+        _ => panic!("unknown acceleration structure"),
+    };
+    let sampler = match matches.value_of("sampler").unwrap() {
+        _ if matches.occurrences_of("sampler") == 0 => scene.sampler,
+        "independent" => rustlight::samplers::SamplerType::Independent,
+        "stratified" => rustlight::samplers::SamplerType::Stratified,
+        "halton" => rustlight::samplers::SamplerType::Halton,
+        "sobol" => rustlight::samplers::SamplerType::Sobol,
+        _ => panic!("unknown sampler"),
+    };
+    let filter = if matches.occurrences_of("filter") == 0 {
+        scene.filter
+    } else {
+        // Format: name[:radius]
+        let filter = matches.value_of("filter").unwrap();
+        let (name, radius) = match filter.find(':') {
+            Some(i) => (
+                &filter[..i],
+                Some(
+                    filter[i + 1..]
+                        .parse::<f32>()
+                        .expect("wrong filter radius"),
+                ),
+            ),
+            None => (filter, None),
+        };
+        match rustlight::integrators::film::Filter::from_name(name, radius) {
+            Some(f) => f,
+            None => panic!("unknown filter: {}", name),
+        }
+    };
+    let mut aovs = match matches.value_of("aovs") {
+        None => vec![],
+        Some(v) => v
+            .split(',')
+            .map(|name| match rustlight::integrators::aovs::AOV::from_name(name) {
+                Some(aov) => aov,
+                None => panic!("unknown aov: {}", name),
+            })
+            .collect::<Vec<_>>(),
+    };
+    if matches.is_present("denoise") {
+        // Features used by the denoiser
+        for name in &rustlight::integrators::denoise::FEATURE_BUFFERS {
+            let aov = rustlight::integrators::aovs::AOV::from_name(name).unwrap();
+            if !aovs.contains(&aov) {
+                aovs.push(aov);
+            }
+        }
+    }
+    let mut scene = scene
+        .nb_samples(nb_samples)
+        .output_img(imgout_path_str)
+        .acceleration(acceleration)
+        .sampler(sampler)
+        .filter(filter)
+        .aovs(aovs);
+    if matches.is_present("adaptive") {
+        let mut adaptive = rustlight::integrators::adaptive::AdaptiveSampling::new(
+            value_t_or_exit!(matches.value_of("adaptive"), f32),
+            matches
+                .value_of("adaptive_time")
+                .map(|_| value_t_or_exit!(matches.value_of("adaptive_time"), usize)),
+        );
+        if matches.is_present("adaptive_uniform") {
+            adaptive.uniform_fraction = value_t_or_exit!(matches.value_of("adaptive_uniform"), f32);
+        }
+        scene = scene.adaptive(Some(adaptive));
+    }
+    if matches.is_present("seed") {
+        scene = scene.seed(value_t_or_exit!(matches.value_of("seed"), u64));
+    }
 
     ///////////////// Medium
     // TODO: Read from PBRT file
     {
-        let medium_density = value_t_or_exit!(matches.value_of("medium"), f32);
+        // Format: density[,phase] (phase: isotropic or hg:g)
+        let medium_args = matches
+            .value_of("medium")
+            .unwrap()
+            .split(',')
+            .collect::<Vec<_>>();
+        let medium_density = medium_args[0]
+            .parse::<f32>()
+            .expect("Wrong medium density");
+        let phase = medium_args.get(1).map(|p| {
+            p.parse::<rustlight::volume::PhaseFunction>()
+                .unwrap_or_else(|e| panic!("{}", e))
+        });
         if medium_density != 0.0 {
             let sigma_a = rustlight::structure::Color::value(0.0) * medium_density;
             let sigma_s = rustlight::structure::Color::value(1.0) * medium_density;
             let sigma_t = sigma_a + sigma_s;
+            let phase = phase.unwrap_or(rustlight::volume::PhaseFunction::Isotropic());
+            info!("Create volume with: ");
+            info!(" - sigma_a: {:?}", sigma_a);
+            info!(" - sigma_s: {:?}", sigma_s);
+            info!(" - sigma_t: {:?}", sigma_t);
+            info!(" - phase: {:?}", phase);
             scene.volume = Some(rustlight::volume::HomogenousVolume {
                 sigma_a,
                 sigma_s,
                 sigma_t,
                 density: 1.0,
+                phase,
             });
-
-            info!("Create volume with: ");
-            info!(" - sigma_a: {:?}", sigma_a);
-            info!(" - sigma_s: {:?}", sigma_s);
-            info!(" - sigma_t: {:?}", sigma_t);
+        } else if let Some(phase) = phase {
+            // Only override the phase function of the scene medium
+            match scene.volume {
+                Some(ref mut m) => {
+                    info!("Override medium phase function: {:?}", phase);
+                    m.phase = phase;
+                }
+                None => warn!("No medium inside the scene, ignore the phase function"),
+            }
         }
     }
     ///////////////// Tweak the image size
@@ -374,6 +765,33 @@ fn main() {
             scene.camera.scale_image(image_scale);
         }
     }
+    if let Some(crop) = matches.values_of("crop") {
+        let crop = crop
+            .collect::<Vec<_>>()
+            .join(" ")
+            .parse::<rustlight::scene::ImageRegion>()
+            .unwrap_or_else(|e| panic!("{}", e));
+        info!("Render the pixels: {}", crop);
+        scene = scene.crop(Some(crop));
+    }
+    if let Some(filename) = matches.value_of("save_scene") {
+        let desc = match scene.to_description() {
+            Some(desc) => desc,
+            None => {
+                eprintln!("--save-scene only supports the scenes loaded from a JSON file");
+                std::process::exit(1);
+            }
+        };
+        let data = serde_json::to_string_pretty(&desc).unwrap();
+        if let Err(e) = std::fs::write(filename, data) {
+            eprintln!("Impossible to write {}: {}", filename, e);
+            std::process::exit(1);
+        }
+        info!("Scene saved: {}", filename);
+        if matches.subcommand_name().is_none() {
+            return;
+        }
+    }
 
     ///////////////// Get the reconstruction algorithm
     let recons = match matches.subcommand() {
@@ -407,7 +825,7 @@ fn main() {
     };
 
     ///////////////// Create the main integrator
-    let mut int = match matches.subcommand() {
+    let int = match matches.subcommand() {
         ("path_kulla", Some(m)) => {
             let strategy = value_t_or_exit!(m.value_of("strategy"), String);
             let strategy = match strategy.as_ref() {
@@ -471,6 +889,60 @@ fn main() {
                 },
             ))
         }
+        ("bdpt", Some(m)) => {
+            let max_depth = match_infinity(m.value_of("max").unwrap());
+            IntegratorType::Primal(Box::new(
+                rustlight::integrators::explicit::bdpt::IntegratorBDPT {
+                    max_depth,
+                    strategies: m.is_present("strategies"),
+                },
+            ))
+        }
+        ("sppm", Some(m)) => {
+            let max_depth = match_infinity(m.value_of("max").unwrap());
+            let init_radius = match m.value_of("radius").unwrap() {
+                "auto" => None,
+                _ => Some(value_t_or_exit!(m.value_of("radius"), f32)),
+            };
+            let nb_photons = value_t_or_exit!(m.value_of("photons"), usize);
+            if nb_photons == 0 {
+                eprintln!("SPPM needs at least one photon per iteration (-p)");
+                std::process::exit(1);
+            }
+            if !scene.aovs.is_empty() {
+                eprintln!("SPPM does not support AOVs (--aov or --denoise)");
+                std::process::exit(1);
+            }
+            if !scene.media.is_empty() {
+                eprintln!("SPPM does not support the media attached to the meshes");
+                std::process::exit(1);
+            }
+            if scene.filter != rustlight::integrators::film::Filter::default() {
+                warn!("SPPM gathers per pixel, ignore the reconstruction filter");
+            }
+            IntegratorType::Primal(Box::new(
+                rustlight::integrators::explicit::sppm::IntegratorSPPM::new(
+                    max_depth,
+                    nb_photons,
+                    init_radius,
+                    value_t_or_exit!(m.value_of("alpha"), f32),
+                ),
+            ))
+        }
+        ("vcm", Some(m)) => {
+            let max_depth = match_infinity(m.value_of("max").unwrap());
+            let init_radius = match m.value_of("radius").unwrap() {
+                "auto" => None,
+                _ => Some(value_t_or_exit!(m.value_of("radius"), f32)),
+            };
+            IntegratorType::Primal(Box::new(
+                rustlight::integrators::explicit::vcm::IntegratorVCM::new(
+                    max_depth,
+                    init_radius,
+                    value_t_or_exit!(m.value_of("alpha"), f32),
+                ),
+            ))
+        }
         ("gradient-path", Some(m)) => {
             let max_depth = match_infinity(m.value_of("max").unwrap());
             let min_depth = match_infinity(m.value_of("min").unwrap());
@@ -632,6 +1104,10 @@ fn main() {
             }))
         }
         ("direct", Some(m)) => {
+            if !scene.media.is_empty() {
+                eprintln!("Direct lighting does not support the media attached to the meshes");
+                std::process::exit(1);
+            }
             IntegratorType::Primal(Box::new(rustlight::integrators::direct::IntegratorDirect {
                 nb_bsdf_samples: value_t_or_exit!(m.value_of("bsdf"), u32),
                 nb_light_samples: value_t_or_exit!(m.value_of("light"), u32),
@@ -639,18 +1115,88 @@ fn main() {
         }
         _ => panic!("unknown integrator"),
     };
-    let img = if matches.is_present("average") {
+    let start = std::time::Instant::now();
+    let denoise = matches.is_present("denoise");
+    let mut int = if matches.is_present("average") {
+        if denoise {
+            if let IntegratorType::Gradient(_) = int {
+                eprintln!("--denoise with -a does not support the gradient-domain integrators");
+                std::process::exit(1);
+            }
+            if int.progressive() {
+                eprintln!("--denoise with -a does not support the progressive integrators");
+                std::process::exit(1);
+            }
+        }
         let time_out = match_infinity(matches.value_of("average").unwrap());
+        let resume = matches.value_of("resume").map(|filename| {
+            let checkpoint = match rustlight::integrators::avg::Checkpoint::load(filename) {
+                Ok(c) => c,
+                Err(e) => {
+                    eprintln!("Impossible to resume from the checkpoint: {}", e);
+                    std::process::exit(1);
+                }
+            };
+            if let Err(e) = checkpoint.check(&scene, &int, denoise) {
+                eprintln!("Impossible to resume from {}: {}", filename, e);
+                std::process::exit(1);
+            }
+            checkpoint
+        });
+        let checkpoint = match matches.value_of("checkpoint") {
+            None => None,
+            Some(_) => Some(value_t_or_exit!(matches.value_of("checkpoint"), usize)),
+        };
         let mut int =
-            IntegratorType::Primal(Box::new(rustlight::integrators::avg::IntegratorAverage {
-                time_out,
+            rustlight::integrators::avg::IntegratorAverage::new(time_out, int, checkpoint, resume);
+        // Denoise once the final average (not each pass)
+        if denoise {
+            int.denoiser = Some(rustlight::integrators::denoise::Denoiser::default());
+        }
+        IntegratorType::Primal(Box::new(int))
+    } else if denoise {
+        IntegratorType::Primal(Box::new(
+            rustlight::integrators::denoise::IntegratorDenoise {
                 integrator: int,
-            }));
-        int.compute(&scene)
+                denoiser: rustlight::integrators::denoise::Denoiser::default(),
+            },
+        ))
     } else {
-        int.compute(&scene)
+        int
     };
+    let img = int.compute(&scene);
+
+    let render_time = start.elapsed();
 
     // Save the image
+    if matches.is_present("layers") {
+        let mut attributes = vec![
+            (
+                "integrator".to_string(),
+                matches.subcommand_name().unwrap().to_string(),
+            ),
+            (
+                "spp".to_string(),
+                int.samples_per_pixel(&scene, &img).to_string(),
+            ),
+            (
+                "renderTime".to_string(),
+                format!("{}", render_time.as_secs_f32()),
+            ),
+            ("seed".to_string(), scene.seed.to_string()),
+        ];
+        if let Some(crop) = scene.crop {
+            attributes.push(("crop".to_string(), crop.to_string()));
+        }
+        img.save_layers(imgout_path_str, &attributes);
+        return;
+    }
     img.save("primal", imgout_path_str);
+    let strategies = match matches.subcommand() {
+        ("bdpt", Some(m)) => m.is_present("strategies"),
+        _ => false,
+    };
+    if strategies || !scene.aovs.is_empty() || scene.adaptive.is_some() {
+        img.dump_all(imgout_path_str);
+    }
 }
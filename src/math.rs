@@ -38,6 +38,52 @@ pub fn sample_uniform_sphere(u: Point2<f32>) -> Vector3<f32> {
     Vector3::new(r * phi.cos(), r * phi.sin(), z)
 }
 
+/// Error function (Abramowitz and Stegun approximation)
+pub fn erf(x: f32) -> f32 {
+    let a1 = 0.254829592;
+    let a2 = -0.284496736;
+    let a3 = 1.421413741;
+    let a4 = -1.453152027;
+    let a5 = 1.061405429;
+    let p = 0.3275911;
+
+    let sign = x.signum();
+    let x = x.abs();
+    let t = 1.0 / (1.0 + p * x);
+    let y = 1.0 - (((((a5 * t + a4) * t) + a3) * t + a2) * t + a1) * t * (-x * x).exp();
+    sign * y
+}
+
+/// Inverse error function (Giles approximation)
+pub fn erf_inv(x: f32) -> f32 {
+    let x = x.max(-0.99999).min(0.99999);
+    let w = -((1.0 - x) * (1.0 + x)).ln();
+    let p = if w < 5.0 {
+        let w = w - 2.5;
+        let mut p = 2.81022636e-08;
+        p = 3.43273939e-07 + p * w;
+        p = -3.5233877e-06 + p * w;
+        p = -4.39150654e-06 + p * w;
+        p = 0.00021858087 + p * w;
+        p = -0.00125372503 + p * w;
+        p = -0.00417768164 + p * w;
+        p = 0.246640727 + p * w;
+        1.50140941 + p * w
+    } else {
+        let w = w.sqrt() - 3.0;
+        let mut p = -0.000200214257;
+        p = 0.000100950558 + p * w;
+        p = 0.00134934322 + p * w;
+        p = -0.00367342844 + p * w;
+        p = 0.00573950773 + p * w;
+        p = -0.0076224613 + p * w;
+        p = 0.00943887047 + p * w;
+        p = 1.00167406 + p * w;
+        2.83297682 + p * w
+    };
+    p * x
+}
+
 /// Create an orthogonal basis by taking the normal vector
 /// code based on Pixar paper.
 #[derive(Clone)]
@@ -136,4 +182,17 @@ impl Distribution1D {
         assert!(i < self.cdf.len() - 1);
         self.cdf[i + 1] - self.cdf[i]
     }
+
+    /// Sample an element and remap the random number
+    /// return the element index and the position inside the element ([0, 1[)
+    pub fn sample_continuous(&self, v: f32) -> (usize, f32) {
+        let i = self.sample(v);
+        let pdf = self.pdf(i);
+        let offset = if pdf > 0.0 {
+            ((v - self.cdf[i]) / pdf).clamp(0.0, 0.99999)
+        } else {
+            0.0
+        };
+        (i, offset)
+    }
 }
@@ -1,17 +1,16 @@
+use crate::bsdfs::Transport;
 use crate::cgmath::InnerSpace;
 use crate::emitter::*;
-use crate::math::*;
 use crate::paths::vertex::*;
 use crate::samplers::*;
 use crate::scene::*;
 use crate::structure::*;
-use crate::volume::*;
 use crate::Scale;
 use std;
 use std::mem;
 
 pub trait SamplingStrategy {
-    fn sample<'scene, 'emitter>(
+    fn sample<'scene: 'emitter, 'emitter>(
         &self,
         path: &mut Path<'scene, 'emitter>,
         vertex_id: VertexID,
@@ -20,7 +19,6 @@ pub trait SamplingStrategy {
         emitters: &'emitter EmitterSampler,
         throughput: Color,
         sampler: &mut dyn Sampler,
-        medium: Option<&HomogenousVolume>,
         id_strategy: usize,
     ) -> Option<(VertexID, Color)>;
 
@@ -39,21 +37,28 @@ pub struct DirectionalSamplingStrategy {
     pub from_sensor: bool,
 }
 impl DirectionalSamplingStrategy {
-    pub fn bounce<'scene>(
+    fn transport(&self) -> Transport {
+        if self.from_sensor {
+            Transport::Radiance
+        } else {
+            Transport::Importance
+        }
+    }
+
+    pub fn bounce<'scene: 'emitter, 'emitter>(
         &self,
-        path: &mut Path<'scene, '_>,
+        path: &mut Path<'scene, 'emitter>,
         vertex_id: VertexID,
         accel: &'scene dyn Acceleration,
         scene: &'scene Scene,
         throughput: &mut Color,
         sampler: &mut dyn Sampler,
-        medium: Option<&HomogenousVolume>,
         id_strategy: usize,
     ) -> (Option<EdgeID>, Option<VertexID>) {
         match path.vertex(vertex_id) {
             Vertex::Sensor(ref v) => {
-                // Generate the path from the sensor
-                let ray = scene.camera.generate(v.uv);
+                // Generate the path from the sensor (from the sampled sensor position)
+                let ray = scene.camera.generate_from(&v.pos, v.uv);
                 let (edge, new_vertex) = Edge::from_ray(
                     path,
                     &ray,
@@ -63,7 +68,8 @@ impl DirectionalSamplingStrategy {
                     1.0,
                     sampler,
                     accel,
-                    medium,
+                    scene,
+                    scene.medium_at(accel, &ray.o, &ray.d),
                     id_strategy,
                 );
                 (Some(edge), new_vertex)
@@ -73,7 +79,7 @@ impl DirectionalSamplingStrategy {
                     v.its
                         .mesh
                         .bsdf
-                        .sample(&v.its.uv, &v.its.wi, sampler.next2d())
+                        .sample(&v.its.uv, &v.its.wi, sampler.next2d(), self.transport())
                 {
                     let d_out_global = v.its.frame.to_world(sampled_bsdf.d);
 
@@ -103,6 +109,7 @@ impl DirectionalSamplingStrategy {
 
                     // Generate the new ray and do the intersection
                     let ray = Ray::new(v.its.p, d_out_global);
+                    let medium = v.its.medium(&d_out_global);
                     let (edge, new_vertex) = Edge::from_ray(
                         path,
                         &ray,
@@ -112,6 +119,7 @@ impl DirectionalSamplingStrategy {
                         rr_weight,
                         sampler,
                         accel,
+                        scene,
                         medium,
                         id_strategy,
                     );
@@ -139,6 +147,7 @@ impl DirectionalSamplingStrategy {
 
                 // Generate the new ray and do the intersection
                 let ray = Ray::new(v.pos, sampled_phase.d);
+                let medium = v.medium;
                 let (edge, new_vertex) = Edge::from_ray(
                     path,
                     &ray,
@@ -148,25 +157,21 @@ impl DirectionalSamplingStrategy {
                     rr_weight,
                     sampler,
                     accel,
+                    scene,
                     medium,
                     id_strategy,
                 );
                 (Some(edge), new_vertex)
             }
             Vertex::Light(ref v) => {
-                // For now, just computing the outgoing direction
-                // Using cosine base weighting as we know that the light source
-                // can only be cosine based isotropic lighting
-                let d_out = cosine_sample_hemisphere(sampler.next2d());
-                if d_out.z == 0.0 {
-                    return (None, None); // Failed to sample the outgoing direction
-                }
-
-                let frame = Frame::new(v.n);
-                let d_out_global = frame.to_world(d_out);
-                let ray = Ray::new(v.pos, d_out_global);
-                // FIXME: This might be wrong!
-                let weight = Color::one(); // Perfectly importance sampled
+                // The emitter chooses the outgoing direction
+                // (cosine based for the area lights)
+                let (d_out, pdf, weight) =
+                    match v.emitter.sample_direction(&v.pos, &v.n, sampler.next2d()) {
+                        Some(v) => v,
+                        None => return (None, None), // Failed to sample the outgoing direction
+                    };
+                let ray = Ray::new(v.pos, d_out);
 
                 // This will generate the edge
                 // if there is a participating media
@@ -175,12 +180,13 @@ impl DirectionalSamplingStrategy {
                     path,
                     &ray,
                     vertex_id,
-                    PDF::SolidAngle(d_out.z * std::f32::consts::FRAC_1_PI),
+                    pdf,
                     weight,
                     1.0,
                     sampler,
                     accel,
-                    medium,
+                    scene,
+                    scene.medium_at(accel, &ray.o, &ray.d),
                     id_strategy,
                 );
 
@@ -190,7 +196,7 @@ impl DirectionalSamplingStrategy {
     }
 }
 impl SamplingStrategy for DirectionalSamplingStrategy {
-    fn sample<'scene, 'emitter>(
+    fn sample<'scene: 'emitter, 'emitter>(
         &self,
         path: &mut Path<'scene, 'emitter>,
         vertex_id: VertexID,
@@ -199,7 +205,6 @@ impl SamplingStrategy for DirectionalSamplingStrategy {
         _emitters: &'emitter EmitterSampler,
         mut throughput: Color,
         sampler: &mut dyn Sampler,
-        medium: Option<&HomogenousVolume>,
         id_strategy: usize,
     ) -> Option<(VertexID, Color)> {
         // Generate the next edge and the next vertex
@@ -210,7 +215,6 @@ impl SamplingStrategy for DirectionalSamplingStrategy {
             scene,
             &mut throughput,
             sampler,
-            medium,
             id_strategy,
         );
 
@@ -235,10 +239,13 @@ impl SamplingStrategy for DirectionalSamplingStrategy {
             }
         }
 
-        if let Some(new_vertex) = new_vertex {
-            Some((new_vertex, throughput))
-        } else {
-            None
+        match new_vertex {
+            // The paths cannot continue after reaching the environment
+            Some(new_vertex) => match path.vertex(new_vertex) {
+                Vertex::Light(ref _v) => None,
+                _ => Some((new_vertex, throughput)),
+            },
+            None => None,
         }
     }
     fn pdf<'scene, 'emitter>(
@@ -327,16 +334,15 @@ impl LightSamplingStrategy {
     }
 }
 impl SamplingStrategy for LightSamplingStrategy {
-    fn sample<'scene, 'emitter>(
+    fn sample<'scene: 'emitter, 'emitter>(
         &self,
         path: &mut Path<'scene, 'emitter>,
         vertex_id: VertexID,
         accel: &'scene dyn Acceleration,
-        _scene: &'scene Scene,
+        scene: &'scene Scene,
         emitters: &'emitter EmitterSampler,
         _throughput: Color,
         sampler: &mut dyn Sampler,
-        medium: Option<&HomogenousVolume>,
         id_strategy: usize,
     ) -> Option<(VertexID, Color)> {
         let (edge, _next_vertex) = match path.vertex(vertex_id) {
@@ -355,8 +361,13 @@ impl SamplingStrategy for LightSamplingStrategy {
                     sampler.next(),
                     sampler.next2d(),
                 );
-                let visible = accel.visible(&v.its.p, &light_record.p);
-                if light_record.is_valid() && visible {
+                let transmittance = if light_record.is_valid() {
+                    let medium = v.its.medium(&light_record.d);
+                    scene.transmittance(accel, &v.its.p, &light_record.p, medium, sampler)
+                } else {
+                    Color::zero()
+                };
+                if !transmittance.is_zero() {
                     // We create a new vertex as it is a light
                     let next_vertex = Vertex::Light(EmitterVertex {
                         pos: light_record.p,
@@ -366,13 +377,9 @@ impl SamplingStrategy for LightSamplingStrategy {
                         edge_out: None,
                     });
 
-                    // FIXME: Only work for diffuse light
-                    // FIXME: Check the direction of hte light
-                    let mut weight = light_record.weight;
-                    let emission = light_record.emitter.emitted_luminance(light_record.d);
-                    weight.r /= emission.r;
-                    weight.g /= emission.g;
-                    weight.b /= emission.b;
+                    // The emission is evaluated by the light vertex
+                    // so the weight only contains the sampling pdf
+                    let mut weight = Color::value(1.0 / light_record.pdf.value());
 
                     // Need to evaluate the BSDF
                     weight *= &v.its.mesh.bsdf.eval(
@@ -380,19 +387,10 @@ impl SamplingStrategy for LightSamplingStrategy {
                         &v.its.wi,
                         &v.its.to_local(&light_record.d),
                         Domain::SolidAngle,
+                        Transport::Radiance,
                     );
 
-                    if let Some(m) = medium {
-                        // Evaluate the transmittance
-                        let mut ray = Ray::new(v.its.p, light_record.d);
-                        let d = light_record.p - v.its.p;
-                        // Trick to compute the distance
-                        ray.tfar = d.dot(light_record.d);
-                        assert!(ray.tfar > 0.0);
-                        // Compute the transmittance
-                        let transmittance = m.transmittance(ray);
-                        weight *= transmittance;
-                    }
+                    weight *= transmittance;
 
                     let next_vertex_id = path.register_vertex(next_vertex);
                     (
@@ -418,8 +416,12 @@ impl SamplingStrategy for LightSamplingStrategy {
                 // will be computed.
                 let light_record =
                     emitters.sample_light(&v.pos, sampler.next(), sampler.next(), sampler.next2d());
-                let visible = accel.visible(&v.pos, &light_record.p);
-                if light_record.is_valid() && visible {
+                let transmittance = if light_record.is_valid() {
+                    scene.transmittance(accel, &v.pos, &light_record.p, v.medium, sampler)
+                } else {
+                    Color::zero()
+                };
+                if !transmittance.is_zero() {
                     let next_vertex = Vertex::Light(EmitterVertex {
                         pos: light_record.p,
                         n: light_record.n,
@@ -428,28 +430,14 @@ impl SamplingStrategy for LightSamplingStrategy {
                         edge_out: None,
                     });
 
-                    // FIXME: Only work for diffuse light
-                    // FIXME: This is the wrong -d_out_local, no?
-                    let mut weight = light_record.weight;
-                    let emission = light_record.emitter.emitted_luminance(-light_record.d);
-                    weight.r /= emission.r;
-                    weight.g /= emission.g;
-                    weight.b /= emission.b;
+                    // The emission is evaluated by the light vertex
+                    // so the weight only contains the sampling pdf
+                    let mut weight = Color::value(1.0 / light_record.pdf.value());
 
                     // Need to evaluate the phase function
                     weight *= &v.phase_function.eval(&v.d_in, &light_record.d);
 
-                    if let Some(m) = medium {
-                        // Evaluate the transmittance
-                        let mut ray = Ray::new(v.pos, light_record.d);
-                        let d = light_record.p - v.pos;
-                        // Trick to compute the distance
-                        ray.tfar = d.dot(light_record.d);
-                        assert!(ray.tfar > 0.0);
-                        // Generate the ray and compute the transmittance
-                        let transmittance = m.transmittance(ray);
-                        weight *= transmittance;
-                    }
+                    weight *= transmittance;
 
                     let next_vertex_id = path.register_vertex(next_vertex);
                     (
@@ -529,7 +517,7 @@ impl SamplingStrategy for LightSamplingStrategy {
     }
 }
 
-pub fn generate<'scene, 'emitter, T: Technique>(
+pub fn generate<'scene: 'emitter, 'emitter, T: Technique>(
     path: &mut Path<'scene, 'emitter>,
     accel: &'scene dyn Acceleration,
     scene: &'scene Scene,
@@ -563,7 +551,6 @@ pub fn generate<'scene, 'emitter, T: Technique>(
                         emitters,
                         *throughput,
                         sampler,
-                        scene.volume.as_ref(), // TODO: For now volume is global
                         id_sampling,
                     ) {
                         next.push((new_vertex, new_throughput));
@@ -57,8 +57,10 @@ impl Edge {
         edge
     }
 
-    pub fn from_ray<'scene>(
-        path: &mut Path<'scene, '_>,
+    /// Trace the ray and create the next vertex (surface or volume)
+    /// The media boundaries (null BSDF) are crossed by updating the current medium
+    pub fn from_ray<'scene: 'emitter, 'emitter>(
+        path: &mut Path<'scene, 'emitter>,
         ray: &Ray,
         org_vertex_id: VertexID,
         pdf_direction: PDF,
@@ -66,7 +68,8 @@ impl Edge {
         rr_weight: f32,
         sampler: &mut dyn Sampler,
         accel: &'scene dyn Acceleration,
-        medium: Option<&HomogenousVolume>,
+        scene: &'scene Scene,
+        mut medium: Option<usize>,
         id_sampling: usize,
     ) -> (EdgeID, Option<VertexID>) {
         let edge = Edge {
@@ -80,110 +83,119 @@ impl Edge {
             id_sampling,
         };
         let edge = path.register_edge(edge);
-        let its = match accel.trace(&ray) {
-            Some(its) => its,
-            None => {
-                if let Some(ref m) = medium {
-                    // Sample the participating media
-                    let mrec = m.sample(ray, sampler.next2d());
-                    let pos = Point3::from_vec(ray.o.to_vec() + ray.d * mrec.t);
-                    // We are sure to suceed as the distance is infine...
-                    // TODO: Note that this design decision makes the env map incompatible with participating media presence
-                    assert_eq!(mrec.exited, false);
-                    let new_vertex = Vertex::Volume(VolumeVertex {
-                        phase_function: PhaseFunction::Isotropic(),
-                        pos,
+
+        // Distance and transmittance weights are accumulated
+        // over the media crossed by the edge
+        let mut ray = *ray;
+        let mut dist = 0.0;
+        let mut weight_media = Color::one();
+        let mut sampled_distance: Option<SampledDistance> = None;
+        let new_vertex = loop {
+            let its = accel.trace(&ray);
+            if let Some(m) = scene.medium(medium) {
+                // Sample the participating media
+                // Need to create a new ray as tfar need to store
+                // the distance to the surface
+                let mut ray_med = ray;
+                if let Some(ref its) = its {
+                    ray_med.tfar = its.dist;
+                }
+                let mut mrec = m.sample_distance(&ray_med, sampler);
+                let t = mrec.t;
+                let exited = mrec.exited;
+                weight_media *= mrec.w;
+                mrec.t += dist;
+                mrec.continued_t += dist;
+                // Combine with the media crossed before
+                sampled_distance = Some(match sampled_distance {
+                    None => mrec,
+                    Some(prev) => SampledDistance {
+                        w: prev.w * mrec.w,
+                        continued_w: prev.w * mrec.continued_w,
+                        pdf: prev.pdf * mrec.pdf,
+                        ..mrec
+                    },
+                });
+                if !exited {
+                    // Hit the volume
+                    dist += t;
+                    break Some(Vertex::Volume(VolumeVertex {
+                        phase_function: m.phase().clone(),
+                        pos: ray.o + ray.d * t,
                         d_in: -ray.d,
+                        medium,
                         rr_weight: 1.0,
                         edge_in: edge,
                         edge_out: vec![],
-                    });
-                    let new_vertex = path.register_vertex(new_vertex);
+                    }));
+                }
+            }
 
-                    // Update the edge
-                    {
-                        let edge = path.edge_mut(edge);
-                        edge.dist = Some(mrec.t);
-                        edge.vertices.1 = Some(new_vertex);
-                        edge.weight *= mrec.w;
-                        edge.sampled_distance = Some(mrec);
+            match its {
+                None => {
+                    // The environment is represented by a light vertex
+                    // located outside the scene bounds
+                    break match scene.emitter_environment {
+                        None => None,
+                        Some(ref env) => {
+                            dist += 2.0 * env.world_radius;
+                            Some(Vertex::Light(EmitterVertex {
+                                pos: ray.o + ray.d * (2.0 * env.world_radius),
+                                n: -ray.d,
+                                emitter: env,
+                                edge_in: Some(edge),
+                                edge_out: None,
+                            }))
+                        }
+                    };
+                }
+                Some(its) => {
+                    dist += its.dist;
+                    if its.mesh.bsdf.is_null() {
+                        // Continue the ray inside the next medium
+                        medium = its.medium(&ray.d);
+                        ray = Ray::new(its.p, ray.d);
+                    } else {
+                        // Hit the surface
+                        break Some(Vertex::Surface(SurfaceVertex {
+                            its,
+                            rr_weight: 1.0,
+                            edge_in: edge,
+                            edge_out: vec![],
+                        }));
                     }
-
-                    return (edge, Some(new_vertex));
-                } else {
-                    // Create an edge without distance
-                    return (edge, None);
                 }
             }
         };
 
-        // Create the new vertex
-        // This depends if there is a participating media or not
-        let mut intersection_distance = its.dist;
-        let (mrec, new_vertex) = if let Some(ref m) = medium {
-            // Sample the participating media
-            // Need to create a new ray as tfar need to store
-            // the distance to the surface
-            let mut ray_med = *ray;
-            ray_med.tfar = intersection_distance;
-            let mrec = m.sample(&ray_med, sampler.next2d());
-            let new_vertex = if !mrec.exited {
-                // Hit the volume
-                // --- Update the distance
-                intersection_distance = mrec.t;
-                // --- Create the volume vertex
-                let pos = Point3::from_vec(ray.o.to_vec() + ray.d * mrec.t);
-                Vertex::Volume(VolumeVertex {
-                    phase_function: PhaseFunction::Isotropic(),
-                    pos,
-                    d_in: -ray.d,
-                    rr_weight: 1.0,
-                    edge_in: edge,
-                    edge_out: vec![],
-                })
-            } else {
-                // Hit the surface
-                Vertex::Surface(SurfaceVertex {
-                    its,
-                    rr_weight: 1.0,
-                    edge_in: edge,
-                    edge_out: vec![],
-                })
-            };
-            (Some(mrec), new_vertex)
-        } else {
-            (
-                None,
-                Vertex::Surface(SurfaceVertex {
-                    its,
-                    rr_weight: 1.0,
-                    edge_in: edge,
-                    edge_out: vec![],
-                }),
-            )
-        };
+        // The distance covers the segments without media too
+        if let Some(ref mut s) = sampled_distance {
+            if s.exited && new_vertex.is_some() {
+                s.t = dist;
+            }
+        }
 
         // Register the new vertex
-        let new_vertex = path.register_vertex(new_vertex);
+        let new_vertex = new_vertex.map(|v| path.register_vertex(v));
 
         // Update the edge information
         {
             let edge = path.edge_mut(edge);
-            edge.dist = Some(intersection_distance);
-            edge.vertices.1 = Some(new_vertex);
-            if mrec.is_some() {
-                edge.weight *= mrec.as_ref().unwrap().w;
+            if new_vertex.is_some() {
+                edge.dist = Some(dist);
             }
-            edge.sampled_distance = mrec;
+            edge.vertices.1 = new_vertex;
+            edge.weight *= weight_media;
+            edge.sampled_distance = sampled_distance;
         }
-        (edge, Some(new_vertex))
+        (edge, new_vertex)
     }
 
     pub fn next_on_light_source(&self, path: &Path) -> bool {
         if let Some(v) = &self.vertices.1 {
             path.vertex(*v).on_light_source()
         } else {
-            false //TODO: No env map
+            false // Escaped without environment (otherwise a light vertex)
         }
     }
 
@@ -193,7 +205,7 @@ impl Edge {
         if let Some(v) = &self.vertices.1 {
             self.weight * self.rr_weight * path.vertex(*v).contribution(self)
         } else {
-            Color::zero() //TODO: No env map
+            Color::zero() // Escaped without environment (otherwise a light vertex)
         }
     }
 }
@@ -228,6 +240,8 @@ pub struct VolumeVertex {
     pub phase_function: PhaseFunction,
     pub pos: Point3<f32>,
     pub d_in: Vector3<f32>,
+    // Medium where the vertex is (None is the scene medium)
+    pub medium: Option<usize>,
     pub rr_weight: f32,
     pub edge_in: EdgeID,
     pub edge_out: Vec<EdgeID>,
@@ -255,6 +269,20 @@ impl<'scene, 'emitter> Vertex<'scene, 'emitter> {
             Vertex::Volume(ref v) => v.pos,
         }
     }
+    /// Medium traversed when leaving the vertex toward d
+    pub fn medium(
+        &self,
+        scene: &Scene,
+        accel: &dyn Acceleration,
+        d: &Vector3<f32>,
+    ) -> Option<usize> {
+        match *self {
+            Vertex::Surface(ref v) => v.its.medium(d),
+            Vertex::Volume(ref v) => v.medium,
+            Vertex::Sensor(ref v) => scene.medium_at(accel, &v.pos, d),
+            Vertex::Light(ref v) => scene.medium_at(accel, &v.pos, d),
+        }
+    }
     pub fn on_surface(&self) -> bool {
         match *self {
             Vertex::Surface(ref _v) => true,
@@ -283,7 +311,10 @@ impl<'scene, 'emitter> Vertex<'scene, 'emitter> {
             }
             Vertex::Volume(ref _v) => Color::zero(),
             Vertex::Sensor(ref _v) => Color::zero(),
-            Vertex::Light(ref v) => v.emitter.emitted_luminance(-edge.d), // FIXME: Check the normal orientation
+            Vertex::Light(ref v) => match edge.dist {
+                Some(dist) => v.emitter.emitted_contribution(-edge.d, dist),
+                None => v.emitter.emitted_luminance(-edge.d), // FIXME: Check the normal orientation
+            },
         }
     }
 }
@@ -0,0 +1,69 @@
+use crate::samplers::*;
+use cgmath::Point2;
+
+// Bases of the Halton sequence
+// the dimensions above use the same bases with other permutations
+const PRIMES: [u32; 64] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131, 137, 139, 149, 151, 157, 163, 167, 173, 179, 181, 191, 193,
+    197, 199, 211, 223, 227, 229, 233, 239, 241, 251, 257, 263, 269, 271, 277, 281, 283, 293, 307,
+    311,
+];
+
+/// Halton sampler with random digit permutations
+/// (different permutations for each pixel, dimension and digit)
+pub struct HaltonSampler {
+    seed: u64,
+    pixel_seed: u64,
+    index: u32,
+    dimension: u64,
+}
+
+impl HaltonSampler {
+    pub fn new(seed: u64) -> HaltonSampler {
+        HaltonSampler {
+            seed,
+            pixel_seed: pixel_seed(seed, Point2::new(0, 0)),
+            index: 0,
+            dimension: 0,
+        }
+    }
+
+    fn scrambled_radical_inverse(&mut self) -> f32 {
+        let base = PRIMES[(self.dimension % PRIMES.len() as u64) as usize];
+        let seed = hash_combine(self.pixel_seed, self.dimension);
+        self.dimension += 1;
+
+        // The digits after the index are permuted too:
+        // continue until they cannot change the value
+        let inv_base = 1.0 / f64::from(base);
+        let mut inv_base_m = 1.0;
+        let mut value = 0.0;
+        let mut a = self.index;
+        let mut digit_id = 0;
+        while inv_base_m > 1e-8 {
+            let digit = permute(a % base, base, hash_combine(seed, digit_id) as u32);
+            a /= base;
+            digit_id += 1;
+            inv_base_m *= inv_base;
+            value += f64::from(digit) * inv_base_m;
+        }
+        (value as f32).min(1.0 - f32::EPSILON)
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn next(&mut self) -> f32 {
+        self.scrambled_radical_inverse()
+    }
+    fn next2d(&mut self) -> Point2<f32> {
+        let x = self.scrambled_radical_inverse();
+        let y = self.scrambled_radical_inverse();
+        Point2::new(x, y)
+    }
+    fn start_sample(&mut self, pixel: Point2<u32>, index: usize) {
+        self.pixel_seed = pixel_seed(self.seed, pixel);
+        self.index = index as u32;
+        self.dimension = 0;
+    }
+}
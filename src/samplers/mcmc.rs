@@ -132,6 +132,13 @@ impl Default for IndependentSamplerReplay {
 
 //FIXME: Make not representable a sampler that are not accept
 impl IndependentSamplerReplay {
+    pub fn from_seed(seed: u64) -> Self {
+        IndependentSamplerReplay {
+            rnd: rand::rngs::StdRng::seed_from_u64(seed),
+            ..Default::default()
+        }
+    }
+
     // Constructor to change the mutator technique
     pub fn mutator(mut self, mutator: Box<dyn Mutator>) -> Self {
         self.mutator = mutator;
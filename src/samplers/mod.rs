@@ -3,6 +3,9 @@ use cgmath::Point2;
 pub trait Sampler: Send {
     fn next(&mut self) -> f32;
     fn next2d(&mut self) -> Point2<f32>;
+    /// Start the sample index of the pixel (restart the dimensions)
+    /// only used by the low-discrepancy samplers
+    fn start_sample(&mut self, _pixel: Point2<u32>, _index: usize) {}
 }
 
 pub trait SamplerMCMC {
@@ -10,5 +13,179 @@ pub trait SamplerMCMC {
     fn reject(&mut self);
 }
 
+/// Sampler used by the Monte Carlo integrators (see compute_mc)
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SamplerType {
+    #[default]
+    Independent,
+    /// Jittered stratified (the strata are defined by the number of samples)
+    Stratified,
+    /// Halton with random digit permutations
+    Halton,
+    /// Owen-scrambled Sobol (Burley 2020)
+    Sobol,
+}
+
+impl SamplerType {
+    /// Create a sampler where the scrambling is defined by the seed
+    pub fn create(self, seed: u64, nb_samples: usize) -> Box<dyn Sampler> {
+        match self {
+            SamplerType::Independent => Box::new(independent::IndependentSampler::from_seed(seed)),
+            SamplerType::Stratified => {
+                Box::new(stratified::StratifiedSampler::new(seed, nb_samples))
+            }
+            SamplerType::Halton => Box::new(halton::HaltonSampler::new(seed)),
+            SamplerType::Sobol => Box::new(sobol::SobolSampler::new(seed)),
+        }
+    }
+}
+
+// Integer hash (MurmurHash3 finalizer)
+pub(crate) fn hash(mut x: u64) -> u64 {
+    x ^= x >> 33;
+    x = x.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    x ^= x >> 33;
+    x = x.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    x ^= x >> 33;
+    x
+}
+
+pub(crate) fn hash_combine(seed: u64, v: u64) -> u64 {
+    hash(seed ^ (hash(v).wrapping_add(0x9e37_79b9_7f4a_7c15)))
+}
+
+// Uniform value in [0, 1) from a hash
+pub(crate) fn hash_float(h: u64) -> f32 {
+    (h >> 40) as f32 / (1u64 << 24) as f32
+}
+
+/// Random permutation of i inside [0, l) without any table
+/// (Kensler 2013, "Correlated Multi-Jittered Sampling")
+pub(crate) fn permute(mut i: u32, l: u32, p: u32) -> u32 {
+    let mut w = l - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170_893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929_eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935_fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dc_b303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e50_1cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860_a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < l {
+            break;
+        }
+    }
+    ((u64::from(i) + u64::from(p)) % u64::from(l)) as u32
+}
+
+// Seed of the pixel, shared by all the samples of this pixel
+pub(crate) fn pixel_seed(seed: u64, pixel: Point2<u32>) -> u64 {
+    hash_combine(hash_combine(seed, u64::from(pixel.x)), u64::from(pixel.y))
+}
+
+pub mod halton;
 pub mod independent;
 pub mod mcmc;
+pub mod sobol;
+pub mod stratified;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NB_SAMPLES: usize = 16;
+    const LOW_DISCREPANCY: [SamplerType; 3] = [
+        SamplerType::Stratified,
+        SamplerType::Halton,
+        SamplerType::Sobol,
+    ];
+
+    // First 1D and 2D values of each sample of the pixel
+    fn samples(t: SamplerType, seed: u64, pixel: Point2<u32>) -> Vec<(f32, Point2<f32>)> {
+        let mut sampler = t.create(seed, NB_SAMPLES);
+        (0..NB_SAMPLES)
+            .map(|i| {
+                sampler.start_sample(pixel, i);
+                (sampler.next(), sampler.next2d())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn one_sample_per_stratum() {
+        for &t in &LOW_DISCREPANCY {
+            for &pixel in &[Point2::new(0, 0), Point2::new(13, 7)] {
+                let values = samples(t, 42, pixel);
+                let mut strata = values
+                    .iter()
+                    .map(|(v, _)| (v * NB_SAMPLES as f32) as usize)
+                    .collect::<Vec<_>>();
+                strata.sort_unstable();
+                assert_eq!(strata, (0..NB_SAMPLES).collect::<Vec<_>>(), "{:?}", t);
+            }
+        }
+    }
+
+    #[test]
+    fn one_sample_per_cell_2d() {
+        // Halton uses the bases 2 and 3 for the 2D samples:
+        // only the first coordinate is stratified with 16 samples
+        for &t in &[SamplerType::Stratified, SamplerType::Sobol] {
+            let values = samples(t, 42, Point2::new(5, 3));
+            let mut cells = values
+                .iter()
+                .map(|(_, p)| (p.y * 4.0) as usize * 4 + (p.x * 4.0) as usize)
+                .collect::<Vec<_>>();
+            cells.sort_unstable();
+            assert_eq!(cells, (0..NB_SAMPLES).collect::<Vec<_>>(), "{:?}", t);
+        }
+    }
+
+    #[test]
+    fn values_inside_unit_interval() {
+        for &t in &LOW_DISCREPANCY {
+            let mut sampler = t.create(7, NB_SAMPLES);
+            for i in 0..4 * NB_SAMPLES {
+                sampler.start_sample(Point2::new(i as u32 % 3, 1), i);
+                for _ in 0..64 {
+                    let v = sampler.next();
+                    let p = sampler.next2d();
+                    for &v in &[v, p.x, p.y] {
+                        assert!((0.0..1.0).contains(&v), "{:?}: {}", t, v);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn deterministic() {
+        for &t in &LOW_DISCREPANCY {
+            let pixel = Point2::new(3, 9);
+            assert_eq!(samples(t, 1, pixel), samples(t, 1, pixel), "{:?}", t);
+            assert_ne!(samples(t, 1, pixel), samples(t, 2, pixel), "{:?}", t);
+            // The same sample does not depend on the previous ones
+            let mut sampler = t.create(1, NB_SAMPLES);
+            sampler.start_sample(pixel, 5);
+            let v = (sampler.next(), sampler.next2d());
+            assert_eq!(v, samples(t, 1, pixel)[5], "{:?}", t);
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use crate::samplers::*;
+use cgmath::Point2;
+
+/// Owen-scrambled Sobol sampler
+/// (Burley 2020, "Practical Hash-based Owen Scrambling")
+/// Each call to next/next2d uses the first two Sobol dimensions
+/// with a shuffled index (padding) to avoid the correlations between the dimensions
+pub struct SobolSampler {
+    seed: u64,
+    pixel_seed: u64,
+    index: u32,
+    dimension: u64,
+}
+
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50_b47c);
+    x ^= x.wrapping_mul(0xb82f_1e52);
+    x ^= x.wrapping_mul(0xc7af_e638);
+    x ^= x.wrapping_mul(0x8d22_f6e6);
+    x
+}
+
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+// Second Sobol dimension (primitive polynomial x + 1)
+fn sobol_dim1(mut index: u32) -> u32 {
+    let mut v = 1 << 31;
+    let mut result = 0;
+    while index != 0 {
+        if index & 1 != 0 {
+            result ^= v;
+        }
+        index >>= 1;
+        v ^= v >> 1;
+    }
+    result
+}
+
+fn to_float(x: u32) -> f32 {
+    (x >> 8) as f32 / (1u32 << 24) as f32
+}
+
+impl SobolSampler {
+    pub fn new(seed: u64) -> SobolSampler {
+        SobolSampler {
+            seed,
+            pixel_seed: pixel_seed(seed, Point2::new(0, 0)),
+            index: 0,
+            dimension: 0,
+        }
+    }
+
+    // Shuffled index and seeds for the current dimension
+    fn next_dimension(&mut self) -> (u32, u64) {
+        let h = hash_combine(self.pixel_seed, self.dimension);
+        self.dimension += 1;
+        (nested_uniform_scramble(self.index, h as u32), h)
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn next(&mut self) -> f32 {
+        let (index, h) = self.next_dimension();
+        // The first Sobol dimension is the van der Corput sequence
+        to_float(nested_uniform_scramble(
+            index.reverse_bits(),
+            (h >> 32) as u32,
+        ))
+    }
+    fn next2d(&mut self) -> Point2<f32> {
+        let (index, h) = self.next_dimension();
+        let x = nested_uniform_scramble(index.reverse_bits(), (h >> 32) as u32);
+        let y = nested_uniform_scramble(sobol_dim1(index), hash_combine(h, 1) as u32);
+        Point2::new(to_float(x), to_float(y))
+    }
+    fn start_sample(&mut self, pixel: Point2<u32>, index: usize) {
+        self.pixel_seed = pixel_seed(self.seed, pixel);
+        self.index = index as u32;
+        self.dimension = 0;
+    }
+}
@@ -0,0 +1,82 @@
+use crate::samplers::*;
+use cgmath::Point2;
+
+/// Jittered stratified sampler
+/// each dimension uses a different permutation of the strata
+/// The 2D samples use a grid when the number of samples can be factorized
+/// (Latin hypercube otherwise)
+pub struct StratifiedSampler {
+    seed: u64,
+    nb_samples: u32,
+    // Grid used for the 2D samples
+    grid: (u32, u32),
+    pixel_seed: u64,
+    index: u32,
+    dimension: u64,
+}
+
+impl StratifiedSampler {
+    pub fn new(seed: u64, nb_samples: usize) -> StratifiedSampler {
+        let nb_samples = nb_samples.max(1) as u32;
+        // Largest factorization of the number of samples
+        let nx = (1..=(nb_samples as f32).sqrt() as u32)
+            .rev()
+            .find(|nx| nb_samples / nx * nx == nb_samples)
+            .unwrap();
+        StratifiedSampler {
+            seed,
+            nb_samples,
+            grid: (nx, nb_samples / nx),
+            pixel_seed: pixel_seed(seed, Point2::new(0, 0)),
+            index: 0,
+            dimension: 0,
+        }
+    }
+
+    // Hash of the current dimension
+    // (changes every nb_samples to support more samples)
+    fn next_dimension(&mut self) -> u64 {
+        self.dimension += 1;
+        hash_combine(
+            hash_combine(self.pixel_seed, self.dimension),
+            u64::from(self.index / self.nb_samples),
+        )
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn next(&mut self) -> f32 {
+        let n = self.nb_samples;
+        let h = self.next_dimension();
+        let stratum = permute(self.index % n, n, h as u32);
+        let jitter = hash_float(hash_combine(h, u64::from(self.index)));
+        ((stratum as f32 + jitter) / n as f32).min(1.0 - f32::EPSILON)
+    }
+    fn next2d(&mut self) -> Point2<f32> {
+        let n = self.nb_samples;
+        let h = self.next_dimension();
+        let jitter_x = hash_float(hash_combine(h, u64::from(self.index)));
+        let jitter_y = hash_float(hash_combine(h, u64::from(self.index) + u64::from(n)));
+        let (sx, sy, nx, ny) = match self.grid {
+            (1, _) if n > 1 => (
+                permute(self.index % n, n, h as u32),
+                permute(self.index % n, n, (h >> 32) as u32),
+                n,
+                n,
+            ),
+            (nx, ny) => {
+                let stratum = permute(self.index % n, n, h as u32);
+                (stratum % nx, stratum / nx, nx, ny)
+            }
+        };
+        Point2::new(
+            ((sx as f32 + jitter_x) / nx as f32).min(1.0 - f32::EPSILON),
+            ((sy as f32 + jitter_y) / ny as f32).min(1.0 - f32::EPSILON),
+        )
+    }
+    fn start_sample(&mut self, pixel: Point2<u32>, index: usize) {
+        self.pixel_seed = pixel_seed(self.seed, pixel);
+        self.index = index as u32;
+        self.dimension = 0;
+    }
+}
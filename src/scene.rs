@@ -1,22 +1,125 @@
+use crate::accel::*;
 use crate::camera::Camera;
 use crate::emitter::*;
 use crate::geometry;
+use crate::integrators::adaptive::AdaptiveSampling;
+use crate::integrators::aovs::AOV;
+use crate::integrators::film::Filter;
 use crate::math::Distribution1DConstruct;
 use crate::math::Frame;
+use crate::samplers::{hash_combine, Sampler, SamplerType};
+use crate::scene_description::*;
 use crate::structure::*;
 use crate::volume;
 use cgmath::*;
+use std::sync::atomic::{AtomicU64, Ordering};
 
 pub trait Acceleration: Sync + Send {
     fn trace(&self, ray: &Ray) -> Option<Intersection>;
     fn visible(&self, p0: &Point3<f32>, p1: &Point3<f32>) -> bool;
 }
 
+/// Acceleration data structure used to intersect the scene
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AccelerationType {
+    /// Embree kernels (need the "embree" feature)
+    Embree,
+    /// Pure Rust BVH (see accel.rs)
+    BVH,
+}
+
+impl Default for AccelerationType {
+    fn default() -> Self {
+        if cfg!(feature = "embree") {
+            AccelerationType::Embree
+        } else {
+            AccelerationType::BVH
+        }
+    }
+}
+
+/// Build the intersection information from a triangle hit
+/// u, v are the barycentric coordinates of the hit point
+/// n_g is the geometric normal (not necessary normalized)
+fn triangle_intersection<'a>(
+    meshes: &'a [geometry::Mesh],
+    mesh_id: usize,
+    prim_id: usize,
+    ray: &Ray,
+    t: f32,
+    u: f32,
+    v: f32,
+    mut n_g: Vector3<f32>,
+) -> Intersection<'a> {
+    let mesh = &meshes[mesh_id];
+    let index = mesh.indices[prim_id];
+
+    // The geometric normal is not normalized...
+    let n_g_dot = n_g.dot(n_g);
+    if n_g_dot != 1.0 {
+        n_g /= n_g_dot.sqrt();
+    }
+
+    let n_s = if let Some(ref normals) = mesh.normals {
+        let d0 = &normals[index.x];
+        let d1 = &normals[index.y];
+        let d2 = &normals[index.z];
+        let mut n_s = d0 * (1.0 - u - v) + d1 * u + d2 * v;
+        if n_g.dot(n_s) < 0.0 {
+            n_s = -n_s;
+        }
+        n_s
+    } else {
+        n_g
+    };
+
+    // TODO: Hack for now for make automatic twosided.
+    // The normal is kept for media boundaries to know the inside of the mesh
+    let (n_s, n_g) = if mesh.bsdf.is_twosided()
+        && mesh.emission.is_zero()
+        && !mesh.have_media()
+        && ray.d.dot(n_s) > 0.0
+    {
+        (
+            Vector3::new(-n_s.x, -n_s.y, -n_s.z),
+            Vector3::new(-n_g.x, -n_g.y, -n_g.z),
+        )
+    } else {
+        (n_s, n_g)
+    };
+
+    // UV interpolation
+    let uv = if let Some(ref uv_data) = mesh.uv {
+        let d0 = &uv_data[index.x];
+        let d1 = &uv_data[index.y];
+        let d2 = &uv_data[index.z];
+        Some(d0 * (1.0 - u - v) + d1 * u + d2 * v)
+    } else {
+        None
+    };
+
+    let frame = Frame::new(n_s);
+    let wi = frame.to_local(-ray.d);
+    Intersection {
+        dist: t,
+        n_g,
+        n_s,
+        p: ray.o + ray.d * t,
+        uv,
+        mesh,
+        mesh_id,
+        frame,
+        wi,
+    }
+}
+
+#[cfg(feature = "embree")]
 pub struct EmbreeAcceleration<'a, 'scene> {
     pub scene: &'a Scene,
     pub rtscene: embree_rs::CommittedScene<'scene>,
 }
 
+#[cfg(feature = "embree")]
 impl<'a, 'scene> EmbreeAcceleration<'a, 'scene> {
     pub fn new(
         scene: &'a Scene,
@@ -29,6 +132,7 @@ impl<'a, 'scene> EmbreeAcceleration<'a, 'scene> {
     }
 }
 
+#[cfg(feature = "embree")]
 impl<'a, 'scene> Acceleration for EmbreeAcceleration<'a, 'scene> {
     fn trace(&self, ray: &Ray) -> Option<Intersection> {
         let mut intersection_ctx = embree_rs::IntersectContext::coherent();
@@ -41,73 +145,16 @@ impl<'a, 'scene> Acceleration for EmbreeAcceleration<'a, 'scene> {
         let mut ray_hit = embree_rs::RayHit::new(embree_ray);
         self.rtscene.intersect(&mut intersection_ctx, &mut ray_hit);
         if ray_hit.hit.hit() {
-            let mesh = &self.scene.meshes[ray_hit.hit.geomID as usize];
-            let index = mesh.indices[ray_hit.hit.primID as usize];
-
-            // Retrive the mesh
-            // The geometric normal is not normalized...
-            let mut n_g = Vector3::new(ray_hit.hit.Ng_x, ray_hit.hit.Ng_y, ray_hit.hit.Ng_z);
-            let n_g_dot = n_g.dot(n_g);
-            if n_g_dot != 1.0 {
-                n_g /= n_g_dot.sqrt();
-            }
-
-            let n_s = if let Some(ref normals) = mesh.normals {
-                let d0 = &normals[index.x];
-                let d1 = &normals[index.y];
-                let d2 = &normals[index.z];
-                let mut n_s = d0 * (1.0 - ray_hit.hit.u - ray_hit.hit.v)
-                    + d1 * ray_hit.hit.u
-                    + d2 * ray_hit.hit.v;
-                if n_g.dot(n_s) < 0.0 {
-                    n_s = -n_s;
-                }
-                n_s
-            } else {
-                n_g
-            };
-
-            // TODO: Hack for now for make automatic twosided.
-            let (n_s, n_g) =
-                if mesh.bsdf.is_twosided() && mesh.emission.is_zero() && ray.d.dot(n_s) > 0.0 {
-                    (
-                        Vector3::new(-n_s.x, -n_s.y, -n_s.z),
-                        Vector3::new(-n_g.x, -n_g.y, -n_g.z),
-                    )
-                } else {
-                    (n_s, n_g)
-                };
-
-            // UV interpolation
-            let uv = if let Some(ref uv_data) = mesh.uv {
-                let d0 = &uv_data[index.x];
-                let d1 = &uv_data[index.y];
-                let d2 = &uv_data[index.z];
-                Some(
-                    d0 * (1.0 - ray_hit.hit.u - ray_hit.hit.v)
-                        + d1 * ray_hit.hit.u
-                        + d2 * ray_hit.hit.v,
-                )
-            } else {
-                None
-            };
-
-            let frame = Frame::new(n_s);
-            let wi = frame.to_local(-ray.d);
-            Some(Intersection {
-                dist: ray_hit.ray.tfar,
-                n_g,
-                n_s,
-                p: Point3::new(
-                    ray_hit.ray.org_x + ray_hit.ray.tfar * ray_hit.ray.dir_x,
-                    ray_hit.ray.org_y + ray_hit.ray.tfar * ray_hit.ray.dir_y,
-                    ray_hit.ray.org_z + ray_hit.ray.tfar * ray_hit.ray.dir_z,
-                ),
-                uv,
-                mesh,
-                frame,
-                wi,
-            })
+            Some(triangle_intersection(
+                &self.scene.meshes,
+                ray_hit.hit.geomID as usize,
+                ray_hit.hit.primID as usize,
+                ray,
+                ray_hit.ray.tfar,
+                ray_hit.hit.u,
+                ray_hit.hit.v,
+                Vector3::new(ray_hit.hit.Ng_x, ray_hit.hit.Ng_y, ray_hit.hit.Ng_z),
+            ))
         } else {
             None
         }
@@ -125,6 +172,186 @@ impl<'a, 'scene> Acceleration for EmbreeAcceleration<'a, 'scene> {
     }
 }
 
+/// Triangle stored inside the BVH
+/// (keep the mesh and primitive ID to retrieve the shading information)
+pub struct Triangle {
+    pub mesh_id: usize,
+    pub prim_id: usize,
+    p0: Point3<f32>,
+    e1: Vector3<f32>,
+    e2: Vector3<f32>,
+}
+
+/// Ray/triangle hit information (distance and barycentric coordinates)
+pub struct TriangleHit {
+    pub t: f32,
+    pub u: f32,
+    pub v: f32,
+}
+
+impl BVHHit for TriangleHit {
+    fn t(&self) -> f32 {
+        self.t
+    }
+}
+
+impl BVHElement<TriangleHit> for Triangle {
+    fn aabb(&self) -> AABB {
+        let p0 = self.p0.to_vec();
+        AABB::default()
+            .union_vec(&p0)
+            .union_vec(&(p0 + self.e1))
+            .union_vec(&(p0 + self.e2))
+    }
+    fn position(&self) -> Point3<f32> {
+        self.p0 + (self.e1 + self.e2) / 3.0
+    }
+    // Moller-Trumbore intersection
+    fn intersection(&self, r: &Ray) -> Option<TriangleHit> {
+        let p = r.d.cross(self.e2);
+        let det = self.e1.dot(p);
+        if det == 0.0 {
+            return None;
+        }
+        let inv_det = 1.0 / det;
+
+        let t_vec = r.o - self.p0;
+        let u = t_vec.dot(p) * inv_det;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+
+        let q = t_vec.cross(self.e1);
+        let v = r.d.dot(q) * inv_det;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+
+        let t = self.e2.dot(q) * inv_det;
+        if t < r.tnear || t > r.tfar {
+            return None;
+        }
+        Some(TriangleHit { t, u, v })
+    }
+}
+
+/// Pure Rust acceleration structure (BVH over all the scene triangles)
+pub struct BVHAcceleration<'a> {
+    pub scene: &'a Scene,
+    pub bvh: BHVAccel<TriangleHit, Triangle>,
+}
+
+impl<'a> BVHAcceleration<'a> {
+    pub fn new(scene: &'a Scene) -> BVHAcceleration<'a> {
+        let mut triangles = vec![];
+        for (mesh_id, m) in scene.meshes.iter().enumerate() {
+            for (prim_id, index) in m.indices.iter().enumerate() {
+                let p0 = Point3::from_vec(m.vertices[index.x]);
+                triangles.push(Triangle {
+                    mesh_id,
+                    prim_id,
+                    p0,
+                    e1: m.vertices[index.y] - m.vertices[index.x],
+                    e2: m.vertices[index.z] - m.vertices[index.x],
+                });
+            }
+        }
+        BVHAcceleration {
+            scene,
+            bvh: BHVAccel::create(triangles),
+        }
+    }
+}
+
+impl<'a> Acceleration for BVHAcceleration<'a> {
+    fn trace(&self, ray: &Ray) -> Option<Intersection> {
+        let (hit, id) = self.bvh.closest(ray)?;
+        let tri = &self.bvh.elements[id];
+        Some(triangle_intersection(
+            &self.scene.meshes,
+            tri.mesh_id,
+            tri.prim_id,
+            ray,
+            hit.t,
+            hit.u,
+            hit.v,
+            tri.e1.cross(tri.e2),
+        ))
+    }
+    fn visible(&self, p0: &Point3<f32>, p1: &Point3<f32>) -> bool {
+        let mut d = p1 - p0;
+        let length = d.magnitude();
+        d /= length;
+        let mut ray = Ray::new(*p0, d);
+        ray.tnear = 0.00001;
+        ray.tfar = length - 0.00001;
+        !self.bvh.any_hit(&ray)
+    }
+}
+
+/// Rectangle of pixels of the image: [pos, pos + size[
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ImageRegion {
+    pub pos: Point2<u32>,
+    pub size: Vector2<u32>,
+}
+
+impl ImageRegion {
+    /// Region between the pixels min (included) and max (excluded)
+    pub fn new(min: Point2<u32>, max: Point2<u32>) -> ImageRegion {
+        assert!(min.x < max.x && min.y < max.y);
+        ImageRegion {
+            pos: min,
+            size: max - min,
+        }
+    }
+
+    pub fn max(&self) -> Point2<u32> {
+        self.pos + self.size
+    }
+
+    /// Is the film position p (in pixels) inside the region
+    pub fn contains(&self, p: Point2<f32>) -> bool {
+        let max = self.max();
+        p.x >= self.pos.x as f32
+            && p.y >= self.pos.y as f32
+            && p.x < max.x as f32
+            && p.y < max.y as f32
+    }
+
+    pub fn nb_pixels(&self) -> usize {
+        (self.size.x * self.size.y) as usize
+    }
+}
+
+/// Format: "x0 y0 x1 y1" (pixels, x1 and y1 excluded)
+impl std::fmt::Display for ImageRegion {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let max = self.max();
+        write!(f, "{} {} {} {}", self.pos.x, self.pos.y, max.x, max.y)
+    }
+}
+
+impl std::str::FromStr for ImageRegion {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let v = s
+            .split_whitespace()
+            .map(|v| v.parse::<u32>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("Wrong image region {}: {}", s, e))?;
+        match v[..] {
+            [x0, y0, x1, y1] if x0 < x1 && y0 < y1 => {
+                Ok(ImageRegion::new(Point2::new(x0, y0), Point2::new(x1, y1)))
+            }
+            _ => Err(format!(
+                "Wrong image region {}: expected x0 y0 x1 y1 with x0 < x1 and y0 < y1",
+                s
+            )),
+        }
+    }
+}
+
 /// Scene representation
 pub struct Scene {
     /// Main camera
@@ -135,7 +362,30 @@ pub struct Scene {
     // Geometry information
     pub meshes: Vec<geometry::Mesh>,
     pub emitter_environment: Option<EnvironmentLight>,
+    /// Analytic emitters (point, spot and directional lights)
+    pub emitters: Vec<Box<dyn Emitter>>,
     pub volume: Option<volume::HomogenousVolume>,
+    /// Media attached to the meshes (see Mesh::interior/exterior)
+    pub media: Vec<Box<dyn volume::Medium>>,
+    /// Acceleration structure used for ray intersection
+    pub acceleration: AccelerationType,
+    /// Sampler used by the Monte Carlo integrators
+    pub sampler: SamplerType,
+    /// Pixel reconstruction filter
+    pub filter: Filter,
+    /// Auxiliary outputs written by the integrators (see IntegratorMC::compute_aovs)
+    pub aovs: Vec<AOV>,
+    /// Adaptive sampling of the Monte Carlo integrators (None: uniform sampling)
+    pub adaptive: Option<AdaptiveSampling>,
+    /// Seed of the random numbers (see Scene::pass_seed)
+    pub seed: u64,
+    /// Region of the image rendered (None: the full image)
+    /// the image keeps its size, the pixels outside the crop are black
+    pub crop: Option<ImageRegion>,
+    // Number of rendering passes done with this scene
+    pub(crate) passes: AtomicU64,
+    /// Scene file content (None if not loaded from a JSON file)
+    pub description: Option<SceneDescription>,
 }
 
 impl Scene {
@@ -151,6 +401,82 @@ impl Scene {
         self.nb_samples = n;
         self
     }
+    pub fn acceleration(mut self, a: AccelerationType) -> Self {
+        self.acceleration = a;
+        self
+    }
+    pub fn sampler(mut self, s: SamplerType) -> Self {
+        self.sampler = s;
+        self
+    }
+    pub fn filter(mut self, f: Filter) -> Self {
+        self.filter = f;
+        self
+    }
+    pub fn aovs(mut self, aovs: Vec<AOV>) -> Self {
+        self.aovs = aovs;
+        self
+    }
+    pub fn adaptive(mut self, adaptive: Option<AdaptiveSampling>) -> Self {
+        self.adaptive = adaptive;
+        self
+    }
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+    /// Scene file content with the current image size, medium and rendering settings
+    /// The loaded objects (meshes, BSDFs, emitters) are not serialized back:
+    /// the description parsed from the JSON file is patched instead
+    /// so None is returned for the other scene formats (e.g., pbrt)
+    pub fn to_description(&self) -> Option<SceneDescription> {
+        let mut desc = self.description.clone()?;
+        desc.camera.img = *self.camera.film_size();
+        desc.medium = self.volume.as_ref().map(HomogeneousDescription::from);
+        desc.render = Some(RenderDescription {
+            samples: Some(self.nb_samples),
+            sampler: Some(self.sampler),
+            filter: Some(self.filter),
+            seed: Some(self.seed),
+        });
+        Some(desc)
+    }
+    pub fn crop(mut self, crop: Option<ImageRegion>) -> Self {
+        if let Some(ref crop) = crop {
+            let size = self.camera.size();
+            assert!(
+                crop.max().x <= size.x && crop.max().y <= size.y,
+                "the crop {} is outside the image ({}x{})",
+                crop,
+                size.x,
+                size.y
+            );
+        }
+        self.crop = crop;
+        self
+    }
+
+    /// Pixels rendered: the crop or the full image
+    pub fn image_region(&self) -> ImageRegion {
+        match self.crop {
+            Some(crop) => crop,
+            None => ImageRegion {
+                pos: Point2::new(0, 0),
+                size: *self.camera.size(),
+            },
+        }
+    }
+
+    /// Seed of a new rendering pass (one per call to Integrator::compute)
+    /// The integrators derive the seeds of their blocks from it
+    /// so the image only depends on the scene seed (not on the number of threads)
+    pub fn pass_seed(&self) -> u64 {
+        hash_combine(self.seed, self.passes.fetch_add(1, Ordering::SeqCst))
+    }
+    /// Skip the rendering passes already done (resume from a checkpoint)
+    pub fn skip_passes(&self, n: u64) {
+        self.passes.fetch_add(n, Ordering::SeqCst);
+    }
 
     pub fn emitters_sampler(&self) -> EmitterSampler {
         // Append emission mesh to the emitter list
@@ -160,6 +486,12 @@ impl Scene {
                 emitters.push(e)
             }
         }
+        for e in &self.emitters {
+            emitters.push(e.as_ref());
+        }
+        if let Some(ref env) = self.emitter_environment {
+            emitters.push(env);
+        }
         // Construct the CDF for all the emitters
         let emitters_cdf = {
             let mut cdf_construct = Distribution1DConstruct::new(emitters.len());
@@ -176,10 +508,97 @@ impl Scene {
         }
     }
 
+    /// Get the medium from its index (None is the scene medium)
+    pub fn medium(&self, id: Option<usize>) -> Option<&dyn volume::Medium> {
+        match id {
+            None => self.volume.as_ref().map(|m| m as &dyn volume::Medium),
+            Some(id) => Some(self.media[id].as_ref()),
+        }
+    }
+
+    /// Medium traversed when leaving p toward d
+    /// (given by the next media boundary along the ray)
+    pub fn medium_at(
+        &self,
+        accel: &dyn Acceleration,
+        p: &Point3<f32>,
+        d: &Vector3<f32>,
+    ) -> Option<usize> {
+        if self.media.is_empty() {
+            return None;
+        }
+        let mut ray = Ray::new(*p, *d);
+        while let Some(its) = accel.trace(&ray) {
+            if its.mesh.have_media() {
+                return its.medium(&-*d);
+            }
+            ray = Ray::new(its.p, *d);
+        }
+        None
+    }
+
+    /// Transmittance between two points (through the media boundaries)
+    /// Return zero if the two points are not visible.
+    pub fn transmittance(
+        &self,
+        accel: &dyn Acceleration,
+        p0: &Point3<f32>,
+        p1: &Point3<f32>,
+        mut medium: Option<usize>,
+        sampler: &mut dyn Sampler,
+    ) -> Color {
+        if self.media.is_empty() {
+            // Only the scene medium, no boundaries to cross
+            if !accel.visible(p0, p1) {
+                return Color::zero();
+            }
+            return match self.volume {
+                None => Color::one(),
+                Some(ref m) => {
+                    let d = p1 - p0;
+                    let mut ray = Ray::new(*p0, d.normalize());
+                    ray.tfar = d.magnitude();
+                    m.transmittance(ray)
+                }
+            };
+        }
+
+        let mut tr = Color::one();
+        let mut p = *p0;
+        loop {
+            let mut d = p1 - p;
+            let length = d.magnitude();
+            d /= length;
+            let mut ray = Ray::new(p, d);
+            ray.tnear = 0.00001;
+            ray.tfar = length - 0.00001;
+            let its = accel.trace(&ray);
+            if let Some(ref its) = its {
+                if !its.mesh.bsdf.is_null() {
+                    return Color::zero();
+                }
+                ray.tfar = its.dist;
+            }
+            if let Some(m) = self.medium(medium) {
+                tr *= m.eval_transmittance(&ray, sampler);
+                if tr.is_zero() {
+                    return tr;
+                }
+            }
+            match its {
+                None => return tr,
+                Some(its) => {
+                    medium = its.medium(&d);
+                    p = its.p;
+                }
+            }
+        }
+    }
+
     pub fn enviroment_luminance(&self, d: Vector3<f32>) -> Color {
         match self.emitter_environment {
             None => Color::zero(),
-            Some(ref env) => env.emitted_luminance(d),
+            Some(ref env) => env.eval(d),
         }
     }
 }
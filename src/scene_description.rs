@@ -0,0 +1,635 @@
+use crate::bsdfs::dielectric::BSDFDielectric;
+use crate::bsdfs::diffuse::BSDFDiffuse;
+use crate::bsdfs::distribution::*;
+use crate::bsdfs::metal::BSDFMetal;
+use crate::bsdfs::null::BSDFNull;
+use crate::bsdfs::phong::BSDFPhong;
+use crate::bsdfs::specular::BSDFSpecular;
+use crate::bsdfs::substrate::BSDFSubstrate;
+use crate::bsdfs::{BSDFColor, BSDF};
+use crate::camera::*;
+use crate::emitter::*;
+use crate::integrators::film::Filter;
+use crate::samplers::SamplerType;
+use crate::structure::*;
+use crate::volume::*;
+use cgmath::*;
+use std::collections::BTreeMap;
+use std::error::Error;
+
+/// Version of the scene files written and read by this version of rustlight
+pub const SCENE_VERSION: u32 = 1;
+
+/// Typed description of a JSON scene file
+/// the names (textures, materials and media) are referenced by the other entries
+/// and the file paths are relative to the scene file
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct SceneDescription {
+    /// Format version (see SCENE_VERSION)
+    pub version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub render: Option<RenderDescription>,
+    pub camera: CameraDescription,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub textures: BTreeMap<String, TextureDescription>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub materials: BTreeMap<String, MaterialDescription>,
+    /// Media bounded by the shapes (see ObjDescription::interior)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub media: BTreeMap<String, MediumDescription>,
+    pub shapes: Vec<ShapeDescription>,
+    /// Analytic and environment emitters (the shapes can also emit light)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub emitters: Vec<EmitterDescription>,
+    /// Infinite medium filling the scene
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub medium: Option<HomogeneousDescription>,
+}
+
+/// Default rendering settings (the command line options override them)
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+#[serde(deny_unknown_fields)]
+pub struct RenderDescription {
+    /// Number of samples per pixel
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub samples: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sampler: Option<SamplerType>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filter: Option<Filter>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+}
+
+/// Elementary transformation (a list of them is applied in order)
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum TransformDescription {
+    /// 16 values given column by column
+    Matrix([f32; 16]),
+    Translate(Vector3<f32>),
+    /// Rotation around the axis (in degrees)
+    Rotate {
+        axis: Vector3<f32>,
+        angle: f32,
+    },
+    Scale(Vector3<f32>),
+    /// Place the local space at the origin, looking at the target (see camera::look_at)
+    LookAt {
+        origin: Point3<f32>,
+        target: Point3<f32>,
+        #[serde(default = "default_up")]
+        up: Vector3<f32>,
+    },
+}
+
+fn default_up() -> Vector3<f32> {
+    Vector3::new(0.0, 1.0, 0.0)
+}
+
+impl TransformDescription {
+    pub fn matrix(&self) -> Result<Matrix4<f32>, String> {
+        match self {
+            TransformDescription::Matrix(m) => Ok(Matrix4::new(
+                m[0], m[1], m[2], m[3], m[4], m[5], m[6], m[7], m[8], m[9], m[10], m[11], m[12],
+                m[13], m[14], m[15],
+            )),
+            TransformDescription::Translate(t) => Ok(Matrix4::from_translation(*t)),
+            TransformDescription::Rotate { axis, angle } => {
+                if axis.magnitude2() == 0.0 {
+                    return Err("null rotation axis".to_string());
+                }
+                Ok(Matrix4::from_axis_angle(axis.normalize(), Deg(*angle)))
+            }
+            TransformDescription::Scale(s) => Ok(Matrix4::from_nonuniform_scale(s.x, s.y, s.z)),
+            TransformDescription::LookAt { origin, target, up } => look_at(*origin, *target, *up)
+                .ok_or_else(|| {
+                    "the view direction is null or collinear with the up vector".to_string()
+                }),
+        }
+    }
+}
+
+/// Composition of the transformations (the first one is applied first)
+pub fn transform_matrix(transform: &[TransformDescription]) -> Result<Matrix4<f32>, String> {
+    transform
+        .iter()
+        .try_fold(Matrix4::identity(), |m, t| Ok(t.matrix()? * m))
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct CameraDescription {
+    /// Image size (in pixels)
+    pub img: Vector2<u32>,
+    /// Camera to world
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub transform: Vec<TransformDescription>,
+    pub sensor: SensorDescription,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub near_clip: Option<f32>,
+    /// Normalized [x0, x1, y0, y1] as pbrt
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub crop_window: Option<[f32; 4]>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum SensorDescription {
+    Perspective {
+        /// Field of view (in degrees) along fov_axis
+        fov: f32,
+        #[serde(default)]
+        fov_axis: FovAxis,
+        /// Depth of field (pinhole otherwise)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        thin_lens: Option<ThinLensDescription>,
+    },
+    /// The film covers [-scale, scale] along its smallest axis
+    Orthographic {
+        #[serde(default = "default_one")]
+        scale: f32,
+    },
+    Equirectangular {},
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ThinLensDescription {
+    pub lens_radius: f32,
+    pub focus_distance: f32,
+}
+
+fn default_one() -> f32 {
+    1.0
+}
+
+impl SensorDescription {
+    pub fn build(&self, img: Vector2<u32>) -> Result<Box<dyn Sensor>, String> {
+        match self {
+            SensorDescription::Perspective {
+                fov,
+                fov_axis,
+                thin_lens,
+            } => {
+                if *fov <= 0.0 || *fov >= 180.0 {
+                    return Err("the field of view needs to be inside ]0, 180[".to_string());
+                }
+                let mut sensor = PerspectiveSensor::new(img, *fov, *fov_axis);
+                if let Some(lens) = thin_lens {
+                    if lens.lens_radius < 0.0 {
+                        return Err("negative lens radius".to_string());
+                    }
+                    if lens.focus_distance <= 0.0 {
+                        return Err("the focus distance needs to be positive".to_string());
+                    }
+                    sensor = sensor.thin_lens(lens.lens_radius, lens.focus_distance);
+                }
+                Ok(Box::new(sensor))
+            }
+            SensorDescription::Orthographic { scale } => {
+                if *scale <= 0.0 {
+                    return Err("the scale needs to be positive".to_string());
+                }
+                Ok(Box::new(OrthographicSensor::new(img, *scale)))
+            }
+            SensorDescription::Equirectangular {} => Ok(Box::new(EquirectangularSensor)),
+        }
+    }
+}
+
+/// Image file (loaded once, shared by the materials)
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct TextureDescription {
+    pub file: String,
+}
+
+/// Material color: {"uniform": {"r": .., "g": .., "b": ..}} or {"texture": "name"}
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum ColorDescription {
+    Uniform(Color),
+    Texture(String),
+}
+
+fn default_white() -> ColorDescription {
+    ColorDescription::Uniform(Color::one())
+}
+fn default_int_ior() -> f32 {
+    crate::bsdfs::dielectric::DEFAULT_INT_IOR
+}
+fn default_ext_ior() -> f32 {
+    crate::bsdfs::dielectric::DEFAULT_EXT_IOR
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum MaterialDescription {
+    Diffuse {
+        diffuse: ColorDescription,
+    },
+    Phong {
+        specular: ColorDescription,
+        exponent: f32,
+    },
+    Specular {
+        specular: ColorDescription,
+    },
+    Dielectric {
+        #[serde(default = "default_white")]
+        specular_reflectance: ColorDescription,
+        #[serde(default = "default_white")]
+        specular_transmittance: ColorDescription,
+        #[serde(default = "default_int_ior")]
+        int_ior: f32,
+        #[serde(default = "default_ext_ior")]
+        ext_ior: f32,
+    },
+    Metal {
+        #[serde(default = "default_white")]
+        specular: ColorDescription,
+        eta: ColorDescription,
+        k: ColorDescription,
+        distribution: DistributionDescription,
+    },
+    Substrate {
+        diffuse: ColorDescription,
+        specular: ColorDescription,
+        distribution: DistributionDescription,
+    },
+    /// Invisible boundary of a medium
+    Null {},
+}
+
+impl MaterialDescription {
+    /// color converts the colors (e.g., to find the textures)
+    pub fn build(
+        &self,
+        color: impl Fn(&ColorDescription) -> Result<BSDFColor, String>,
+    ) -> Result<Box<dyn BSDF + Send + Sync>, String> {
+        Ok(match self {
+            MaterialDescription::Diffuse { diffuse } => Box::new(BSDFDiffuse {
+                diffuse: color(diffuse)?,
+            }),
+            MaterialDescription::Phong { specular, exponent } => Box::new(BSDFPhong {
+                specular: color(specular)?,
+                exponent: *exponent,
+            }),
+            MaterialDescription::Specular { specular } => Box::new(BSDFSpecular {
+                specular: color(specular)?,
+            }),
+            MaterialDescription::Dielectric {
+                specular_reflectance,
+                specular_transmittance,
+                int_ior,
+                ext_ior,
+            } => Box::new(BSDFDielectric {
+                specular_reflectance: color(specular_reflectance)?,
+                specular_transmittance: color(specular_transmittance)?,
+                int_ior: *int_ior,
+                ext_ior: *ext_ior,
+            }),
+            MaterialDescription::Metal {
+                specular,
+                eta,
+                k,
+                distribution,
+            } => Box::new(BSDFMetal {
+                specular: color(specular)?,
+                eta: color(eta)?,
+                k: color(k)?,
+                distribution: distribution.build(),
+            }),
+            MaterialDescription::Substrate {
+                diffuse,
+                specular,
+                distribution,
+            } => Box::new(BSDFSubstrate {
+                diffuse: color(diffuse)?,
+                specular: color(specular)?,
+                distribution: distribution.build(),
+            }),
+            MaterialDescription::Null {} => Box::new(BSDFNull {}),
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DistributionType {
+    Beckmann,
+    GGX,
+}
+
+/// Microfacet distribution (alpha_v is alpha_u if not provided)
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct DistributionDescription {
+    #[serde(rename = "type")]
+    pub distribution: DistributionType,
+    pub alpha_u: f32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alpha_v: Option<f32>,
+    #[serde(default = "default_true")]
+    pub sample_visible: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl DistributionDescription {
+    pub fn build(&self) -> Box<dyn MicrofacetDistribution> {
+        let alpha_v = self.alpha_v.unwrap_or(self.alpha_u);
+        match self.distribution {
+            DistributionType::Beckmann => Box::new(BeckmannDistribution::new(
+                self.alpha_u,
+                alpha_v,
+                self.sample_visible,
+            )),
+            DistributionType::GGX => Box::new(TrowbridgeReitzDistribution::new(
+                self.alpha_u,
+                alpha_v,
+                self.sample_visible,
+            )),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum PhaseDescription {
+    Isotropic {},
+    /// Henyey-Greenstein with g inside ]-1, 1[
+    HG {
+        g: f32,
+    },
+}
+
+impl Default for PhaseDescription {
+    fn default() -> Self {
+        PhaseDescription::Isotropic {}
+    }
+}
+
+impl PhaseDescription {
+    pub fn build(&self) -> Result<PhaseFunction, String> {
+        match *self {
+            PhaseDescription::Isotropic {} => Ok(PhaseFunction::Isotropic()),
+            PhaseDescription::HG { g } => henyey_greenstein(g),
+        }
+    }
+}
+
+impl From<&PhaseFunction> for PhaseDescription {
+    fn from(phase: &PhaseFunction) -> Self {
+        match *phase {
+            PhaseFunction::Isotropic() => PhaseDescription::Isotropic {},
+            PhaseFunction::HenyeyGreenstein(g) => PhaseDescription::HG { g },
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct HomogeneousDescription {
+    pub sigma_a: Color,
+    pub sigma_s: Color,
+    #[serde(default = "default_one")]
+    pub density: f32,
+    #[serde(default)]
+    pub phase: PhaseDescription,
+}
+
+impl HomogeneousDescription {
+    pub fn build(&self) -> Result<HomogenousVolume, String> {
+        Ok(HomogenousVolume {
+            sigma_a: self.sigma_a,
+            sigma_s: self.sigma_s,
+            sigma_t: self.sigma_a + self.sigma_s,
+            density: self.density,
+            phase: self.phase.build()?,
+        })
+    }
+}
+
+impl From<&HomogenousVolume> for HomogeneousDescription {
+    fn from(m: &HomogenousVolume) -> Self {
+        HomogeneousDescription {
+            sigma_a: m.sigma_a,
+            sigma_s: m.sigma_s,
+            density: m.density,
+            phase: PhaseDescription::from(&m.phase),
+        }
+    }
+}
+
+/// Density grid: {"file": "smoke.vol"} or {"inline": {"res": .., "values": [..]}}
+/// the bounding box (p_min, p_max) is required for the inlined grids
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum GridDescription {
+    File(String),
+    Inline {
+        res: Vector3<usize>,
+        values: Vec<f32>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct HeterogeneousDescription {
+    pub sigma_a: Color,
+    pub sigma_s: Color,
+    #[serde(default = "default_one")]
+    pub density: f32,
+    #[serde(default)]
+    pub phase: PhaseDescription,
+    pub grid: GridDescription,
+    /// Bounding box of the grid (overrides the file one)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub p_min: Option<Vector3<f32>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub p_max: Option<Vector3<f32>>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum MediumDescription {
+    Homogeneous(HomogeneousDescription),
+    Heterogeneous(HeterogeneousDescription),
+}
+
+impl MediumDescription {
+    /// wk is the directory of the scene file
+    pub fn build(&self, wk: &std::path::Path) -> Result<Box<dyn Medium>, Box<dyn Error>> {
+        let m = match self {
+            MediumDescription::Homogeneous(m) => return Ok(Box::new(m.build()?)),
+            MediumDescription::Heterogeneous(m) => m,
+        };
+        let mut grid = match m.grid {
+            GridDescription::File(ref filename) => VoxelGrid::load_vol(&wk.join(filename))?,
+            GridDescription::Inline { res, ref values } => {
+                if res.x * res.y * res.z != values.len() {
+                    return Err(format!(
+                        "Wrong number of grid values: {} (res: {:?})",
+                        values.len(),
+                        res
+                    )
+                    .into());
+                }
+                VoxelGrid::new(res, values.clone(), AABB::default())
+            }
+        };
+        match (m.p_min, m.p_max) {
+            (Some(p_min), Some(p_max)) => grid.aabb = AABB { p_min, p_max },
+            (None, None) => {}
+            _ => return Err("p_min and p_max need to be given together".into()),
+        }
+        if grid.aabb.surface_area() == 0.0 {
+            return Err("The heterogeneous medium bounding box is empty".into());
+        }
+        Ok(Box::new(HeterogeneousVolume {
+            sigma_a: m.sigma_a,
+            sigma_s: m.sigma_s,
+            sigma_t: m.sigma_a + m.sigma_s,
+            density: m.density,
+            grid,
+            phase: m.phase.build()?,
+        }))
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ShapeDescription {
+    Obj(ObjDescription),
+}
+
+/// Properties of the objects of an OBJ file (None: keep the OBJ file ones)
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ObjectDescription {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub material: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub emission: Option<Color>,
+    /// Media inside and outside the object
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interior: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exterior: Option<String>,
+}
+
+/// OBJ file where the properties are given for all the objects
+/// and can be changed for some of them (objects)
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ObjDescription {
+    pub file: String,
+    /// Object to world
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub transform: Vec<TransformDescription>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub material: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub emission: Option<Color>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interior: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exterior: Option<String>,
+    /// Per object properties (by name inside the OBJ file)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub objects: BTreeMap<String, ObjectDescription>,
+}
+
+impl ObjDescription {
+    /// Properties of an object (its own ones first)
+    pub fn object(&self, name: &str) -> ObjectDescription {
+        let o = self.objects.get(name).cloned().unwrap_or_default();
+        ObjectDescription {
+            material: o.material.or_else(|| self.material.clone()),
+            emission: o.emission.or(self.emission),
+            interior: o.interior.or_else(|| self.interior.clone()),
+            exterior: o.exterior.or_else(|| self.exterior.clone()),
+        }
+    }
+}
+
+fn default_cone_angle() -> f32 {
+    30.0
+}
+fn default_cone_delta_angle() -> f32 {
+    5.0
+}
+fn default_luminance() -> Color {
+    Color::one()
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum EmitterDescription {
+    Point {
+        position: Point3<f32>,
+        intensity: Color,
+    },
+    /// The angles are in degrees
+    Spot {
+        position: Point3<f32>,
+        direction: Vector3<f32>,
+        intensity: Color,
+        #[serde(default = "default_cone_angle")]
+        cone_angle: f32,
+        #[serde(default = "default_cone_delta_angle")]
+        cone_delta_angle: f32,
+    },
+    Directional {
+        direction: Vector3<f32>,
+        irradiance: Color,
+    },
+    /// Constant or lat-long map (scaled by the luminance)
+    /// rotated by the Euler angles (in degrees)
+    Environment {
+        #[serde(default = "default_luminance")]
+        luminance: Color,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        map: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        rotation: Option<Vector3<f32>>,
+    },
+}
+
+impl EmitterDescription {
+    /// Analytic emitters (the environment is built by the scene loader)
+    pub fn build(&self) -> Option<Box<dyn Emitter>> {
+        match *self {
+            EmitterDescription::Point {
+                position,
+                intensity,
+            } => Some(Box::new(PointLight {
+                position,
+                intensity,
+            })),
+            EmitterDescription::Spot {
+                position,
+                direction,
+                intensity,
+                cone_angle,
+                cone_delta_angle,
+            } => Some(Box::new(spot_light(
+                position,
+                direction,
+                intensity,
+                cone_angle,
+                cone_delta_angle,
+            ))),
+            EmitterDescription::Directional {
+                direction,
+                irradiance,
+            } => Some(Box::new(directional_light(direction, irradiance))),
+            EmitterDescription::Environment { .. } => None,
+        }
+    }
+}
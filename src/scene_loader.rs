@@ -1,21 +1,183 @@
 use crate::bsdfs;
 use crate::bsdfs::*;
-use crate::camera::Camera;
+use crate::camera::*;
 use crate::emitter::*;
 use crate::geometry;
+use crate::integrators::film::Filter;
+use crate::samplers::SamplerType;
 use crate::scene::*;
+use crate::scene_description::*;
 use crate::structure::*;
 use cgmath::*;
 #[cfg(feature = "pbrt")]
 use pbrt_rs;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
 use serde_json;
 use std::collections::HashMap;
 use std::error::Error;
 use std::io::Read;
 use std::rc::Rc;
+use std::sync::atomic::AtomicU64;
+
+// Bounds of all the meshes
+fn scene_aabb(meshes: &[geometry::Mesh]) -> AABB {
+    meshes.iter().fold(AABB::default(), |aabb, m| {
+        m.vertices.iter().fold(aabb, |aabb, v| aabb.union_vec(v))
+    })
+}
+
+// Image referenced by the scene
+fn read_bitmap(filename: &std::path::Path) -> Result<Bitmap, SceneLoadError> {
+    Bitmap::try_read(filename).map_err(|error| SceneLoadError::Io {
+        file: filename.to_string_lossy().to_string(),
+        error,
+    })
+}
+
+// Color of a light parameter (intensity or scale)
+#[cfg(feature = "pbrt")]
+fn pbrt_color(filename: &str, v: &pbrt_rs::Param) -> Result<Color, SceneLoadError> {
+    match v {
+        pbrt_rs::Param::Float(ref v) if v.len() == 1 => Ok(Color::value(v[0])),
+        pbrt_rs::Param::RGB(ref rgb) => Ok(Color::new(rgb.r, rgb.g, rgb.b)),
+        _ => Err(SceneLoadError::invalid(
+            filename,
+            "LightSource",
+            format!("unsupported light color: {:?}", v),
+        )),
+    }
+}
+
+/// Error raised when a scene description cannot be loaded
+#[derive(Debug)]
+pub enum SceneLoadError {
+    /// Impossible to read a file (scene or resource)
+    Io { file: String, error: std::io::Error },
+    /// No loader is registered for the file extension
+    UnknownFormat {
+        file: String,
+        extension: Option<String>,
+    },
+    /// The scene file is not a valid JSON document (or scene description)
+    Json {
+        file: String,
+        error: serde_json::Error,
+    },
+    /// The OBJ file referenced by the scene cannot be loaded
+    Obj {
+        file: String,
+        error: tobj::LoadError,
+    },
+    /// Missing or invalid entry in the scene description
+    /// path is the JSON pointer of the entry (e.g. "/bsdfs/2/mesh")
+    Invalid {
+        file: String,
+        path: String,
+        reason: String,
+    },
+}
+impl SceneLoadError {
+    fn invalid<T: std::fmt::Display>(file: &str, path: &str, reason: T) -> Self {
+        SceneLoadError::Invalid {
+            file: file.to_string(),
+            path: path.to_string(),
+            reason: reason.to_string(),
+        }
+    }
+}
+impl std::fmt::Display for SceneLoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SceneLoadError::Io { file, error } => write!(f, "{}: {}", file, error),
+            SceneLoadError::UnknownFormat {
+                file,
+                extension: None,
+            } => write!(f, "{}: no file extension provided", file),
+            SceneLoadError::UnknownFormat {
+                file,
+                extension: Some(ext),
+            } => write!(f, "{}: no scene loader for {} extension", file, ext),
+            SceneLoadError::Json { file, error } if error.is_data() => {
+                write!(f, "{}: invalid scene description: {}", file, error)
+            }
+            SceneLoadError::Json { file, error } => write!(f, "{}: invalid JSON: {}", file, error),
+            SceneLoadError::Obj { file, error } => {
+                write!(f, "{}: impossible to load the OBJ: {}", file, error)
+            }
+            SceneLoadError::Invalid { file, path, reason } => {
+                write!(f, "{}: {}: {}", file, path, reason)
+            }
+        }
+    }
+}
+impl Error for SceneLoadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SceneLoadError::Io { error, .. } => Some(error),
+            SceneLoadError::Json { error, .. } => Some(error),
+            SceneLoadError::Obj { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+// Find the entry responsible for a description error as the errors inside
+// the tagged entries (e.g., the materials) do not have a position in the file
+fn description_error(
+    file: &str,
+    v: &serde_json::Value,
+    error: serde_json::Error,
+) -> SceneLoadError {
+    fn check<T: DeserializeOwned>(v: &serde_json::Value, path: &str) -> Option<(String, String)> {
+        T::deserialize(v)
+            .err()
+            .map(|e| (path.to_string(), e.to_string()))
+    }
+    // Entries of an array or a map
+    fn check_entries<T: DeserializeOwned>(
+        v: &serde_json::Value,
+        path: &str,
+    ) -> Option<(String, String)> {
+        match v.pointer(path) {
+            Some(serde_json::Value::Array(a)) => a
+                .iter()
+                .enumerate()
+                .find_map(|(i, e)| check::<T>(e, &format!("{}/{}", path, i))),
+            Some(serde_json::Value::Object(m)) => m
+                .iter()
+                .find_map(|(k, e)| check::<T>(e, &format!("{}/{}", path, k))),
+            _ => None,
+        }
+    }
+    fn check_entry<T: DeserializeOwned>(
+        v: &serde_json::Value,
+        path: &str,
+    ) -> Option<(String, String)> {
+        v.pointer(path).and_then(|e| check::<T>(e, path))
+    }
+
+    let located = check_entry::<SensorDescription>(v, "/camera/sensor")
+        .or_else(|| check_entries::<TransformDescription>(v, "/camera/transform"))
+        .or_else(|| check_entry::<CameraDescription>(v, "/camera"))
+        .or_else(|| check_entry::<RenderDescription>(v, "/render"))
+        .or_else(|| check_entries::<TextureDescription>(v, "/textures"))
+        .or_else(|| check_entries::<MaterialDescription>(v, "/materials"))
+        .or_else(|| check_entries::<MediumDescription>(v, "/media"))
+        .or_else(|| check_entries::<ShapeDescription>(v, "/shapes"))
+        .or_else(|| check_entries::<EmitterDescription>(v, "/emitters"))
+        .or_else(|| check_entry::<HomogeneousDescription>(v, "/medium"));
+    match located {
+        Some((path, reason)) => SceneLoadError::invalid(file, &path, reason),
+        None => SceneLoadError::Json {
+            file: file.to_string(),
+            error,
+        },
+    }
+}
 
 pub trait SceneLoader {
-    fn load(&self, filename: &str) -> Result<Scene, Box<dyn Error>>;
+    fn load(&self, filename: &str) -> Result<Scene, SceneLoadError>;
 }
 pub struct SceneLoaderManager {
     loader: HashMap<String, Rc<dyn SceneLoader>>,
@@ -24,18 +186,16 @@ impl SceneLoaderManager {
     pub fn register(&mut self, name: &str, loader: Rc<dyn SceneLoader>) {
         self.loader.insert(name.to_string(), loader);
     }
-    pub fn load(&self, filename: String) -> Result<Scene, Box<dyn Error>> {
-        let filename_ext = match std::path::Path::new(&filename).extension() {
-            None => panic!("No file extension provided"),
-            Some(x) => std::ffi::OsStr::to_str(x).expect("Issue to unpack the file"),
-        };
-        if let Some(loader) = self.loader.get(filename_ext) {
-            loader.load(&filename)
-        } else {
-            panic!(
-                "Impossible to found scene loader for {} extension",
-                filename_ext
-            )
+    pub fn load(&self, filename: String) -> Result<Scene, SceneLoadError> {
+        let filename_ext = std::path::Path::new(&filename)
+            .extension()
+            .and_then(std::ffi::OsStr::to_str);
+        match filename_ext.and_then(|ext| self.loader.get(ext)) {
+            Some(loader) => loader.load(&filename),
+            None => Err(SceneLoadError::UnknownFormat {
+                file: filename.clone(),
+                extension: filename_ext.map(|ext| ext.to_string()),
+            }),
         }
     }
 }
@@ -52,105 +212,290 @@ impl Default for SceneLoaderManager {
     }
 }
 
+/// Scene file following SceneDescription
 pub struct JSONSceneLoader {}
 impl SceneLoader for JSONSceneLoader {
-    fn load(&self, filename: &str) -> Result<Scene, Box<dyn Error>> {
+    fn load(&self, filename: &str) -> Result<Scene, SceneLoadError> {
         // Reading the scene
         let scene_path = std::path::Path::new(filename);
-        let mut fscene = std::fs::File::open(scene_path).expect("scene file not found");
+        let io_error = |error| SceneLoadError::Io {
+            file: filename.to_string(),
+            error,
+        };
+        let mut fscene = std::fs::File::open(scene_path).map_err(io_error)?;
         let mut data = String::new();
-        fscene
-            .read_to_string(&mut data)
-            .expect("impossible to read the file");
+        fscene.read_to_string(&mut data).map_err(io_error)?;
         let wk = scene_path
             .parent()
-            .expect("impossible to extract parent directory for OBJ loading");
+            .unwrap_or_else(|| std::path::Path::new(""));
 
         // Read json string
-        let v: serde_json::Value = serde_json::from_str(&data)?;
+        let v: serde_json::Value =
+            serde_json::from_str(&data).map_err(|error| SceneLoadError::Json {
+                file: filename.to_string(),
+                error,
+            })?;
+
+        // The version is checked first as the other entries depend on it
+        match v.get("version") {
+            None => {
+                return Err(SceneLoadError::invalid(
+                    filename,
+                    "/version",
+                    format!("missing entry (current version: {})", SCENE_VERSION),
+                ))
+            }
+            Some(version) if version.as_u64() != Some(SCENE_VERSION as u64) => {
+                return Err(SceneLoadError::invalid(
+                    filename,
+                    "/version",
+                    format!(
+                        "unsupported version {} (current version: {})",
+                        version, SCENE_VERSION
+                    ),
+                ))
+            }
+            _ => {}
+        }
+        let desc = SceneDescription::deserialize(&v)
+            .map_err(|error| description_error(filename, &v, error))?;
+        let invalid = |path: &str, reason: String| SceneLoadError::invalid(filename, path, reason);
 
-        // Read the object
-        let obj_path_str: String = v["meshes"].as_str().unwrap().to_string();
-        let obj_path = wk.join(obj_path_str);
-        let mut meshes = geometry::load_obj(obj_path.as_path())?;
+        // - Textures (shared by the materials)
+        let mut textures = HashMap::new();
+        for (name, t) in &desc.textures {
+            let texture_filename = wk.join(&t.file);
+            if !texture_filename.is_file() {
+                return Err(invalid(
+                    &format!("/textures/{}", name),
+                    format!("file not found {:?}", texture_filename),
+                ));
+            }
+            textures.insert(name.as_str(), read_bitmap(&texture_filename)?);
+        }
+        let color = |c: &ColorDescription| match c {
+            ColorDescription::Uniform(c) => Ok(BSDFColor::UniformColor(*c)),
+            ColorDescription::Texture(name) => match textures.get(name.as_str()) {
+                Some(img) => Ok(BSDFColor::TextureColor(Texture { img: img.clone() })),
+                None => Err(format!("not found {} in the textures", name)),
+            },
+        };
+        // - Materials (built for each mesh using them)
+        for (name, m) in &desc.materials {
+            m.build(color)
+                .map_err(|e| invalid(&format!("/materials/{}", name), e))?;
+        }
 
-        // Update meshes information
-        //  - which are light?
+        // - Media (bounded by the meshes)
+        info!("Media:");
+        let mut media = vec![];
+        let mut media_ids = HashMap::new();
+        for (name, m) in &desc.media {
+            info!(" - medium: {}", name);
+            media_ids.insert(name.as_str(), media.len());
+            media.push(
+                m.build(wk)
+                    .map_err(|e| invalid(&format!("/media/{}", name), e.to_string()))?,
+            );
+        }
+        let medium_id = |name: &Option<String>| match name {
+            None => Ok(None),
+            Some(name) => match media_ids.get(name.as_str()) {
+                Some(id) => Ok(Some(*id)),
+                None => Err(format!("not found {} in the media", name)),
+            },
+        };
+
+        // - Shapes
+        //   the properties (material, emission and media) override the OBJ ones
         info!("Emitters:");
-        if let Some(emitters_json) = v.get("emitters") {
-            for e in emitters_json.as_array().unwrap() {
-                let name: String = e["mesh"].as_str().unwrap().to_string();
-                let emission: Color = serde_json::from_value(e["emission"].clone())?;
-                info!(" - emission: {}", name);
-                // Get the set of matched meshes
-                let mut matched_meshes = meshes
-                    .iter_mut()
-                    .filter(|m| m.name == name)
-                    .collect::<Vec<_>>();
-                match matched_meshes.len() {
-                    0 => panic!("Not found {} in the obj list", name),
-                    1 => {
-                        matched_meshes[0].emission = emission;
-                        info!("   * flux: {:?}", matched_meshes[0].flux());
-                    }
-                    _ => panic!("Several {} in the obj list", name),
-                };
+        let mut meshes = vec![];
+        for (i, shape) in desc.shapes.iter().enumerate() {
+            let path = format!("/shapes/{}", i);
+            let ShapeDescription::Obj(obj) = shape;
+            let obj_path = wk.join(&obj.file);
+            let obj_meshes = geometry::load_obj(obj_path.as_path())?;
+            if let Some(name) = obj
+                .objects
+                .keys()
+                .find(|name| !obj_meshes.iter().any(|m| &m.name == *name))
+            {
+                return Err(invalid(
+                    &format!("{}/objects/{}", path, name),
+                    format!("not found {} in {}", name, obj.file),
+                ));
+            }
+            let to_world = transform_matrix(&obj.transform)
+                .map_err(|e| invalid(&format!("{}/transform", path), e))?;
+            if to_world.determinant() == 0.0 {
+                return Err(invalid(
+                    &format!("{}/transform", path),
+                    "the transformation is not invertible".to_string(),
+                ));
+            }
+
+            for mut mesh in obj_meshes {
+                if !obj.transform.is_empty() {
+                    mesh.transform(&to_world);
+                }
+                let object = obj.object(&mesh.name);
+                let mesh_name = mesh.name.clone();
+                let object_error = |e: String| invalid(&path, format!("{}: {}", mesh_name, e));
+                if let Some(ref name) = object.material {
+                    mesh.bsdf = match desc.materials.get(name) {
+                        Some(m) => m.build(color).map_err(object_error)?,
+                        None => {
+                            return Err(object_error(format!(
+                                "not found {} in the materials",
+                                name
+                            )))
+                        }
+                    };
+                }
+                if let Some(emission) = object.emission {
+                    info!(" - emission: {}", mesh.name);
+                    mesh.emission = emission;
+                    info!("   * flux: {:?}", mesh.flux());
+                }
+                mesh.interior = medium_id(&object.interior).map_err(object_error)?;
+                mesh.exterior = medium_id(&object.exterior).map_err(object_error)?;
+                meshes.push(mesh);
             }
         }
-        // - BSDF
-        info!("BSDFS:");
-        if let Some(bsdfs_json) = v.get("bsdfs") {
-            for b in bsdfs_json.as_array().unwrap() {
-                let name: String = serde_json::from_value(b["mesh"].clone())?;
-                info!(" - replace bsdf: {}", name);
-                let new_bsdf = parse_bsdf(&b)?;
-                let mut matched_meshes = meshes
-                    .iter_mut()
-                    .filter(|m| m.name == name)
-                    .collect::<Vec<_>>();
-                match matched_meshes.len() {
-                    0 => panic!("Not found {} in the obj list", name),
-                    1 => {
-                        matched_meshes[0].bsdf = new_bsdf;
+
+        // - Medium (infinite homogenous)
+        let volume = match desc.medium {
+            Some(ref m) => {
+                let medium = m.build().map_err(|e| invalid("/medium", e))?;
+                info!("Medium:");
+                info!(" - sigma_a: {:?}", medium.sigma_a);
+                info!(" - sigma_s: {:?}", medium.sigma_s);
+                info!(" - phase: {:?}", medium.phase);
+                Some(medium)
+            }
+            None => None,
+        };
+
+        // - Emitters (analytic or environment)
+        let mut emitters = vec![];
+        let mut emitter_environment = None;
+        let aabb = scene_aabb(&meshes);
+        for (i, e) in desc.emitters.iter().enumerate() {
+            let path = format!("/emitters/{}", i);
+            if let EmitterDescription::Environment {
+                luminance,
+                map,
+                rotation,
+            } = e
+            {
+                if emitter_environment.is_some() {
+                    return Err(invalid(&path, "several environment emitters".to_string()));
+                }
+                let map = match map {
+                    Some(map_filename) => {
+                        let map_filename = wk.join(map_filename);
+                        if !map_filename.is_file() {
+                            return Err(invalid(
+                                &format!("{}/map", path),
+                                format!("file not found {:?}", map_filename),
+                            ));
+                        }
+                        info!("Environment map: {:?}", map_filename);
+                        let map = EnvironmentMap::new(read_bitmap(&map_filename)?)
+                            .map_err(|e| invalid(&format!("{}/map", path), e))?;
+                        Some(map)
                     }
-                    _ => panic!("Several {} in the obj list", name),
+                    None => None,
+                };
+                let to_world = match rotation {
+                    Some(r) => Matrix3::from(Euler::new(Deg(r.x), Deg(r.y), Deg(r.z))),
+                    None => Matrix3::identity(),
                 };
+                let mut env = EnvironmentLight {
+                    luminance: *luminance,
+                    map,
+                    to_world,
+                    world_radius: 1.0,
+                    world_position: Point3::origin(),
+                };
+                env.preprocess(&aabb);
+                emitter_environment = Some(env);
+            } else {
+                let mut emitter = e.build().unwrap();
+                emitter.preprocess(&aabb);
+                info!(" - light flux: {:?}", emitter.flux());
+                emitters.push(emitter);
             }
         }
 
         // Read the camera config
         let camera = {
-            if let Some(camera_json) = v.get("camera") {
-                let fov: f32 = serde_json::from_value(camera_json["fov"].clone())?;
-                let img: Vector2<u32> = serde_json::from_value(camera_json["img"].clone())?;
-                let m: Vec<f32> = serde_json::from_value(camera_json["matrix"].clone())?;
-
-                //let matrix = Matrix4::new(
-                //    m[0], m[4], m[8], m[12], m[1], m[5], m[9], m[13], m[2], m[6], m[10], m[14],
-                //    m[3], m[7], m[11], m[15],
-                //);
-                let matrix = Matrix4::new(
-                    m[0], m[1], m[2], m[3], m[4], m[5], m[6], m[7], m[8], m[9], m[10], m[11],
-                    m[12], m[13], m[14], m[15],
-                );
-
-                info!("m: {:?}", matrix);
-                Camera::new(img, fov, matrix)
-            } else {
-                panic!("The camera is not set!");
+            let c = &desc.camera;
+            let matrix =
+                transform_matrix(&c.transform).map_err(|e| invalid("/camera/transform", e))?;
+            info!("m: {:?}", matrix);
+            let sensor = c
+                .sensor
+                .build(c.img)
+                .map_err(|e| invalid("/camera/sensor", e))?;
+            let mut camera = Camera::with_sensor(c.img, sensor, matrix);
+            if let Some(near_clip) = c.near_clip {
+                if near_clip < 0.0 {
+                    return Err(invalid(
+                        "/camera/near_clip",
+                        "negative near clip distance".to_string(),
+                    ));
+                }
+                camera = camera.near_clip(near_clip);
+            }
+            if let Some(w) = c.crop_window {
+                let img = c.img;
+                let valid = w.iter().all(|v| (0.0..=1.0).contains(v))
+                    && w[0] < w[1]
+                    && w[2] < w[3]
+                    && (w[1] * img.x as f32).ceil() > (w[0] * img.x as f32).ceil()
+                    && (w[3] * img.y as f32).ceil() > (w[2] * img.y as f32).ceil();
+                if !valid {
+                    return Err(invalid(
+                        "/camera/crop_window",
+                        "expected x0 < x1 and y0 < y1 inside [0, 1] (covering one pixel at least)"
+                            .to_string(),
+                    ));
+                }
+                camera = camera.crop_window(Point2::new(w[0], w[2]), Point2::new(w[1], w[3]));
             }
+            camera
         };
         camera.print_info();
 
-        // Define a default scene
+        // - Rendering settings (the command line can override them)
+        let render = desc.render.clone().unwrap_or_default();
+        if render.samples == Some(0) {
+            return Err(invalid(
+                "/render/samples",
+                "the number of samples needs to be positive".to_string(),
+            ));
+        }
+
         Ok(Scene {
             camera,
             meshes,
-            nb_samples: 1,
+            nb_samples: render.samples.unwrap_or(1),
             nb_threads: None,
             output_img_path: "out.pfm".to_string(),
-            emitter_environment: None,
-            volume: None,
+            emitter_environment,
+            emitters,
+            volume,
+            media,
+            acceleration: AccelerationType::default(),
+            sampler: render.sampler.unwrap_or_default(),
+            filter: render.filter.unwrap_or_default(),
+            aovs: vec![],
+            adaptive: None,
+            crop: None,
+            seed: render.seed.unwrap_or_else(rand::random),
+            passes: AtomicU64::new(0),
+            description: Some(desc),
         })
     }
 }
@@ -159,10 +504,19 @@ impl SceneLoader for JSONSceneLoader {
 pub struct PBRTSceneLoader {}
 #[cfg(feature = "pbrt")]
 impl SceneLoader for PBRTSceneLoader {
-    fn load(&self, filename: &str) -> Result<Scene, Box<dyn Error>> {
+    fn load(&self, filename: &str) -> Result<Scene, SceneLoadError> {
+        // The PBRT parser panics on missing files
+        if let Err(error) = std::fs::metadata(filename) {
+            return Err(SceneLoadError::Io {
+                file: filename.to_string(),
+                error,
+            });
+        }
         let mut scene_info = pbrt_rs::Scene::default();
         let mut state = pbrt_rs::State::default();
-        let working_dir = std::path::Path::new(filename).parent().unwrap();
+        let working_dir = std::path::Path::new(filename)
+            .parent()
+            .unwrap_or_else(|| std::path::Path::new(""));
         pbrt_rs::read_pbrt_file(filename, &working_dir, &mut scene_info, &mut state);
 
         // Load the data
@@ -221,30 +575,80 @@ impl SceneLoader for PBRTSceneLoader {
 
         // Check if there is other emitter type
         let mut emitter_environment = None;
+        let mut emitters: Vec<Box<dyn Emitter>> = vec![];
         {
             let mut have_env = false;
             for l in scene_info.lights {
                 match l {
                     pbrt_rs::Light::Infinite(ref infinite) => {
-                        match infinite.luminance {
-                            pbrt_rs::Param::RGB(ref rgb) => {
-                                if have_env {
-                                    panic!("Multiple env map is NOT supported");
-                                }
-                                emitter_environment = Some(EnvironmentLight {
-                                    luminance: Color::new(rgb.r, rgb.g, rgb.b),
-                                    world_radius: 1.0, // TODO: Add the correct radius
-                                    world_position: Point3::new(0.0, 0.0, 0.0), // TODO:
-                                });
-                                have_env = true;
+                        if have_env {
+                            return Err(SceneLoadError::invalid(
+                                filename,
+                                "LightSource \"infinite\"",
+                                "multiple env map is NOT supported",
+                            ));
+                        }
+                        // TODO: The light transformation is not supported
+                        let (luminance, map) = match infinite.luminance {
+                            pbrt_rs::Param::RGB(ref rgb) => (Color::new(rgb.r, rgb.g, rgb.b), None),
+                            pbrt_rs::Param::Name(ref map_filename) => {
+                                let map_filename = working_dir.join(map_filename);
+                                info!("Environment map: {:?}", map_filename);
+                                let map = EnvironmentMap::new(read_bitmap(&map_filename)?)
+                                    .map_err(|e| {
+                                        SceneLoadError::invalid(
+                                            filename,
+                                            "LightSource \"infinite\"",
+                                            format!("{:?}: {}", map_filename, e),
+                                        )
+                                    })?;
+                                (Color::one(), Some(map))
                             }
                             _ => {
                                 warn!("Unsupported luminance field: {:?}", infinite.luminance);
+                                continue;
                             }
-                        }
+                        };
+                        let mut env = EnvironmentLight {
+                            luminance,
+                            map,
+                            to_world: Matrix3::identity(),
+                            world_radius: 1.0,
+                            world_position: Point3::origin(),
+                        };
+                        env.preprocess(&scene_aabb(&meshes));
+                        emitter_environment = Some(env);
+                        have_env = true;
+                    }
+                    pbrt_rs::Light::Point(ref point) => {
+                        let light_to_world = point.world_to_light.inverse_transform().unwrap();
+                        let intensity = pbrt_color(filename, &point.intensity)?
+                            * pbrt_color(filename, &point.scale)?;
+                        emitters.push(Box::new(PointLight {
+                            position: light_to_world.transform_point(point.from),
+                            intensity,
+                        }));
                     }
-                    _ => {
-                        warn!("Igoring light type: {:?}", l);
+                    pbrt_rs::Light::Spot(ref spot) => {
+                        let light_to_world = spot.world_to_light.inverse_transform().unwrap();
+                        let intensity = pbrt_color(filename, &spot.intensity)?
+                            * pbrt_color(filename, &spot.scale)?;
+                        let from = light_to_world.transform_point(spot.from);
+                        let to = light_to_world.transform_point(spot.to);
+                        emitters.push(Box::new(spot_light(
+                            from,
+                            to - from,
+                            intensity,
+                            spot.cone_angle,
+                            spot.cone_delta_angle,
+                        )));
+                    }
+                    pbrt_rs::Light::Distant(ref distant) => {
+                        let irradiance = pbrt_color(filename, &distant.luminance)?
+                            * pbrt_color(filename, &distant.scale)?;
+                        let mut emitter = directional_light(distant.to - distant.from, irradiance);
+                        emitter.preprocess(&scene_aabb(&meshes));
+                        emitters.push(Box::new(emitter));
                     }
                 }
             }
@@ -256,11 +660,24 @@ impl SceneLoader for PBRTSceneLoader {
                     pbrt_rs::Camera::Perspective(ref cam) => {
                         let mat = cam.world_to_camera.inverse_transform().unwrap();
                         info!("camera matrix: {:?}", mat);
-                        Camera::new(scene_info.image_size, cam.fov, mat)
+                        // The field of view is along the smaller image axis
+                        let mut sensor = PerspectiveSensor::new(
+                            scene_info.image_size,
+                            cam.fov,
+                            FovAxis::Smaller,
+                        );
+                        if cam.lensradius > 0.0 {
+                            sensor = sensor.thin_lens(cam.lensradius, cam.focaldistance);
+                        }
+                        Camera::with_sensor(scene_info.image_size, Box::new(sensor), mat)
                     }
                 }
             } else {
-                panic!("The camera is not set!");
+                return Err(SceneLoadError::invalid(
+                    filename,
+                    "Camera",
+                    "the camera is not set",
+                ));
             }
         };
         camera.print_info();
@@ -273,7 +690,18 @@ impl SceneLoader for PBRTSceneLoader {
             nb_threads: None,
             output_img_path: "out.pfm".to_string(),
             emitter_environment,
+            emitters,
             volume: None,
+            media: vec![],
+            acceleration: AccelerationType::default(),
+            sampler: SamplerType::default(),
+            filter: Filter::default(),
+            aovs: vec![],
+            adaptive: None,
+            crop: None,
+            seed: rand::random(),
+            passes: AtomicU64::new(0),
+            description: None,
         })
     }
 }
@@ -1,10 +1,11 @@
 use crate::constants;
+use crate::exr;
 use crate::geometry::Mesh;
 use crate::math::Frame;
 use crate::tools::*;
 use crate::Scale;
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
-use cgmath::{EuclideanSpace, Point2, Point3, Vector2, Vector3};
+use cgmath::{EuclideanSpace, InnerSpace, Point2, Point3, Vector2, Vector3};
 #[cfg(feature = "image")]
 use image::{DynamicImage, GenericImage, Pixel};
 #[cfg(feature = "openexr")]
@@ -209,7 +210,6 @@ impl Div<f32> for Color {
             }
         }
         //assert_ne!(other, 0.0);
-        
     }
 }
 
@@ -328,6 +328,7 @@ impl<'a> Add<&'a Color> for Color {
     }
 }
 
+#[derive(Clone)]
 pub struct Bitmap {
     pub size: Vector2<u32>,
     pub colors: Vec<Color>,
@@ -491,52 +492,67 @@ impl Bitmap {
     }
 
     // Load images
-    pub fn read_pfm(filename: &str) -> Self {
-        let f = File::open(Path::new(filename)).unwrap();
+    pub fn read_pfm(filename: &Path) -> std::io::Result<Self> {
+        let invalid = |msg: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, msg);
+        let f = File::open(filename)?;
         let mut f = BufReader::new(f);
         // Check the flag
         {
             let mut header_str = String::new();
-            f.read_line(&mut header_str).unwrap();
+            f.read_line(&mut header_str)?;
             if header_str != "PF\n" {
-                panic!("Wrong PF flag encounter");
+                return Err(invalid("wrong PF flag encounter"));
             }
         }
-        // Check the dim
+        // Check the dim ("width height") and the scale (negative: little endian)
         let size = {
-            let mut img_dim_y = String::new();
-            f.read_line(&mut img_dim_y).unwrap();
-            let mut img_dim_x = String::new();
-            f.read_line(&mut img_dim_x).unwrap();
-            Vector2::new(
-                img_dim_x.parse::<u32>().unwrap(),
-                img_dim_y.parse::<u32>().unwrap(),
-            )
+            let mut header = String::new();
+            while header.split_whitespace().count() < 3 {
+                if f.read_line(&mut header)? == 0 {
+                    return Err(invalid("wrong PFM header"));
+                }
+            }
+            let values = header.split_whitespace().collect::<Vec<_>>();
+            let scale = values[2]
+                .parse::<f32>()
+                .map_err(|_| invalid("wrong PFM scale"))?;
+            if scale > 0.0 {
+                return Err(invalid("big endian PFM files are not supported"));
+            }
+            match (values[0].parse::<u32>(), values[1].parse::<u32>()) {
+                (Ok(width), Ok(height)) => Vector2::new(width, height),
+                _ => return Err(invalid("wrong PFM size")),
+            }
         };
 
         let mut colors = vec![Color::zero(); (size.x * size.y) as usize];
         for y in 0..size.y {
             for x in 0..size.x {
-                let r = f.read_f32::<LittleEndian>().unwrap();
-                let g = f.read_f32::<LittleEndian>().unwrap();
-                let b = f.read_f32::<LittleEndian>().unwrap();
+                let r = f.read_f32::<LittleEndian>()?;
+                let g = f.read_f32::<LittleEndian>()?;
+                let b = f.read_f32::<LittleEndian>()?;
                 //
                 let p = Point2::new(x, size.y - y - 1);
                 colors[(p.y * size.x + p.x) as usize] = Color::new(r, g, b);
             }
         }
 
-        Bitmap { size, colors }
+        Ok(Bitmap { size, colors })
     }
     #[cfg(not(feature = "openexr"))]
-    pub fn read_exr(_filename: &str) -> Self {
-        panic!("Rustlight wasn't built with OpenEXR support");
+    pub fn read_exr(_filename: &Path) -> std::io::Result<Self> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Other,
+            "Rustlight wasn't built with OpenEXR support",
+        ))
     }
     #[cfg(feature = "openexr")]
-    pub fn read_exr(filename: &str) -> Self {
+    pub fn read_exr(filename: &Path) -> std::io::Result<Self> {
+        let to_io =
+            |e: openexr::Error| std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string());
         // Open the EXR file.
-        let mut file = std::fs::File::open(filename).unwrap();
-        let mut input_file = openexr::InputFile::new(&mut file).unwrap();
+        let mut file = std::fs::File::open(filename)?;
+        let mut input_file = openexr::InputFile::new(&mut file).map_err(to_io)?;
 
         // Get the image dimensions, so we know how large of a buffer to make.
         let (width, height) = input_file.header().data_dimensions();
@@ -554,25 +570,29 @@ impl Bitmap {
             fb.insert_channels(&[("R", 0.0), ("G", 0.0), ("B", 0.0)], &mut pixel_data);
 
             // Read pixel data from the file.
-            input_file.read_pixels(&mut fb).unwrap();
+            input_file.read_pixels(&mut fb).map_err(to_io)?;
         }
 
         let colors = pixel_data
             .into_iter()
             .map(|v| Color::new(v.0, v.1, v.2))
             .collect::<Vec<Color>>();
-        Bitmap { size, colors }
+        Ok(Bitmap { size, colors })
     }
     #[cfg(not(feature = "image"))]
-    pub fn read_ldr_image(_filename: &str) -> Self {
-        panic!("Rustlight wasn't built with image support");
-        Bitmap::default()
+    pub fn read_ldr_image(_filename: &Path) -> std::io::Result<Self> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Other,
+            "Rustlight wasn't built with image support",
+        ))
     }
     #[cfg(feature = "image")]
-    pub fn read_ldr_image(filename: &str) -> Self {
+    pub fn read_ldr_image(filename: &Path) -> std::io::Result<Self> {
         // The image that we will render
-        let image_ldr = image::open(filename)
-            .unwrap_or_else(|_| panic!("Impossible to read image: {}", filename));
+        let image_ldr = image::open(filename).map_err(|e| match e {
+            image::ImageError::IoError(e) => e,
+            e => std::io::Error::new(std::io::ErrorKind::InvalidData, e),
+        })?;
         let image_ldr = image_ldr.to_rgb();
         let size = Vector2::new(image_ldr.width(), image_ldr.height());
         let mut colors = vec![Color::zero(); (size.x * size.y) as usize];
@@ -587,23 +607,51 @@ impl Bitmap {
             }
         }
 
-        Bitmap { size, colors }
+        Ok(Bitmap { size, colors })
     }
 
-    pub fn read(filename: &str) -> Self {
-        let ext = match std::path::Path::new(filename).extension() {
-            None => panic!("No file extension provided"),
-            Some(x) => std::ffi::OsStr::to_str(x).expect("Issue to unpack the file"),
-        };
-        match ext {
-            "pfm" => Bitmap::read_pfm(filename),
-            "exr" => Bitmap::read_exr(filename),
-            _ => {
+    /// Read all the layers of an uncompressed EXR file (see exr::read)
+    pub fn read_layers(filename: &str) -> std::io::Result<exr::LayeredImage> {
+        exr::read(&std::fs::read(filename)?)
+    }
+    // Default layer of the uncompressed EXR files
+    // (the other files are read with the openexr library)
+    fn read_exr_layer(filename: &Path) -> std::io::Result<Self> {
+        let image = exr::read(&std::fs::read(filename)?);
+        match image {
+            Ok(image) => {
+                let mut layers = image.layers;
+                match layers.iter().position(|(l, _)| l == exr::DEFAULT_LAYER) {
+                    Some(id) => Ok(layers.swap_remove(id).1),
+                    None => Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!("no {} layer", exr::DEFAULT_LAYER),
+                    )),
+                }
+            }
+            Err(_) => Bitmap::read_exr(filename),
+        }
+    }
+
+    /// Read an image (the format is deduced from the file extension)
+    pub fn try_read(filename: &Path) -> std::io::Result<Self> {
+        match filename.extension().and_then(|x| x.to_str()) {
+            Some("pfm") => Bitmap::read_pfm(filename),
+            Some("exr") => Bitmap::read_exr_layer(filename),
+            Some(_) => {
                 // Try the default implementation support
                 Bitmap::read_ldr_image(filename)
             }
+            None => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "no file extension provided",
+            )),
         }
     }
+    pub fn read(filename: &str) -> Self {
+        Bitmap::try_read(Path::new(filename))
+            .unwrap_or_else(|e| panic!("Impossible to read image {}: {}", filename, e))
+    }
 }
 // By default, create a black image
 impl Default for Bitmap {
@@ -698,6 +746,39 @@ impl AABB {
         self.size() * 0.5 + self.p_min
     }
 
+    pub fn surface_area(&self) -> f32 {
+        let s = self.size();
+        if s.x < 0.0 || s.y < 0.0 || s.z < 0.0 {
+            // Empty AABB
+            0.0
+        } else {
+            2.0 * (s.x * s.y + s.x * s.z + s.y * s.z)
+        }
+    }
+
+    pub fn contains(&self, p: &Point3<f32>) -> bool {
+        p.x >= self.p_min.x
+            && p.y >= self.p_min.y
+            && p.z >= self.p_min.z
+            && p.x <= self.p_max.x
+            && p.y <= self.p_max.y
+            && p.z <= self.p_max.z
+    }
+
+    /// Entry and exit distances of the ray inside the box
+    /// (clamped to the ray [tnear, tfar] interval)
+    pub fn intersect_range(&self, r: &Ray) -> Option<(f32, f32)> {
+        let t_0 = vec_div(&(self.p_min - r.o.to_vec()), &r.d);
+        let t_1 = vec_div(&(self.p_max - r.o.to_vec()), &r.d);
+        let t_min = vec_max_coords(vec_min(&t_0, &t_1)).max(r.tnear);
+        let t_max = vec_min_coords(vec_max(&t_0, &t_1)).min(r.tfar);
+        if t_min <= t_max {
+            Some((t_min, t_max))
+        } else {
+            None
+        }
+    }
+
     pub fn intersect(&self, r: &Ray) -> Option<f32> {
         // TODO: direction inverse could be precomputed
         let t_0 = vec_div(&(self.p_min - r.o.to_vec()), &r.d);
@@ -731,6 +812,8 @@ pub struct Intersection<'a> {
     pub uv: Option<Vector2<f32>>,
     /// Mesh which we have intersected
     pub mesh: &'a Mesh,
+    /// Index of the mesh inside Scene::meshes
+    pub mesh_id: usize,
     /// Frame from the intersection point
     pub frame: Frame,
     /// Incomming direction in the local coordinates
@@ -747,6 +830,15 @@ impl<'a> Intersection<'a> {
     pub fn to_world(&self, d: &Vector3<f32>) -> Vector3<f32> {
         self.frame.to_world(*d)
     }
+    /// Medium on the side of the world direction d
+    /// (None is the scene medium)
+    pub fn medium(&self, d: &Vector3<f32>) -> Option<usize> {
+        if d.dot(self.n_g) < 0.0 {
+            self.mesh.interior
+        } else {
+            self.mesh.exterior
+        }
+    }
 }
 
 #[derive(Clone, Debug, Copy)]
@@ -756,14 +848,15 @@ pub struct VarianceEstimator {
     pub sample_count: u32,
 }
 impl VarianceEstimator {
-    fn add(&mut self, v: f32) {
+    pub fn add(&mut self, v: f32) {
         self.sample_count += 1;
         let delta = v - self.mean;
         self.mean += delta / self.sample_count as f32;
         self.mean_sqr += delta * (v - self.mean);
     }
 
-    fn variance(&self) -> f32 {
+    /// Unbiased variance (needs at least 2 samples)
+    pub fn variance(&self) -> f32 {
         self.mean_sqr / (self.sample_count - 1) as f32
     }
 }
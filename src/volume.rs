@@ -1,6 +1,10 @@
 use crate::math;
+use crate::samplers::Sampler;
 use crate::structure::*;
+use byteorder::{LittleEndian, ReadBytesExt};
 use cgmath::*;
+use std::error::Error;
+use std::io::{BufReader, Read};
 
 // Phase function
 pub struct SampledPhase {
@@ -16,44 +20,101 @@ pub enum PhaseFunction {
 }
 
 impl PhaseFunction {
-    pub fn eval(&self, _w_i: &Vector3<f32>, _w_o: &Vector3<f32>) -> Color {
-        match self {
-            Self::Isotropic() => Color::value(1.0 / (std::f32::consts::PI * 4.0)),
-            Self::HenyeyGreenstein(ref _g) => {
-                unimplemented!();
-            }
-        }
+    // Convention: w_i and w_o are both pointing away from the scattering point
+    pub fn eval(&self, w_i: &Vector3<f32>, w_o: &Vector3<f32>) -> Color {
+        Color::value(self.pdf(w_i, w_o))
     }
 
-    pub fn pdf(&self, _w_i: &Vector3<f32>, _w_o: &Vector3<f32>) -> f32 {
+    pub fn pdf(&self, w_i: &Vector3<f32>, w_o: &Vector3<f32>) -> f32 {
         match self {
             Self::Isotropic() => 1.0 / (std::f32::consts::PI * 4.0),
-            Self::HenyeyGreenstein(ref _g) => {
-                unimplemented!();
+            Self::HenyeyGreenstein(ref g) => {
+                let tmp = 1.0 + g * g + 2.0 * g * w_i.dot(*w_o);
+                (1.0 - g * g) / (4.0 * std::f32::consts::PI * tmp * tmp.sqrt())
             }
         }
     }
 
-    pub fn sample(&self, _d_in: &Vector3<f32>, u: Point2<f32>) -> SampledPhase {
+    pub fn sample(&self, d_in: &Vector3<f32>, u: Point2<f32>) -> SampledPhase {
         match self {
             Self::Isotropic() => SampledPhase {
                 d: math::sample_uniform_sphere(u),
                 weight: Color::one(),
                 pdf: 1.0 / (std::f32::consts::PI * 4.0),
             },
-            Self::HenyeyGreenstein(ref _g) => {
-                unimplemented!();
+            Self::HenyeyGreenstein(ref g) => {
+                // Cosine with the propagation direction (-d_in)
+                let cos_theta = if g.abs() < 1e-3 {
+                    1.0 - 2.0 * u.x
+                } else {
+                    let sqr_term = (1.0 - g * g) / (1.0 - g + 2.0 * g * u.x);
+                    (1.0 + g * g - sqr_term * sqr_term) / (2.0 * g)
+                };
+                let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+                let phi = 2.0 * std::f32::consts::PI * u.y;
+                let d = math::Frame::new(-*d_in).to_world(Vector3::new(
+                    sin_theta * phi.cos(),
+                    sin_theta * phi.sin(),
+                    cos_theta,
+                ));
+                SampledPhase {
+                    d,
+                    weight: Color::one(), // Perfectly importance sampled
+                    pdf: self.pdf(d_in, &d),
+                }
             }
         }
     }
 }
 
+// Check the validity of the HG parameter
+pub fn henyey_greenstein(g: f32) -> Result<PhaseFunction, String> {
+    if g <= -1.0 || g >= 1.0 {
+        Err(format!(
+            "HG phase function need g inside ]-1, 1[ (g: {})",
+            g
+        ))
+    } else {
+        Ok(PhaseFunction::HenyeyGreenstein(g))
+    }
+}
+
+/// Parse the phase function from a string
+/// "isotropic" or "hg:g" (Henyey-Greenstein with g the mean cosine)
+impl std::str::FromStr for PhaseFunction {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values = s.split(':').collect::<Vec<_>>();
+        match values[..] {
+            ["isotropic"] => Ok(PhaseFunction::Isotropic()),
+            ["hg", g] => {
+                let g = g
+                    .parse::<f32>()
+                    .map_err(|e| format!("Wrong g value for HG phase function: {}", e))?;
+                henyey_greenstein(g)
+            }
+            _ => Err(format!("Unknown phase function: {}", s)),
+        }
+    }
+}
+
+/// Participating media (global or bounded by meshes)
+pub trait Medium: Send + Sync {
+    /// Sample a scattering distance along the ray
+    /// r.tfar is the distance to the next surface
+    fn sample_distance(&self, r: &Ray, sampler: &mut dyn Sampler) -> SampledDistance;
+    /// Transmittance between r.o and r.o + r.d * r.tfar
+    fn eval_transmittance(&self, r: &Ray, sampler: &mut dyn Sampler) -> Color;
+    fn phase(&self) -> &PhaseFunction;
+}
+
 // Consider isotropic participating media
 pub struct HomogenousVolume {
     pub sigma_a: Color,
     pub sigma_s: Color,
     pub sigma_t: Color,
     pub density: f32,
+    pub phase: PhaseFunction,
 }
 
 // Take the tungsten convention
@@ -128,3 +189,200 @@ impl HomogenousVolume {
         }
     }
 }
+
+impl Medium for HomogenousVolume {
+    fn sample_distance(&self, r: &Ray, sampler: &mut dyn Sampler) -> SampledDistance {
+        self.sample(r, sampler.next2d())
+    }
+    fn eval_transmittance(&self, r: &Ray, _sampler: &mut dyn Sampler) -> Color {
+        self.transmittance(*r)
+    }
+    fn phase(&self) -> &PhaseFunction {
+        &self.phase
+    }
+}
+
+/// Density values stored on a regular grid
+/// which is mapped to an axis aligned box (world space)
+pub struct VoxelGrid {
+    pub res: Vector3<usize>,
+    pub values: Vec<f32>,
+    pub aabb: AABB,
+    max_value: f32,
+}
+
+impl VoxelGrid {
+    /// Values are ordered x first, then y and z
+    pub fn new(res: Vector3<usize>, values: Vec<f32>, aabb: AABB) -> VoxelGrid {
+        assert_eq!(res.x * res.y * res.z, values.len());
+        let max_value = values.iter().cloned().fold(0.0, f32::max);
+        VoxelGrid {
+            res,
+            values,
+            aabb,
+            max_value,
+        }
+    }
+
+    /// Read Mitsuba binary volume (.vol)
+    /// only single channel float32 grids are supported
+    pub fn load_vol(filename: &std::path::Path) -> Result<VoxelGrid, Box<dyn Error>> {
+        let mut f = BufReader::new(std::fs::File::open(filename)?);
+        let mut header = [0; 4];
+        f.read_exact(&mut header)?;
+        if &header[0..3] != b"VOL" || header[3] != 3 {
+            return Err(format!("{:?} is not a valid volume file", filename).into());
+        }
+        let encoding = f.read_i32::<LittleEndian>()?;
+        let mut res = [0; 3];
+        for r in &mut res {
+            *r = f.read_i32::<LittleEndian>()? as usize;
+        }
+        let channels = f.read_i32::<LittleEndian>()?;
+        if encoding != 1 || channels != 1 {
+            return Err(format!(
+                "Unsupported volume file {:?} (encoding: {}, channels: {})",
+                filename, encoding, channels
+            )
+            .into());
+        }
+        let mut bbox = [0.0; 6];
+        for v in &mut bbox {
+            *v = f.read_f32::<LittleEndian>()?;
+        }
+        let aabb = AABB {
+            p_min: Vector3::new(bbox[0], bbox[1], bbox[2]),
+            p_max: Vector3::new(bbox[3], bbox[4], bbox[5]),
+        };
+        let mut values = vec![0.0; res[0] * res[1] * res[2]];
+        f.read_f32_into::<LittleEndian>(&mut values)?;
+        Ok(VoxelGrid::new(res.into(), values, aabb))
+    }
+
+    pub fn max(&self) -> f32 {
+        self.max_value
+    }
+
+    fn value(&self, x: usize, y: usize, z: usize) -> f32 {
+        self.values[(z * self.res.y + y) * self.res.x + x]
+    }
+
+    /// Trilinear interpolation of the density (0 outside the grid)
+    pub fn lookup(&self, p: &Point3<f32>) -> f32 {
+        let size = self.aabb.size();
+        let p_local = p.to_vec() - self.aabb.p_min;
+        let mut i = [0; 2 * 3];
+        let mut w = [0.0; 3];
+        for a in 0..3 {
+            let v = p_local[a] / size[a];
+            if !(0.0..=1.0).contains(&v) {
+                return 0.0;
+            }
+            // Voxel values are located at the voxel centers
+            let res = self.res[a];
+            let v = (v * res as f32 - 0.5).max(0.0);
+            let i0 = (v as usize).min(res - 1);
+            i[2 * a] = i0;
+            i[2 * a + 1] = (i0 + 1).min(res - 1);
+            w[a] = (v - i0 as f32).min(1.0);
+        }
+        let lerp = |a: f32, b: f32, t: f32| a * (1.0 - t) + b * t;
+        let v_y = |y: usize, z: usize| lerp(self.value(i[0], y, z), self.value(i[1], y, z), w[0]);
+        let v_z = |z: usize| lerp(v_y(i[2], z), v_y(i[3], z), w[1]);
+        lerp(v_z(i[4]), v_z(i[5]), w[2])
+    }
+}
+
+/// Participating media defined by a density grid
+/// sigma_a and sigma_s are scaled by the local density
+pub struct HeterogeneousVolume {
+    pub sigma_a: Color,
+    pub sigma_s: Color,
+    pub sigma_t: Color,
+    pub density: f32,
+    pub grid: VoxelGrid,
+    pub phase: PhaseFunction,
+}
+
+impl HeterogeneousVolume {
+    // Upper bound of the extinction (all channels)
+    fn majorant(&self) -> f32 {
+        self.sigma_t.channel_max() * self.density * self.grid.max()
+    }
+}
+
+impl Medium for HeterogeneousVolume {
+    // Delta tracking with the spectral tracking weights [Kutz et al. 2017]
+    // to support chromatic extinction. The absorption is always
+    // accounted inside the weight.
+    fn sample_distance(&self, r: &Ray, sampler: &mut dyn Sampler) -> SampledDistance {
+        let exit = |w: Color| SampledDistance {
+            t: r.tfar,
+            w,
+            continued_t: r.tfar,
+            continued_w: w,
+            pdf: 1.0, // Not tractable with delta tracking
+            exited: true,
+        };
+        let majorant = self.majorant();
+        let (t_min, t_max) = match self.grid.aabb.intersect_range(r) {
+            Some(v) if majorant > 0.0 => v,
+            _ => return exit(Color::one()),
+        };
+
+        let mut w = Color::one();
+        let mut t = t_min;
+        loop {
+            t -= (1.0 - sampler.next()).ln() / majorant;
+            if t >= t_max {
+                return exit(w);
+            }
+            let density = self.density * self.grid.lookup(&(r.o + r.d * t));
+            let sigma_s = self.sigma_s * density;
+            let sigma_n = Color::value(majorant) - self.sigma_t * density;
+            // Probabilities to scatter or to continue (null collision)
+            let p_s = (w * sigma_s).avg();
+            let p_n = (w * sigma_n).avg();
+            if p_s + p_n <= 0.0 {
+                return exit(Color::zero());
+            }
+            if sampler.next() * (p_s + p_n) < p_s {
+                w *= sigma_s * ((p_s + p_n) / (majorant * p_s));
+                return SampledDistance {
+                    t,
+                    w,
+                    continued_t: t,
+                    continued_w: w,
+                    pdf: 1.0,
+                    exited: false,
+                };
+            } else {
+                w *= sigma_n * ((p_s + p_n) / (majorant * p_n));
+            }
+        }
+    }
+
+    // Ratio tracking
+    fn eval_transmittance(&self, r: &Ray, sampler: &mut dyn Sampler) -> Color {
+        let majorant = self.majorant();
+        let (t_min, t_max) = match self.grid.aabb.intersect_range(r) {
+            Some(v) if majorant > 0.0 => v,
+            _ => return Color::one(),
+        };
+
+        let mut tr = Color::one();
+        let mut t = t_min;
+        loop {
+            t -= (1.0 - sampler.next()).ln() / majorant;
+            if t >= t_max || tr.is_zero() {
+                return tr;
+            }
+            let density = self.density * self.grid.lookup(&(r.o + r.d * t));
+            tr *= Color::one() - self.sigma_t * (density / majorant);
+        }
+    }
+
+    fn phase(&self) -> &PhaseFunction {
+        &self.phase
+    }
+}